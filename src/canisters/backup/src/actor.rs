@@ -0,0 +1,156 @@
+use candid::{candid_method, CandidType, Deserialize, Principal};
+use ic_cdk::api;
+use ic_cdk_macros::*;
+use log::{error, info};
+use serde_bytes::ByteBuf;
+
+use common::backup::{
+    BeginSnapshotArgs, RetentionPolicy, SnapshotChunkResponse, SnapshotId, SnapshotIdResponse,
+    SnapshotListResponse, SnapshotMetaResponse,
+};
+use common::errors::{BooleanActorResponse, CommonError, ServiceResult};
+use common::permissions::{is_admin, must_be_system_owner};
+use common::types::TimeInNs;
+
+use crate::archive::{Archive, ARCHIVE};
+use crate::memory::StableMemory;
+
+/// Arguments of `init`, upgrades keep the persisted sources.
+#[derive(CandidType, Deserialize, Clone, Debug, Default)]
+pub struct BackupArgs {
+    /// canisters allowed to push snapshots
+    pub sources: Vec<Principal>,
+}
+
+fn now() -> TimeInNs {
+    TimeInNs(api::time())
+}
+
+fn must_be_source(caller: &Principal) -> ServiceResult<()> {
+    if ARCHIVE.with(|a| a.borrow().is_source(caller)) {
+        Ok(())
+    } else {
+        Err(CommonError::PermissionDenied)
+    }
+}
+
+/// Admins read every snapshot, a source only its own.
+fn reader(caller: &Principal) -> ServiceResult<Option<Principal>> {
+    if is_admin(caller) {
+        return Ok(None);
+    }
+    must_be_source(caller)?;
+    Ok(Some(*caller))
+}
+
+#[update(name = "begin_snapshot")]
+#[candid_method(update, rename = "begin_snapshot")]
+fn begin_snapshot(args: BeginSnapshotArgs) -> SnapshotIdResponse {
+    let caller = api::caller();
+    let result = ARCHIVE.with(|a| a.borrow_mut().begin(caller, args, now()));
+    SnapshotIdResponse::new(result)
+}
+
+#[update(name = "put_chunk")]
+#[candid_method(update, rename = "put_chunk")]
+fn put_chunk(id: SnapshotId, index: u32, chunk: ByteBuf) -> BooleanActorResponse {
+    let caller = api::caller();
+    let result = ARCHIVE.with(|a| a.borrow_mut().put_chunk(&caller, id, index, chunk));
+    BooleanActorResponse::new(result.map(|_| true))
+}
+
+#[update(name = "commit_snapshot")]
+#[candid_method(update, rename = "commit_snapshot")]
+fn commit_snapshot(id: SnapshotId) -> SnapshotMetaResponse {
+    let caller = api::caller();
+    let result = ARCHIVE.with(|a| a.borrow_mut().commit(&caller, id, now()));
+    SnapshotMetaResponse::new(result)
+}
+
+#[query(name = "list_snapshots")]
+#[candid_method(query, rename = "list_snapshots")]
+fn list_snapshots() -> SnapshotListResponse {
+    let caller = api::caller();
+    let result = reader(&caller).map(|source| ARCHIVE.with(|a| a.borrow().list(source.as_ref())));
+    SnapshotListResponse::new(result)
+}
+
+#[query(name = "get_snapshot")]
+#[candid_method(query, rename = "get_snapshot")]
+fn get_snapshot(id: SnapshotId) -> SnapshotMetaResponse {
+    let caller = api::caller();
+    let result = reader(&caller)
+        .and_then(|source| ARCHIVE.with(|a| a.borrow().get_meta(source.as_ref(), id)));
+    SnapshotMetaResponse::new(result)
+}
+
+#[query(name = "get_chunk")]
+#[candid_method(query, rename = "get_chunk")]
+fn get_chunk(id: SnapshotId, index: u32) -> SnapshotChunkResponse {
+    let caller = api::caller();
+    let result = reader(&caller)
+        .and_then(|source| ARCHIVE.with(|a| a.borrow().get_chunk(source.as_ref(), id, index)));
+    SnapshotChunkResponse::new(result)
+}
+
+#[update(name = "set_sources")]
+#[candid_method(update, rename = "set_sources")]
+fn set_sources(sources: Vec<Principal>) -> BooleanActorResponse {
+    let caller = &api::caller();
+    if let Err(e) = must_be_system_owner(caller) {
+        return BooleanActorResponse::new(Err(e));
+    }
+    info!("backup sources set by {}: {:?}", caller, sources);
+    ARCHIVE.with(|a| a.borrow_mut().set_sources(sources));
+    BooleanActorResponse::new(Ok(true))
+}
+
+#[query(name = "get_retention_policy")]
+#[candid_method(query, rename = "get_retention_policy")]
+fn get_retention_policy() -> RetentionPolicy {
+    ARCHIVE.with(|a| a.borrow().retention().clone())
+}
+
+#[update(name = "set_retention_policy")]
+#[candid_method(update, rename = "set_retention_policy")]
+fn set_retention_policy(retention: RetentionPolicy) -> BooleanActorResponse {
+    let caller = &api::caller();
+    if let Err(e) = must_be_system_owner(caller) {
+        return BooleanActorResponse::new(Err(e));
+    }
+    let result = ARCHIVE.with(|a| a.borrow_mut().set_retention(retention, now()));
+    BooleanActorResponse::new(result.map(|_| true))
+}
+
+#[init]
+#[candid_method(init)]
+fn init(args: BackupArgs) {
+    ARCHIVE.with(|a| a.borrow_mut().set_sources(args.sources));
+}
+
+/// Only the index is written, the chunks are in stable memory already.
+#[pre_upgrade]
+fn pre_upgrade() {
+    ARCHIVE
+        .with(|a| a.borrow_mut().save())
+        .expect("failed to save the archive index to stable memory");
+}
+
+#[post_upgrade]
+fn post_upgrade() {
+    match Archive::load(StableMemory).expect("failed to decode the archive index") {
+        Some(archive) => {
+            ARCHIVE.with(|a| a.replace(archive));
+            info!("post_upgrade: archive restored");
+        }
+        None => error!("post_upgrade: no archive index in stable memory"),
+    }
+}
+
+candid::export_service!();
+
+#[query(name = "__get_candid_interface_tmp_hack")]
+#[candid_method(query, rename = "__get_candid_interface_tmp_hack")]
+fn __export_did_tmp_() -> String {
+    __export_service()
+}
@@ -0,0 +1,460 @@
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet};
+
+use candid::{decode_args, encode_args, CandidType, Deserialize, Principal};
+use log::info;
+use serde_bytes::ByteBuf;
+
+use common::backup::{
+    sha256_hex, sha256_hex_of_chunks, BeginSnapshotArgs, RetentionPolicy, SnapshotId, SnapshotMeta,
+    BACKUP_CHUNK_SIZE,
+};
+use common::errors::{CommonError, ServiceResult};
+use common::state::StableState;
+use common::types::TimeInNs;
+
+use crate::memory::{ChunkMemory, StableMemory};
+
+#[cfg(test)]
+mod tests;
+
+/// 64 MiB, far above a compressed state, keeps a single source from filling the archive.
+pub const MAX_SNAPSHOT_SIZE: u64 = 64 * 1024 * 1024;
+/// 32 MiB at the start of the memory for the index, the chunks are stored after it.
+pub const INDEX_REGION_SIZE: u64 = 32 * 1024 * 1024;
+/// Written before the length of the index, an empty memory has no index yet.
+const INDEX_MAGIC: &[u8; 4] = b"BKP1";
+const INDEX_HEADER_SIZE: u64 = 12;
+const SLOT_SIZE: u64 = BACKUP_CHUNK_SIZE as u64;
+
+thread_local! {
+    pub static ARCHIVE: RefCell<Archive<StableMemory>> = RefCell::new(Archive::default());
+}
+
+/// A chunk takes one slot of `BACKUP_CHUNK_SIZE` bytes after the index region.
+#[derive(CandidType, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+struct ChunkLocation {
+    slot: u64,
+    len: u32,
+}
+
+impl ChunkLocation {
+    fn offset(&self) -> u64 {
+        INDEX_REGION_SIZE + self.slot * SLOT_SIZE
+    }
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+struct StoredSnapshot {
+    meta: SnapshotMeta,
+    chunks: Vec<Option<ChunkLocation>>,
+}
+
+/// Snapshots pushed by the source canisters, each source only sees its own snapshots.
+/// The chunks are written straight to `memory`, the heap only holds the index which `save`
+/// writes to the start of the memory, so an upgrade does not copy the snapshots.
+#[derive(Clone, Debug, Default)]
+pub struct Archive<M: ChunkMemory> {
+    /// canisters allowed to push snapshots
+    sources: BTreeSet<Principal>,
+    retention: RetentionPolicy,
+    next_id: SnapshotId,
+    snapshots: BTreeMap<SnapshotId, StoredSnapshot>,
+    /// slots taken so far, freed ones are reused first
+    next_slot: u64,
+    free_slots: BTreeSet<u64>,
+    memory: M,
+}
+
+fn not_found(id: SnapshotId) -> CommonError {
+    CommonError::InvalidArgument {
+        field: "id".to_string(),
+        detail: format!("snapshot {} not found", id),
+    }
+}
+
+impl<M: ChunkMemory + Default> Archive<M> {
+    /// Loads the index saved by `save`, `None` when the memory holds no index.
+    pub fn load(memory: M) -> Result<Option<Self>, String> {
+        if memory.size() < INDEX_HEADER_SIZE {
+            return Ok(None);
+        }
+        let mut header = [0u8; INDEX_HEADER_SIZE as usize];
+        memory.read(0, &mut header);
+        if &header[..4] != INDEX_MAGIC {
+            return Ok(None);
+        }
+        let mut len = [0u8; 8];
+        len.copy_from_slice(&header[4..]);
+        let mut bytes = vec![0u8; u64::from_le_bytes(len) as usize];
+        memory.read(INDEX_HEADER_SIZE, &mut bytes);
+        let mut archive = Archive::decode(bytes)?;
+        archive.memory = memory;
+        Ok(Some(archive))
+    }
+
+    /// Writes the index to the start of the memory, it must fit into `INDEX_REGION_SIZE`.
+    pub fn save(&mut self) -> ServiceResult<()> {
+        let bytes = self.encode();
+        let size = INDEX_HEADER_SIZE + bytes.len() as u64;
+        if size > INDEX_REGION_SIZE {
+            return Err(CommonError::InvalidState {
+                detail: format!(
+                    "index of {} bytes does not fit into {} bytes",
+                    size, INDEX_REGION_SIZE
+                ),
+            });
+        }
+        self.memory.grow_to(size)?;
+        self.memory.write(INDEX_HEADER_SIZE, &bytes);
+        let mut header = INDEX_MAGIC.to_vec();
+        header.extend_from_slice(&(bytes.len() as u64).to_le_bytes());
+        self.memory.write(0, &header);
+        Ok(())
+    }
+}
+
+impl<M: ChunkMemory> Archive<M> {
+    pub fn sources(&self) -> Vec<Principal> {
+        self.sources.iter().copied().collect()
+    }
+
+    pub fn set_sources(&mut self, sources: Vec<Principal>) {
+        self.sources = sources.into_iter().collect();
+    }
+
+    pub fn is_source(&self, caller: &Principal) -> bool {
+        self.sources.contains(caller)
+    }
+
+    pub fn retention(&self) -> &RetentionPolicy {
+        &self.retention
+    }
+
+    pub fn set_retention(
+        &mut self,
+        retention: RetentionPolicy,
+        now: TimeInNs,
+    ) -> ServiceResult<()> {
+        if retention.keep_last == 0 {
+            return Err(CommonError::InvalidArgument {
+                field: "keep_last".to_string(),
+                detail: "at least one snapshot must be kept".to_string(),
+            });
+        }
+        self.retention = retention;
+        let sources: BTreeSet<Principal> = self.snapshots.values().map(|s| s.meta.source).collect();
+        for source in sources {
+            self.apply_retention(&source, now);
+        }
+        Ok(())
+    }
+
+    /// `None` when `caller` may read every snapshot, i.e. an admin.
+    fn get(&self, caller: Option<&Principal>, id: SnapshotId) -> ServiceResult<&StoredSnapshot> {
+        let snapshot = self.snapshots.get(&id).ok_or_else(|| not_found(id))?;
+        match caller {
+            Some(caller) if *caller != snapshot.meta.source => Err(not_found(id)),
+            _ => Ok(snapshot),
+        }
+    }
+
+    fn get_mut(
+        &mut self,
+        source: &Principal,
+        id: SnapshotId,
+    ) -> ServiceResult<&mut StoredSnapshot> {
+        match self.snapshots.get_mut(&id) {
+            Some(snapshot) if snapshot.meta.source == *source => {
+                if snapshot.meta.complete {
+                    return Err(CommonError::InvalidState {
+                        detail: format!("snapshot {} is already complete", id),
+                    });
+                }
+                Ok(snapshot)
+            }
+            _ => Err(not_found(id)),
+        }
+    }
+
+    pub fn begin(
+        &mut self,
+        source: Principal,
+        args: BeginSnapshotArgs,
+        now: TimeInNs,
+    ) -> ServiceResult<SnapshotId> {
+        if !self.is_source(&source) {
+            return Err(CommonError::PermissionDenied);
+        }
+        if args.size == 0 || args.size > MAX_SNAPSHOT_SIZE {
+            return Err(CommonError::ValueShouldBeInRangeError {
+                field: "size".to_string(),
+                min: 1,
+                max: MAX_SNAPSHOT_SIZE as usize,
+            });
+        }
+        let expected_chunks = (args.size as usize + BACKUP_CHUNK_SIZE - 1) / BACKUP_CHUNK_SIZE;
+        if args.chunk_hashes.len() != expected_chunks {
+            return Err(CommonError::InvalidArgument {
+                field: "chunk_hashes".to_string(),
+                detail: format!(
+                    "{} bytes are {} chunks, not {}",
+                    args.size,
+                    expected_chunks,
+                    args.chunk_hashes.len()
+                ),
+            });
+        }
+        self.apply_retention(&source, now);
+        let id = self.next_id;
+        self.next_id += 1;
+        let chunks = vec![None; args.chunk_hashes.len()];
+        self.snapshots.insert(
+            id,
+            StoredSnapshot {
+                meta: SnapshotMeta {
+                    id,
+                    source,
+                    // the clock of the archive, retention compares it with its own `now`
+                    created_at: now,
+                    size: args.size,
+                    hash: args.hash,
+                    chunk_hashes: args.chunk_hashes,
+                    complete: false,
+                },
+                chunks,
+            },
+        );
+        Ok(id)
+    }
+
+    pub fn put_chunk(
+        &mut self,
+        source: &Principal,
+        id: SnapshotId,
+        index: u32,
+        chunk: ByteBuf,
+    ) -> ServiceResult<()> {
+        // a chunk must fit into its slot
+        if chunk.len() > BACKUP_CHUNK_SIZE {
+            return Err(CommonError::ValueShouldBeInRangeError {
+                field: "chunk".to_string(),
+                min: 1,
+                max: BACKUP_CHUNK_SIZE,
+            });
+        }
+        let snapshot = self.get_mut(source, id)?;
+        let expected = snapshot
+            .meta
+            .chunk_hashes
+            .get(index as usize)
+            .ok_or_else(|| CommonError::ValueShouldBeInRangeError {
+                field: "index".to_string(),
+                min: 0,
+                max: snapshot.chunks.len(),
+            })?;
+        if sha256_hex(&chunk) != *expected {
+            return Err(CommonError::InvalidArgument {
+                field: "chunk".to_string(),
+                detail: format!("chunk {} does not match its hash", index),
+            });
+        }
+        // a chunk pushed again, e.g. by a retry, overwrites its slot
+        let previous = snapshot.chunks[index as usize];
+        let slot = match previous {
+            Some(location) => location.slot,
+            None => self.allocate_slot()?,
+        };
+        let location = ChunkLocation {
+            slot,
+            len: chunk.len() as u32,
+        };
+        self.memory.write(location.offset(), &chunk);
+        self.get_mut(source, id)?.chunks[index as usize] = Some(location);
+        Ok(())
+    }
+
+    fn allocate_slot(&mut self) -> ServiceResult<u64> {
+        if let Some(slot) = self.free_slots.iter().next().copied() {
+            self.free_slots.remove(&slot);
+            return Ok(slot);
+        }
+        let slot = self.next_slot;
+        self.memory
+            .grow_to(INDEX_REGION_SIZE + (slot + 1) * SLOT_SIZE)?;
+        self.next_slot += 1;
+        Ok(slot)
+    }
+
+    fn read_chunk(&self, location: &ChunkLocation) -> Vec<u8> {
+        let mut chunk = vec![0u8; location.len as usize];
+        self.memory.read(location.offset(), &mut chunk);
+        chunk
+    }
+
+    pub fn commit(
+        &mut self,
+        source: &Principal,
+        id: SnapshotId,
+        now: TimeInNs,
+    ) -> ServiceResult<SnapshotMeta> {
+        let snapshot = self.get_mut(source, id)?;
+        if let Some(missing) = snapshot.chunks.iter().position(|c| c.is_none()) {
+            return Err(CommonError::InvalidState {
+                detail: format!("chunk {} of snapshot {} is missing", missing, id),
+            });
+        }
+        let locations: Vec<ChunkLocation> = snapshot.chunks.iter().flatten().copied().collect();
+        let size: u64 = locations.iter().map(|l| l.len as u64).sum();
+        let hash = sha256_hex_of_chunks(locations.iter().map(|l| self.read_chunk(l)));
+        let snapshot = self.get_mut(source, id)?;
+        if size != snapshot.meta.size || hash != snapshot.meta.hash {
+            return Err(CommonError::InvalidState {
+                detail: format!("snapshot {} does not match its size or hash", id),
+            });
+        }
+        snapshot.meta.complete = true;
+        let meta = snapshot.meta.clone();
+        info!(
+            "snapshot {} of {} complete, {} bytes",
+            id, meta.source, meta.size
+        );
+        self.apply_retention(source, now);
+        Ok(meta)
+    }
+
+    /// Newest first, `source` `None` lists the snapshots of every source.
+    pub fn list(&self, source: Option<&Principal>) -> Vec<SnapshotMeta> {
+        self.snapshots
+            .values()
+            .rev()
+            .filter(|s| source.map_or(true, |source| s.meta.source == *source))
+            .map(|s| s.meta.clone())
+            .collect()
+    }
+
+    pub fn get_meta(
+        &self,
+        caller: Option<&Principal>,
+        id: SnapshotId,
+    ) -> ServiceResult<SnapshotMeta> {
+        self.get(caller, id).map(|s| s.meta.clone())
+    }
+
+    pub fn get_chunk(
+        &self,
+        caller: Option<&Principal>,
+        id: SnapshotId,
+        index: u32,
+    ) -> ServiceResult<ByteBuf> {
+        let snapshot = self.get(caller, id)?;
+        if !snapshot.meta.complete {
+            return Err(CommonError::InvalidState {
+                detail: format!("snapshot {} is incomplete", id),
+            });
+        }
+        snapshot
+            .chunks
+            .get(index as usize)
+            .copied()
+            .flatten()
+            .map(|location| ByteBuf::from(self.read_chunk(&location)))
+            .ok_or_else(|| CommonError::ValueShouldBeInRangeError {
+                field: "index".to_string(),
+                min: 0,
+                max: snapshot.chunks.len(),
+            })
+    }
+
+    /// Drops the snapshots of `source` the retention policy no longer covers.
+    fn apply_retention(&mut self, source: &Principal, now: TimeInNs) {
+        let retention = &self.retention;
+        let mut complete_seen = 0;
+        let mut dropped = vec![];
+        // newest first, ids grow with time
+        for (id, snapshot) in self.snapshots.iter().rev() {
+            let meta = &snapshot.meta;
+            if meta.source != *source {
+                continue;
+            }
+            let age = now.0.saturating_sub(meta.created_at.0);
+            let keep = if meta.complete {
+                complete_seen += 1;
+                complete_seen <= retention.keep_last
+                    && retention.max_age.map_or(true, |max| age <= max.0)
+            } else {
+                age <= retention.incomplete_timeout.0
+            };
+            if !keep {
+                dropped.push(*id);
+            }
+        }
+        for id in dropped.iter() {
+            if let Some(snapshot) = self.snapshots.remove(id) {
+                self.free_slots
+                    .extend(snapshot.chunks.iter().flatten().map(|l| l.slot));
+            }
+        }
+        if !dropped.is_empty() {
+            info!("retention dropped snapshots {:?} of {}", dropped, source);
+        }
+    }
+}
+
+impl<M: ChunkMemory + Default> StableState for Archive<M> {
+    /// The index only, the chunks stay in the memory.
+    fn encode(&self) -> Vec<u8> {
+        let snapshots: Vec<&StoredSnapshot> = self.snapshots.values().collect();
+        encode_args((
+            &self.sources,
+            &self.retention,
+            self.next_id,
+            snapshots,
+            self.next_slot,
+            &self.free_slots,
+        ))
+        .unwrap()
+    }
+
+    fn decode(bytes: Vec<u8>) -> Result<Self, String> {
+        let (sources, retention, next_id, snapshots, next_slot, free_slots): (
+            BTreeSet<Principal>,
+            RetentionPolicy,
+            SnapshotId,
+            Vec<StoredSnapshot>,
+            u64,
+            BTreeSet<u64>,
+        ) = decode_args(&bytes).map_err(|e| format!("{:?}", e))?;
+        Ok(Archive {
+            sources,
+            retention,
+            next_id,
+            snapshots: snapshots.into_iter().map(|s| (s.meta.id, s)).collect(),
+            next_slot,
+            free_slots,
+            memory: M::default(),
+        })
+    }
+
+    fn check_invariants(&self) -> Vec<String> {
+        let mut violations: Vec<String> = self
+            .snapshots
+            .iter()
+            .filter(|(id, s)| **id != s.meta.id || **id >= self.next_id)
+            .map(|(id, s)| format!("snapshot {} is stored as {}", s.meta.id, id))
+            .collect();
+        let mut taken = BTreeSet::new();
+        for (id, snapshot) in self.snapshots.iter() {
+            for location in snapshot.chunks.iter().flatten() {
+                let free = self.free_slots.contains(&location.slot);
+                if location.slot >= self.next_slot || free || !taken.insert(location.slot) {
+                    violations.push(format!(
+                        "slot {} of snapshot {} is not its own",
+                        location.slot, id
+                    ));
+                }
+            }
+        }
+        violations
+    }
+}
@@ -0,0 +1,227 @@
+use rstest::*;
+
+use common::backup::split_chunks;
+use test_common::ic_api::init_test;
+use test_common::principal::*;
+
+use super::*;
+
+const DAY: u64 = 24 * 60 * 60 * 1_000_000_000;
+
+/// Stable memory of a test, cloned to simulate an upgrade.
+#[derive(Clone, Debug, Default)]
+pub struct VecMemory(Vec<u8>);
+
+impl ChunkMemory for VecMemory {
+    fn size(&self) -> u64 {
+        self.0.len() as u64
+    }
+
+    fn grow_to(&mut self, size: u64) -> ServiceResult<()> {
+        if size > self.size() {
+            self.0.resize(size as usize, 0);
+        }
+        Ok(())
+    }
+
+    fn write(&mut self, offset: u64, bytes: &[u8]) {
+        let offset = offset as usize;
+        self.0[offset..offset + bytes.len()].copy_from_slice(bytes);
+    }
+
+    fn read(&self, offset: u64, buf: &mut [u8]) {
+        let offset = offset as usize;
+        buf.copy_from_slice(&self.0[offset..offset + buf.len()]);
+    }
+}
+
+type TestArchive = Archive<VecMemory>;
+
+fn archive() -> TestArchive {
+    let mut archive = TestArchive::default();
+    archive.set_sources(vec![mock_user1(), mock_user2()]);
+    archive
+}
+
+fn args(data: &[u8]) -> BeginSnapshotArgs {
+    BeginSnapshotArgs {
+        size: data.len() as u64,
+        hash: sha256_hex(data),
+        chunk_hashes: split_chunks(data).iter().map(|c| sha256_hex(c)).collect(),
+    }
+}
+
+fn push(archive: &mut TestArchive, source: Principal, data: &[u8], now: TimeInNs) -> SnapshotId {
+    let id = archive.begin(source, args(data), now).unwrap();
+    for (index, chunk) in split_chunks(data).into_iter().enumerate() {
+        archive
+            .put_chunk(&source, id, index as u32, ByteBuf::from(chunk.to_vec()))
+            .unwrap();
+    }
+    archive.commit(&source, id, now).unwrap();
+    id
+}
+
+#[rstest]
+fn test_push_and_read(_init_test: ()) {
+    let mut archive = archive();
+    let data = vec![5u8; BACKUP_CHUNK_SIZE + 1];
+    let id = push(&mut archive, mock_user1(), &data, TimeInNs(1));
+
+    let meta = archive.get_meta(Some(&mock_user1()), id).unwrap();
+    assert!(meta.complete);
+    assert_eq!(meta.size, data.len() as u64);
+    let first = archive.get_chunk(Some(&mock_user1()), id, 0).unwrap();
+    let second = archive.get_chunk(None, id, 1).unwrap();
+    assert_eq!(first.len() + second.len(), data.len());
+
+    assert!(
+        archive.get_meta(Some(&mock_user2()), id).is_err(),
+        "another source can not read it"
+    );
+    assert!(archive.list(Some(&mock_user2())).is_empty());
+    assert_eq!(archive.list(None).len(), 1);
+}
+
+#[rstest]
+fn test_unknown_source_is_rejected(_init_test: ()) {
+    let mut archive = archive();
+    assert_eq!(
+        archive.begin(mock_user3(), args(&[1, 2, 3]), TimeInNs(1)),
+        Err(CommonError::PermissionDenied)
+    );
+}
+
+#[rstest]
+fn test_corrupted_chunk_is_rejected(_init_test: ()) {
+    let mut archive = archive();
+    let data = vec![1u8, 2, 3];
+    let id = archive
+        .begin(mock_user1(), args(&data), TimeInNs(1))
+        .unwrap();
+
+    assert!(archive
+        .put_chunk(&mock_user1(), id, 0, ByteBuf::from(vec![1u8, 2, 4]))
+        .is_err());
+    assert!(archive.commit(&mock_user1(), id, TimeInNs(1)).is_err());
+    assert!(archive.get_chunk(None, id, 0).is_err(), "still incomplete");
+}
+
+#[rstest]
+fn test_retention_keeps_the_latest(_init_test: ()) {
+    let mut archive = archive();
+    archive
+        .set_retention(
+            RetentionPolicy {
+                keep_last: 2,
+                max_age: None,
+                incomplete_timeout: TimeInNs(DAY),
+            },
+            TimeInNs(0),
+        )
+        .unwrap();
+    let ids: Vec<SnapshotId> = (0..3)
+        .map(|i| push(&mut archive, mock_user1(), &[i], TimeInNs(i as u64)))
+        .collect();
+    let other = push(&mut archive, mock_user2(), &[9], TimeInNs(3));
+
+    let kept: Vec<SnapshotId> = archive.list(None).iter().map(|m| m.id).collect();
+    assert_eq!(kept, vec![other, ids[2], ids[1]]);
+}
+
+#[rstest]
+fn test_retention_drops_old_and_stale(_init_test: ()) {
+    let mut archive = archive();
+    let old = push(&mut archive, mock_user1(), &[1], TimeInNs(0));
+    let stale = archive
+        .begin(mock_user1(), args(&[2]), TimeInNs(0))
+        .unwrap();
+    archive
+        .set_retention(
+            RetentionPolicy {
+                keep_last: 5,
+                max_age: Some(TimeInNs(7 * DAY)),
+                incomplete_timeout: TimeInNs(DAY),
+            },
+            TimeInNs(2 * DAY),
+        )
+        .unwrap();
+    assert_eq!(archive.list(None).len(), 1, "the incomplete one timed out");
+    assert_eq!(archive.list(None)[0].id, old);
+    assert_ne!(old, stale);
+
+    push(&mut archive, mock_user1(), &[3], TimeInNs(8 * DAY));
+    assert!(archive.get_meta(None, old).is_err(), "older than max_age");
+}
+
+#[rstest]
+fn test_encode_decode(_init_test: ()) {
+    let mut archive = archive();
+    push(&mut archive, mock_user1(), &[1, 2, 3], TimeInNs(1));
+
+    let decoded = TestArchive::decode(archive.encode()).unwrap();
+    assert_eq!(decoded.list(None), archive.list(None));
+    assert_eq!(decoded.sources(), archive.sources());
+    assert!(decoded.check_invariants().is_empty());
+}
+
+#[rstest]
+fn test_save_and_load(_init_test: ()) {
+    assert!(TestArchive::load(VecMemory::default()).unwrap().is_none());
+    let mut archive = archive();
+    let data: Vec<u8> = (0..BACKUP_CHUNK_SIZE + 5).map(|i| i as u8).collect();
+    let id = push(&mut archive, mock_user1(), &data, TimeInNs(1));
+
+    archive.save().unwrap();
+    let loaded = TestArchive::load(archive.memory.clone()).unwrap().unwrap();
+
+    assert_eq!(loaded.list(None), archive.list(None));
+    let chunks: Vec<u8> = (0..2)
+        .flat_map(|index| loaded.get_chunk(None, id, index).unwrap().into_vec())
+        .collect();
+    assert_eq!(chunks, data);
+    assert!(loaded.check_invariants().is_empty());
+}
+
+#[rstest]
+fn test_slots_of_dropped_snapshots_are_reused(_init_test: ()) {
+    let mut archive = archive();
+    archive
+        .set_retention(
+            RetentionPolicy {
+                keep_last: 1,
+                max_age: None,
+                incomplete_timeout: TimeInNs(DAY),
+            },
+            TimeInNs(0),
+        )
+        .unwrap();
+    for i in 0..5 {
+        push(&mut archive, mock_user1(), &[i], TimeInNs(i as u64));
+    }
+
+    assert_eq!(archive.list(None).len(), 1);
+    assert_eq!(
+        archive.next_slot, 2,
+        "the slot of the dropped snapshot is reused"
+    );
+    assert!(archive.check_invariants().is_empty());
+}
+
+#[rstest]
+fn test_oversized_chunk_is_rejected(_init_test: ()) {
+    let mut archive = archive();
+    let chunk = vec![1u8; BACKUP_CHUNK_SIZE + 1];
+    // a size of one chunk with the hash of a bigger one
+    let mut args = args(&chunk);
+    args.size = 10;
+    args.chunk_hashes = vec![sha256_hex(&chunk)];
+    let id = archive.begin(mock_user1(), args, TimeInNs(1)).unwrap();
+
+    let result = archive.put_chunk(&mock_user1(), id, 0, ByteBuf::from(chunk));
+
+    assert!(matches!(
+        result,
+        Err(CommonError::ValueShouldBeInRangeError { .. })
+    ));
+}
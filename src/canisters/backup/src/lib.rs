@@ -0,0 +1,3 @@
+mod actor;
+mod archive;
+mod memory;
@@ -0,0 +1,46 @@
+use ic_cdk::api::stable::{stable64_grow, stable64_read, stable64_size, stable64_write};
+
+use common::errors::{CommonError, ServiceResult};
+
+pub const WASM_PAGE_SIZE: u64 = 64 * 1024;
+
+/// Memory the archive keeps its index and chunks in, `StableMemory` on the IC.
+pub trait ChunkMemory {
+    /// in bytes
+    fn size(&self) -> u64;
+    /// Grows the memory to at least `size` bytes.
+    fn grow_to(&mut self, size: u64) -> ServiceResult<()>;
+    fn write(&mut self, offset: u64, bytes: &[u8]);
+    fn read(&self, offset: u64, buf: &mut [u8]);
+}
+
+/// The stable memory of the canister, it survives upgrades without being copied to the heap.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct StableMemory;
+
+impl ChunkMemory for StableMemory {
+    fn size(&self) -> u64 {
+        stable64_size() * WASM_PAGE_SIZE
+    }
+
+    fn grow_to(&mut self, size: u64) -> ServiceResult<()> {
+        let current = self.size();
+        if size <= current {
+            return Ok(());
+        }
+        let pages = (size - current + WASM_PAGE_SIZE - 1) / WASM_PAGE_SIZE;
+        stable64_grow(pages)
+            .map(|_| ())
+            .map_err(|e| CommonError::InvalidState {
+                detail: format!("stable memory can not grow by {} pages, {:?}", pages, e),
+            })
+    }
+
+    fn write(&mut self, offset: u64, bytes: &[u8]) {
+        stable64_write(offset, bytes)
+    }
+
+    fn read(&self, offset: u64, buf: &mut [u8]) {
+        stable64_read(offset, buf)
+    }
+}
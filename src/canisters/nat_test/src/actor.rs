@@ -1,10 +1,14 @@
 use candid::{candid_method, CandidType, Deserialize, Nat};
+use ic_cdk::api;
+use ic_cdk::api::call::{accept_message, method_name};
 use ic_cdk_macros::*;
 use log::{debug, info};
 use num_bigint::BigUint;
 use serde::de;
 
 use common::permissions::{is_admin, must_be_system_owner};
+use common::rate_limit::{check_rate_limit, peek_rate_limit};
+use common::types::TimeInNs;
 
 #[derive(CandidType, Deserialize, Clone, Debug)]
 pub struct TestRequest {
@@ -18,11 +22,32 @@ pub struct TestResponse {
     pub num_res: Nat,
 }
 
+common::actor_response!(pub TestActorResponse, TestResponse);
+
+/// Limited per caller by `inspect_message`. The token taken here only counts when `test` is
+/// called as an update, a query discards it.
 #[query(name = "test")]
 #[candid_method(query, rename = "test")]
-fn test(req: TestRequest) -> TestResponse {
-    TestResponse {
+fn test(req: TestRequest) -> TestActorResponse {
+    if let Err(e) = check_rate_limit(&api::caller(), "test", TimeInNs(api::time())) {
+        return TestActorResponse::new(Err(e));
+    }
+    TestActorResponse::new(Ok(TestResponse {
         num_res: req.num_req,
+    }))
+}
+
+/// Rejects ingress calls of callers out of tokens, before they consume cycles.
+#[inspect_message]
+fn inspect_message() {
+    let method = method_name();
+    let caller = api::caller();
+    match peek_rate_limit(&caller, &method, TimeInNs(api::time())) {
+        Ok(_) => accept_message(),
+        Err(e) => debug!(
+            "inspect_message: {} from {} rejected, {}",
+            method, caller, e
+        ),
     }
 }
 
@@ -0,0 +1,208 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use anyhow::Result;
+use serde::{Serialize, Serializer};
+
+use crate::schema::ENV_DEV;
+use crate::{env_features, ENV_VARIABLE};
+
+#[cfg(test)]
+mod tests;
+
+pub const BUILD_GIT_SHA: &str = "COMMON_BUILD_GIT_SHA";
+pub const BUILD_TIMESTAMP: &str = "COMMON_BUILD_TIMESTAMP";
+pub const BUILD_RUSTC_VERSION: &str = "COMMON_BUILD_RUSTC_VERSION";
+pub const BUILD_FEATURES: &str = "COMMON_BUILD_FEATURES";
+pub const BUILD_DEPENDENCIES: &str = "COMMON_BUILD_DEPENDENCIES";
+/// Name of the file written to `OUT_DIR`, embedded as the `icp:public wasm_info` custom section.
+pub const BUILD_INFO_FILE: &str = "build_info.json";
+/// Dependencies whose resolved versions are reported.
+pub const REPORTED_DEPENDENCIES: [&str; 2] = ["candid", "ic-cdk"];
+pub const UNKNOWN: &str = "unknown";
+
+/// What the build script knows about the build, embedded as `common::wasm_info::BuildInfo`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct BuildMetadata {
+    pub git_sha: String,
+    pub semver: String,
+    /// nanoseconds since the epoch, like the canister clock
+    pub build_timestamp: u64,
+    pub env: String,
+    pub rustc_version: String,
+    pub features: Vec<String>,
+    /// `(name, version)` as resolved in `Cargo.lock`, an object of name to version in JSON
+    #[serde(serialize_with = "serialize_dependencies")]
+    pub dependencies: Vec<(String, String)>,
+}
+
+fn serialize_dependencies<S: Serializer>(
+    dependencies: &[(String, String)],
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.collect_map(dependencies.iter().map(|(name, version)| (name, version)))
+}
+
+/// Versions of `names` resolved in the content of a `Cargo.lock`, in the order of `names`.
+/// A name which is not locked is skipped, the first version wins when several are locked.
+pub fn lock_versions(lock: &str, names: &[&str]) -> Vec<(String, String)> {
+    let mut locked: Vec<(String, String)> = vec![];
+    let mut name: Option<String> = None;
+    for line in lock.lines().map(|line| line.trim()) {
+        if line == "[[package]]" {
+            name = None;
+        } else if let Some(value) = toml_string(line, "name") {
+            name = Some(value);
+        } else if let (Some(value), Some(package)) = (toml_string(line, "version"), name.take()) {
+            locked.push((package, value));
+        }
+    }
+    names
+        .iter()
+        .filter_map(|name| locked.iter().find(|(package, _)| package == name).cloned())
+        .collect()
+}
+
+fn toml_string(line: &str, key: &str) -> Option<String> {
+    let value = line
+        .strip_prefix(key)?
+        .trim_start()
+        .strip_prefix('=')?
+        .trim();
+    value
+        .strip_prefix('"')
+        .and_then(|v| v.strip_suffix('"'))
+        .map(|v| v.to_string())
+}
+
+/// Cargo features of the crate being built, from the `CARGO_FEATURE_*` variables of its build script.
+/// Cargo writes `-` as `_` in those names, features are reported lowercase with `_`.
+pub fn enabled_features(vars: impl Iterator<Item = (String, String)>) -> Vec<String> {
+    let mut features: Vec<String> = vars
+        .filter_map(|(name, _)| {
+            name.strip_prefix("CARGO_FEATURE_")
+                .map(|f| f.to_lowercase())
+        })
+        .collect();
+    features.sort();
+    features
+}
+
+/// The build metadata as JSON, dependencies are an object of name to version.
+pub fn render_json(info: &BuildMetadata) -> String {
+    serde_json::to_string(info).unwrap()
+}
+
+fn command_output(program: &str, args: &[&str]) -> Option<String> {
+    let output = Command::new(program).args(args).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let text = String::from_utf8(output.stdout).ok()?;
+    Some(text.trim().to_string()).filter(|text| !text.is_empty())
+}
+
+/// `COMMON_BUILD_GIT_SHA` when set, e.g. by a build from a source archive, otherwise `git`.
+fn git_sha() -> String {
+    println!("cargo:rerun-if-env-changed={}", BUILD_GIT_SHA);
+    if let Ok(sha) = std::env::var(BUILD_GIT_SHA) {
+        return sha;
+    }
+    // a new commit changes the ref HEAD points to, not HEAD itself
+    for path in ["HEAD", "refs/heads"] {
+        if let Some(path) = command_output("git", &["rev-parse", "--git-path", path]) {
+            println!("cargo:rerun-if-changed={}", path);
+        }
+    }
+    command_output("git", &["rev-parse", "HEAD"]).unwrap_or_else(|| UNKNOWN.to_string())
+}
+
+/// `SOURCE_DATE_EPOCH` when set, otherwise the time of the commit being built, so building the
+/// same commit twice embeds the same timestamp. 0 with a warning when neither is known.
+fn build_timestamp() -> Result<u64> {
+    println!("cargo:rerun-if-env-changed=SOURCE_DATE_EPOCH");
+    if let Ok(seconds) = std::env::var("SOURCE_DATE_EPOCH") {
+        return Ok(seconds.trim().parse::<u64>()? * 1_000_000_000);
+    }
+    match command_output("git", &["show", "-s", "--format=%ct", "HEAD"]) {
+        Some(seconds) => Ok(seconds.parse::<u64>()? * 1_000_000_000),
+        None => {
+            println!("cargo:warning=no SOURCE_DATE_EPOCH nor git commit, build timestamp is 0");
+            Ok(0)
+        }
+    }
+}
+
+fn find_cargo_lock(manifest_dir: &Path) -> Option<PathBuf> {
+    manifest_dir
+        .ancestors()
+        .map(|dir| dir.join("Cargo.lock"))
+        .find(|path| path.exists())
+}
+
+/// Collects the `BuildMetadata` of the crate running the build script. The features are its
+/// cargo features and the `cfg` features `generate_envs` enables for the env.
+pub fn collect_build_metadata() -> Result<BuildMetadata> {
+    let manifest_dir = PathBuf::from(std::env::var("CARGO_MANIFEST_DIR")?);
+    let dependencies = match find_cargo_lock(&manifest_dir) {
+        Some(path) => {
+            println!("cargo:rerun-if-changed={}", path.display());
+            lock_versions(&fs::read_to_string(path)?, &REPORTED_DEPENDENCIES)
+        }
+        None => vec![],
+    };
+    println!("cargo:rerun-if-env-changed={}", ENV_VARIABLE);
+    let env = std::env::var(ENV_VARIABLE).unwrap_or_else(|_| ENV_DEV.to_string());
+    let mut features = enabled_features(std::env::vars());
+    features.extend(env_features(&env).into_iter().map(|f| f.to_string()));
+    features.sort();
+    features.dedup();
+    let rustc = std::env::var("RUSTC").unwrap_or_else(|_| "rustc".to_string());
+    Ok(BuildMetadata {
+        git_sha: git_sha(),
+        semver: std::env::var("CARGO_PKG_VERSION")?,
+        build_timestamp: build_timestamp()?,
+        env,
+        rustc_version: command_output(&rustc, &["--version"])
+            .unwrap_or_else(|| UNKNOWN.to_string()),
+        features,
+        dependencies,
+    })
+}
+
+/// Build script entry of the actors, replaces `vergen`.
+/// Every value is passed on as `cargo:rustc-env` for `common::build_info!`, features and dependencies
+/// joined with `,`, and as JSON in `$OUT_DIR/build_info.json` for `common::wasm_info_metadata!`.
+/// Values which can not be found are `unknown`, the build does not depend on env variables.
+pub fn generate_build_info() -> Result<()> {
+    let info = collect_build_metadata()?;
+    println!("cargo:rustc-env={}={}", BUILD_GIT_SHA, info.git_sha);
+    println!(
+        "cargo:rustc-env={}={}",
+        BUILD_TIMESTAMP, info.build_timestamp
+    );
+    println!(
+        "cargo:rustc-env={}={}",
+        BUILD_RUSTC_VERSION, info.rustc_version
+    );
+    println!(
+        "cargo:rustc-env={}={}",
+        BUILD_FEATURES,
+        info.features.join(",")
+    );
+    let dependencies: Vec<String> = info
+        .dependencies
+        .iter()
+        .map(|(name, version)| format!("{}={}", name, version))
+        .collect();
+    println!(
+        "cargo:rustc-env={}={}",
+        BUILD_DEPENDENCIES,
+        dependencies.join(",")
+    );
+
+    let out_dir = PathBuf::from(std::env::var("OUT_DIR")?);
+    fs::write(out_dir.join(BUILD_INFO_FILE), render_json(&info))?;
+    Ok(())
+}
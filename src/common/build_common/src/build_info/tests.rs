@@ -0,0 +1,75 @@
+use rstest::*;
+
+use super::*;
+
+const LOCK: &str = r#"
+# This file is automatically @generated by Cargo.
+version = 3
+
+[[package]]
+name = "candid"
+version = "0.7.14"
+source = "registry+https://github.com/rust-lang/crates.io-index"
+dependencies = [
+ "ic-cdk 0.5.1",
+]
+
+[[package]]
+name = "ic-cdk"
+version = "0.5.1"
+
+[[package]]
+name = "candid"
+version = "0.8.0"
+"#;
+
+#[rstest]
+fn test_lock_versions() {
+    let versions = lock_versions(LOCK, &["ic-cdk", "serde", "candid"]);
+
+    assert_eq!(
+        versions,
+        vec![
+            ("ic-cdk".to_string(), "0.5.1".to_string()),
+            ("candid".to_string(), "0.7.14".to_string()),
+        ]
+    );
+}
+
+#[rstest]
+fn test_enabled_features() {
+    let vars = vec![
+        ("CARGO_FEATURE_DEV_ENV".to_string(), "1".to_string()),
+        ("CARGO_PKG_VERSION".to_string(), "0.1.0".to_string()),
+        ("CARGO_FEATURE_DEFAULT".to_string(), "1".to_string()),
+    ];
+
+    assert_eq!(
+        enabled_features(vars.into_iter()),
+        vec!["default", "dev_env"]
+    );
+}
+
+#[rstest]
+fn test_env_features_are_reported_for_dev() {
+    assert_eq!(env_features(ENV_DEV), vec!["dev_env"]);
+    assert!(env_features("production").is_empty());
+}
+
+#[rstest]
+fn test_render_json() {
+    let info = BuildMetadata {
+        git_sha: "abc".to_string(),
+        semver: "0.1.0".to_string(),
+        build_timestamp: 5,
+        env: "dev".to_string(),
+        rustc_version: "rustc \"1.62.0\"".to_string(),
+        features: vec!["default".to_string()],
+        dependencies: vec![("candid".to_string(), "0.7.14".to_string())],
+    };
+
+    assert_eq!(
+        render_json(&info),
+        r#"{"git_sha":"abc","semver":"0.1.0","build_timestamp":5,"env":"dev","rustc_version":"rustc \"1.62.0\"","features":["default"],"dependencies":{"candid":"0.7.14"}}"#
+    );
+}
@@ -0,0 +1,64 @@
+use std::fmt::Write;
+
+use candid::Principal;
+
+use crate::schema::{EnvEntry, EnvKind, EnvValue};
+
+#[cfg(test)]
+mod tests;
+
+fn principal_literal(principal: &Principal) -> String {
+    format!("candid::Principal::from_slice(&{:?})", principal.as_slice())
+}
+
+fn value_literal(value: &EnvValue) -> String {
+    match value {
+        EnvValue::Text(text) => format!("{:?}", text),
+        EnvValue::Number(number) => format!("{}", number),
+        EnvValue::CanisterId(principal) => principal_literal(principal),
+        EnvValue::Principals(principals) => {
+            let items: Vec<String> = principals.iter().map(principal_literal).collect();
+            format!("&[{}]", items.join(", "))
+        }
+    }
+}
+
+fn type_name(kind: EnvKind) -> &'static str {
+    match kind {
+        EnvKind::Text => "&str",
+        EnvKind::Number => "u64",
+        EnvKind::CanisterId => "candid::Principal",
+        EnvKind::Principals => "&[candid::Principal]",
+    }
+}
+
+/// Renders one constant per entry, to be `include!`d by the crate.
+/// An unset principal list is an empty slice, other unset optional keys are `None`.
+pub fn render_constants(env: &str, entries: &[EnvEntry]) -> String {
+    let mut out = String::new();
+    writeln!(
+        out,
+        "// generated by build_common::generate_envs from the {} env files, do not edit",
+        env
+    )
+    .unwrap();
+    writeln!(out, "pub const COMMON_CANISTER_ENV: &str = {:?};", env).unwrap();
+    for entry in entries {
+        let (ty, literal) = match (&entry.value, entry.kind, entry.optional) {
+            (None, EnvKind::Principals, _) => {
+                (type_name(entry.kind).to_string(), "&[]".to_string())
+            }
+            (Some(value), EnvKind::Principals, _) => {
+                (type_name(entry.kind).to_string(), value_literal(value))
+            }
+            (None, kind, _) => (format!("Option<{}>", type_name(kind)), "None".to_string()),
+            (Some(value), kind, true) => (
+                format!("Option<{}>", type_name(kind)),
+                format!("Some({})", value_literal(value)),
+            ),
+            (Some(value), kind, false) => (type_name(kind).to_string(), value_literal(value)),
+        };
+        writeln!(out, "pub const {}: {} = {};", entry.name, ty, literal).unwrap();
+    }
+    out
+}
@@ -0,0 +1,50 @@
+use rstest::*;
+
+use super::*;
+
+fn entry(name: &str, kind: EnvKind, optional: bool, value: Option<EnvValue>) -> EnvEntry {
+    EnvEntry {
+        name: name.to_string(),
+        kind,
+        optional,
+        raw: None,
+        value,
+    }
+}
+
+#[rstest]
+fn test_render_constants() {
+    let principal = Principal::from_slice(&[1, 2]);
+    let entries = vec![
+        entry(
+            "ADMINS",
+            EnvKind::Principals,
+            false,
+            Some(EnvValue::Principals(vec![principal])),
+        ),
+        entry("EXPORTERS", EnvKind::Principals, true, None),
+        entry("LIMIT", EnvKind::Number, true, Some(EnvValue::Number(3))),
+        entry("NAME", EnvKind::Text, true, None),
+        entry(
+            "SAMPLE",
+            EnvKind::CanisterId,
+            false,
+            Some(EnvValue::CanisterId(principal)),
+        ),
+    ];
+
+    let code = render_constants("staging", &entries);
+
+    let lines: Vec<&str> = code.lines().skip(1).collect();
+    assert_eq!(
+        lines,
+        vec![
+            r#"pub const COMMON_CANISTER_ENV: &str = "staging";"#,
+            "pub const ADMINS: &[candid::Principal] = &[candid::Principal::from_slice(&[1, 2])];",
+            "pub const EXPORTERS: &[candid::Principal] = &[];",
+            "pub const LIMIT: Option<u64> = Some(3);",
+            "pub const NAME: Option<&str> = None;",
+            "pub const SAMPLE: candid::Principal = candid::Principal::from_slice(&[1, 2]);",
+        ]
+    );
+}
@@ -1,35 +1,103 @@
-use anyhow::{Ok, Result};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
 use env_file_reader::read_file;
-use vergen::{vergen, Config};
 
+use crate::codegen::render_constants;
+use crate::schema::{validate_env_name, EnvEntry, EnvError, EnvPart, EnvSchema, ENV_DEV};
+
+pub mod build_info;
+pub mod codegen;
+pub mod schema;
+
+/// Name of the env variable selecting the env files, `dev` when not set.
+pub const ENV_VARIABLE: &str = "COMMON_CANISTER_ENV";
+/// Name of the file written to `OUT_DIR`.
+pub const GENERATED_FILE: &str = "envs.rs";
+
+/// Features `generate_envs_with` enables as `cfg` for `env`, they are not cargo features.
+pub fn env_features(env: &str) -> Vec<&'static str> {
+    if env == ENV_DEV {
+        vec!["dev_env"]
+    } else {
+        vec![]
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LoadedEnvs {
+    pub env: String,
+    pub entries: Vec<EnvEntry>,
+}
+
+/// Reads and validates `{config_dir}/{env}.{part}.env` for every part.
+/// A missing file is read as an empty one, its required keys are reported as missing, unless the
+/// part is required for the env, see `EnvPart::is_required`.
+pub fn load_envs(schema: &EnvSchema, config_dir: &Path, env: &str) -> Result<LoadedEnvs, EnvError> {
+    validate_env_name(env)?;
+    let mut entries = vec![];
+    for part in EnvPart::ALL {
+        let file_name = part.file_name(env);
+        let path = config_dir.join(&file_name);
+        let values = if path.exists() {
+            read_file(&path).map_err(|e| EnvError::Unreadable {
+                file: file_name.clone(),
+                detail: e.to_string(),
+            })?
+        } else if part.is_required(env) {
+            return Err(EnvError::MissingFile { file: file_name });
+        } else {
+            HashMap::new()
+        };
+        entries.extend(schema.validate(part, &file_name, &values)?);
+    }
+    Ok(LoadedEnvs {
+        env: env.to_string(),
+        entries,
+    })
+}
+
+/// Build script entry of `common`, see `generate_envs_with`.
 pub fn generate_envs() -> Result<()> {
-    // Generate the default 'cargo:' instruction output
-
-    // generate git info
-    // vergen(Config::default())?;
-    //
-    // println!("rerun-if-env-changed=COMMON_CANISTER_ENV");
-    // let env = if let Some(env) = option_env!("COMMON_CANISTER_ENV") {
-    //     env
-    // } else {
-    //     "dev"
-    // };
-    // println!("load env: {}", env);
-    // println!("warning={}", env);
-    //
-    // // enable feature dev_env if env is dev
-    // if env == "dev" {
-    //     println!("cargo:rustc-cfg=feature=\"dev_env\"");
-    // }
-    //
-    // // load env files
-    // let env_parts = vec!["canister_ids", "config", "principals"];
-    // for env_part in env_parts {
-    //     let env_variables = read_file(format!("../../env_configs/{}.{}.env", env, env_part))?;
-    //     for (key, value) in env_variables {
-    //         println!("cargo:rustc-env={}={}", key, value.replace("\n", "||||"));
-    //     }
-    // }
+    generate_envs_with(&EnvSchema::common())
+}
+
+/// Loads the env files of `COMMON_CANISTER_ENV` from `env_configs`, two levels above the crate,
+/// and fails the build if they do not match `schema`.
+/// Every key is passed on as `cargo:rustc-env`, multiline values joined with `||||`, and as a typed
+/// constant in `$OUT_DIR/envs.rs`. The `dev_env` feature is enabled for the dev env.
+pub fn generate_envs_with(schema: &EnvSchema) -> Result<()> {
+    println!("cargo:rerun-if-env-changed={}", ENV_VARIABLE);
+    let env = std::env::var(ENV_VARIABLE).unwrap_or_else(|_| ENV_DEV.to_string());
+    let config_dir = PathBuf::from(std::env::var("CARGO_MANIFEST_DIR")?).join("../../env_configs");
+    for part in EnvPart::ALL {
+        println!(
+            "cargo:rerun-if-changed={}",
+            config_dir.join(part.file_name(&env)).display()
+        );
+    }
+
+    let envs = load_envs(schema, &config_dir, &env)?;
+    for feature in env_features(&envs.env) {
+        println!("cargo:rustc-cfg=feature=\"{}\"", feature);
+    }
+    println!("cargo:rustc-env={}={}", ENV_VARIABLE, envs.env);
+    for entry in envs.entries.iter() {
+        if let Some(raw) = &entry.raw {
+            println!(
+                "cargo:rustc-env={}={}",
+                entry.name,
+                raw.replace('\n', "||||")
+            );
+        }
+    }
 
+    let out_dir = PathBuf::from(std::env::var("OUT_DIR")?);
+    fs::write(
+        out_dir.join(GENERATED_FILE),
+        render_constants(&envs.env, &envs.entries),
+    )?;
     Ok(())
 }
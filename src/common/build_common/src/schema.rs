@@ -0,0 +1,319 @@
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+
+use candid::Principal;
+use thiserror::Error;
+
+#[cfg(test)]
+mod tests;
+
+pub const ENV_DEV: &str = "dev";
+pub const ENV_STAGING: &str = "staging";
+pub const ENV_PRODUCTION: &str = "production";
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum EnvError {
+    #[error("unknown env {0:?}, expected one of dev, staging, production")]
+    UnknownEnv(String),
+    #[error("{file}: missing, it has to be supplied for this env")]
+    MissingFile { file: String },
+    #[error("{file}: can not be read, {detail}")]
+    Unreadable { file: String, detail: String },
+    #[error("{file}: missing required key {key}")]
+    MissingKey { file: String, key: String },
+    #[error("{file}: unknown key {key}, expected one of {expected}")]
+    UnknownKey {
+        file: String,
+        key: String,
+        expected: String,
+    },
+    #[error("{file}: {key} is not a valid {kind}, {detail}")]
+    InvalidValue {
+        file: String,
+        key: String,
+        kind: EnvKind,
+        detail: String,
+    },
+}
+
+pub fn validate_env_name(env: &str) -> Result<(), EnvError> {
+    match env {
+        ENV_DEV | ENV_STAGING | ENV_PRODUCTION => Ok(()),
+        _ => Err(EnvError::UnknownEnv(env.to_string())),
+    }
+}
+
+/// The files of an env, `env_configs/{env}.{part}.env`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EnvPart {
+    CanisterIds,
+    Config,
+    Principals,
+}
+
+impl EnvPart {
+    pub const ALL: [EnvPart; 3] = [EnvPart::CanisterIds, EnvPart::Config, EnvPart::Principals];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            EnvPart::CanisterIds => "canister_ids",
+            EnvPart::Config => "config",
+            EnvPart::Principals => "principals",
+        }
+    }
+
+    pub fn file_name(&self, env: &str) -> String {
+        format!("{}.{}.env", env, self.name())
+    }
+
+    /// Only the dev principals are checked in, the other envs get theirs from the deployment
+    /// and must not be built without them.
+    pub fn is_required(&self, env: &str) -> bool {
+        *self == EnvPart::Principals && env != ENV_DEV
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EnvKind {
+    Text,
+    Number,
+    /// a canister id in textual form
+    CanisterId,
+    /// principals one per line, empty lines and lines starting with `#` are skipped
+    Principals,
+}
+
+impl Display for EnvKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EnvKind::Text => write!(f, "text"),
+            EnvKind::Number => write!(f, "number"),
+            EnvKind::CanisterId => write!(f, "canister id"),
+            EnvKind::Principals => write!(f, "principal list"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum EnvValue {
+    Text(String),
+    Number(u64),
+    CanisterId(Principal),
+    Principals(Vec<Principal>),
+}
+
+impl EnvKind {
+    pub fn parse(&self, raw: &str) -> Result<EnvValue, String> {
+        match self {
+            EnvKind::Text => Ok(EnvValue::Text(raw.to_string())),
+            EnvKind::Number => raw
+                .trim()
+                .parse()
+                .map(EnvValue::Number)
+                .map_err(|e| format!("{}", e)),
+            EnvKind::CanisterId => parse_canister_id(raw.trim()).map(EnvValue::CanisterId),
+            EnvKind::Principals => raw
+                .lines()
+                .map(|line| line.trim())
+                .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                .map(parse_principal)
+                .collect::<Result<Vec<_>, _>>()
+                .map(EnvValue::Principals),
+        }
+    }
+}
+
+fn parse_principal(text: &str) -> Result<Principal, String> {
+    Principal::from_text(text).map_err(|e| format!("{}: {}", text, e))
+}
+
+fn parse_canister_id(text: &str) -> Result<Principal, String> {
+    let principal = parse_principal(text)?;
+    // canister ids are opaque ids, their last byte is the opaque class 0x01
+    if principal.as_slice().last() != Some(&0x01) {
+        return Err(format!("{}: not a canister id", text));
+    }
+    Ok(principal)
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EnvKey {
+    pub name: &'static str,
+    pub part: EnvPart,
+    pub kind: EnvKind,
+    pub required: bool,
+}
+
+impl EnvKey {
+    pub fn required(part: EnvPart, name: &'static str, kind: EnvKind) -> Self {
+        EnvKey {
+            name,
+            part,
+            kind,
+            required: true,
+        }
+    }
+
+    pub fn optional(part: EnvPart, name: &'static str, kind: EnvKind) -> Self {
+        EnvKey {
+            name,
+            part,
+            kind,
+            required: false,
+        }
+    }
+}
+
+/// A validated key of an env file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EnvEntry {
+    pub name: String,
+    pub kind: EnvKind,
+    /// declared optional by the schema, the generated constant is an `Option`
+    pub optional: bool,
+    /// the value as written in the file, `None` for an optional key which is not set
+    pub raw: Option<String>,
+    pub value: Option<EnvValue>,
+}
+
+/// The keys the env files may contain. A key which is neither declared nor matches a declared
+/// prefix is rejected, so a typo fails the build instead of silently falling back to a default.
+#[derive(Clone, Debug, Default)]
+pub struct EnvSchema {
+    keys: Vec<EnvKey>,
+    prefixes: Vec<(EnvPart, &'static str, EnvKind)>,
+}
+
+impl EnvSchema {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn key(mut self, key: EnvKey) -> Self {
+        self.keys.push(key);
+        self
+    }
+
+    /// Accepts every key starting with `prefix` in `part`, e.g. the canister ids written by the deploy scripts.
+    pub fn prefix(mut self, part: EnvPart, prefix: &'static str, kind: EnvKind) -> Self {
+        self.prefixes.push((part, prefix, kind));
+        self
+    }
+
+    /// The keys read by `common`.
+    pub fn common() -> Self {
+        EnvSchema::new()
+            .prefix(
+                EnvPart::CanisterIds,
+                "COMMON_CANISTER_IDS_",
+                EnvKind::CanisterId,
+            )
+            .key(EnvKey::optional(
+                EnvPart::Config,
+                "TEST_ENV_VALUE",
+                EnvKind::Number,
+            ))
+            .key(EnvKey::required(
+                EnvPart::Principals,
+                "COMMON_PRINCIPAL_NAME_ADMIN",
+                EnvKind::Principals,
+            ))
+            .key(EnvKey::optional(
+                EnvPart::Principals,
+                "COMMON_PRINCIPAL_NAME_STATE_EXPORTER",
+                EnvKind::Principals,
+            ))
+            .key(EnvKey::optional(
+                EnvPart::Principals,
+                "COMMON_PRINCIPAL_NAME_TIMER_TRIGGER",
+                EnvKind::Principals,
+            ))
+    }
+
+    fn expected(&self, part: EnvPart) -> String {
+        let keys = self
+            .keys
+            .iter()
+            .filter(|k| k.part == part)
+            .map(|k| k.name.to_string());
+        let prefixes = self
+            .prefixes
+            .iter()
+            .filter(|(p, _, _)| *p == part)
+            .map(|(_, prefix, _)| format!("{}*", prefix));
+        keys.chain(prefixes).collect::<Vec<_>>().join(", ")
+    }
+
+    /// Validates the content of the file of `part`, entries are sorted by name.
+    pub fn validate(
+        &self,
+        part: EnvPart,
+        file: &str,
+        values: &HashMap<String, String>,
+    ) -> Result<Vec<EnvEntry>, EnvError> {
+        let mut entries = vec![];
+        for key in self.keys.iter().filter(|k| k.part == part) {
+            match values.get(key.name) {
+                Some(raw) => {
+                    entries.push(parse_entry(file, key.name, key.kind, !key.required, raw)?)
+                }
+                None if key.required => {
+                    return Err(EnvError::MissingKey {
+                        file: file.to_string(),
+                        key: key.name.to_string(),
+                    })
+                }
+                None => entries.push(EnvEntry {
+                    name: key.name.to_string(),
+                    kind: key.kind,
+                    optional: true,
+                    raw: None,
+                    value: None,
+                }),
+            }
+        }
+        for (name, raw) in values {
+            if self.keys.iter().any(|k| k.part == part && k.name == name) {
+                continue;
+            }
+            let prefix = self
+                .prefixes
+                .iter()
+                .find(|(p, prefix, _)| *p == part && name.starts_with(prefix));
+            match prefix {
+                Some((_, _, kind)) => entries.push(parse_entry(file, name, *kind, false, raw)?),
+                None => {
+                    return Err(EnvError::UnknownKey {
+                        file: file.to_string(),
+                        key: name.to_string(),
+                        expected: self.expected(part),
+                    })
+                }
+            }
+        }
+        entries.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(entries)
+    }
+}
+
+fn parse_entry(
+    file: &str,
+    name: &str,
+    kind: EnvKind,
+    optional: bool,
+    raw: &str,
+) -> Result<EnvEntry, EnvError> {
+    let value = kind.parse(raw).map_err(|detail| EnvError::InvalidValue {
+        file: file.to_string(),
+        key: name.to_string(),
+        kind,
+        detail,
+    })?;
+    Ok(EnvEntry {
+        name: name.to_string(),
+        kind,
+        optional,
+        raw: Some(raw.to_string()),
+        value: Some(value),
+    })
+}
@@ -0,0 +1,108 @@
+use rstest::*;
+
+use super::*;
+
+const ADMIN: &str = "2eis6-ev3kx-wr3pi-otbsb-kzzrp-z3oyb-poe6w-bdbtz-gtigi-6ipki-3qe";
+const CANISTER: &str = "qoctq-giaaa-aaaaa-aaaea-cai";
+
+fn values(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+    pairs
+        .iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect()
+}
+
+#[rstest]
+fn test_multiline_principals_with_comments() {
+    let raw = format!("\n# main node\n{}\n\n", ADMIN);
+    let value = EnvKind::Principals.parse(&raw).unwrap();
+    assert_eq!(
+        value,
+        EnvValue::Principals(vec![Principal::from_text(ADMIN).unwrap()])
+    );
+}
+
+#[rstest]
+#[case(EnvKind::Principals, "not-a-principal")]
+#[case(EnvKind::CanisterId, ADMIN)]
+#[case(EnvKind::Number, "three")]
+fn test_invalid_value(#[case] kind: EnvKind, #[case] raw: &str) {
+    assert!(kind.parse(raw).is_err());
+}
+
+#[rstest]
+fn test_canister_id() {
+    assert_eq!(
+        EnvKind::CanisterId.parse(CANISTER).unwrap(),
+        EnvValue::CanisterId(Principal::from_text(CANISTER).unwrap())
+    );
+}
+
+#[rstest]
+fn test_missing_required_key() {
+    let result = EnvSchema::common().validate(
+        EnvPart::Principals,
+        "staging.principals.env",
+        &HashMap::new(),
+    );
+    assert_eq!(
+        result,
+        Err(EnvError::MissingKey {
+            file: "staging.principals.env".to_string(),
+            key: "COMMON_PRINCIPAL_NAME_ADMIN".to_string(),
+        })
+    );
+}
+
+#[rstest]
+fn test_unknown_key() {
+    let result = EnvSchema::common().validate(
+        EnvPart::Config,
+        "dev.config.env",
+        &values(&[("TEST_ENV_VALEU", "3")]),
+    );
+    assert!(matches!(
+        result,
+        Err(EnvError::UnknownKey { key, expected, .. })
+            if key == "TEST_ENV_VALEU" && expected == "TEST_ENV_VALUE"
+    ));
+}
+
+#[rstest]
+fn test_optional_and_prefixed_keys() {
+    let schema = EnvSchema::common();
+    let config = schema
+        .validate(EnvPart::Config, "dev.config.env", &HashMap::new())
+        .unwrap();
+    assert_eq!(config[0].value, None);
+    assert!(config[0].optional);
+
+    let canister_ids = schema
+        .validate(
+            EnvPart::CanisterIds,
+            "dev.canister_ids.env",
+            &values(&[("COMMON_CANISTER_IDS_NAT_TEST", CANISTER)]),
+        )
+        .unwrap();
+    assert_eq!(canister_ids[0].name, "COMMON_CANISTER_IDS_NAT_TEST");
+    assert!(!canister_ids[0].optional);
+}
+
+#[rstest]
+fn test_invalid_value_names_the_key() {
+    let result = EnvSchema::common().validate(
+        EnvPart::Principals,
+        "dev.principals.env",
+        &values(&[("COMMON_PRINCIPAL_NAME_ADMIN", "# admins\nnot-a-principal")]),
+    );
+    assert!(matches!(
+        result,
+        Err(EnvError::InvalidValue { key, kind: EnvKind::Principals, .. })
+            if key == "COMMON_PRINCIPAL_NAME_ADMIN"
+    ));
+}
+
+#[rstest]
+fn test_unknown_env() {
+    assert!(validate_env_name("prod").is_err());
+}
@@ -0,0 +1,82 @@
+use std::fs;
+use std::path::PathBuf;
+
+use build_common::load_envs;
+use build_common::schema::{EnvError, EnvKind, EnvSchema, EnvValue};
+use rstest::*;
+
+fn repo_env_configs() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("../../env_configs")
+}
+
+fn temp_config_dir(name: &str, files: &[(&str, &str)]) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("build_common_{}_{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    for (file, content) in files {
+        fs::write(dir.join(file), content).unwrap();
+    }
+    dir
+}
+
+#[rstest]
+fn test_repo_dev_env_is_valid() {
+    let envs = load_envs(&EnvSchema::common(), &repo_env_configs(), "dev").unwrap();
+
+    let admin = envs
+        .entries
+        .iter()
+        .find(|e| e.name == "COMMON_PRINCIPAL_NAME_ADMIN")
+        .unwrap();
+    assert_eq!(admin.kind, EnvKind::Principals);
+    assert!(matches!(&admin.value, Some(EnvValue::Principals(p)) if !p.is_empty()));
+}
+
+/// The repo has no principals of these envs, a build has to be given them by the deployment.
+#[rstest]
+#[case("staging")]
+#[case("production")]
+fn test_repo_envs_without_principals_fail(#[case] env: &str) {
+    let result = load_envs(&EnvSchema::common(), &repo_env_configs(), env);
+
+    assert_eq!(
+        result,
+        Err(EnvError::MissingFile {
+            file: format!("{}.principals.env", env),
+        })
+    );
+}
+
+#[rstest]
+fn test_missing_dev_file_reports_missing_key() {
+    let dir = temp_config_dir("missing", &[("dev.config.env", "TEST_ENV_VALUE=2\n")]);
+
+    let result = load_envs(&EnvSchema::common(), &dir, "dev");
+
+    assert_eq!(
+        result,
+        Err(EnvError::MissingKey {
+            file: "dev.principals.env".to_string(),
+            key: "COMMON_PRINCIPAL_NAME_ADMIN".to_string(),
+        })
+    );
+}
+
+#[rstest]
+fn test_multiline_export() {
+    let dir = temp_config_dir(
+        "multiline",
+        &[(
+            "production.principals.env",
+            "export COMMON_PRINCIPAL_NAME_ADMIN=\"\n# main node\n2eis6-ev3kx-wr3pi-otbsb-kzzrp-z3oyb-poe6w-bdbtz-gtigi-6ipki-3qe\n\"\n",
+        )],
+    );
+
+    let envs = load_envs(&EnvSchema::common(), &dir, "production").unwrap();
+
+    assert_eq!(envs.env, "production");
+    assert!(envs
+        .entries
+        .iter()
+        .any(|e| e.name == "COMMON_PRINCIPAL_NAME_ADMIN" && e.value.is_some()));
+}
@@ -0,0 +1,145 @@
+use std::cell::RefCell;
+
+use candid::{decode_args, encode_args, CandidType, Deserialize, Principal};
+use sha2::{Digest, Sha256};
+
+use crate::config::get_config;
+use crate::dto::{GetPageInput, GetPageOutput};
+use crate::errors::{ActorResult, ErrorInfo, ServiceResult};
+use crate::pagination::paginate_iter;
+use crate::state::StableState;
+use crate::types::TimeInNs;
+
+#[cfg(test)]
+mod tests;
+
+thread_local! {
+    pub static AUDIT_LOG: RefCell<AuditLog> = RefCell::new(AuditLog::default());
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub enum AuditOutcome {
+    Success,
+    Failure(ErrorInfo),
+}
+
+impl<T> From<&ServiceResult<T>> for AuditOutcome {
+    fn from(result: &ServiceResult<T>) -> Self {
+        match result {
+            Ok(_) => AuditOutcome::Success,
+            Err(e) => AuditOutcome::Failure(ErrorInfo::from(e.clone())),
+        }
+    }
+}
+
+impl<T> From<&ActorResult<T>> for AuditOutcome {
+    fn from(result: &ActorResult<T>) -> Self {
+        match result {
+            Ok(_) => AuditOutcome::Success,
+            Err(e) => AuditOutcome::Failure(e.clone()),
+        }
+    }
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct AuditRecord {
+    /// position in the log, starting at 0
+    pub id: u64,
+    pub timestamp: TimeInNs,
+    pub caller: Principal,
+    pub method: String,
+    /// hex sha256 of the candid encoded arguments, see `args_digest`
+    pub args_digest: String,
+    pub outcome: AuditOutcome,
+}
+
+/// Append-only log of privileged calls. Records are never changed or dropped, a `LoadState`
+/// proposal keeps the log of the canister instead of the one in the loaded snapshot.
+#[derive(Clone, Debug, Default)]
+pub struct AuditLog {
+    records: Vec<AuditRecord>,
+}
+
+impl AuditLog {
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+
+    pub fn append(
+        &mut self,
+        caller: Principal,
+        method: &str,
+        args_digest: String,
+        outcome: AuditOutcome,
+        now: TimeInNs,
+    ) -> u64 {
+        let id = self.records.len() as u64;
+        self.records.push(AuditRecord {
+            id,
+            timestamp: now,
+            caller,
+            method: method.to_string(),
+            args_digest,
+            outcome,
+        });
+        id
+    }
+
+    /// Newest records first.
+    pub fn get_page(&self, page: &GetPageInput) -> ServiceResult<GetPageOutput<AuditRecord>> {
+        Ok(paginate_iter(self.records.iter().rev(), page)?.map(AuditRecord::clone))
+    }
+}
+
+impl StableState for AuditLog {
+    fn encode(&self) -> Vec<u8> {
+        encode_args((&self.records,)).unwrap()
+    }
+
+    fn decode(bytes: Vec<u8>) -> Result<Self, String> {
+        let (records,): (Vec<AuditRecord>,) =
+            decode_args(&bytes).map_err(|e| format!("{:?}", e))?;
+        Ok(AuditLog { records })
+    }
+
+    fn check_invariants(&self) -> Vec<String> {
+        self.records
+            .iter()
+            .enumerate()
+            .filter(|(i, record)| record.id != *i as u64)
+            .map(|(i, record)| format!("audit record {} is at position {}", record.id, i))
+            .collect()
+    }
+}
+
+/// The arguments are not kept, they may be large (`LoadState`) or sensitive. The digest is
+/// enough to match a record with a request known from elsewhere.
+pub fn args_digest(args: &[u8]) -> String {
+    hex::encode(Sha256::digest(args))
+}
+
+/// Records the outcome of a privileged call. Call it once the caller passed the permission
+/// checks, so callers without permission can not grow the log.
+pub fn audit(
+    caller: &Principal,
+    method: &str,
+    args_digest: String,
+    outcome: impl Into<AuditOutcome>,
+    now: TimeInNs,
+) {
+    AUDIT_LOG.with(|l| {
+        l.borrow_mut()
+            .append(*caller, method, args_digest, outcome.into(), now)
+    });
+}
+
+pub fn get_audit_records(page: &GetPageInput) -> ServiceResult<GetPageOutput<AuditRecord>> {
+    page.validate(get_config().max_page_limit)?;
+    AUDIT_LOG.with(|l| l.borrow().get_page(page))
+}
+
+crate::actor_response!(pub GetAuditRecordsResponse, GetPageOutput<AuditRecord>);
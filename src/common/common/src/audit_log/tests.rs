@@ -0,0 +1,117 @@
+use rstest::*;
+
+use super::*;
+use crate::errors::CommonError;
+use crate::test_common::test::init_test;
+
+#[fixture]
+pub fn setup() {
+    init_test();
+}
+
+fn caller() -> Principal {
+    Principal::from_slice(&[1; 29])
+}
+
+fn first_page() -> GetPageInput {
+    GetPageInput {
+        offset: 0,
+        limit: 10,
+        ..Default::default()
+    }
+}
+
+#[rstest]
+fn test_append_newest_first(_setup: ()) {
+    let mut log = AuditLog::default();
+    let ok: ServiceResult<bool> = Ok(true);
+    let denied: ServiceResult<bool> = Err(CommonError::InvalidState {
+        detail: "feature load_state is disabled".to_string(),
+    });
+    assert_eq!(
+        log.append(
+            caller(),
+            "export_state",
+            args_digest(b""),
+            AuditOutcome::from(&ok),
+            TimeInNs(1)
+        ),
+        0
+    );
+    assert_eq!(
+        log.append(
+            caller(),
+            "load_state",
+            args_digest(b"state"),
+            AuditOutcome::from(&denied),
+            TimeInNs(2)
+        ),
+        1
+    );
+
+    let page = log.get_page(&first_page()).unwrap();
+    assert_eq!(page.total, 2);
+    assert_eq!(page.items[0].id, 1);
+    assert_eq!(page.items[0].method, "load_state");
+    assert_eq!(
+        page.items[0].outcome,
+        AuditOutcome::Failure(ErrorInfo::from(denied.unwrap_err()))
+    );
+    assert_eq!(page.items[1].outcome, AuditOutcome::Success);
+}
+
+#[rstest]
+fn test_get_audit_records_validates_input(_setup: ()) {
+    let result = get_audit_records(&GetPageInput {
+        offset: 0,
+        limit: 0,
+        ..Default::default()
+    });
+    assert!(matches!(
+        result,
+        Err(CommonError::ValueShouldBeInRangeError { .. })
+    ));
+}
+
+#[rstest]
+fn test_args_digest(_setup: ()) {
+    assert_eq!(
+        args_digest(b""),
+        "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+    );
+    assert_ne!(args_digest(b"a"), args_digest(b"b"));
+}
+
+#[rstest]
+fn test_encode_decode(_setup: ()) {
+    let mut log = AuditLog::default();
+    log.append(
+        caller(),
+        "set_config",
+        args_digest(b"config"),
+        AuditOutcome::Success,
+        TimeInNs(3),
+    );
+
+    let decoded = AuditLog::decode(log.encode()).unwrap();
+    assert_eq!(decoded.records, log.records);
+}
+
+#[rstest]
+fn test_check_invariants(_setup: ()) {
+    let mut log = AuditLog::default();
+    log.append(
+        caller(),
+        "set_config",
+        args_digest(b"config"),
+        AuditOutcome::Success,
+        TimeInNs(3),
+    );
+    assert!(log.check_invariants().is_empty());
+
+    log.records[0].id = 5;
+    assert_eq!(
+        log.check_invariants(),
+        vec!["audit record 5 is at position 0".to_string()]
+    );
+}
@@ -0,0 +1,179 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use async_trait::async_trait;
+use candid::{CandidType, Deserialize, Principal};
+use log::info;
+use serde_bytes::ByteBuf;
+use sha2::{Digest, Sha256};
+
+use crate::canister_api::IBackupApi;
+use crate::errors::{CommonError, ServiceResult};
+use crate::scheduler::Job;
+use crate::types::TimeInNs;
+
+#[cfg(test)]
+mod tests;
+
+thread_local! {
+    /// Snapshots of this canister as last listed by the backup canister, kept in memory only.
+    static BACKUP_LIST: RefCell<Vec<SnapshotMeta>> = RefCell::new(vec![]);
+}
+
+/// 1 MiB, a chunk and its call envelope stay below the 2 MiB inter-canister message limit.
+pub const BACKUP_CHUNK_SIZE: usize = 1024 * 1024;
+pub const BACKUP_JOB_NAME: &str = "backup";
+
+pub type SnapshotId = u64;
+
+/// Announces a snapshot, its chunks follow with `put_chunk`. The backup canister dates it with
+/// its own clock, the one its retention runs on.
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct BeginSnapshotArgs {
+    /// size of the compressed snapshot
+    pub size: u64,
+    /// hex sha256 of the compressed snapshot
+    pub hash: String,
+    pub chunk_hashes: Vec<String>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct SnapshotMeta {
+    pub id: SnapshotId,
+    /// canister the snapshot was taken from
+    pub source: Principal,
+    pub created_at: TimeInNs,
+    pub size: u64,
+    pub hash: String,
+    pub chunk_hashes: Vec<String>,
+    /// `false` until every chunk is stored and the hash of the whole snapshot is verified
+    pub complete: bool,
+}
+
+/// Applied by the backup canister per source canister whenever a snapshot is completed.
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct RetentionPolicy {
+    /// complete snapshots kept, the newest ones
+    pub keep_last: u32,
+    /// complete snapshots older than this are dropped, even if fewer than `keep_last` are left
+    pub max_age: Option<TimeInNs>,
+    /// incomplete snapshots older than this are dropped, e.g. the source trapped while pushing
+    pub incomplete_timeout: TimeInNs,
+}
+
+impl Default for RetentionPolicy {
+    fn default() -> Self {
+        RetentionPolicy {
+            keep_last: 7,
+            max_age: None,
+            incomplete_timeout: TimeInNs(24 * 60 * 60 * 1_000_000_000),
+        }
+    }
+}
+
+pub fn sha256_hex(data: &[u8]) -> String {
+    hex::encode(Sha256::digest(data))
+}
+
+/// `sha256_hex` of the chunks one after the other, without concatenating them.
+pub fn sha256_hex_of_chunks<I: IntoIterator<Item = Vec<u8>>>(chunks: I) -> String {
+    let mut hasher = Sha256::new();
+    for chunk in chunks {
+        hasher.update(&chunk);
+    }
+    hex::encode(hasher.finalize())
+}
+
+pub fn split_chunks(data: &[u8]) -> Vec<&[u8]> {
+    data.chunks(BACKUP_CHUNK_SIZE).collect()
+}
+
+/// Pushes a compressed snapshot chunk by chunk, the backup canister verifies every hash.
+pub async fn push_snapshot<T: IBackupApi>(api: &T, data: &[u8]) -> ServiceResult<SnapshotMeta> {
+    let chunks = split_chunks(data);
+    let args = BeginSnapshotArgs {
+        size: data.len() as u64,
+        hash: sha256_hex(data),
+        chunk_hashes: chunks.iter().map(|chunk| sha256_hex(chunk)).collect(),
+    };
+    let id = api.begin_snapshot(args).await?;
+    for (index, chunk) in chunks.into_iter().enumerate() {
+        api.put_chunk(id, index as u32, ByteBuf::from(chunk.to_vec()))
+            .await?;
+    }
+    let meta = api.commit_snapshot(id).await?;
+    info!(
+        "backup: snapshot {} pushed, {} bytes in {} chunks",
+        meta.id,
+        meta.size,
+        meta.chunk_hashes.len()
+    );
+    Ok(meta)
+}
+
+/// Fetches a complete snapshot and checks it against the hashes of its meta.
+pub async fn fetch_snapshot<T: IBackupApi>(api: &T, id: SnapshotId) -> ServiceResult<Vec<u8>> {
+    let meta = api.get_snapshot(id).await?;
+    if !meta.complete {
+        return Err(CommonError::InvalidState {
+            detail: format!("backup snapshot {} is incomplete", id),
+        });
+    }
+    let mut data = Vec::with_capacity(meta.size as usize);
+    for (index, expected) in meta.chunk_hashes.iter().enumerate() {
+        let chunk = api.get_chunk(id, index as u32).await?;
+        if &sha256_hex(&chunk) != expected {
+            return Err(CommonError::InvalidState {
+                detail: format!("chunk {} of backup snapshot {} is corrupted", index, id),
+            });
+        }
+        data.extend_from_slice(&chunk);
+    }
+    if sha256_hex(&data) != meta.hash {
+        return Err(CommonError::InvalidState {
+            detail: format!("backup snapshot {} does not match its hash", id),
+        });
+    }
+    Ok(data)
+}
+
+/// Periodically pushes the snapshot returned by `snapshot`, e.g. the compressed `State` of the actor.
+pub struct BackupJob<T: IBackupApi> {
+    api: T,
+    snapshot: Rc<dyn Fn() -> Vec<u8>>,
+}
+
+impl<T: IBackupApi> BackupJob<T> {
+    pub fn new(api: T, snapshot: Rc<dyn Fn() -> Vec<u8>>) -> Self {
+        BackupJob { api, snapshot }
+    }
+}
+
+#[async_trait(?Send)]
+impl<T: IBackupApi> Job for BackupJob<T> {
+    async fn run(&self, _now: TimeInNs) -> ServiceResult<()> {
+        let data = (self.snapshot)();
+        push_snapshot(&self.api, &data).await?;
+        refresh_backup_list(&self.api).await.map(|_| ())
+    }
+}
+
+/// The list cached by the latest `refresh_backup_list`, empty after an upgrade until then.
+pub fn get_backup_list() -> Vec<SnapshotMeta> {
+    BACKUP_LIST.with(|l| l.borrow().clone())
+}
+
+/// Lists the snapshots kept by the backup canister, so queries can read them.
+pub async fn refresh_backup_list<T: IBackupApi>(api: &T) -> ServiceResult<Vec<SnapshotMeta>> {
+    let snapshots = api.list_snapshots().await?;
+    BACKUP_LIST.with(|l| l.replace(snapshots.clone()));
+    Ok(snapshots)
+}
+
+crate::actor_response!(pub SnapshotIdResponse, SnapshotId);
+
+crate::actor_response!(pub SnapshotMetaResponse, SnapshotMeta);
+
+crate::actor_response!(pub SnapshotListResponse, Vec<SnapshotMeta>);
+
+crate::actor_response!(pub SnapshotChunkResponse, ByteBuf);
@@ -0,0 +1,174 @@
+use std::sync::Mutex;
+
+use rstest::*;
+
+use super::*;
+use crate::errors::{ActorResult, ErrorInfo};
+use crate::test_common::test::init_test;
+
+#[fixture]
+pub fn setup() {
+    init_test();
+}
+
+#[rstest]
+fn test_split_chunks(_setup: ()) {
+    let data = vec![7u8; BACKUP_CHUNK_SIZE * 2 + 1];
+    let chunks = split_chunks(&data);
+    assert_eq!(chunks.len(), 3);
+    assert_eq!(chunks[0].len(), BACKUP_CHUNK_SIZE);
+    assert_eq!(chunks[2].len(), 1);
+
+    assert!(split_chunks(&[]).is_empty());
+}
+
+#[rstest]
+fn test_sha256_hex(_setup: ()) {
+    assert_eq!(
+        sha256_hex(b"abc"),
+        "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+    );
+    assert_eq!(
+        sha256_hex_of_chunks(vec![b"a".to_vec(), b"bc".to_vec()]),
+        sha256_hex(b"abc")
+    );
+}
+
+/// Stores the pushed chunks like the backup canister, the mocks of `test_common` implement the
+/// trait of another build of this crate.
+#[derive(Default)]
+struct FakeBackupApi {
+    snapshot: Mutex<Option<SnapshotMeta>>,
+    chunks: Mutex<Vec<Vec<u8>>>,
+}
+
+impl FakeBackupApi {
+    fn with_snapshot(data: &[u8]) -> Self {
+        let api = FakeBackupApi::default();
+        *api.snapshot.lock().unwrap() = Some(meta(data));
+        *api.chunks.lock().unwrap() = split_chunks(data).iter().map(|c| c.to_vec()).collect();
+        api
+    }
+
+    fn get(&self, id: SnapshotId) -> ActorResult<SnapshotMeta> {
+        self.snapshot
+            .lock()
+            .unwrap()
+            .clone()
+            .filter(|meta| meta.id == id)
+            .ok_or_else(|| ErrorInfo::new(1, "snapshot not found"))
+    }
+}
+
+#[async_trait]
+impl IBackupApi for FakeBackupApi {
+    async fn begin_snapshot(&self, args: BeginSnapshotArgs) -> ActorResult<SnapshotId> {
+        *self.snapshot.lock().unwrap() = Some(SnapshotMeta {
+            id: 3,
+            source: source(),
+            created_at: TimeInNs(1),
+            size: args.size,
+            hash: args.hash,
+            chunk_hashes: args.chunk_hashes,
+            complete: false,
+        });
+        Ok(3)
+    }
+
+    async fn put_chunk(&self, id: SnapshotId, index: u32, chunk: ByteBuf) -> ActorResult<()> {
+        self.get(id)?;
+        let mut chunks = self.chunks.lock().unwrap();
+        assert_eq!(chunks.len(), index as usize, "chunks are pushed in order");
+        chunks.push(chunk.into_vec());
+        Ok(())
+    }
+
+    async fn commit_snapshot(&self, id: SnapshotId) -> ActorResult<SnapshotMeta> {
+        let mut meta = self.get(id)?;
+        meta.complete = true;
+        *self.snapshot.lock().unwrap() = Some(meta.clone());
+        Ok(meta)
+    }
+
+    async fn list_snapshots(&self) -> ActorResult<Vec<SnapshotMeta>> {
+        Ok(self.snapshot.lock().unwrap().iter().cloned().collect())
+    }
+
+    async fn get_snapshot(&self, id: SnapshotId) -> ActorResult<SnapshotMeta> {
+        self.get(id)
+    }
+
+    async fn get_chunk(&self, id: SnapshotId, index: u32) -> ActorResult<ByteBuf> {
+        self.get(id)?;
+        self.chunks
+            .lock()
+            .unwrap()
+            .get(index as usize)
+            .map(|chunk| ByteBuf::from(chunk.clone()))
+            .ok_or_else(|| ErrorInfo::new(1, "chunk not found"))
+    }
+}
+
+fn source() -> Principal {
+    Principal::from_slice(&[4, 2])
+}
+
+fn meta(data: &[u8]) -> SnapshotMeta {
+    SnapshotMeta {
+        id: 3,
+        source: source(),
+        created_at: TimeInNs(1),
+        size: data.len() as u64,
+        hash: sha256_hex(data),
+        chunk_hashes: split_chunks(data).iter().map(|c| sha256_hex(c)).collect(),
+        complete: true,
+    }
+}
+
+#[rstest]
+#[async_std::test]
+async fn test_push_snapshot_in_chunks(_setup: ()) {
+    let api = FakeBackupApi::default();
+    let data = vec![1u8; BACKUP_CHUNK_SIZE + 10];
+
+    let result = push_snapshot(&api, &data).await;
+
+    assert_eq!(result, Ok(meta(&data)));
+    let chunk_sizes: Vec<usize> = api.chunks.lock().unwrap().iter().map(|c| c.len()).collect();
+    assert_eq!(chunk_sizes, vec![BACKUP_CHUNK_SIZE, 10]);
+}
+
+#[rstest]
+#[async_std::test]
+async fn test_fetch_snapshot_verifies_hashes(_setup: ()) {
+    let api = FakeBackupApi::with_snapshot(&[1, 2, 3]);
+    api.chunks.lock().unwrap()[0] = vec![1, 2, 4];
+
+    let result = fetch_snapshot(&api, 3).await;
+
+    assert!(
+        matches!(result, Err(CommonError::InvalidState { .. })),
+        "a corrupted chunk is rejected"
+    );
+}
+
+#[rstest]
+#[async_std::test]
+async fn test_fetch_snapshot(_setup: ()) {
+    let api = FakeBackupApi::with_snapshot(&[1, 2, 3]);
+
+    assert_eq!(fetch_snapshot(&api, 3).await, Ok(vec![1, 2, 3]));
+    assert!(fetch_snapshot(&api, 4).await.is_err());
+}
+
+#[rstest]
+#[async_std::test]
+async fn test_backup_list_is_cached(_setup: ()) {
+    let api = FakeBackupApi::with_snapshot(&[1, 2, 3]);
+    assert!(get_backup_list().is_empty());
+
+    let listed = refresh_backup_list(&api).await.unwrap();
+
+    assert_eq!(listed, vec![meta(&[1, 2, 3])]);
+    assert_eq!(get_backup_list(), listed);
+}
@@ -6,7 +6,11 @@ use ic_cdk::call;
 use log::{debug, error};
 use serde::Deserialize;
 
+use serde_bytes::ByteBuf;
+
+use crate::backup::{BeginSnapshotArgs, SnapshotId, SnapshotMeta};
 use crate::errors::ActorResult;
+use crate::types::cycles_minting_types::*;
 use crate::types::ic_ledger_types::{Subaccount, TransferArgs, TransferResult};
 use crate::types::ic_management_types::*;
 
@@ -60,10 +64,49 @@ pub trait IICManagementAPI {
         wasm_module: Vec<u8>,
         args: Vec<u8>,
     ) -> ActorResult<()>;
+    async fn install_code(&self, install: CanisterInstall) -> ActorResult<()>;
+    async fn update_settings(&self, args: UpdateSettingsArgument) -> ActorResult<()>;
+    async fn uninstall_code(&self, id_record: CanisterIdRecord) -> ActorResult<()>;
+    async fn start_canister(&self, id_record: CanisterIdRecord) -> ActorResult<()>;
+    async fn stop_canister(&self, id_record: CanisterIdRecord) -> ActorResult<()>;
+    async fn delete_canister(&self, id_record: CanisterIdRecord) -> ActorResult<()>;
+    async fn deposit_cycles(&self, id_record: CanisterIdRecord, cycles: u64) -> ActorResult<()>;
+    async fn raw_rand(&self) -> ActorResult<Vec<u8>>;
     async fn ecdsa_public_key(
         &self,
         get_public_key_req: ECDSAPublicKey,
     ) -> ActorResult<ECDSAPublicKeyReply>;
     async fn sign_with_ecdsa(&self, sign_request: SignWithECDSA)
         -> ActorResult<SignWithECDSAReply>;
+    async fn schnorr_public_key(
+        &self,
+        args: SchnorrPublicKeyArgs,
+    ) -> ActorResult<SchnorrPublicKeyResult>;
+    async fn sign_with_schnorr(
+        &self,
+        args: SignWithSchnorrArgs,
+    ) -> ActorResult<SignWithSchnorrResult>;
+}
+
+#[async_trait]
+pub trait ICyclesMintingApi {
+    async fn get_icp_xdr_conversion_rate(
+        &self,
+    ) -> ActorResult<IcpXdrConversionRateCertifiedResponse>;
+    async fn notify_top_up(&self, args: NotifyTopUpArg) -> ActorResult<NotifyTopUpResult>;
+    async fn notify_create_canister(
+        &self,
+        args: NotifyCreateCanisterArg,
+    ) -> ActorResult<NotifyCreateCanisterResult>;
+}
+
+/// The archive canister `CanisterNames::Backup`, every method only sees the snapshots of the caller.
+#[async_trait]
+pub trait IBackupApi {
+    async fn begin_snapshot(&self, args: BeginSnapshotArgs) -> ActorResult<SnapshotId>;
+    async fn put_chunk(&self, id: SnapshotId, index: u32, chunk: ByteBuf) -> ActorResult<()>;
+    async fn commit_snapshot(&self, id: SnapshotId) -> ActorResult<SnapshotMeta>;
+    async fn list_snapshots(&self) -> ActorResult<Vec<SnapshotMeta>>;
+    async fn get_snapshot(&self, id: SnapshotId) -> ActorResult<SnapshotMeta>;
+    async fn get_chunk(&self, id: SnapshotId, index: u32) -> ActorResult<ByteBuf>;
 }
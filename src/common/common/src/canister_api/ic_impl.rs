@@ -1,7 +1,12 @@
 use candid::Principal;
+use serde_bytes::ByteBuf;
+
+use crate::backup::{BeginSnapshotArgs, SnapshotId, SnapshotMeta};
 
 use crate::constants::*;
 use crate::named_canister_ids::CanisterNames;
+/// Kept at its former path, the fee moved to `types::ic_management_types` with the Schnorr fee.
+pub use crate::types::ic_management_types::ECDSA_SIGNATURE_FEE;
 use crate::types::CanisterId;
 
 use super::*;
@@ -11,9 +16,12 @@ pub struct DFTApi(pub CanisterId);
 
 impl Default for DFTApi {
     fn default() -> Self {
-        DFTApi(get_named_canister_id(CanisterNames::DFTCanister(
-            CanisterId(Principal::anonymous()),
-        )))
+        DFTApi(
+            get_named_canister_id(CanisterNames::DFTCanister(CanisterId(
+                Principal::anonymous(),
+            )))
+            .expect("DFTCanister carries its own id"),
+        )
     }
 }
 
@@ -73,12 +81,6 @@ impl IICLedgerApi for ICLedgerApi {
 #[derive(Default)]
 pub struct ICManagementAPI;
 
-/// 10B cycles corresponds to 1 SDR cent. Assuming we can create 1 signature per
-/// second, that would come to  26k SDR per month if we spent the whole time
-/// creating signatures. At 13 nodes and 2k SDR per node per month this would
-/// cover the cost of the subnet.
-pub const ECDSA_SIGNATURE_FEE: u64 = 10_000_000_000;
-
 #[cfg_attr(coverage_nightly, no_coverage)]
 #[async_trait]
 impl IICManagementAPI for ICManagementAPI {
@@ -90,7 +92,13 @@ impl IICManagementAPI for ICManagementAPI {
         let in_arg = In {
             settings: Some(args.settings),
         };
-        call_canister_as_result(CanisterNames::ICManagement, "create_canister", (in_arg,)).await
+        call_canister_with_payment_as_result(
+            CanisterNames::ICManagement,
+            "create_canister",
+            (in_arg,),
+            args.cycles,
+        )
+        .await
     }
 
     async fn canister_status(
@@ -109,17 +117,50 @@ impl IICManagementAPI for ICManagementAPI {
         let install_config = CanisterInstall {
             mode: InstallMode::Install,
             canister_id: *canister_id,
-            wasm_module: wasm_module.clone(),
+            wasm_module,
             arg: args,
         };
-        call_canister_as_result(
+        self.install_code(install_config).await
+    }
+
+    async fn install_code(&self, install: CanisterInstall) -> ActorResult<()> {
+        call_canister_as_result(CanisterNames::ICManagement, "install_code", (install,)).await
+    }
+
+    async fn update_settings(&self, args: UpdateSettingsArgument) -> ActorResult<()> {
+        call_canister_as_result(CanisterNames::ICManagement, "update_settings", (args,)).await
+    }
+
+    async fn uninstall_code(&self, id_record: CanisterIdRecord) -> ActorResult<()> {
+        call_canister_as_result(CanisterNames::ICManagement, "uninstall_code", (id_record,)).await
+    }
+
+    async fn start_canister(&self, id_record: CanisterIdRecord) -> ActorResult<()> {
+        call_canister_as_result(CanisterNames::ICManagement, "start_canister", (id_record,)).await
+    }
+
+    async fn stop_canister(&self, id_record: CanisterIdRecord) -> ActorResult<()> {
+        call_canister_as_result(CanisterNames::ICManagement, "stop_canister", (id_record,)).await
+    }
+
+    async fn delete_canister(&self, id_record: CanisterIdRecord) -> ActorResult<()> {
+        call_canister_as_result(CanisterNames::ICManagement, "delete_canister", (id_record,)).await
+    }
+
+    async fn deposit_cycles(&self, id_record: CanisterIdRecord, cycles: u64) -> ActorResult<()> {
+        call_canister_with_payment_as_result(
             CanisterNames::ICManagement,
-            "install_code",
-            (install_config,),
+            "deposit_cycles",
+            (id_record,),
+            cycles,
         )
         .await
     }
 
+    async fn raw_rand(&self) -> ActorResult<Vec<u8>> {
+        call_canister_as_result(CanisterNames::ICManagement, "raw_rand", ()).await
+    }
+
     async fn ecdsa_public_key(
         &self,
         get_public_key_req: ECDSAPublicKey,
@@ -144,4 +185,96 @@ impl IICManagementAPI for ICManagementAPI {
         )
         .await
     }
+
+    async fn schnorr_public_key(
+        &self,
+        args: SchnorrPublicKeyArgs,
+    ) -> ActorResult<SchnorrPublicKeyResult> {
+        call_canister_as_result(CanisterNames::ICManagement, "schnorr_public_key", (args,)).await
+    }
+
+    async fn sign_with_schnorr(
+        &self,
+        args: SignWithSchnorrArgs,
+    ) -> ActorResult<SignWithSchnorrResult> {
+        call_canister_with_payment_as_result(
+            CanisterNames::ICManagement,
+            "sign_with_schnorr",
+            (args,),
+            SCHNORR_SIGNATURE_FEE,
+        )
+        .await
+    }
+}
+
+#[derive(Default)]
+pub struct CyclesMintingApi;
+
+#[cfg_attr(coverage_nightly, no_coverage)]
+#[async_trait]
+impl ICyclesMintingApi for CyclesMintingApi {
+    async fn get_icp_xdr_conversion_rate(
+        &self,
+    ) -> ActorResult<IcpXdrConversionRateCertifiedResponse> {
+        call_canister_as_result(
+            CanisterNames::CyclesMinting,
+            "get_icp_xdr_conversion_rate",
+            (),
+        )
+        .await
+    }
+
+    async fn notify_top_up(&self, args: NotifyTopUpArg) -> ActorResult<NotifyTopUpResult> {
+        call_canister_as_result(CanisterNames::CyclesMinting, "notify_top_up", (args,)).await
+    }
+
+    async fn notify_create_canister(
+        &self,
+        args: NotifyCreateCanisterArg,
+    ) -> ActorResult<NotifyCreateCanisterResult> {
+        call_canister_as_result(
+            CanisterNames::CyclesMinting,
+            "notify_create_canister",
+            (args,),
+        )
+        .await
+    }
+}
+
+#[derive(Default)]
+pub struct BackupApi;
+
+#[cfg_attr(coverage_nightly, no_coverage)]
+#[async_trait]
+impl IBackupApi for BackupApi {
+    async fn begin_snapshot(&self, args: BeginSnapshotArgs) -> ActorResult<SnapshotId> {
+        call_canister_as_actor_result(CanisterNames::Backup, "begin_snapshot", (args,)).await
+    }
+
+    async fn put_chunk(&self, id: SnapshotId, index: u32, chunk: ByteBuf) -> ActorResult<()> {
+        // chunks are up to 1 MiB, logging them would flood the log
+        call_canister_as_actor_result_no_logging(
+            CanisterNames::Backup,
+            "put_chunk",
+            (id, index, chunk),
+        )
+        .await
+    }
+
+    async fn commit_snapshot(&self, id: SnapshotId) -> ActorResult<SnapshotMeta> {
+        call_canister_as_actor_result(CanisterNames::Backup, "commit_snapshot", (id,)).await
+    }
+
+    async fn list_snapshots(&self) -> ActorResult<Vec<SnapshotMeta>> {
+        call_canister_as_actor_result(CanisterNames::Backup, "list_snapshots", ()).await
+    }
+
+    async fn get_snapshot(&self, id: SnapshotId) -> ActorResult<SnapshotMeta> {
+        call_canister_as_actor_result(CanisterNames::Backup, "get_snapshot", (id,)).await
+    }
+
+    async fn get_chunk(&self, id: SnapshotId, index: u32) -> ActorResult<ByteBuf> {
+        call_canister_as_actor_result_no_logging(CanisterNames::Backup, "get_chunk", (id, index))
+            .await
+    }
 }
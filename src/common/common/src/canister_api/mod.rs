@@ -11,6 +11,7 @@ pub use ic_api::*;
 
 use crate::errors::{ActorResult, CommonError, ErrorInfo};
 use crate::named_canister_ids::{get_named_canister_id, CanisterNames};
+use crate::types::cycles_minting_types::*;
 use crate::types::ic_ledger_types::{Subaccount, TransferArgs, TransferResult};
 use crate::types::ic_management_types::*;
 
@@ -30,7 +31,7 @@ where
     if logging {
         debug!("Calling {:?}::{}", canister_name, method);
     }
-    let canister_id = get_named_canister_id(canister_name);
+    let canister_id = get_named_canister_id(canister_name)?;
     let (call_res,): (TResult,) =
         call(canister_id.0, method, args)
             .await
@@ -71,6 +72,22 @@ where
     }
 }
 
+async fn call_canister_as_actor_result_no_logging<T, TResult>(
+    canister_name: CanisterNames,
+    method: &str,
+    args: T,
+) -> ActorResult<TResult>
+where
+    T: candid::utils::ArgumentEncoder,
+    TResult: for<'a> Deserialize<'a> + CandidType + Debug,
+{
+    let result = call_core::<T, ActorResult<TResult>>(canister_name, method, args, false).await;
+    match result {
+        Ok(result) => result,
+        Err(error) => Err(ErrorInfo::from(error)),
+    }
+}
+
 async fn call_canister_as_result<T, TResult>(
     canister_name: CanisterNames,
     method: &str,
@@ -116,7 +133,7 @@ where
             canister_name, method, cycles
         );
     }
-    let canister_id = get_named_canister_id(canister_name);
+    let canister_id = get_named_canister_id(canister_name)?;
     let (call_res,): (TResult,) = call_with_payment(canister_id.0, method, args, cycles)
         .await
         .map_err(|(code, message)| {
@@ -0,0 +1,508 @@
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::rc::Rc;
+
+use candid::{decode_args, encode_args, CandidType, Deserialize, Nat, Principal};
+use ic_cdk::api;
+use log::{error, info};
+use serde_bytes::ByteBuf;
+use sha2::{Digest, Sha256};
+
+use crate::canister_api::IICManagementAPI;
+use crate::errors::{CommonError, ServiceResult};
+use crate::state::StableState;
+use crate::timeout_lock::{try_acquire_lock, LockId, LockKey};
+use crate::types::ic_management_types::{
+    CanisterIdRecord, CanisterInstall, CanisterSettings, CreateCanisterArgs, InstallMode,
+};
+use crate::types::TimeInNs;
+
+thread_local! {
+    pub static CANISTER_FACTORY_STATE: RefCell<CanisterFactoryState> = RefCell::new(CanisterFactoryState::default());
+    /// The module bytes live here rather than in `CanisterFactoryState`, so state exports stay small.
+    pub static WASM_STORE: RefCell<WasmStore> = RefCell::new(WasmStore::default());
+}
+
+/// The wasm committed to the factory, its module is kept in `WASM_STORE`.
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct StoredWasm {
+    pub hash: String,
+    pub size: u64,
+    pub uploaded_at: TimeInNs,
+}
+
+/// Chunks of the committed wasm module, and of the upload in progress.
+/// An upload is sent with `append_chunk` calls and becomes the stored module on `commit`.
+#[derive(CandidType, Deserialize, Clone, Default, Debug)]
+pub struct WasmStore {
+    uploading: Vec<ByteBuf>,
+    chunks: Vec<ByteBuf>,
+}
+
+impl WasmStore {
+    /// Appends a chunk to the upload in progress, returns the size uploaded so far.
+    pub fn append_chunk(&mut self, chunk: Vec<u8>) -> u64 {
+        self.uploading.push(ByteBuf::from(chunk));
+        self.uploading.iter().map(|c| c.len() as u64).sum()
+    }
+
+    /// Makes the upload in progress the stored module if its hash is `expected_hash`.
+    /// The upload is discarded either way, a mismatch has to be uploaded again.
+    pub fn commit(&mut self, expected_hash: &str) -> ServiceResult<(String, u64)> {
+        let uploading = std::mem::take(&mut self.uploading);
+        let module: Vec<u8> = uploading.iter().flat_map(|c| c.iter().cloned()).collect();
+        let hash = wasm_hash(&module);
+        if hash != expected_hash {
+            return Err(CommonError::InvalidArgument {
+                field: "wasm_hash".to_string(),
+                detail: format!(
+                    "uploaded wasm hash {} does not match {}",
+                    hash, expected_hash
+                ),
+            });
+        }
+        self.chunks = uploading;
+        Ok((hash, module.len() as u64))
+    }
+
+    pub fn module(&self) -> Vec<u8> {
+        self.chunks.iter().flat_map(|c| c.iter().cloned()).collect()
+    }
+}
+
+impl StableState for WasmStore {
+    fn encode(&self) -> Vec<u8> {
+        encode_args((&self.uploading, &self.chunks)).unwrap()
+    }
+
+    fn decode(bytes: Vec<u8>) -> Result<Self, String> {
+        let (uploading, chunks): (Vec<ByteBuf>, Vec<ByteBuf>) =
+            decode_args(&bytes).map_err(|e| format!("{:?}", e))?;
+        Ok(WasmStore { uploading, chunks })
+    }
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub enum ManagedCanisterStatus {
+    Created,
+    Installed,
+    InstallFailed,
+    UpgradeFailed,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct ManagedCanister {
+    pub canister_id: Principal,
+    pub status: ManagedCanisterStatus,
+    /// hash of the wasm currently installed, None until the first install succeeds
+    pub wasm_hash: Option<String>,
+    pub created_at: TimeInNs,
+    pub updated_at: TimeInNs,
+    pub last_cycles: Option<Nat>,
+    pub last_checked_at: Option<TimeInNs>,
+    pub last_error: Option<String>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct UpgradeProgress {
+    pub target_wasm_hash: Option<String>,
+    pub total: usize,
+    pub upgraded: usize,
+    pub failed: Vec<Principal>,
+    pub pending: usize,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct TopUpRecord {
+    pub canister_id: Principal,
+    pub cycles_before: Nat,
+    pub deposited: u64,
+}
+
+#[derive(CandidType, Deserialize, Clone, Default, Debug)]
+pub struct CanisterFactoryState {
+    pub wasm: Option<StoredWasm>,
+    pub canisters: BTreeMap<Principal, ManagedCanister>,
+}
+
+impl CanisterFactoryState {
+    fn update_canister(&mut self, canister_id: &Principal, f: impl FnOnce(&mut ManagedCanister)) {
+        if let Some(canister) = self.canisters.get_mut(canister_id) {
+            f(canister);
+        }
+    }
+
+    /// Marks canisters that failed to upgrade as pending again, so the next batch retries them.
+    fn reset_failed_upgrades(&mut self) {
+        for canister in self.canisters.values_mut() {
+            if canister.status == ManagedCanisterStatus::UpgradeFailed {
+                canister.status = ManagedCanisterStatus::Installed;
+            }
+        }
+    }
+
+    pub fn get_failed_canisters(&self) -> Vec<ManagedCanister> {
+        self.canisters
+            .values()
+            .filter(|c| c.last_error.is_some())
+            .cloned()
+            .collect()
+    }
+
+    pub fn get_upgrade_progress(&self) -> UpgradeProgress {
+        let target = self.wasm.as_ref().map(|w| w.hash.clone());
+        let mut upgraded = 0;
+        let mut failed = vec![];
+        let mut pending = 0;
+        for canister in self.canisters.values() {
+            if target.is_some() && canister.wasm_hash == target {
+                upgraded += 1;
+            } else if canister.status == ManagedCanisterStatus::UpgradeFailed {
+                failed.push(canister.canister_id);
+            } else {
+                pending += 1;
+            }
+        }
+        UpgradeProgress {
+            target_wasm_hash: target,
+            total: self.canisters.len(),
+            upgraded,
+            failed,
+            pending,
+        }
+    }
+}
+
+impl StableState for CanisterFactoryState {
+    fn encode(&self) -> Vec<u8> {
+        encode_args((&self.wasm, &self.canisters)).unwrap()
+    }
+
+    fn decode(bytes: Vec<u8>) -> Result<Self, String> {
+        let (wasm, canisters): (Option<StoredWasm>, BTreeMap<Principal, ManagedCanister>) =
+            decode_args(&bytes).map_err(|e| format!("{:?}", e))?;
+        Ok(CanisterFactoryState { wasm, canisters })
+    }
+
+    fn check_invariants(&self) -> Vec<String> {
+        self.canisters
+            .iter()
+            .filter(|(id, canister)| **id != canister.canister_id)
+            .map(|(id, canister)| {
+                format!(
+                    "canister {} is stored as {}",
+                    canister.canister_id.to_text(),
+                    id.to_text()
+                )
+            })
+            .collect()
+    }
+}
+
+pub fn wasm_hash(module: &[u8]) -> String {
+    hex::encode(Sha256::digest(module))
+}
+
+fn busy(lock_id: LockId) -> CommonError {
+    CommonError::Busy {
+        resource: format!("{:?}", lock_id),
+    }
+}
+
+/// Creates canisters from the stored wasm, keeps track of them and keeps them upgraded and topped up.
+/// The state is read again after every await, other calls may have changed it meanwhile.
+pub struct CanisterFactory<T: IICManagementAPI> {
+    api: T,
+    clock: Rc<dyn Fn() -> TimeInNs>,
+}
+
+impl<T: IICManagementAPI> CanisterFactory<T> {
+    pub fn new(api: T) -> Self {
+        Self::with_clock(api, Rc::new(|| TimeInNs(api::time())))
+    }
+
+    /// `clock` is read whenever a timestamp is recorded, i.e. again after every await.
+    pub fn with_clock(api: T, clock: Rc<dyn Fn() -> TimeInNs>) -> Self {
+        Self { api, clock }
+    }
+
+    fn now(&self) -> TimeInNs {
+        (self.clock)()
+    }
+
+    /// Appends a chunk to the wasm being uploaded, returns the size uploaded so far.
+    pub fn upload_wasm_chunk(&self, chunk: Vec<u8>) -> u64 {
+        WASM_STORE.with(|s| s.borrow_mut().append_chunk(chunk))
+    }
+
+    /// Stores the uploaded wasm, used for new canisters and as the target of `upgrade_next_batch`.
+    /// A new wasm gives canisters that failed the previous upgrade another chance.
+    pub fn commit_wasm(&self, expected_hash: &str) -> ServiceResult<String> {
+        let (hash, size) = WASM_STORE.with(|s| s.borrow_mut().commit(expected_hash))?;
+        info!("canister factory: set wasm {}", hash);
+        let now = self.now();
+        CANISTER_FACTORY_STATE.with(|s| {
+            let mut state = s.borrow_mut();
+            state.wasm = Some(StoredWasm {
+                hash: hash.clone(),
+                size,
+                uploaded_at: now,
+            });
+            state.reset_failed_upgrades();
+        });
+        Ok(hash)
+    }
+
+    /// Uploads and commits `module` at once, returns its hash.
+    pub fn set_wasm(&self, module: Vec<u8>) -> String {
+        let hash = wasm_hash(&module);
+        self.upload_wasm_chunk(module);
+        self.commit_wasm(&hash)
+            .expect("hash of the uploaded module must match");
+        hash
+    }
+
+    fn get_wasm(&self) -> ServiceResult<(StoredWasm, Vec<u8>)> {
+        let wasm = CANISTER_FACTORY_STATE
+            .with(|s| s.borrow().wasm.clone())
+            .ok_or_else(|| CommonError::InvalidState {
+                detail: "no wasm uploaded to the canister factory".to_string(),
+            })?;
+        let module = WASM_STORE.with(|s| s.borrow().module());
+        Ok((wasm, module))
+    }
+
+    /// Creates a canister with `cycles` attached and installs the stored wasm into it.
+    /// The canister is tracked even if the install fails, so it can be retried by an upgrade.
+    pub async fn create_canister(
+        &self,
+        settings: CanisterSettings,
+        cycles: u64,
+        arg: Vec<u8>,
+    ) -> ServiceResult<Principal> {
+        let (wasm, module) = self.get_wasm()?;
+        let record = self
+            .api
+            .create_canister(CreateCanisterArgs { cycles, settings })
+            .await?;
+        let canister_id = record.canister_id;
+        let now = self.now();
+        CANISTER_FACTORY_STATE.with(|s| {
+            s.borrow_mut().canisters.insert(
+                canister_id,
+                ManagedCanister {
+                    canister_id,
+                    status: ManagedCanisterStatus::Created,
+                    wasm_hash: None,
+                    created_at: now,
+                    updated_at: now,
+                    last_cycles: None,
+                    last_checked_at: None,
+                    last_error: None,
+                },
+            );
+        });
+        info!("canister factory: created canister {}", canister_id);
+
+        let result = self
+            .api
+            .install_code(CanisterInstall {
+                mode: InstallMode::Install,
+                canister_id,
+                wasm_module: module,
+                arg,
+            })
+            .await;
+        let now = self.now();
+        CANISTER_FACTORY_STATE.with(|s| {
+            s.borrow_mut().update_canister(&canister_id, |canister| {
+                canister.updated_at = now;
+                match &result {
+                    Ok(_) => {
+                        canister.status = ManagedCanisterStatus::Installed;
+                        canister.wasm_hash = Some(wasm.hash.clone());
+                        canister.last_error = None;
+                    }
+                    Err(e) => {
+                        canister.status = ManagedCanisterStatus::InstallFailed;
+                        canister.last_error = Some(e.to_string());
+                    }
+                }
+            })
+        });
+        result?;
+        Ok(canister_id)
+    }
+
+    /// Upgrades at most `batch_size` canisters which are not yet running the stored wasm.
+    /// Canisters that failed to upgrade to the current wasm are skipped until a new wasm is set.
+    pub async fn upgrade_next_batch(
+        &self,
+        batch_size: usize,
+        arg: Vec<u8>,
+    ) -> ServiceResult<UpgradeProgress> {
+        let (wasm, module) = self.get_wasm()?;
+        let lock_id = LockId::CanisterFactoryUpgrade;
+        let _guard =
+            try_acquire_lock(LockKey::global(lock_id), self.now()).ok_or_else(|| busy(lock_id))?;
+        let batch: Vec<(Principal, InstallMode)> = CANISTER_FACTORY_STATE.with(|s| {
+            s.borrow()
+                .canisters
+                .values()
+                .filter(|c| {
+                    c.wasm_hash.as_ref() != Some(&wasm.hash)
+                        && c.status != ManagedCanisterStatus::UpgradeFailed
+                })
+                .take(batch_size)
+                .map(|c| {
+                    let mode = if c.wasm_hash.is_some() {
+                        InstallMode::Upgrade
+                    } else {
+                        InstallMode::Install
+                    };
+                    (c.canister_id, mode)
+                })
+                .collect()
+        });
+
+        for (canister_id, mode) in batch {
+            let result = self
+                .api
+                .install_code(CanisterInstall {
+                    mode,
+                    canister_id,
+                    wasm_module: module.clone(),
+                    arg: arg.clone(),
+                })
+                .await;
+            if let Err(e) = &result {
+                error!("canister factory: upgrade {} failed: {}", canister_id, e);
+            }
+            let now = self.now();
+            CANISTER_FACTORY_STATE.with(|s| {
+                s.borrow_mut().update_canister(&canister_id, |canister| {
+                    canister.updated_at = now;
+                    match result {
+                        Ok(_) => {
+                            canister.status = ManagedCanisterStatus::Installed;
+                            canister.wasm_hash = Some(wasm.hash.clone());
+                            canister.last_error = None;
+                        }
+                        Err(e) => {
+                            canister.status = ManagedCanisterStatus::UpgradeFailed;
+                            canister.last_error = Some(e.to_string());
+                        }
+                    }
+                })
+            });
+        }
+        Ok(self.get_upgrade_progress())
+    }
+
+    /// Marks canisters that failed to upgrade as pending again, so the next batch retries them.
+    pub fn retry_failed_upgrades(&self) {
+        CANISTER_FACTORY_STATE.with(|s| s.borrow_mut().reset_failed_upgrades());
+    }
+
+    /// Checks the cycles balance of every tracked canister and deposits `amount` cycles
+    /// into those below `threshold`.
+    pub async fn top_up_canisters(
+        &self,
+        threshold: u64,
+        amount: u64,
+    ) -> ServiceResult<Vec<TopUpRecord>> {
+        let lock_id = LockId::CanisterFactoryTopUp;
+        let _guard =
+            try_acquire_lock(LockKey::global(lock_id), self.now()).ok_or_else(|| busy(lock_id))?;
+        let canister_ids: Vec<Principal> =
+            CANISTER_FACTORY_STATE.with(|s| s.borrow().canisters.keys().cloned().collect());
+        let mut records = vec![];
+        for canister_id in canister_ids {
+            let status = self
+                .api
+                .canister_status(CanisterIdRecord { canister_id })
+                .await;
+            let now = self.now();
+            let cycles = match status {
+                Ok(status) => status.cycles,
+                Err(e) => {
+                    error!("canister factory: status of {} failed: {}", canister_id, e);
+                    CANISTER_FACTORY_STATE.with(|s| {
+                        s.borrow_mut().update_canister(&canister_id, |canister| {
+                            canister.last_checked_at = Some(now);
+                            canister.last_error = Some(e.to_string());
+                        })
+                    });
+                    continue;
+                }
+            };
+            CANISTER_FACTORY_STATE.with(|s| {
+                s.borrow_mut().update_canister(&canister_id, |canister| {
+                    canister.last_cycles = Some(cycles.clone());
+                    canister.last_checked_at = Some(now);
+                })
+            });
+            if cycles >= Nat::from(threshold) {
+                continue;
+            }
+            match self
+                .api
+                .deposit_cycles(CanisterIdRecord { canister_id }, amount)
+                .await
+            {
+                Ok(_) => {
+                    info!(
+                        "canister factory: topped up {} with {} cycles",
+                        canister_id, amount
+                    );
+                    records.push(TopUpRecord {
+                        canister_id,
+                        cycles_before: cycles,
+                        deposited: amount,
+                    });
+                }
+                Err(e) => {
+                    error!("canister factory: top up {} failed: {}", canister_id, e);
+                    let now = self.now();
+                    CANISTER_FACTORY_STATE.with(|s| {
+                        s.borrow_mut().update_canister(&canister_id, |canister| {
+                            canister.updated_at = now;
+                            canister.last_error = Some(e.to_string());
+                        })
+                    });
+                }
+            }
+        }
+        Ok(records)
+    }
+
+    pub fn get_canisters(&self) -> Vec<ManagedCanister> {
+        CANISTER_FACTORY_STATE.with(|s| s.borrow().canisters.values().cloned().collect())
+    }
+
+    pub fn get_failed_canisters(&self) -> Vec<ManagedCanister> {
+        get_failed_canisters()
+    }
+
+    pub fn get_upgrade_progress(&self) -> UpgradeProgress {
+        get_upgrade_progress()
+    }
+}
+
+pub fn get_failed_canisters() -> Vec<ManagedCanister> {
+    CANISTER_FACTORY_STATE.with(|s| s.borrow().get_failed_canisters())
+}
+
+pub fn get_upgrade_progress() -> UpgradeProgress {
+    CANISTER_FACTORY_STATE.with(|s| s.borrow().get_upgrade_progress())
+}
+
+crate::actor_response!(pub CommitWasmResponse, String);
+
+crate::actor_response!(pub CreateCanisterResponse, Principal);
+
+crate::actor_response!(pub GetUpgradeProgressResponse, UpgradeProgress);
+
+crate::actor_response!(pub TopUpCanistersResponse, Vec<TopUpRecord>);
+
+crate::actor_response!(pub GetManagedCanistersResponse, Vec<ManagedCanister>);
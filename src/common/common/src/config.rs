@@ -0,0 +1,284 @@
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+
+use candid::{decode_args, encode_args, CandidType, Deserialize, Principal};
+use log::info;
+
+use crate::constants::{COMMON_CANISTER_ENV, ENV_DEV, PAGE_INPUT_MAX_LIMIT, PAGE_INPUT_MIN_LIMIT};
+use crate::errors::{CommonError, ServiceResult};
+use crate::rate_limit::{configure_rate_limits, RateLimit, RateLimits};
+use crate::state::StableState;
+use crate::timeout_lock::{configure_lock_timeouts, LockId, LOCKER_TIMEOUT_NS};
+use crate::types::TimeInNs;
+
+#[cfg(test)]
+mod tests;
+
+/// Allows a `LoadState` proposal to replace the whole state, on by default in dev only.
+pub const FEATURE_LOAD_STATE: &str = "load_state";
+/// Upper bound an admin can raise `max_page_limit` to.
+pub const CONFIG_MAX_PAGE_LIMIT: usize = 1_000;
+pub const CONFIG_HISTORY_LEN: usize = 20;
+
+thread_local! {
+    pub static CONFIG_STORE: RefCell<ConfigStore> = RefCell::new(ConfigStore::default());
+}
+
+/// Behaviour which used to be decided at compile time by `COMMON_CANISTER_ENV`,
+/// now kept in the state and changed by admins with `set_config`.
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Config {
+    /// feature name to enabled, a feature which is not listed is disabled
+    pub features: BTreeMap<String, bool>,
+    pub max_page_limit: usize,
+    /// timeout of every lock without an entry in `lock_timeouts`
+    pub lock_timeout: TimeInNs,
+    pub lock_timeouts: BTreeMap<LockId, TimeInNs>,
+    pub maintenance_mode: bool,
+    pub rate_limits: RateLimits,
+}
+
+/// Limits of a new config, a full export is expensive and the exporter needs a few per hour at most.
+fn default_rate_limits() -> RateLimits {
+    let mut limits = RateLimits::default();
+    limits.limits.insert(
+        "export_state".to_string(),
+        RateLimit::per(10, TimeInNs(60 * 60 * 1_000_000_000)),
+    );
+    limits
+}
+
+impl Config {
+    pub fn for_env(env: &str) -> Self {
+        let mut features = BTreeMap::new();
+        features.insert(FEATURE_LOAD_STATE.to_string(), env == ENV_DEV);
+        Config {
+            features,
+            max_page_limit: PAGE_INPUT_MAX_LIMIT,
+            lock_timeout: LOCKER_TIMEOUT_NS,
+            lock_timeouts: BTreeMap::new(),
+            maintenance_mode: false,
+            rate_limits: default_rate_limits(),
+        }
+    }
+
+    pub fn is_feature_enabled(&self, name: &str) -> bool {
+        self.features.get(name).copied().unwrap_or(false)
+    }
+
+    pub fn validate(&self) -> ServiceResult<()> {
+        // the range of the error excludes its max
+        let max_page_limits = PAGE_INPUT_MIN_LIMIT..CONFIG_MAX_PAGE_LIMIT + 1;
+        if !max_page_limits.contains(&self.max_page_limit) {
+            return Err(CommonError::ValueShouldBeInRangeError {
+                field: "max_page_limit".to_string(),
+                min: max_page_limits.start,
+                max: max_page_limits.end,
+            });
+        }
+        let zero_timeout = self.lock_timeout == TimeInNs(0)
+            || self.lock_timeouts.values().any(|t| *t == TimeInNs(0));
+        if zero_timeout {
+            return Err(CommonError::InvalidArgument {
+                field: "lock_timeouts".to_string(),
+                detail: "a lock timeout must be greater than 0".to_string(),
+            });
+        }
+        if self.features.keys().any(|name| name.is_empty()) {
+            return Err(CommonError::InvalidArgument {
+                field: "features".to_string(),
+                detail: "a feature name must not be empty".to_string(),
+            });
+        }
+        self.rate_limits.validate()?;
+        Ok(())
+    }
+
+    /// Pushes the values other modules keep for themselves.
+    fn apply(&self) {
+        configure_lock_timeouts(self.lock_timeout, &self.lock_timeouts);
+        configure_rate_limits(&self.rate_limits);
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config::for_env(COMMON_CANISTER_ENV)
+    }
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct ConfigChange {
+    pub version: u64,
+    pub changed_by: Principal,
+    pub changed_at: TimeInNs,
+    pub previous: Config,
+    pub config: Config,
+}
+
+/// The active config with its version, `changed_at` and `changed_by` are `None` for the defaults.
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct ConfigRecord {
+    pub version: u64,
+    pub config: Config,
+    pub changed_at: Option<TimeInNs>,
+    pub changed_by: Option<Principal>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct ConfigStore {
+    config: Config,
+    version: u64,
+    /// latest `CONFIG_HISTORY_LEN` changes, oldest first
+    history: Vec<ConfigChange>,
+}
+
+impl ConfigStore {
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    pub fn record(&self) -> ConfigRecord {
+        let last = self.history.last();
+        ConfigRecord {
+            version: self.version,
+            config: self.config.clone(),
+            changed_at: last.map(|c| c.changed_at),
+            changed_by: last.map(|c| c.changed_by),
+        }
+    }
+
+    pub fn history(&self) -> Vec<ConfigChange> {
+        self.history.clone()
+    }
+
+    /// Replaces the config if it is still at `expected_version`, so concurrent edits of two admins
+    /// can not silently overwrite each other. Returns the new version.
+    pub fn update(
+        &mut self,
+        config: Config,
+        expected_version: u64,
+        caller: Principal,
+        now: TimeInNs,
+    ) -> ServiceResult<u64> {
+        if expected_version != self.version {
+            return Err(CommonError::InvalidState {
+                detail: format!(
+                    "config is at version {}, not {}",
+                    self.version, expected_version
+                ),
+            });
+        }
+        config.validate()?;
+        self.version += 1;
+        let previous = std::mem::replace(&mut self.config, config.clone());
+        self.history.push(ConfigChange {
+            version: self.version,
+            changed_by: caller,
+            changed_at: now,
+            previous,
+            config,
+        });
+        if self.history.len() > CONFIG_HISTORY_LEN {
+            let overflow = self.history.len() - CONFIG_HISTORY_LEN;
+            self.history.drain(..overflow);
+        }
+        Ok(self.version)
+    }
+}
+
+impl StableState for ConfigStore {
+    fn encode(&self) -> Vec<u8> {
+        encode_args((&self.config, self.version, &self.history)).unwrap()
+    }
+
+    fn decode(bytes: Vec<u8>) -> Result<Self, String> {
+        let (config, version, history): (Config, u64, Vec<ConfigChange>) =
+            decode_args(&bytes).map_err(|e| format!("{:?}", e))?;
+        Ok(ConfigStore {
+            config,
+            version,
+            history,
+        })
+    }
+
+    fn check_invariants(&self) -> Vec<String> {
+        let mut violations = vec![];
+        if let Err(e) = self.config.validate() {
+            violations.push(format!("config: {}", e));
+        }
+        if let Some(last) = self.history.last() {
+            if last.version != self.version || last.config != self.config {
+                violations.push(format!(
+                    "config version {} does not match its last change {}",
+                    self.version, last.version
+                ));
+            }
+        }
+        if self
+            .history
+            .windows(2)
+            .any(|w| w[0].version >= w[1].version)
+        {
+            violations.push("config history is not ordered by version".to_string());
+        }
+        violations
+    }
+}
+
+pub fn get_config() -> Config {
+    CONFIG_STORE.with(|s| s.borrow().config().clone())
+}
+
+pub fn get_config_record() -> ConfigRecord {
+    CONFIG_STORE.with(|s| s.borrow().record())
+}
+
+pub fn get_config_history() -> Vec<ConfigChange> {
+    CONFIG_STORE.with(|s| s.borrow().history())
+}
+
+pub fn is_feature_enabled(name: &str) -> bool {
+    CONFIG_STORE.with(|s| s.borrow().config().is_feature_enabled(name))
+}
+
+pub fn set_config(
+    config: Config,
+    expected_version: u64,
+    caller: Principal,
+    now: TimeInNs,
+) -> ServiceResult<u64> {
+    CONFIG_STORE.with(|s| {
+        let mut store = s.borrow_mut();
+        let version = store.update(config, expected_version, caller, now)?;
+        store.config().apply();
+        info!(
+            "config version {} set by {}: {:?}",
+            version,
+            caller,
+            store.config()
+        );
+        Ok(version)
+    })
+}
+
+/// Pushes the active config to the modules keeping their own copy, for `init` where nothing
+/// is restored.
+pub fn apply_config() {
+    CONFIG_STORE.with(|s| s.borrow().config().apply());
+}
+
+pub fn restore_config(store: ConfigStore) {
+    store.config().apply();
+    CONFIG_STORE.with(|s| s.replace(store));
+}
+
+crate::actor_response!(pub GetConfigResponse, ConfigRecord);
+
+crate::actor_response!(pub GetConfigHistoryResponse, Vec<ConfigChange>);
+
+crate::actor_response!(pub SetConfigResponse, u64);
@@ -0,0 +1,179 @@
+use rstest::*;
+
+use super::*;
+use crate::constants::ENV_PRODUCTION;
+use crate::rate_limit::{check_rate_limit, get_rate_limiter_stats, RATE_LIMIT_DEFAULT};
+use crate::test_common::test::init_test;
+use crate::timeout_lock::TimeoutLocker;
+
+#[fixture]
+pub fn setup() {
+    init_test();
+}
+
+fn admin() -> Principal {
+    Principal::from_slice(&[1, 2, 3])
+}
+
+fn empty_rate_limit() -> RateLimits {
+    RateLimits {
+        default_limit: Some(RateLimit {
+            capacity: 0,
+            refill_interval: TimeInNs(1),
+        }),
+        limits: BTreeMap::new(),
+    }
+}
+
+#[rstest]
+fn test_env_defaults(_setup: ()) {
+    assert!(Config::for_env(ENV_DEV).is_feature_enabled(FEATURE_LOAD_STATE));
+    let production = Config::for_env(ENV_PRODUCTION);
+    assert!(!production.is_feature_enabled(FEATURE_LOAD_STATE));
+    assert!(!production.is_feature_enabled("unknown"));
+    assert_eq!(production.max_page_limit, PAGE_INPUT_MAX_LIMIT);
+}
+
+#[rstest]
+fn test_update_is_versioned(_setup: ()) {
+    let mut store = ConfigStore::default();
+    let mut config = store.config().clone();
+    config.maintenance_mode = true;
+
+    let version = store
+        .update(config.clone(), 0, admin(), TimeInNs(10))
+        .unwrap();
+
+    assert_eq!(version, 1);
+    let record = store.record();
+    assert_eq!(record.config, config);
+    assert_eq!(record.changed_by, Some(admin()));
+    assert_eq!(record.changed_at, Some(TimeInNs(10)));
+    let history = store.history();
+    assert_eq!(history.len(), 1);
+    assert!(!history[0].previous.maintenance_mode);
+}
+
+#[rstest]
+fn test_stale_version_is_rejected(_setup: ()) {
+    let mut store = ConfigStore::default();
+    let config = store.config().clone();
+    store
+        .update(config.clone(), 0, admin(), TimeInNs(10))
+        .unwrap();
+
+    let result = store.update(config, 0, admin(), TimeInNs(20));
+
+    assert!(matches!(result, Err(CommonError::InvalidState { .. })));
+    assert_eq!(store.version(), 1);
+}
+
+#[rstest]
+#[case(Config { max_page_limit: 0, ..Config::default() })]
+#[case(Config { max_page_limit: CONFIG_MAX_PAGE_LIMIT + 1, ..Config::default() })]
+#[case(Config { lock_timeout: TimeInNs(0), ..Config::default() })]
+#[case(Config { rate_limits: empty_rate_limit(), ..Config::default() })]
+fn test_invalid_config_is_rejected(_setup: (), #[case] config: Config) {
+    let mut store = ConfigStore::default();
+    assert!(store.update(config, 0, admin(), TimeInNs(10)).is_err());
+    assert_eq!(store.version(), 0);
+}
+
+#[rstest]
+#[case(PAGE_INPUT_MIN_LIMIT)]
+#[case(CONFIG_MAX_PAGE_LIMIT)]
+fn test_max_page_limit_bounds_are_accepted(_setup: (), #[case] max_page_limit: usize) {
+    let config = Config {
+        max_page_limit,
+        ..Config::default()
+    };
+    assert_eq!(config.validate(), Ok(()));
+}
+
+#[rstest]
+fn test_max_page_limit_error_excludes_max(_setup: ()) {
+    let config = Config {
+        max_page_limit: CONFIG_MAX_PAGE_LIMIT + 1,
+        ..Config::default()
+    };
+    assert_eq!(
+        config.validate(),
+        Err(CommonError::ValueShouldBeInRangeError {
+            field: "max_page_limit".to_string(),
+            min: PAGE_INPUT_MIN_LIMIT,
+            max: CONFIG_MAX_PAGE_LIMIT + 1,
+        })
+    );
+}
+
+#[rstest]
+fn test_history_is_bounded(_setup: ()) {
+    let mut store = ConfigStore::default();
+    for version in 0..(CONFIG_HISTORY_LEN as u64 + 5) {
+        let config = store.config().clone();
+        store
+            .update(config, version, admin(), TimeInNs(version))
+            .unwrap();
+    }
+    let history = store.history();
+    assert_eq!(history.len(), CONFIG_HISTORY_LEN);
+    assert_eq!(history.last().unwrap().version, store.version());
+}
+
+#[rstest]
+fn test_lock_timeouts_are_configured(_setup: ()) {
+    let mut config = Config::default();
+    config.lock_timeout = TimeInNs(100);
+    config
+        .lock_timeouts
+        .insert(LockId::SchedulerJob, TimeInNs(10));
+
+    let mut locker = TimeoutLocker::default();
+    locker.configure(config.lock_timeout, &config.lock_timeouts);
+
+    assert_eq!(locker.timeout(LockId::SchedulerJob), TimeInNs(10));
+    assert_eq!(locker.timeout(LockId::Saga), TimeInNs(100));
+}
+
+#[rstest]
+fn test_rate_limits_are_configured(_setup: ()) {
+    let user = Principal::from_slice(&[9, 1]);
+    let mut config = get_config();
+    config
+        .rate_limits
+        .limits
+        .insert("test".to_string(), RateLimit::per(1, TimeInNs(10)));
+
+    set_config(config, 0, admin(), TimeInNs(10)).unwrap();
+
+    check_rate_limit(&user, "test", TimeInNs(20)).unwrap();
+    assert!(check_rate_limit(&user, "test", TimeInNs(20)).is_err());
+    let stats = get_rate_limiter_stats();
+    assert_eq!(stats.default_limit, Some(RATE_LIMIT_DEFAULT));
+    assert!(stats.limits.iter().any(|l| l.method == "export_state"));
+}
+
+#[rstest]
+fn test_encode_decode(_setup: ()) {
+    let mut store = ConfigStore::default();
+    let mut config = store.config().clone();
+    config.features.insert("new_ui".to_string(), true);
+    store.update(config, 0, admin(), TimeInNs(10)).unwrap();
+
+    let decoded = ConfigStore::decode(store.encode()).unwrap();
+
+    assert_eq!(decoded.record(), store.record());
+    assert!(decoded.config().is_feature_enabled("new_ui"));
+}
+
+#[rstest]
+fn test_check_invariants(_setup: ()) {
+    let mut store = ConfigStore::default();
+    let config = store.config().clone();
+    store.update(config, 0, admin(), TimeInNs(10)).unwrap();
+    assert!(store.check_invariants().is_empty());
+
+    store.version = 3;
+    store.config.max_page_limit = 0;
+    assert_eq!(store.check_invariants().len(), 2);
+}
@@ -0,0 +1,292 @@
+use std::cell::RefCell;
+use std::collections::{BTreeMap, VecDeque};
+use std::panic::PanicInfo;
+
+use candid::{decode_args, encode_args, CandidType, Deserialize};
+
+use crate::config::get_config;
+use crate::dto::{GetPageInput, GetPageOutput};
+use crate::errors::ServiceResult;
+use crate::named_canister_ids::NAMED_CANISTER_IDS;
+use crate::pagination::paginate_iter;
+use crate::state::StableState;
+use crate::types::TimeInNs;
+
+#[cfg(test)]
+mod tests;
+
+pub const CRASH_LOG_CAPACITY: usize = 100;
+/// 30 minutes, far longer than the awaits of an update call
+pub const OPEN_CALL_TIMEOUT_NS: TimeInNs = TimeInNs(30 * 60 * 1_000_000_000);
+
+thread_local! {
+    pub static CRASH_LOG: RefCell<CrashLog> = RefCell::new(CrashLog::default());
+    static CURRENT_METHOD: RefCell<Option<String>> = RefCell::new(None);
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct CrashRecord {
+    pub timestamp: TimeInNs,
+    pub canister_name: String,
+    /// method set with `enter_method`, `None` when the panic happened outside of one
+    pub method: Option<String>,
+    pub message: String,
+    /// `file:line:column` of the panic
+    pub location: Option<String>,
+}
+
+impl CrashRecord {
+    pub fn from_panic(
+        info: &PanicInfo,
+        canister_name: String,
+        method: Option<String>,
+        now: TimeInNs,
+    ) -> Self {
+        let message = if let Some(s) = info.payload().downcast_ref::<&str>() {
+            s.to_string()
+        } else if let Some(s) = info.payload().downcast_ref::<String>() {
+            s.clone()
+        } else {
+            info.to_string()
+        };
+        CrashRecord {
+            timestamp: now,
+            canister_name,
+            method,
+            message,
+            location: info
+                .location()
+                .map(|l| format!("{}:{}:{}", l.file(), l.line(), l.column())),
+        }
+    }
+}
+
+impl std::fmt::Display for CrashRecord {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} panicked in {} at {}: {}",
+            self.canister_name,
+            self.method.as_deref().unwrap_or("<unknown method>"),
+            self.location.as_deref().unwrap_or("<unknown location>"),
+            self.message
+        )
+    }
+}
+
+/// An update call entered with `enter_update` which did not return yet.
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct OpenCall {
+    pub method: String,
+    pub started_at: TimeInNs,
+}
+
+/// The latest crash records, the oldest one is dropped when the log is full.
+///
+/// A trap discards every state change of the message since its last await, a record written by
+/// the panic hook included. The changes before that await are kept, so an update call is opened
+/// on entry and closed when it returns: a call which trapped after an await stays open, and is
+/// recorded as a crash once it is open for longer than `OPEN_CALL_TIMEOUT_NS` or on post_upgrade.
+/// A trap before the first await leaves no trace in the state, only the line printed by the hook.
+#[derive(Clone, Debug)]
+pub struct CrashLog {
+    pub capacity: usize,
+    records: VecDeque<CrashRecord>,
+    next_call_id: u64,
+    open_calls: BTreeMap<u64, OpenCall>,
+}
+
+impl Default for CrashLog {
+    fn default() -> Self {
+        CrashLog {
+            capacity: CRASH_LOG_CAPACITY,
+            records: VecDeque::new(),
+            next_call_id: 0,
+            open_calls: BTreeMap::new(),
+        }
+    }
+}
+
+impl CrashLog {
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+
+    pub fn push(&mut self, record: CrashRecord) {
+        while self.records.len() >= self.capacity && !self.records.is_empty() {
+            self.records.pop_front();
+        }
+        if self.capacity > 0 {
+            self.records.push_back(record);
+        }
+    }
+
+    pub fn open_call(&mut self, method: &str, now: TimeInNs) -> u64 {
+        let id = self.next_call_id;
+        self.next_call_id += 1;
+        self.open_calls.insert(
+            id,
+            OpenCall {
+                method: method.to_string(),
+                started_at: now,
+            },
+        );
+        id
+    }
+
+    pub fn close_call(&mut self, id: u64) {
+        self.open_calls.remove(&id);
+    }
+
+    pub fn open_calls(&self) -> Vec<OpenCall> {
+        self.open_calls.values().cloned().collect()
+    }
+
+    /// Records the calls opened before `opened_before` as crashes, they trapped after an await.
+    pub fn record_abandoned_calls(
+        &mut self,
+        canister_name: &str,
+        opened_before: TimeInNs,
+        now: TimeInNs,
+    ) {
+        let abandoned: Vec<u64> = self
+            .open_calls
+            .iter()
+            .filter(|(_, call)| call.started_at < opened_before)
+            .map(|(id, _)| *id)
+            .collect();
+        for id in abandoned {
+            if let Some(call) = self.open_calls.remove(&id) {
+                self.push(CrashRecord {
+                    timestamp: now,
+                    canister_name: canister_name.to_string(),
+                    method: Some(call.method),
+                    message: format!(
+                        "the call started at {} did not return, it trapped after an await",
+                        call.started_at
+                    ),
+                    location: None,
+                });
+            }
+        }
+    }
+
+    /// Newest records first.
+    pub fn get_page(&self, page: &GetPageInput) -> ServiceResult<GetPageOutput<CrashRecord>> {
+        Ok(paginate_iter(self.records.iter().rev(), page)?.map(CrashRecord::clone))
+    }
+}
+
+impl StableState for CrashLog {
+    fn encode(&self) -> Vec<u8> {
+        let records: Vec<&CrashRecord> = self.records.iter().collect();
+        encode_args((self.capacity, records, self.next_call_id, &self.open_calls)).unwrap()
+    }
+
+    fn decode(bytes: Vec<u8>) -> Result<Self, String> {
+        let (capacity, records, next_call_id, open_calls): (
+            usize,
+            Vec<CrashRecord>,
+            u64,
+            BTreeMap<u64, OpenCall>,
+        ) = decode_args(&bytes).map_err(|e| format!("{:?}", e))?;
+        Ok(CrashLog {
+            capacity,
+            records: records.into(),
+            next_call_id,
+            open_calls,
+        })
+    }
+
+    fn check_invariants(&self) -> Vec<String> {
+        let mut violations = vec![];
+        if self.records.len() > self.capacity {
+            violations.push(format!(
+                "crash log holds {} records, more than its capacity {}",
+                self.records.len(),
+                self.capacity
+            ));
+        }
+        if let Some(id) = self.open_calls.keys().find(|id| **id >= self.next_call_id) {
+            violations.push(format!(
+                "open call {} is not below the next call id {}",
+                id, self.next_call_id
+            ));
+        }
+        violations
+    }
+}
+
+/// Marks the method being executed until the guard is dropped, so a panic can be attributed to it.
+/// After an await another message may have entered, the name is then the one of the latest entry.
+pub struct MethodGuard {
+    previous: Option<String>,
+    /// the open call of `enter_update`, closed on drop
+    call: Option<u64>,
+}
+
+impl Drop for MethodGuard {
+    fn drop(&mut self) {
+        let previous = self.previous.take();
+        CURRENT_METHOD.with(|m| *m.borrow_mut() = previous);
+        if let Some(id) = self.call.take() {
+            CRASH_LOG.with(|c| {
+                if let Ok(mut log) = c.try_borrow_mut() {
+                    log.close_call(id);
+                }
+            });
+        }
+    }
+}
+
+/// Names the method for the panic hook, for queries and the lifecycle hooks.
+pub fn enter_method(name: &str) -> MethodGuard {
+    let previous = CURRENT_METHOD.with(|m| m.borrow_mut().replace(name.to_string()));
+    MethodGuard {
+        previous,
+        call: None,
+    }
+}
+
+/// `enter_method` of an update call, which also opens the call in the crash log so a trap after
+/// one of its awaits is recorded. Calls open for longer than `OPEN_CALL_TIMEOUT_NS` are recorded
+/// as crashes first.
+pub fn enter_update(name: &str, now: TimeInNs) -> MethodGuard {
+    let mut guard = enter_method(name);
+    let opened_before = TimeInNs(now.0.saturating_sub(OPEN_CALL_TIMEOUT_NS.0));
+    record_abandoned_calls(opened_before, now);
+    guard.call = CRASH_LOG.with(|c| {
+        c.try_borrow_mut()
+            .ok()
+            .map(|mut log| log.open_call(name, now))
+    });
+    guard
+}
+
+/// Records the calls opened before `opened_before` as crashes. post_upgrade passes the current
+/// time: a canister is stopped before an upgrade, every call still open trapped.
+pub fn record_abandoned_calls(opened_before: TimeInNs, now: TimeInNs) {
+    let name = NAMED_CANISTER_IDS
+        .with(|n| n.try_borrow().map(|n| n.current_name.clone()))
+        .unwrap_or_default();
+    CRASH_LOG.with(|c| {
+        if let Ok(mut log) = c.try_borrow_mut() {
+            log.record_abandoned_calls(&name, opened_before, now);
+        }
+    });
+}
+
+pub fn current_method() -> Option<String> {
+    CURRENT_METHOD.with(|m| m.borrow().clone())
+}
+
+pub fn get_crash_records(page: &GetPageInput) -> ServiceResult<GetPageOutput<CrashRecord>> {
+    page.validate(get_config().max_page_limit)?;
+    CRASH_LOG.with(|c| c.borrow().get_page(page))
+}
+
+crate::actor_response!(pub GetCrashRecordsResponse, GetPageOutput<CrashRecord>);
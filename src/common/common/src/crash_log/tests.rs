@@ -0,0 +1,126 @@
+use rstest::*;
+
+use super::*;
+use crate::test_common::test::init_test;
+
+#[fixture]
+pub fn setup() {
+    init_test();
+}
+
+fn record(i: u64) -> CrashRecord {
+    CrashRecord {
+        timestamp: TimeInNs(i),
+        canister_name: "test".to_string(),
+        method: Some("load_state".to_string()),
+        message: format!("crash {}", i),
+        location: Some("src/actor.rs:1:1".to_string()),
+    }
+}
+
+#[rstest]
+fn test_crash_log_is_bounded(_setup: ()) {
+    let mut log = CrashLog {
+        capacity: 2,
+        ..CrashLog::default()
+    };
+    for i in 0..3 {
+        log.push(record(i));
+    }
+
+    let page = log
+        .get_page(&GetPageInput {
+            offset: 0,
+            limit: 10,
+            ..Default::default()
+        })
+        .unwrap();
+    assert_eq!(page.items, vec![record(2), record(1)]);
+}
+
+#[rstest]
+fn test_method_guard_restores_previous_method(_setup: ()) {
+    assert_eq!(current_method(), None);
+    {
+        let _outer = enter_method("export_state");
+        {
+            let _inner = enter_method("load_state");
+            assert_eq!(current_method(), Some("load_state".to_string()));
+        }
+        assert_eq!(current_method(), Some("export_state".to_string()));
+    }
+    assert_eq!(current_method(), None);
+}
+
+#[rstest]
+fn test_display(_setup: ()) {
+    assert_eq!(
+        record(1).to_string(),
+        "test panicked in load_state at src/actor.rs:1:1: crash 1"
+    );
+}
+
+#[rstest]
+fn test_encode_decode(_setup: ()) {
+    let mut log = CrashLog::default();
+    log.push(record(1));
+
+    let decoded = CrashLog::decode(log.encode()).unwrap();
+
+    assert_eq!(decoded.capacity, CRASH_LOG_CAPACITY);
+    assert_eq!(decoded.len(), 1);
+}
+
+#[rstest]
+fn test_returned_update_closes_its_call(_setup: ()) {
+    {
+        let _method = enter_update("propose", TimeInNs(1));
+        assert_eq!(CRASH_LOG.with(|c| c.borrow().open_calls().len()), 1);
+    }
+    assert!(CRASH_LOG.with(|c| c.borrow().open_calls().is_empty()));
+}
+
+#[rstest]
+fn test_call_trapped_after_await_is_recorded(_setup: ()) {
+    // a trap after an await leaves the call open, the guard is never dropped
+    std::mem::forget(enter_update("propose", TimeInNs(1)));
+
+    let _method = enter_update("vote_proposal", TimeInNs(OPEN_CALL_TIMEOUT_NS.0));
+    assert!(CRASH_LOG.with(|c| c.borrow().is_empty()));
+
+    let _method = enter_update("vote_proposal", TimeInNs(OPEN_CALL_TIMEOUT_NS.0 + 2));
+    CRASH_LOG.with(|c| {
+        let log = c.borrow();
+        assert_eq!(log.len(), 1);
+        let page = log
+            .get_page(&GetPageInput {
+                offset: 0,
+                limit: 10,
+            })
+            .unwrap();
+        assert_eq!(page.items[0].method, Some("propose".to_string()));
+        assert_eq!(
+            log.open_calls()
+                .iter()
+                .map(|c| c.method.as_str())
+                .collect::<Vec<_>>(),
+            vec!["vote_proposal", "vote_proposal"]
+        );
+    });
+}
+
+#[rstest]
+fn test_open_calls_round_trip(_setup: ()) {
+    let mut log = CrashLog::default();
+    log.open_call("propose", TimeInNs(1));
+    log.push(record(1));
+
+    let mut decoded = CrashLog::decode(log.encode()).unwrap();
+
+    assert_eq!(decoded.open_calls(), log.open_calls());
+    assert_eq!(decoded.len(), 1);
+    assert!(decoded.check_invariants().is_empty());
+    decoded.record_abandoned_calls("test", TimeInNs(2), TimeInNs(3));
+    assert!(decoded.open_calls().is_empty());
+    assert_eq!(decoded.len(), 2);
+}
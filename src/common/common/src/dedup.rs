@@ -0,0 +1,268 @@
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::future::Future;
+
+use candid::{
+    decode_args, decode_one, encode_args, encode_one, CandidType, Deserialize, Principal,
+};
+use log::{debug, warn};
+
+use crate::errors::{ActorResult, CommonError, ErrorInfo, ServiceResult};
+use crate::state::StableState;
+use crate::types::TimeInNs;
+
+#[cfg(test)]
+mod tests;
+
+// 24 hours
+pub const DEDUP_DEFAULT_WINDOW_NS: TimeInNs = TimeInNs(24 * 60 * 60 * 1_000_000_000);
+pub const DEDUP_DEFAULT_MAX_ENTRIES: usize = 10_000;
+// 1 minute
+const EXPIRE_INTERVAL_NS: u64 = 60 * 1_000_000_000;
+
+thread_local! {
+    pub static DEDUP_STORE: RefCell<DedupStore> = RefCell::new(DedupStore::default());
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct DedupKey {
+    pub caller: Principal,
+    pub request_id: String,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub enum DedupState {
+    /// the call is awaiting, a replay must not execute it again until it is completed or failed
+    InProgress,
+    /// candid encoded `ActorResult<T>` of the finished call
+    Completed(Vec<u8>),
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct DedupEntry {
+    pub method: String,
+    pub created_at: TimeInNs,
+    pub state: DedupState,
+}
+
+/// Results of update calls keyed by (caller, request id), so a retried request returns
+/// the result of the first execution instead of running again.
+#[derive(Clone, Debug)]
+pub struct DedupStore {
+    pub window: TimeInNs,
+    pub max_entries: usize,
+    entries: BTreeMap<DedupKey, DedupEntry>,
+    /// `expire` walks every entry, `begin` runs it at most once per `EXPIRE_INTERVAL_NS`
+    last_expired_at: Option<TimeInNs>,
+}
+
+impl Default for DedupStore {
+    fn default() -> Self {
+        DedupStore {
+            window: DEDUP_DEFAULT_WINDOW_NS,
+            max_entries: DEDUP_DEFAULT_MAX_ENTRIES,
+            entries: BTreeMap::new(),
+            last_expired_at: None,
+        }
+    }
+}
+
+fn age(entry: &DedupEntry, now: TimeInNs) -> u64 {
+    now.0.saturating_sub(entry.created_at.0)
+}
+
+pub enum DedupLookup<T> {
+    /// first time this request is seen, it is now marked as in progress
+    New,
+    Replay(ActorResult<T>),
+}
+
+impl DedupStore {
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Removes completed entries older than the window. Calls in progress are kept until they
+    /// are completed or failed, a call which trapped has to be failed with `fail`.
+    pub fn expire(&mut self, now: TimeInNs) {
+        let window = self.window;
+        self.entries.retain(|_, entry| {
+            entry.state == DedupState::InProgress || age(entry, now) <= window.0
+        });
+        self.last_expired_at = Some(now);
+    }
+
+    fn expire_throttled(&mut self, now: TimeInNs) {
+        let due = match self.last_expired_at {
+            Some(at) => now.0.saturating_sub(at.0) >= EXPIRE_INTERVAL_NS,
+            None => true,
+        };
+        if due || self.entries.len() >= self.max_entries {
+            self.expire(now);
+        }
+    }
+
+    /// The entry of `key` unless it is a result outside the window.
+    fn live_entry(&self, key: &DedupKey, now: TimeInNs) -> Option<&DedupEntry> {
+        self.entries.get(key).filter(|entry| match entry.state {
+            DedupState::InProgress => true,
+            DedupState::Completed(_) => age(entry, now) <= self.window.0,
+        })
+    }
+
+    /// Evicts the oldest completed entry, calls in progress are never evicted.
+    fn evict_oldest(&mut self) -> bool {
+        let oldest = self
+            .entries
+            .iter()
+            .filter(|(_, entry)| entry.state != DedupState::InProgress)
+            .min_by_key(|(_, entry)| entry.created_at)
+            .map(|(key, _)| key.clone());
+        match oldest {
+            Some(key) => {
+                warn!("dedup store is full, evicting {:?}", key);
+                self.entries.remove(&key);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Returns the cached result of a previous execution, or marks the request as in progress.
+    pub fn begin<T>(
+        &mut self,
+        key: DedupKey,
+        method: &str,
+        now: TimeInNs,
+    ) -> Result<DedupLookup<T>, CommonError>
+    where
+        T: CandidType + for<'de> Deserialize<'de>,
+    {
+        self.expire_throttled(now);
+        if let Some(entry) = self.live_entry(&key, now) {
+            if entry.method != method {
+                return Err(CommonError::InvalidArgument {
+                    field: "request_id".to_string(),
+                    detail: format!(
+                        "request id {} was already used for {}",
+                        key.request_id, entry.method
+                    ),
+                });
+            }
+            return match &entry.state {
+                DedupState::InProgress => Err(CommonError::RequestInProgress {
+                    request_id: key.request_id,
+                }),
+                DedupState::Completed(bytes) => {
+                    let result: ActorResult<T> =
+                        decode_one(bytes).map_err(|e| CommonError::InvalidState {
+                            detail: format!("failed to decode cached result: {}", e),
+                        })?;
+                    Ok(DedupLookup::Replay(result))
+                }
+            };
+        }
+        // a result not yet swept out of the window
+        self.entries.remove(&key);
+        while self.entries.len() >= self.max_entries {
+            if !self.evict_oldest() {
+                return Err(CommonError::Busy {
+                    resource: "dedup store".to_string(),
+                });
+            }
+        }
+        self.entries.insert(
+            key,
+            DedupEntry {
+                method: method.to_string(),
+                created_at: now,
+                state: DedupState::InProgress,
+            },
+        );
+        Ok(DedupLookup::New)
+    }
+
+    pub fn complete<T: CandidType>(&mut self, key: &DedupKey, result: &ActorResult<T>) {
+        if let Some(entry) = self.entries.get_mut(key) {
+            entry.state = DedupState::Completed(encode_one(result).unwrap());
+        }
+    }
+
+    /// Forgets a request in progress, e.g. when its call trapped before changing anything,
+    /// so a retry runs it again. Returns false if the request is not in progress.
+    pub fn fail(&mut self, key: &DedupKey) -> bool {
+        match self.entries.get(key) {
+            Some(entry) if entry.state == DedupState::InProgress => {
+                self.entries.remove(key);
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+impl StableState for DedupStore {
+    fn encode(&self) -> Vec<u8> {
+        encode_args((self.window, self.max_entries, &self.entries)).unwrap()
+    }
+
+    fn decode(bytes: Vec<u8>) -> Result<Self, String> {
+        let (window, max_entries, entries): (TimeInNs, usize, BTreeMap<DedupKey, DedupEntry>) =
+            decode_args(&bytes).map_err(|e| format!("{:?}", e))?;
+        Ok(DedupStore {
+            window,
+            max_entries,
+            entries,
+            last_expired_at: None,
+        })
+    }
+}
+
+/// Runs `f` once per (caller, request id) inside the dedup window and returns the cached
+/// result to any replay of the same request.
+pub async fn dedup_call<T, F, Fut>(
+    caller: Principal,
+    request_id: String,
+    method: &str,
+    now: TimeInNs,
+    f: F,
+) -> ActorResult<T>
+where
+    T: CandidType + for<'de> Deserialize<'de>,
+    F: FnOnce() -> Fut,
+    Fut: Future<Output = ActorResult<T>>,
+{
+    let key = DedupKey { caller, request_id };
+    let lookup = DEDUP_STORE.with(|s| s.borrow_mut().begin::<T>(key.clone(), method, now));
+    match lookup {
+        Ok(DedupLookup::New) => {}
+        Ok(DedupLookup::Replay(result)) => {
+            debug!("dedup: replay {:?} of {}", key, method);
+            return result;
+        }
+        Err(e) => return Err(ErrorInfo::from(e)),
+    }
+    let result = f().await;
+    DEDUP_STORE.with(|s| s.borrow_mut().complete(&key, &result));
+    result
+}
+
+pub fn set_dedup_window(window: TimeInNs) {
+    DEDUP_STORE.with(|s| s.borrow_mut().window = window);
+}
+
+pub fn fail_dedup_request(caller: Principal, request_id: String) -> ServiceResult<()> {
+    let key = DedupKey { caller, request_id };
+    if DEDUP_STORE.with(|s| s.borrow_mut().fail(&key)) {
+        Ok(())
+    } else {
+        Err(CommonError::InvalidArgument {
+            field: "request_id".to_string(),
+            detail: format!("request {} is not in progress", key.request_id),
+        })
+    }
+}
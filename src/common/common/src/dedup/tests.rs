@@ -0,0 +1,252 @@
+use std::cell::Cell;
+
+use candid::Principal;
+use rstest::*;
+
+use super::*;
+use crate::test_common::test::init_test;
+
+#[fixture]
+pub fn setup() {
+    init_test();
+}
+
+fn caller() -> Principal {
+    Principal::from_slice(&[1; 29])
+}
+
+mod dedup_store {
+    use super::*;
+
+    #[rstest]
+    fn test_replay_returns_cached_result(_setup: ()) {
+        let mut store = DedupStore::default();
+        let key = DedupKey {
+            caller: caller(),
+            request_id: "1".to_string(),
+        };
+        assert!(matches!(
+            store.begin::<u64>(key.clone(), "transfer", TimeInNs(1)),
+            Ok(DedupLookup::New)
+        ));
+        assert_eq!(
+            store
+                .begin::<u64>(key.clone(), "transfer", TimeInNs(2))
+                .err(),
+            Some(CommonError::RequestInProgress {
+                request_id: "1".to_string()
+            })
+        );
+
+        store.complete::<u64>(&key, &Ok(42));
+        match store.begin::<u64>(key, "transfer", TimeInNs(3)) {
+            Ok(DedupLookup::Replay(result)) => assert_eq!(result, Ok(42)),
+            _ => panic!("expected a replay"),
+        }
+    }
+
+    #[rstest]
+    fn test_request_id_reused_by_other_method(_setup: ()) {
+        let mut store = DedupStore::default();
+        let key = DedupKey {
+            caller: caller(),
+            request_id: "1".to_string(),
+        };
+        store
+            .begin::<u64>(key.clone(), "transfer", TimeInNs(1))
+            .unwrap();
+        assert!(matches!(
+            store.begin::<u64>(key, "approve", TimeInNs(2)),
+            Err(CommonError::InvalidArgument { field, .. }) if field == "request_id"
+        ));
+    }
+
+    #[rstest]
+    fn test_call_in_progress_never_runs_again_until_failed(_setup: ()) {
+        let mut store = DedupStore {
+            window: TimeInNs(10),
+            ..DedupStore::default()
+        };
+        let key = DedupKey {
+            caller: caller(),
+            request_id: "1".to_string(),
+        };
+        store
+            .begin::<u64>(key.clone(), "transfer", TimeInNs(1))
+            .unwrap();
+        store.expire(TimeInNs(100));
+        assert_eq!(store.len(), 1);
+        assert_eq!(
+            store
+                .begin::<u64>(key.clone(), "transfer", TimeInNs(EXPIRE_INTERVAL_NS * 2))
+                .err(),
+            Some(CommonError::RequestInProgress {
+                request_id: "1".to_string()
+            })
+        );
+
+        assert!(store.fail(&key));
+        assert!(matches!(
+            store.begin::<u64>(key.clone(), "transfer", TimeInNs(EXPIRE_INTERVAL_NS * 3)),
+            Ok(DedupLookup::New)
+        ));
+        store.complete::<u64>(&key, &Ok(1));
+        assert!(!store.fail(&key));
+    }
+
+    #[rstest]
+    fn test_expired_entry_is_not_replayed_between_sweeps(_setup: ()) {
+        let mut store = DedupStore {
+            window: TimeInNs(10),
+            ..DedupStore::default()
+        };
+        let key = DedupKey {
+            caller: caller(),
+            request_id: "1".to_string(),
+        };
+        store
+            .begin::<u64>(key.clone(), "transfer", TimeInNs(1))
+            .unwrap();
+        store.complete::<u64>(&key, &Ok(1));
+        // the sweep ran at 1, the next one is not due yet
+        assert!(matches!(
+            store.begin::<u64>(key, "transfer", TimeInNs(20)),
+            Ok(DedupLookup::New)
+        ));
+    }
+
+    #[rstest]
+    fn test_entries_expire_after_window(_setup: ()) {
+        let mut store = DedupStore {
+            window: TimeInNs(10),
+            ..DedupStore::default()
+        };
+        let key = DedupKey {
+            caller: caller(),
+            request_id: "1".to_string(),
+        };
+        store
+            .begin::<u64>(key.clone(), "transfer", TimeInNs(1))
+            .unwrap();
+        store.complete::<u64>(&key, &Ok(1));
+        store.expire(TimeInNs(11));
+        assert_eq!(store.len(), 1);
+        store.expire(TimeInNs(12));
+        assert!(store.is_empty());
+    }
+
+    #[rstest]
+    fn test_full_store_evicts_oldest(_setup: ()) {
+        let mut store = DedupStore {
+            max_entries: 2,
+            ..DedupStore::default()
+        };
+        for i in 0..3u64 {
+            let key = DedupKey {
+                caller: caller(),
+                request_id: i.to_string(),
+            };
+            store
+                .begin::<u64>(key.clone(), "transfer", TimeInNs(i + 1))
+                .unwrap();
+            store.complete::<u64>(&key, &Ok(i));
+        }
+        assert_eq!(store.len(), 2);
+        assert!(matches!(
+            store.begin::<u64>(
+                DedupKey {
+                    caller: caller(),
+                    request_id: "0".to_string(),
+                },
+                "transfer",
+                TimeInNs(4),
+            ),
+            Ok(DedupLookup::New)
+        ));
+    }
+
+    #[rstest]
+    fn test_full_store_keeps_calls_in_progress(_setup: ()) {
+        let mut store = DedupStore {
+            max_entries: 2,
+            ..DedupStore::default()
+        };
+        for i in 0..2u64 {
+            let key = DedupKey {
+                caller: caller(),
+                request_id: i.to_string(),
+            };
+            store
+                .begin::<u64>(key, "transfer", TimeInNs(i + 1))
+                .unwrap();
+        }
+        let key = DedupKey {
+            caller: caller(),
+            request_id: "2".to_string(),
+        };
+        assert!(matches!(
+            store.begin::<u64>(key, "transfer", TimeInNs(3)),
+            Err(CommonError::Busy { .. })
+        ));
+        assert_eq!(store.len(), 2);
+    }
+
+    #[rstest]
+    fn test_encode_decode(_setup: ()) {
+        let mut store = DedupStore::default();
+        let key = DedupKey {
+            caller: caller(),
+            request_id: "1".to_string(),
+        };
+        store
+            .begin::<String>(key.clone(), "transfer", TimeInNs(1))
+            .unwrap();
+        store.complete::<String>(&key, &Err(CommonError::PermissionDenied.into()));
+
+        let mut decoded = DedupStore::decode(store.encode()).unwrap();
+        match decoded.begin::<String>(key, "transfer", TimeInNs(2)) {
+            Ok(DedupLookup::Replay(result)) => {
+                assert_eq!(result, Err(CommonError::PermissionDenied.into()))
+            }
+            _ => panic!("expected a replay"),
+        }
+    }
+}
+
+mod dedup_call {
+    use super::*;
+
+    #[rstest]
+    #[async_std::test]
+    async fn test_executes_once(_setup: ()) {
+        let executions = Cell::new(0);
+        for _ in 0..3 {
+            let result = dedup_call(
+                caller(),
+                "a".to_string(),
+                "transfer",
+                TimeInNs(1),
+                || async {
+                    executions.set(executions.get() + 1);
+                    Ok(executions.get())
+                },
+            )
+            .await;
+            assert_eq!(result, Ok(1));
+        }
+        assert_eq!(executions.get(), 1);
+
+        let result = dedup_call(
+            caller(),
+            "b".to_string(),
+            "transfer",
+            TimeInNs(1),
+            || async {
+                executions.set(executions.get() + 1);
+                Ok(executions.get())
+            },
+        )
+        .await;
+        assert_eq!(result, Ok(2));
+    }
+}
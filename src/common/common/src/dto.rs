@@ -8,22 +8,60 @@ use flate2::read::ZlibDecoder;
 use flate2::write::ZlibEncoder;
 use flate2::Compression;
 
-use crate::constants::{
-    PAGE_INPUT_MAX_LIMIT, PAGE_INPUT_MAX_OFFSET, PAGE_INPUT_MIN_LIMIT, PAGE_INPUT_MIN_OFFSET,
-};
-use crate::errors::{CommonError, ErrorInfo, ServiceResult};
+use crate::constants::{PAGE_INPUT_MAX_OFFSET, PAGE_INPUT_MIN_LIMIT, PAGE_INPUT_MIN_OFFSET};
+use crate::errors::{CommonError, ServiceResult};
+use crate::named_canister_ids::NamedCanisterId;
 
 #[cfg(test)]
 mod tests;
 
-#[derive(CandidType, Deserialize)]
+#[derive(CandidType, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SortDirection {
+    Asc,
+    Desc,
+}
+
+impl Default for SortDirection {
+    fn default() -> Self {
+        SortDirection::Asc
+    }
+}
+
+/// Either `offset` based, or continuing after `cursor`, the `next_cursor` of the previous page.
+/// `offset` is capped by `PAGE_INPUT_MAX_OFFSET`, a cursor reaches any item.
+#[derive(CandidType, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
 pub struct GetPageInput {
     pub offset: usize,
     pub limit: usize,
+    pub cursor: Option<String>,
+    /// one of the fields the endpoint documents as sortable, `None` for its default order
+    pub sort_by: Option<String>,
+    pub direction: Option<SortDirection>,
 }
 
 impl GetPageInput {
-    pub fn validate(&self) -> ServiceResult<()> {
+    pub fn new(offset: usize, limit: usize) -> Self {
+        GetPageInput {
+            offset,
+            limit,
+            ..Default::default()
+        }
+    }
+
+    pub fn after(cursor: String, limit: usize) -> Self {
+        GetPageInput {
+            limit,
+            cursor: Some(cursor),
+            ..Default::default()
+        }
+    }
+
+    pub fn direction(&self) -> SortDirection {
+        self.direction.unwrap_or_default()
+    }
+
+    /// `max_limit` is the largest page the endpoint returns.
+    pub fn validate(&self, max_limit: usize) -> ServiceResult<()> {
         let max_offset = PAGE_INPUT_MAX_OFFSET;
         let min_offset = PAGE_INPUT_MIN_OFFSET;
         if self.offset > max_offset || self.offset < min_offset {
@@ -33,7 +71,6 @@ impl GetPageInput {
                 max: max_offset,
             });
         }
-        let max_limit = PAGE_INPUT_MAX_LIMIT;
         let min_limit = PAGE_INPUT_MIN_LIMIT;
         if self.limit > max_limit || self.limit < min_limit {
             return Err(CommonError::ValueShouldBeInRangeError {
@@ -42,21 +79,63 @@ impl GetPageInput {
                 max: max_limit,
             });
         }
+        if self.cursor.is_some() && self.offset != 0 {
+            return Err(CommonError::InvalidArgument {
+                field: "offset".to_string(),
+                detail: "offset must be 0 when a cursor is given".to_string(),
+            });
+        }
         Ok(())
     }
+
+    /// Checks that `sort_by` is one of `allowed`.
+    pub fn validate_sort_by(&self, allowed: &[&str]) -> ServiceResult<()> {
+        match &self.sort_by {
+            Some(field) if !allowed.contains(&field.as_str()) => {
+                Err(CommonError::InvalidArgument {
+                    field: "sort_by".to_string(),
+                    detail: format!("{:?} is not one of {:?}", field, allowed),
+                })
+            }
+            _ => Ok(()),
+        }
+    }
 }
 
-#[derive(CandidType, Deserialize)]
+#[derive(CandidType, Deserialize, Debug, PartialEq, Eq)]
 pub struct GetPageOutput<T> {
     pub items: Vec<T>,
+    /// number of items matching the query, on all pages
+    pub total: u64,
+    /// `None` on the last page
+    pub next_cursor: Option<String>,
 }
 
 impl<T> GetPageOutput<T> {
-    pub fn new(items: Vec<T>) -> Self {
-        Self { items }
+    pub fn new(items: Vec<T>, total: u64, next_cursor: Option<String>) -> Self {
+        Self {
+            items,
+            total,
+            next_cursor,
+        }
+    }
+
+    pub fn map<U>(self, f: impl FnMut(T) -> U) -> GetPageOutput<U> {
+        GetPageOutput {
+            items: self.items.into_iter().map(f).collect(),
+            total: self.total,
+            next_cursor: self.next_cursor,
+        }
     }
 }
 
+/// Arguments of `init` and `post_upgrade`, they configure the same wasm for each network.
+#[derive(CandidType, Deserialize, Clone, Debug, Default)]
+pub struct CanisterArgs {
+    /// on upgrade only the given ids are replaced, the others are kept
+    pub named_canister_ids: Vec<NamedCanisterId>,
+}
+
 #[derive(CandidType, Deserialize)]
 pub struct LoadStateRequest {
     pub state_data: Vec<u8>,
@@ -73,20 +152,7 @@ pub struct StateExportData {
     pub state_data: Vec<u8>,
 }
 
-#[derive(CandidType)]
-pub enum StateExportResponse {
-    Ok(StateExportData),
-    Err(ErrorInfo),
-}
-
-impl StateExportResponse {
-    pub fn new(result: ServiceResult<StateExportData>) -> StateExportResponse {
-        match result {
-            Ok(stats) => StateExportResponse::Ok(stats),
-            Err(err) => StateExportResponse::Err(err.into()),
-        }
-    }
-}
+crate::actor_response!(pub StateExportResponse, StateExportData);
 
 pub fn encode_zlib(data: &[u8]) -> Vec<u8> {
     let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
@@ -95,11 +161,15 @@ pub fn encode_zlib(data: &[u8]) -> Vec<u8> {
     data
 }
 
-pub fn decode_zlib(data: &[u8]) -> Vec<u8> {
+pub fn decode_zlib(data: &[u8]) -> ServiceResult<Vec<u8>> {
     let mut d = ZlibDecoder::new(data);
     let mut decoded_data = Vec::new();
-    d.read_to_end(&mut decoded_data).unwrap();
-    decoded_data
+    d.read_to_end(&mut decoded_data)
+        .map_err(|e| CommonError::InvalidArgument {
+            field: "state_data".to_string(),
+            detail: format!("not zlib compressed, {}", e),
+        })?;
+    Ok(decoded_data)
 }
 
 pub fn to_state_export_data(source_state_data: Vec<u8>) -> StateExportData {
@@ -108,21 +178,22 @@ pub fn to_state_export_data(source_state_data: Vec<u8>) -> StateExportData {
     }
 }
 
-pub fn from_state_export_data(request: LoadStateRequest) -> Vec<u8> {
+pub fn from_state_export_data(request: LoadStateRequest) -> ServiceResult<Vec<u8>> {
     decode_zlib(request.state_data.as_slice())
 }
 
-#[derive(CandidType)]
-pub enum GetStatsResponse<T> {
-    Ok(T),
-    Err(ErrorInfo),
+/// Report of `validate_state`, nothing is applied.
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct StateValidation {
+    pub compressed_size: u64,
+    pub size: u64,
+    /// hex sha256 of the decompressed state
+    pub hash: String,
+    pub schema_version: u32,
+    /// empty when the state can be loaded
+    pub violations: Vec<String>,
 }
 
-impl<T> GetStatsResponse<T> {
-    pub fn new(result: ServiceResult<T>) -> GetStatsResponse<T> {
-        match result {
-            Ok(stats) => GetStatsResponse::Ok(stats),
-            Err(err) => GetStatsResponse::Err(err.into()),
-        }
-    }
-}
+crate::actor_response!(pub ValidateStateResponse, StateValidation);
+
+crate::actor_response!(pub GetStatsResponse<T>, T);
@@ -18,8 +18,9 @@ mod get_page_input {
         let input = GetPageInput {
             limit: 10,
             offset: 0,
+            ..Default::default()
         };
-        assert_eq!(input.validate(), Ok(()));
+        assert_eq!(input.validate(PAGE_INPUT_MAX_LIMIT), Ok(()));
     }
 
     #[rstest]
@@ -27,9 +28,10 @@ mod get_page_input {
         let input = GetPageInput {
             limit: PAGE_INPUT_MAX_LIMIT + 1,
             offset: 0,
+            ..Default::default()
         };
         assert_eq!(
-            input.validate(),
+            input.validate(PAGE_INPUT_MAX_LIMIT),
             Err(CommonError::ValueShouldBeInRangeError {
                 field: "limit".to_string(),
                 min: 1,
@@ -43,9 +45,10 @@ mod get_page_input {
         let input = GetPageInput {
             limit: 100,
             offset: PAGE_INPUT_MAX_OFFSET + 1,
+            ..Default::default()
         };
         assert_eq!(
-            input.validate(),
+            input.validate(PAGE_INPUT_MAX_LIMIT),
             Err(CommonError::ValueShouldBeInRangeError {
                 field: "offset".to_string(),
                 min: 0,
@@ -53,4 +56,30 @@ mod get_page_input {
             })
         );
     }
+
+    #[rstest]
+    fn test_get_page_input_cursor_with_offset(_setup: ()) {
+        let input = GetPageInput {
+            offset: 1,
+            ..GetPageInput::after("00".to_string(), 10)
+        };
+        assert!(matches!(
+            input.validate(PAGE_INPUT_MAX_LIMIT),
+            Err(CommonError::InvalidArgument { field, .. }) if field == "offset"
+        ));
+    }
+
+    #[rstest]
+    fn test_get_page_input_max_limit_is_a_parameter(_setup: ()) {
+        let input = GetPageInput::new(0, 20);
+        assert_eq!(input.validate(20), Ok(()));
+        assert_eq!(
+            input.validate(10),
+            Err(CommonError::ValueShouldBeInRangeError {
+                field: "limit".to_string(),
+                min: 1,
+                max: 10,
+            })
+        );
+    }
 }
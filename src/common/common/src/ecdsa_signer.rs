@@ -0,0 +1,279 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+use bech32::{ToBase32, Variant};
+use candid::{CandidType, Deserialize};
+use k256::ecdsa::{recoverable, Signature, VerifyingKey};
+use k256::elliptic_curve::sec1::ToEncodedPoint;
+use k256::{FieldBytes, PublicKey};
+use log::{debug, error};
+use ripemd::Ripemd160;
+use sha2::{Digest, Sha256};
+use sha3::Keccak256;
+
+use crate::canister_api::IICManagementAPI;
+use crate::errors::{CommonError, ServiceResult};
+use crate::types::ic_management_types::{ECDSAPublicKey, SignWithECDSA, ThresholdKeyName};
+
+pub type DerivationPath = Vec<Vec<u8>>;
+
+#[derive(CandidType, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BitcoinNetwork {
+    Mainnet,
+    Testnet,
+    Regtest,
+}
+
+impl BitcoinNetwork {
+    fn p2pkh_version(&self) -> u8 {
+        match self {
+            BitcoinNetwork::Mainnet => 0x00,
+            BitcoinNetwork::Testnet | BitcoinNetwork::Regtest => 0x6f,
+        }
+    }
+
+    fn bech32_hrp(&self) -> &'static str {
+        match self {
+            BitcoinNetwork::Mainnet => "bc",
+            BitcoinNetwork::Testnet => "tb",
+            BitcoinNetwork::Regtest => "bcrt",
+        }
+    }
+}
+
+#[derive(CandidType, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct EthereumSignature {
+    pub r: Vec<u8>,
+    pub s: Vec<u8>,
+    /// recovery id, 0 or 1
+    pub v: u8,
+}
+
+impl EthereumSignature {
+    /// 65 bytes `r || s || v` with `v` in the legacy 27/28 form.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(65);
+        bytes.extend_from_slice(&self.r);
+        bytes.extend_from_slice(&self.s);
+        bytes.push(self.v + 27);
+        bytes
+    }
+
+    /// `v` of a transaction signature with EIP-155 replay protection.
+    pub fn eip155_v(&self, chain_id: u64) -> u64 {
+        chain_id * 2 + 35 + self.v as u64
+    }
+}
+
+fn invalid_argument(field: &str, detail: impl ToString) -> CommonError {
+    CommonError::InvalidArgument {
+        field: field.to_string(),
+        detail: detail.to_string(),
+    }
+}
+
+/// Failures of encodings which are valid by construction.
+fn invalid_state(detail: impl ToString) -> CommonError {
+    CommonError::InvalidState {
+        detail: detail.to_string(),
+    }
+}
+
+pub fn keccak256(data: &[u8]) -> [u8; 32] {
+    Keccak256::digest(data).into()
+}
+
+/// Hash of a message as signed by `personal_sign` / `eth_sign`.
+pub fn ethereum_message_hash(message: &[u8]) -> [u8; 32] {
+    let mut data = format!("\x19Ethereum Signed Message:\n{}", message.len()).into_bytes();
+    data.extend_from_slice(message);
+    keccak256(&data)
+}
+
+fn parse_public_key(public_key: &[u8]) -> ServiceResult<PublicKey> {
+    PublicKey::from_sec1_bytes(public_key).map_err(|e| invalid_argument("public_key", e))
+}
+
+/// EIP-55 checksummed Ethereum address of a SEC1 encoded secp256k1 public key.
+pub fn ethereum_address(public_key: &[u8]) -> ServiceResult<String> {
+    let point = parse_public_key(public_key)?.to_encoded_point(false);
+    let hash = keccak256(&point.as_bytes()[1..]);
+    let address = hex::encode(&hash[12..]);
+    let checksum = keccak256(address.as_bytes());
+    let checksummed: String = address
+        .chars()
+        .enumerate()
+        .map(|(i, c)| {
+            let nibble = (checksum[i / 2] >> (if i % 2 == 0 { 4 } else { 0 })) & 0x0f;
+            if nibble >= 8 {
+                c.to_ascii_uppercase()
+            } else {
+                c
+            }
+        })
+        .collect();
+    Ok(format!("0x{}", checksummed))
+}
+
+fn hash160(data: &[u8]) -> Vec<u8> {
+    Ripemd160::digest(Sha256::digest(data)).to_vec()
+}
+
+fn compressed_public_key(public_key: &[u8]) -> ServiceResult<Vec<u8>> {
+    Ok(parse_public_key(public_key)?
+        .to_encoded_point(true)
+        .as_bytes()
+        .to_vec())
+}
+
+/// Legacy base58 pay-to-public-key-hash address of the compressed public key.
+pub fn bitcoin_p2pkh_address(public_key: &[u8], network: BitcoinNetwork) -> ServiceResult<String> {
+    let mut payload = vec![network.p2pkh_version()];
+    payload.extend(hash160(&compressed_public_key(public_key)?));
+    Ok(bs58::encode(payload).with_check().into_string())
+}
+
+/// Native segwit (bech32, witness version 0) pay-to-witness-public-key-hash address.
+pub fn bitcoin_p2wpkh_address(public_key: &[u8], network: BitcoinNetwork) -> ServiceResult<String> {
+    let program = hash160(&compressed_public_key(public_key)?);
+    let mut data = vec![bech32::u5::try_from_u8(0).map_err(invalid_state)?];
+    data.extend(program.to_base32());
+    bech32::encode(network.bech32_hrp(), data, Variant::Bech32).map_err(invalid_state)
+}
+
+/// Finds the recovery id which recovers `public_key` from a 64 bytes `r || s` signature.
+/// The signature is normalized to low-s first, as required by Ethereum.
+pub fn to_ethereum_signature(
+    signature: &[u8],
+    message_hash: &[u8; 32],
+    public_key: &[u8],
+) -> ServiceResult<EthereumSignature> {
+    let signature = Signature::try_from(signature).map_err(|e| invalid_argument("signature", e))?;
+    let signature = signature.normalize_s().unwrap_or(signature);
+    let expected =
+        VerifyingKey::from_sec1_bytes(public_key).map_err(|e| invalid_argument("public_key", e))?;
+    for v in 0..2u8 {
+        let id = recoverable::Id::new(v).map_err(invalid_state)?;
+        let recoverable = recoverable::Signature::new(&signature, id)
+            .map_err(|e| invalid_argument("signature", e))?;
+        let recovered = recoverable
+            .recover_verifying_key_from_digest_bytes(FieldBytes::from_slice(message_hash));
+        if matches!(recovered, Ok(key) if key == expected) {
+            let bytes: &[u8] = signature.as_ref();
+            return Ok(EthereumSignature {
+                r: bytes[..32].to_vec(),
+                s: bytes[32..].to_vec(),
+                v,
+            });
+        }
+    }
+    Err(invalid_argument(
+        "signature",
+        "no recovery id matches the public key",
+    ))
+}
+
+/// Signs with a threshold ECDSA key of the management canister and derives addresses from it.
+/// Public keys are cached per derivation path, they never change for a given key id.
+pub struct EcdsaSigner<T: IICManagementAPI> {
+    api: T,
+    key_name: ThresholdKeyName,
+    public_keys: RefCell<HashMap<DerivationPath, Vec<u8>>>,
+}
+
+impl<T: IICManagementAPI> EcdsaSigner<T> {
+    pub fn new(api: T, key_name: ThresholdKeyName) -> Self {
+        Self {
+            api,
+            key_name,
+            public_keys: RefCell::new(HashMap::new()),
+        }
+    }
+
+    /// Signer using the key of the environment this wasm was built for.
+    pub fn for_current_env(api: T) -> Self {
+        Self::new(api, ThresholdKeyName::current())
+    }
+
+    pub fn key_name(&self) -> ThresholdKeyName {
+        self.key_name
+    }
+
+    /// SEC1 compressed public key for `derivation_path`.
+    pub async fn public_key(&self, derivation_path: &DerivationPath) -> ServiceResult<Vec<u8>> {
+        if let Some(key) = self.public_keys.borrow().get(derivation_path) {
+            return Ok(key.clone());
+        }
+        let reply = self
+            .api
+            .ecdsa_public_key(ECDSAPublicKey {
+                canister_id: None,
+                derivation_path: derivation_path.clone(),
+                key_id: self.key_name.ecdsa_key_id(),
+            })
+            .await?;
+        debug!(
+            "ecdsa public key for {:?}: {}",
+            derivation_path,
+            hex::encode(&reply.public_key)
+        );
+        self.public_keys
+            .borrow_mut()
+            .insert(derivation_path.clone(), reply.public_key.clone());
+        Ok(reply.public_key)
+    }
+
+    pub async fn ethereum_address(
+        &self,
+        derivation_path: &DerivationPath,
+    ) -> ServiceResult<String> {
+        ethereum_address(&self.public_key(derivation_path).await?)
+    }
+
+    pub async fn bitcoin_p2pkh_address(
+        &self,
+        derivation_path: &DerivationPath,
+        network: BitcoinNetwork,
+    ) -> ServiceResult<String> {
+        bitcoin_p2pkh_address(&self.public_key(derivation_path).await?, network)
+    }
+
+    pub async fn bitcoin_p2wpkh_address(
+        &self,
+        derivation_path: &DerivationPath,
+        network: BitcoinNetwork,
+    ) -> ServiceResult<String> {
+        bitcoin_p2wpkh_address(&self.public_key(derivation_path).await?, network)
+    }
+
+    /// Raw 64 bytes `r || s` signature of a 32 bytes message hash.
+    pub async fn sign(
+        &self,
+        derivation_path: &DerivationPath,
+        message_hash: &[u8; 32],
+    ) -> ServiceResult<Vec<u8>> {
+        let reply = self
+            .api
+            .sign_with_ecdsa(SignWithECDSA {
+                message_hash: message_hash.to_vec(),
+                derivation_path: derivation_path.clone(),
+                key_id: self.key_name.ecdsa_key_id(),
+            })
+            .await?;
+        Ok(reply.signature)
+    }
+
+    /// Recoverable signature of a 32 bytes (keccak256) message hash.
+    pub async fn sign_ethereum(
+        &self,
+        derivation_path: &DerivationPath,
+        message_hash: &[u8; 32],
+    ) -> ServiceResult<EthereumSignature> {
+        let public_key = self.public_key(derivation_path).await?;
+        let signature = self.sign(derivation_path, message_hash).await?;
+        to_ethereum_signature(&signature, message_hash, &public_key).map_err(|e| {
+            error!("failed to build ethereum signature: {}", e);
+            e
+        })
+    }
+}
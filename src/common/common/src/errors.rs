@@ -1,11 +1,63 @@
-use candid::{CandidType, Deserialize};
+use std::cell::RefCell;
+use std::collections::BTreeMap;
 use std::fmt;
 use std::fmt::{Display, Formatter};
+use std::ops::Range;
+
+use candid::{CandidType, Deserialize};
 use thiserror::Error;
 
+use crate::types::TimeInSec;
+
+#[cfg(test)]
+mod tests;
+
+/// Codes a `DomainError` may use, the codes outside of it belong to `CommonError`.
+pub const DOMAIN_ERROR_CODES: Range<u32> = 1_000..10_000;
+
+thread_local! {
+    static ERROR_CATALOG: RefCell<BTreeMap<u32, ErrorCatalogEntry>> =
+        RefCell::new(common_error_catalog());
+}
+
+#[derive(Debug, Clone, Copy, Ord, PartialOrd, Eq, PartialEq, Hash, CandidType, Deserialize)]
+pub enum ErrorCategory {
+    /// the request is invalid, retrying it unchanged fails again
+    Client,
+    Auth,
+    /// another canister failed or rejected the call
+    Remote,
+    Internal,
+}
+
+#[derive(Debug, Clone, Ord, PartialOrd, Eq, PartialEq, CandidType, Deserialize)]
+pub struct ErrorDetail {
+    pub key: String,
+    pub value: String,
+}
+
+impl ErrorDetail {
+    pub fn new(key: &str, value: impl ToString) -> Self {
+        ErrorDetail {
+            key: key.to_string(),
+            value: value.to_string(),
+        }
+    }
+}
+
+/// Error of a canister crate, converted into `CommonError::Domain` so it can travel in a `ServiceResult`.
+/// Codes must be stable and inside `DOMAIN_ERROR_CODES`, register them with `register_error_catalog`.
+pub trait DomainError: Display {
+    fn code(&self) -> u32;
+    fn category(&self) -> ErrorCategory;
+    fn details(&self) -> Vec<ErrorDetail> {
+        vec![]
+    }
+}
+
 #[derive(Debug, Clone, Ord, PartialOrd, Eq, PartialEq, CandidType, Deserialize, Error)]
 pub enum CommonError {
-    #[error("error from remote, {0:?}")]
+    #[error("error from remote, {0}")]
     RemoteError(ErrorInfo),
     #[error("Unauthorized, please login first")]
     Unauthorized,
@@ -22,21 +74,105 @@ pub enum CommonError {
         message: String,
         rejection_code: String,
     },
+    #[error("{resource} is busy, please retry later")]
+    Busy { resource: String },
+    #[error("Request {request_id:?} is still being processed")]
+    RequestInProgress { request_id: String },
+    #[error("Invalid state, detail: {detail:?}")]
+    InvalidState { detail: String },
+    #[error("Invalid argument {field:?}, {detail}")]
+    InvalidArgument { field: String, detail: String },
+    #[error("Canister is under maintenance, please retry later")]
+    UnderMaintenance,
+    #[error("Too many requests, retry after {retry_after}")]
+    RateLimited { retry_after: TimeInSec },
+    #[error("{}", .0.message)]
+    Domain(ErrorInfo),
     #[error("Unknown error, detail: {detail:?}")]
     Unknown { detail: String },
 }
 
 impl CommonError {
-    pub(crate) fn code(&self) -> u32 {
+    pub fn code(&self) -> u32 {
         match self {
             CommonError::RemoteError(_) => 2,
             CommonError::Unauthorized => 3,
             CommonError::PermissionDenied => 4,
             CommonError::ValueShouldBeInRangeError { .. } => 5,
             CommonError::CanisterCallError { .. } => 6,
+            CommonError::Busy { .. } => 7,
+            CommonError::RequestInProgress { .. } => 8,
+            CommonError::InvalidState { .. } => 9,
+            CommonError::InvalidArgument { .. } => 10,
+            CommonError::UnderMaintenance => 11,
+            CommonError::RateLimited { .. } => 12,
+            CommonError::Domain(info) => info.code,
             CommonError::Unknown { .. } => 10000,
         }
     }
+
+    pub fn category(&self) -> ErrorCategory {
+        match self {
+            CommonError::RemoteError(_) | CommonError::CanisterCallError { .. } => {
+                ErrorCategory::Remote
+            }
+            CommonError::Unauthorized | CommonError::PermissionDenied => ErrorCategory::Auth,
+            CommonError::ValueShouldBeInRangeError { .. }
+            | CommonError::RequestInProgress { .. }
+            | CommonError::InvalidArgument { .. }
+            | CommonError::Busy { .. }
+            | CommonError::UnderMaintenance
+            | CommonError::RateLimited { .. } => ErrorCategory::Client,
+            CommonError::Domain(info) => info.category.unwrap_or(ErrorCategory::Internal),
+            CommonError::InvalidState { .. } | CommonError::Unknown { .. } => {
+                ErrorCategory::Internal
+            }
+        }
+    }
+
+    pub fn details(&self) -> Vec<ErrorDetail> {
+        match self {
+            CommonError::ValueShouldBeInRangeError { field, min, max } => vec![
+                ErrorDetail::new("field", field),
+                ErrorDetail::new("min", min),
+                ErrorDetail::new("max", max),
+            ],
+            CommonError::CanisterCallError {
+                message,
+                rejection_code,
+            } => vec![
+                ErrorDetail::new("rejection_code", rejection_code),
+                ErrorDetail::new("message", message),
+            ],
+            CommonError::RequestInProgress { request_id } => {
+                vec![ErrorDetail::new("request_id", request_id)]
+            }
+            CommonError::InvalidArgument { field, .. } => vec![ErrorDetail::new("field", field)],
+            CommonError::Busy { resource } => vec![ErrorDetail::new("resource", resource)],
+            CommonError::RateLimited { retry_after } => {
+                vec![ErrorDetail::new("retry_after_seconds", retry_after.0)]
+            }
+            CommonError::Domain(info) => info.details.clone().unwrap_or_default(),
+            _ => vec![],
+        }
+    }
+}
+
+impl<E: DomainError> From<E> for CommonError {
+    fn from(error: E) -> Self {
+        let details = error.details();
+        CommonError::Domain(ErrorInfo {
+            code: error.code(),
+            message: error.to_string(),
+            category: Some(error.category()),
+            details: if details.is_empty() {
+                None
+            } else {
+                Some(details)
+            },
+            cause: None,
+        })
+    }
 }
 
 /// Error information
@@ -46,6 +182,43 @@ pub struct ErrorInfo {
     pub code: u32,
     /// Error message
     pub message: String,
+    /// `None` in errors of canisters built before categories were added
+    pub category: Option<ErrorCategory>,
+    pub details: Option<Vec<ErrorDetail>>,
+    /// The error this one was caused by, e.g. the error returned by a remote canister
+    pub cause: Option<Box<ErrorInfo>>,
+}
+
+impl ErrorInfo {
+    pub fn new(code: u32, message: impl Into<String>) -> Self {
+        ErrorInfo {
+            code,
+            message: message.into(),
+            category: None,
+            details: None,
+            cause: None,
+        }
+    }
+
+    pub fn with_cause(mut self, cause: ErrorInfo) -> Self {
+        self.cause = Some(Box::new(cause));
+        self
+    }
+
+    /// This error followed by its causes, outermost first.
+    pub fn chain(&self) -> Vec<&ErrorInfo> {
+        let mut chain = vec![self];
+        let mut current = self;
+        while let Some(cause) = current.cause.as_deref() {
+            chain.push(cause);
+            current = cause;
+        }
+        chain
+    }
+
+    pub fn root_cause(&self) -> &ErrorInfo {
+        self.chain().pop().unwrap()
+    }
 }
 
 impl Display for ErrorInfo {
@@ -55,9 +228,27 @@ impl Display for ErrorInfo {
 }
 
 pub fn get_error_code(error: CommonError) -> ErrorInfo {
-    ErrorInfo {
-        code: error.code(),
-        message: error.to_string(),
+    match error {
+        CommonError::Domain(info) => info,
+        error => {
+            let details = error.details();
+            let message = error.to_string();
+            let info = ErrorInfo {
+                code: error.code(),
+                message,
+                category: Some(error.category()),
+                details: if details.is_empty() {
+                    None
+                } else {
+                    Some(details)
+                },
+                cause: None,
+            };
+            match error {
+                CommonError::RemoteError(cause) => info.with_cause(cause),
+                _ => info,
+            }
+        }
     }
 }
 
@@ -77,19 +268,198 @@ impl From<ErrorInfo> for CommonError {
     }
 }
 
-/// When export_service, actor responses will merged by enum type, so if there is two response with same Ok type, the second response will be ignored.
-/// So there is no need to create more than one response type for two boolean ok.
-#[derive(CandidType)]
-pub enum BooleanActorResponse {
-    Ok(bool),
-    Err(ErrorInfo),
+#[derive(Debug, Clone, PartialEq, Eq, CandidType, Deserialize)]
+pub struct ErrorCatalogEntry {
+    pub code: u32,
+    pub name: String,
+    pub category: ErrorCategory,
+    pub description: String,
 }
 
-impl BooleanActorResponse {
-    pub fn new(result: ServiceResult<bool>) -> BooleanActorResponse {
-        match result {
-            Ok(available) => BooleanActorResponse::Ok(available),
-            Err(err) => BooleanActorResponse::Err(err.into()),
+impl ErrorCatalogEntry {
+    pub fn new(code: u32, name: &str, category: ErrorCategory, description: &str) -> Self {
+        ErrorCatalogEntry {
+            code,
+            name: name.to_string(),
+            category,
+            description: description.to_string(),
         }
     }
 }
+
+fn common_error_catalog() -> BTreeMap<u32, ErrorCatalogEntry> {
+    use ErrorCategory::*;
+    [
+        ErrorCatalogEntry::new(
+            2,
+            "RemoteError",
+            Remote,
+            "a remote canister returned an error, see cause",
+        ),
+        ErrorCatalogEntry::new(
+            3,
+            "Unauthorized",
+            Auth,
+            "the caller is anonymous or not allowed",
+        ),
+        ErrorCatalogEntry::new(
+            4,
+            "PermissionDenied",
+            Auth,
+            "the caller lacks the permission",
+        ),
+        ErrorCatalogEntry::new(
+            5,
+            "ValueShouldBeInRangeError",
+            Client,
+            "a value is out of its range",
+        ),
+        ErrorCatalogEntry::new(
+            6,
+            "CanisterCallError",
+            Remote,
+            "an inter-canister call was rejected",
+        ),
+        ErrorCatalogEntry::new(
+            7,
+            "Busy",
+            Client,
+            "a resource is locked by another call, retry later",
+        ),
+        ErrorCatalogEntry::new(
+            8,
+            "RequestInProgress",
+            Client,
+            "a request with the same id is being processed",
+        ),
+        ErrorCatalogEntry::new(
+            9,
+            "InvalidState",
+            Internal,
+            "state data could not be decoded or is inconsistent",
+        ),
+        ErrorCatalogEntry::new(
+            10,
+            "InvalidArgument",
+            Client,
+            "an argument is malformed or refers to something that does not exist",
+        ),
+        ErrorCatalogEntry::new(
+            11,
+            "UnderMaintenance",
+            Client,
+            "maintenance mode is on, calls are rejected until it is switched off",
+        ),
+        ErrorCatalogEntry::new(
+            12,
+            "RateLimited",
+            Client,
+            "the caller sent too many requests to the method, see retry_after",
+        ),
+        ErrorCatalogEntry::new(
+            10000,
+            "Unknown",
+            Internal,
+            "unclassified error, see message",
+        ),
+    ]
+    .into_iter()
+    .map(|entry| (entry.code, entry))
+    .collect()
+}
+
+/// Adds the errors of a canister crate to the catalog, registering the same entries again is a no-op.
+pub fn register_error_catalog(entries: Vec<ErrorCatalogEntry>) -> ServiceResult<()> {
+    ERROR_CATALOG.with(|c| {
+        let mut catalog = c.borrow().clone();
+        for entry in entries {
+            if !DOMAIN_ERROR_CODES.contains(&entry.code) {
+                return Err(CommonError::ValueShouldBeInRangeError {
+                    field: format!("code of {}", entry.name),
+                    min: DOMAIN_ERROR_CODES.start as usize,
+                    max: DOMAIN_ERROR_CODES.end as usize,
+                });
+            }
+            if let Some(existing) = catalog.get(&entry.code) {
+                if *existing != entry {
+                    return Err(CommonError::InvalidState {
+                        detail: format!(
+                            "error code {} is used by both {} and {}",
+                            entry.code, existing.name, entry.name
+                        ),
+                    });
+                }
+            }
+            catalog.insert(entry.code, entry);
+        }
+        c.replace(catalog);
+        Ok(())
+    })
+}
+
+pub fn get_error_catalog() -> Vec<ErrorCatalogEntry> {
+    ERROR_CATALOG.with(|c| c.borrow().values().cloned().collect())
+}
+
+/// Declares a candid result enum `{ Ok(T), Err(ErrorInfo) }` for an endpoint, with `new` and
+/// `From` conversions between it and `ServiceResult<T>` / `ActorResult<T>`.
+///
+/// When export_service, actor responses are merged by enum type, so a generic `Response<T>`
+/// would show up once in the .did. Declaring one named type per endpoint keeps them apart.
+///
+/// ```ignore
+/// actor_response!(pub GetJobsResponse, Vec<JobRecord>);
+/// actor_response!(pub GetStatsResponse<T>, T);
+/// ```
+#[macro_export]
+macro_rules! actor_response {
+    (@define $(#[$meta:meta])* $vis:vis $name:ident [$($param:ident),*] $ok:ty) => {
+        $(#[$meta])*
+        #[derive(candid::CandidType)]
+        $vis enum $name<$($param),*> {
+            Ok($ok),
+            Err($crate::errors::ErrorInfo),
+        }
+
+        impl<$($param),*> $name<$($param),*> {
+            pub fn new(result: $crate::errors::ServiceResult<$ok>) -> Self {
+                match result {
+                    Ok(value) => $name::Ok(value),
+                    Err(err) => $name::Err(err.into()),
+                }
+            }
+        }
+
+        impl<$($param),*> From<$crate::errors::ServiceResult<$ok>> for $name<$($param),*> {
+            fn from(result: $crate::errors::ServiceResult<$ok>) -> Self {
+                $name::new(result)
+            }
+        }
+
+        impl<$($param),*> From<$crate::errors::ActorResult<$ok>> for $name<$($param),*> {
+            fn from(result: $crate::errors::ActorResult<$ok>) -> Self {
+                match result {
+                    Ok(value) => $name::Ok(value),
+                    Err(err) => $name::Err(err),
+                }
+            }
+        }
+
+        impl<$($param),*> From<$name<$($param),*>> for $crate::errors::ActorResult<$ok> {
+            fn from(response: $name<$($param),*>) -> Self {
+                match response {
+                    $name::Ok(value) => Ok(value),
+                    $name::Err(err) => Err(err),
+                }
+            }
+        }
+    };
+    ($(#[$meta:meta])* $vis:vis $name:ident, $ok:ty) => {
+        $crate::actor_response!(@define $(#[$meta])* $vis $name [] $ok);
+    };
+    ($(#[$meta:meta])* $vis:vis $name:ident<$($param:ident),+>, $ok:ty) => {
+        $crate::actor_response!(@define $(#[$meta])* $vis $name [$($param),+] $ok);
+    };
+}
+
+actor_response!(pub BooleanActorResponse, bool);
@@ -0,0 +1,185 @@
+use rstest::*;
+
+use super::*;
+use crate::test_common::test::init_test;
+
+#[fixture]
+pub fn setup() {
+    init_test();
+}
+
+#[derive(Debug)]
+enum LedgerError {
+    InsufficientFunds { balance: u64 },
+    TxTooOld,
+}
+
+impl Display for LedgerError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            LedgerError::InsufficientFunds { balance } => {
+                write!(f, "insufficient funds, balance {}", balance)
+            }
+            LedgerError::TxTooOld => write!(f, "transaction too old"),
+        }
+    }
+}
+
+impl DomainError for LedgerError {
+    fn code(&self) -> u32 {
+        match self {
+            LedgerError::InsufficientFunds { .. } => 1001,
+            LedgerError::TxTooOld => 1002,
+        }
+    }
+
+    fn category(&self) -> ErrorCategory {
+        ErrorCategory::Client
+    }
+
+    fn details(&self) -> Vec<ErrorDetail> {
+        match self {
+            LedgerError::InsufficientFunds { balance } => {
+                vec![ErrorDetail::new("balance", balance)]
+            }
+            LedgerError::TxTooOld => vec![],
+        }
+    }
+}
+
+fn ledger_transfer(balance: u64) -> ServiceResult<()> {
+    Err(LedgerError::InsufficientFunds { balance })?
+}
+
+#[rstest]
+fn test_domain_error_to_error_info(_setup: ()) {
+    let error = ledger_transfer(5).unwrap_err();
+    assert_eq!(error.code(), 1001);
+    assert_eq!(error.category(), ErrorCategory::Client);
+
+    let info = ErrorInfo::from(error);
+    assert_eq!(info.code, 1001);
+    assert_eq!(info.message, "insufficient funds, balance 5");
+    assert_eq!(info.category, Some(ErrorCategory::Client));
+    assert_eq!(info.details, Some(vec![ErrorDetail::new("balance", 5)]));
+}
+
+#[rstest]
+fn test_common_error_to_error_info(_setup: ()) {
+    let info = ErrorInfo::from(CommonError::ValueShouldBeInRangeError {
+        field: "limit".to_string(),
+        min: 1,
+        max: 100,
+    });
+    assert_eq!(info.code, 5);
+    assert_eq!(info.category, Some(ErrorCategory::Client));
+    assert_eq!(info.details.unwrap().len(), 3);
+
+    let info = ErrorInfo::from(CommonError::Unauthorized);
+    assert_eq!(info.category, Some(ErrorCategory::Auth));
+    assert_eq!(info.details, None);
+}
+
+#[rstest]
+fn test_cause_chain_through_remote_errors(_setup: ()) {
+    // the ledger fails, the wallet canister passes it on, and this canister returns it
+    let ledger: ErrorInfo = CommonError::from(LedgerError::TxTooOld).into();
+    let wallet: ErrorInfo = CommonError::from(ledger.clone()).into();
+    let info: ErrorInfo = CommonError::from(wallet.clone()).into();
+
+    assert_eq!(info.code, 2);
+    assert_eq!(info.category, Some(ErrorCategory::Remote));
+    let codes: Vec<u32> = info.chain().iter().map(|e| e.code).collect();
+    assert_eq!(codes, vec![2, 2, 1002]);
+    assert_eq!(info.root_cause(), &ledger);
+}
+
+#[rstest]
+fn test_error_info_decodes_without_new_fields(_setup: ()) {
+    #[derive(CandidType)]
+    struct LegacyErrorInfo {
+        code: u32,
+        message: String,
+    }
+    let bytes = candid::encode_one(LegacyErrorInfo {
+        code: 3,
+        message: "Unauthorized".to_string(),
+    })
+    .unwrap();
+
+    let info: ErrorInfo = candid::decode_one(&bytes).unwrap();
+    assert_eq!(info, ErrorInfo::new(3, "Unauthorized"));
+}
+
+mod error_catalog {
+    use super::*;
+
+    fn ledger_entries() -> Vec<ErrorCatalogEntry> {
+        vec![
+            ErrorCatalogEntry::new(
+                1001,
+                "InsufficientFunds",
+                ErrorCategory::Client,
+                "balance too low",
+            ),
+            ErrorCatalogEntry::new(1002, "TxTooOld", ErrorCategory::Client, "expired"),
+        ]
+    }
+
+    #[rstest]
+    fn test_register(_setup: ()) {
+        register_error_catalog(ledger_entries()).unwrap();
+        // registering again, e.g. in post_upgrade, is fine
+        register_error_catalog(ledger_entries()).unwrap();
+
+        let catalog = get_error_catalog();
+        assert!(catalog.iter().any(|e| e.code == 5));
+        assert!(catalog.iter().any(|e| e.name == "TxTooOld"));
+    }
+
+    #[rstest]
+    fn test_conflicting_code_is_rejected(_setup: ()) {
+        register_error_catalog(ledger_entries()).unwrap();
+        let result = register_error_catalog(vec![ErrorCatalogEntry::new(
+            1001,
+            "OtherError",
+            ErrorCategory::Internal,
+            "",
+        )]);
+        assert!(matches!(result, Err(CommonError::InvalidState { .. })));
+    }
+
+    #[rstest]
+    #[case(8)]
+    #[case(10000)]
+    fn test_reserved_code_is_rejected(_setup: (), #[case] code: u32) {
+        let result = register_error_catalog(vec![ErrorCatalogEntry::new(
+            code,
+            "Reserved",
+            ErrorCategory::Internal,
+            "",
+        )]);
+        assert!(result.is_err());
+    }
+}
+
+mod actor_response {
+    use super::*;
+
+    crate::actor_response!(GetNameResponse, String);
+    crate::actor_response!(GetItemResponse<T>, Vec<T>);
+
+    #[rstest]
+    fn test_conversions(_setup: ()) {
+        let response: GetNameResponse = ServiceResult::Ok("name".to_string()).into();
+        assert!(matches!(&response, GetNameResponse::Ok(name) if name == "name"));
+
+        let response = GetNameResponse::new(Err(CommonError::PermissionDenied));
+        let result: ActorResult<String> = response.into();
+        assert_eq!(result.unwrap_err().code, 4);
+
+        let response: GetItemResponse<u8> = ActorResult::Ok(vec![1, 2]).into();
+        let result: ActorResult<Vec<u8>> = response.into();
+        assert_eq!(result, Ok(vec![1, 2]));
+    }
+}
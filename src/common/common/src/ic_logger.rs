@@ -1,41 +1,425 @@
-use crate::constants::{is_dev_env, COMMON_CANISTER_ENV};
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::fmt::{Display, Formatter};
+use std::panic::{self, PanicInfo};
+use std::str::FromStr;
+
+use candid::{decode_args, encode_args, CandidType, Deserialize};
 use ic_cdk::api;
+use log::kv::{self, Key, Value};
 use log::{info, Level, LevelFilter, Metadata, Record};
-use std::panic;
+use serde_json::json;
 use yansi::Paint;
 
+use crate::config::get_config;
+use crate::constants::{is_dev_env, COMMON_CANISTER_ENV};
+use crate::crash_log::{current_method, CrashRecord};
+use crate::dto::{GetPageInput, GetPageOutput};
+use crate::errors::{CommonError, ServiceResult};
 use crate::named_canister_ids::{update_current_canister_name, NAMED_CANISTER_IDS};
+use crate::pagination::paginate_iter;
+use crate::state::StableState;
+use crate::types::TimeInNs;
+
+#[cfg(test)]
+mod tests;
+
+pub const LOG_BUFFER_CAPACITY: usize = 2_000;
+/// longer messages are truncated, so a single record can not take a large part of the buffer
+pub const LOG_MESSAGE_MAX_LEN: usize = 1_000;
+
+thread_local! {
+    pub static LOG_BUFFER: RefCell<LogBuffer> = RefCell::new(LogBuffer::default());
+}
+
+#[derive(CandidType, Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LogLevel {
+    Off,
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl LogLevel {
+    /// Level a wasm starts with before it is changed with `set_log_level`.
+    pub fn default_for_env() -> Self {
+        if is_dev_env() {
+            LogLevel::Trace
+        } else {
+            LogLevel::Info
+        }
+    }
+}
+
+impl FromStr for LogLevel {
+    type Err = CommonError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "off" => Ok(LogLevel::Off),
+            "error" => Ok(LogLevel::Error),
+            "warn" => Ok(LogLevel::Warn),
+            "info" => Ok(LogLevel::Info),
+            "debug" => Ok(LogLevel::Debug),
+            "trace" => Ok(LogLevel::Trace),
+            _ => Err(CommonError::InvalidArgument {
+                field: "level".to_string(),
+                detail: format!("invalid log level {:?}", s),
+            }),
+        }
+    }
+}
+
+impl Display for LogLevel {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            LogLevel::Off => "off",
+            LogLevel::Error => "error",
+            LogLevel::Warn => "warn",
+            LogLevel::Info => "info",
+            LogLevel::Debug => "debug",
+            LogLevel::Trace => "trace",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl From<Level> for LogLevel {
+    fn from(level: Level) -> Self {
+        match level {
+            Level::Error => LogLevel::Error,
+            Level::Warn => LogLevel::Warn,
+            Level::Info => LogLevel::Info,
+            Level::Debug => LogLevel::Debug,
+            Level::Trace => LogLevel::Trace,
+        }
+    }
+}
+
+impl From<LogLevel> for LevelFilter {
+    fn from(level: LogLevel) -> Self {
+        match level {
+            LogLevel::Off => LevelFilter::Off,
+            LogLevel::Error => LevelFilter::Error,
+            LogLevel::Warn => LevelFilter::Warn,
+            LogLevel::Info => LevelFilter::Info,
+            LogLevel::Debug => LevelFilter::Debug,
+            LogLevel::Trace => LevelFilter::Trace,
+        }
+    }
+}
+
+/// One `target=level` or bare `level` part of a filter.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LogDirective {
+    pub target: Option<String>,
+    pub level: LogLevel,
+}
+
+/// env_logger style filter such as `common::canister_api=debug,info`.
+/// A record is enabled by the directive with the longest target prefixing its target,
+/// or by the bare level when no target matches; without either it is disabled.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LogFilter {
+    directives: Vec<LogDirective>,
+}
+
+impl LogFilter {
+    pub fn new(level: LogLevel) -> Self {
+        LogFilter {
+            directives: vec![LogDirective {
+                target: None,
+                level,
+            }],
+        }
+    }
+
+    pub fn parse(spec: &str) -> ServiceResult<Self> {
+        let mut directives = vec![];
+        for part in spec.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+            let directive = match part.split_once('=') {
+                Some((target, level)) => {
+                    let target = target.trim();
+                    if target.is_empty() {
+                        return Err(CommonError::InvalidArgument {
+                            field: "filter".to_string(),
+                            detail: format!("empty log target in {:?}", part),
+                        });
+                    }
+                    LogDirective {
+                        target: Some(target.to_string()),
+                        level: level.parse()?,
+                    }
+                }
+                None => LogDirective {
+                    target: None,
+                    level: part.parse()?,
+                },
+            };
+            // a later directive for the same target replaces the earlier one
+            directives.retain(|d: &LogDirective| d.target != directive.target);
+            directives.push(directive);
+        }
+        if directives.is_empty() {
+            return Err(CommonError::InvalidArgument {
+                field: "filter".to_string(),
+                detail: "the log filter is empty, use \"off\" to turn logging off".to_string(),
+            });
+        }
+        Ok(LogFilter { directives })
+    }
+
+    pub fn level_for(&self, target: &str) -> LogLevel {
+        self.directives
+            .iter()
+            .filter(|d| {
+                d.target
+                    .as_ref()
+                    .map_or(true, |prefix| target.starts_with(prefix.as_str()))
+            })
+            .max_by_key(|d| d.target.as_ref().map(|prefix| prefix.len()))
+            .map_or(LogLevel::Off, |d| d.level)
+    }
+
+    pub fn enabled(&self, target: &str, level: Level) -> bool {
+        LogLevel::from(level) <= self.level_for(target)
+    }
+
+    /// Most verbose level of any directive, the global `log` max level must not filter below it.
+    pub fn max_level(&self) -> LogLevel {
+        self.directives
+            .iter()
+            .map(|d| d.level)
+            .max()
+            .unwrap_or(LogLevel::Off)
+    }
+}
+
+impl Display for LogFilter {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let parts: Vec<String> = self
+            .directives
+            .iter()
+            .map(|d| match &d.target {
+                Some(target) => format!("{}={}", target, d.level),
+                None => d.level.to_string(),
+            })
+            .collect();
+        write!(f, "{}", parts.join(","))
+    }
+}
+
+/// How records are printed with `api::print`, the buffer keeps them regardless.
+#[derive(CandidType, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LogFormat {
+    Off,
+    /// ANSI colored text, for a terminal running dfx
+    Colored,
+    Plain,
+    /// one JSON object per line
+    Json,
+}
+
+impl LogFormat {
+    pub fn default_for_env() -> Self {
+        if is_dev_env() {
+            LogFormat::Colored
+        } else {
+            LogFormat::Off
+        }
+    }
+
+    pub fn format(&self, record: &LogRecord) -> Option<String> {
+        let text = || {
+            let mut line = format!(
+                "{}, {}: {} - {}",
+                record.canister_name,
+                record.target,
+                record.level.to_string().to_uppercase(),
+                record.message
+            );
+            for field in record.fields.iter() {
+                line.push_str(&format!(" {}={}", field.key, field.value));
+            }
+            line
+        };
+        match self {
+            LogFormat::Off => None,
+            LogFormat::Plain => Some(text()),
+            LogFormat::Colored => {
+                let line = text();
+                let painted = match record.level {
+                    LogLevel::Error => Paint::red(line),
+                    LogLevel::Warn => Paint::yellow(line),
+                    LogLevel::Info => Paint::blue(line),
+                    LogLevel::Debug => Paint::green(line),
+                    LogLevel::Trace | LogLevel::Off => Paint::magenta(line),
+                };
+                Some(painted.to_string())
+            }
+            LogFormat::Json => {
+                let fields: serde_json::Map<String, serde_json::Value> = record
+                    .fields
+                    .iter()
+                    .map(|f| (f.key.clone(), json!(f.value)))
+                    .collect();
+                Some(
+                    json!({
+                        "timestamp": record.timestamp.0,
+                        "level": record.level.to_string(),
+                        "target": record.target,
+                        "canister": record.canister_name,
+                        "message": record.message,
+                        "fields": fields,
+                    })
+                    .to_string(),
+                )
+            }
+        }
+    }
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct LogField {
+    pub key: String,
+    pub value: String,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct LogRecord {
+    pub timestamp: TimeInNs,
+    pub level: LogLevel,
+    pub target: String,
+    pub canister_name: String,
+    pub message: String,
+    /// structured key-values, e.g. `info!(canister_id = id.to_text(); "installed")`
+    pub fields: Vec<LogField>,
+}
+
+struct FieldCollector(Vec<LogField>);
+
+impl<'kvs> kv::Visitor<'kvs> for FieldCollector {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), kv::Error> {
+        self.0.push(LogField {
+            key: key.as_str().to_string(),
+            value: value.to_string(),
+        });
+        Ok(())
+    }
+}
+
+/// The latest log records of this canister, the oldest record is dropped when the buffer is full.
+#[derive(Clone, Debug)]
+pub struct LogBuffer {
+    pub filter: LogFilter,
+    pub format: LogFormat,
+    pub capacity: usize,
+    records: VecDeque<LogRecord>,
+}
+
+impl Default for LogBuffer {
+    fn default() -> Self {
+        LogBuffer {
+            filter: LogFilter::new(LogLevel::default_for_env()),
+            format: LogFormat::default_for_env(),
+            capacity: LOG_BUFFER_CAPACITY,
+            records: VecDeque::new(),
+        }
+    }
+}
+
+impl LogBuffer {
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+
+    pub fn push(&mut self, mut record: LogRecord) {
+        if record.message.len() > LOG_MESSAGE_MAX_LEN {
+            let mut end = LOG_MESSAGE_MAX_LEN;
+            while !record.message.is_char_boundary(end) {
+                end -= 1;
+            }
+            record.message.truncate(end);
+        }
+        while self.records.len() >= self.capacity && !self.records.is_empty() {
+            self.records.pop_front();
+        }
+        if self.capacity > 0 {
+            self.records.push_back(record);
+        }
+    }
+
+    /// Newest records first, only records at least as severe as `level` when it is given.
+    pub fn get_page(
+        &self,
+        page: &GetPageInput,
+        level: Option<LogLevel>,
+    ) -> ServiceResult<GetPageOutput<LogRecord>> {
+        let records = self
+            .records
+            .iter()
+            .rev()
+            .filter(|r| level.map_or(true, |level| r.level <= level));
+        Ok(paginate_iter(records, page)?.map(LogRecord::clone))
+    }
+}
+
+impl StableState for LogBuffer {
+    fn encode(&self) -> Vec<u8> {
+        let records: Vec<&LogRecord> = self.records.iter().collect();
+        encode_args((self.filter.to_string(), self.format, self.capacity, records)).unwrap()
+    }
+
+    fn decode(bytes: Vec<u8>) -> Result<Self, String> {
+        let (filter, format, capacity, records): (String, LogFormat, usize, Vec<LogRecord>) =
+            decode_args(&bytes).map_err(|e| format!("{:?}", e))?;
+        Ok(LogBuffer {
+            filter: LogFilter::parse(&filter).map_err(|e| e.to_string())?,
+            format,
+            capacity,
+            records: records.into(),
+        })
+    }
+}
 
 pub struct ICLogger;
 
 impl log::Log for ICLogger {
-    fn enabled(&self, _metadata: &Metadata) -> bool {
-        true
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        LOG_BUFFER.with(|b| match b.try_borrow() {
+            Ok(buffer) => buffer.filter.enabled(metadata.target(), metadata.level()),
+            Err(_) => false,
+        })
     }
 
     fn log(&self, record: &Record) {
         if self.enabled(record.metadata()) {
-            let level = record.level();
-            let message = NAMED_CANISTER_IDS.with(|n| {
-                let n = n.borrow();
-                let name = n.current_name.as_str();
-                format!(
-                    "{}, {}: {} - {}",
-                    name,
-                    record.target(),
-                    level,
-                    record.args()
-                )
-            });
-
-            let str = match level {
-                Level::Error => Paint::red(message),
-                Level::Warn => Paint::yellow(message),
-                Level::Info => Paint::blue(message),
-                Level::Debug => Paint::green(message),
-                Level::Trace => Paint::magenta(message),
+            let mut fields = FieldCollector(vec![]);
+            let _ = record.key_values().visit(&mut fields);
+            let log_record = LogRecord {
+                timestamp: TimeInNs(api::time()),
+                level: record.level().into(),
+                target: record.target().to_string(),
+                canister_name: NAMED_CANISTER_IDS.with(|n| n.borrow().current_name.clone()),
+                message: record.args().to_string(),
+                fields: fields.0,
             };
-            api::print(str.to_string());
+
+            // a record logged while the buffer is borrowed, e.g. during restore, is dropped
+            LOG_BUFFER.with(|b| {
+                if let Ok(mut buffer) = b.try_borrow_mut() {
+                    if let Some(line) = buffer.format.format(&log_record) {
+                        api::print(line);
+                    }
+                    buffer.push(log_record);
+                }
+            });
         }
     }
 
@@ -45,13 +429,73 @@ impl log::Log for ICLogger {
 impl ICLogger {
     pub fn init(current_name: &str) {
         update_current_canister_name(current_name);
-        if is_dev_env() && log::set_logger(&ICLogger).is_ok() {
-            log::set_max_level(LevelFilter::Trace);
-            panic::set_hook(Box::new(|data| {
-                let message = format!("{}", data);
-                api::print(Paint::red(message).to_string());
-            }));
+        if log::set_logger(&ICLogger).is_ok() {
+            log::set_max_level(LOG_BUFFER.with(|b| b.borrow().filter.max_level()).into());
+            panic::set_hook(Box::new(panic_hook));
             info!("current wasm is a {} package", COMMON_CANISTER_ENV);
         }
     }
 }
+
+/// Prints the panic and traps with it so the caller sees the reason. The trap discards every
+/// state change since the last await, stable memory included, so the printed line is the only
+/// trace of the message; the crash log records the calls left open by it, see `enter_update`.
+fn panic_hook(info: &PanicInfo) {
+    let name = NAMED_CANISTER_IDS
+        .with(|n| n.try_borrow().map(|n| n.current_name.clone()))
+        .unwrap_or_default();
+    let record = CrashRecord::from_panic(info, name, current_method(), TimeInNs(api::time()));
+    let message = record.to_string();
+    if is_dev_env() {
+        api::print(Paint::red(&message).to_string());
+    } else {
+        api::print(&message);
+    }
+    api::trap(&message);
+}
+
+fn set_log_filter_inner(filter: LogFilter) {
+    let max_level = filter.max_level();
+    LOG_BUFFER.with(|b| b.borrow_mut().filter = filter);
+    log::set_max_level(max_level.into());
+}
+
+/// Applies `level` to every target.
+pub fn set_log_level(level: LogLevel) {
+    set_log_filter_inner(LogFilter::new(level));
+}
+
+/// Replaces the filter with env_logger style directives, e.g. `common::canister_api=debug,info`.
+pub fn set_log_filter(spec: &str) -> ServiceResult<()> {
+    set_log_filter_inner(LogFilter::parse(spec)?);
+    Ok(())
+}
+
+pub fn get_log_filter() -> String {
+    LOG_BUFFER.with(|b| b.borrow().filter.to_string())
+}
+
+pub fn set_log_format(format: LogFormat) {
+    LOG_BUFFER.with(|b| b.borrow_mut().format = format);
+}
+
+pub fn get_log_format() -> LogFormat {
+    LOG_BUFFER.with(|b| b.borrow().format)
+}
+
+/// Replaces the buffer, e.g. after an upgrade, and applies its filter to the logger.
+pub fn restore_log_buffer(buffer: LogBuffer) {
+    let max_level = buffer.filter.max_level();
+    LOG_BUFFER.with(|b| b.replace(buffer));
+    log::set_max_level(max_level.into());
+}
+
+pub fn get_logs(
+    page: &GetPageInput,
+    level: Option<LogLevel>,
+) -> ServiceResult<GetPageOutput<LogRecord>> {
+    page.validate(get_config().max_page_limit)?;
+    LOG_BUFFER.with(|b| b.borrow().get_page(page, level))
+}
+
+crate::actor_response!(pub GetLogsResponse, GetPageOutput<LogRecord>);
@@ -0,0 +1,203 @@
+use rstest::*;
+
+use super::*;
+use crate::errors::CommonError;
+use crate::test_common::test::init_test;
+
+#[fixture]
+pub fn setup() {
+    init_test();
+}
+
+fn record(i: u64, level: LogLevel) -> LogRecord {
+    LogRecord {
+        timestamp: TimeInNs(i),
+        level,
+        target: "common::ic_logger".to_string(),
+        canister_name: "test".to_string(),
+        message: format!("message {}", i),
+        fields: vec![],
+    }
+}
+
+fn buffer(capacity: usize) -> LogBuffer {
+    LogBuffer {
+        filter: LogFilter::new(LogLevel::Trace),
+        format: LogFormat::Off,
+        capacity,
+        records: VecDeque::new(),
+    }
+}
+
+#[rstest]
+fn test_oldest_record_is_dropped(_setup: ()) {
+    let mut buffer = buffer(3);
+    for i in 0..5 {
+        buffer.push(record(i, LogLevel::Info));
+    }
+
+    assert_eq!(buffer.len(), 3);
+    let page = buffer
+        .get_page(
+            &GetPageInput {
+                offset: 0,
+                limit: 10,
+                ..Default::default()
+            },
+            None,
+        )
+        .unwrap();
+    let timestamps: Vec<u64> = page.items.iter().map(|r| r.timestamp.0).collect();
+    assert_eq!(timestamps, vec![4, 3, 2]);
+}
+
+#[rstest]
+fn test_get_page_filters_by_level(_setup: ()) {
+    let mut buffer = buffer(10);
+    buffer.push(record(0, LogLevel::Error));
+    buffer.push(record(1, LogLevel::Debug));
+    buffer.push(record(2, LogLevel::Warn));
+    buffer.push(record(3, LogLevel::Info));
+
+    let page = buffer
+        .get_page(
+            &GetPageInput {
+                offset: 1,
+                limit: 1,
+                ..Default::default()
+            },
+            Some(LogLevel::Warn),
+        )
+        .unwrap();
+
+    assert_eq!(page.items, vec![record(0, LogLevel::Error)]);
+}
+
+#[rstest]
+fn test_get_logs_validates_input(_setup: ()) {
+    let result = get_logs(
+        &GetPageInput {
+            offset: 0,
+            limit: 0,
+            ..Default::default()
+        },
+        None,
+    );
+    assert!(matches!(
+        result,
+        Err(CommonError::ValueShouldBeInRangeError { .. })
+    ));
+}
+
+#[rstest]
+fn test_long_message_is_truncated(_setup: ()) {
+    let mut buffer = buffer(10);
+    let mut long = record(0, LogLevel::Info);
+    long.message = "é".repeat(LOG_MESSAGE_MAX_LEN);
+    buffer.push(long);
+
+    let page = buffer
+        .get_page(
+            &GetPageInput {
+                offset: 0,
+                limit: 1,
+                ..Default::default()
+            },
+            None,
+        )
+        .unwrap();
+    assert!(page.items[0].message.len() <= LOG_MESSAGE_MAX_LEN);
+}
+
+#[rstest]
+fn test_encode_decode(_setup: ()) {
+    let mut buffer = buffer(10);
+    buffer.filter = LogFilter::parse("common::dto=debug,warn").unwrap();
+    buffer.format = LogFormat::Json;
+    buffer.push(record(0, LogLevel::Error));
+
+    let decoded = LogBuffer::decode(buffer.encode()).unwrap();
+
+    assert_eq!(decoded.filter, buffer.filter);
+    assert_eq!(decoded.format, LogFormat::Json);
+    assert_eq!(decoded.capacity, 10);
+    assert_eq!(decoded.len(), 1);
+}
+
+mod log_filter {
+    use super::*;
+
+    #[rstest]
+    fn test_longest_target_wins(_setup: ()) {
+        let filter = LogFilter::parse("common::canister_api=debug, common=warn ,info").unwrap();
+
+        assert_eq!(
+            filter.level_for("common::canister_api::ic_impl"),
+            LogLevel::Debug
+        );
+        assert_eq!(filter.level_for("common::dto"), LogLevel::Warn);
+        assert_eq!(filter.level_for("nat_test"), LogLevel::Info);
+        assert!(filter.enabled("common::canister_api", Level::Debug));
+        assert!(!filter.enabled("common::dto", Level::Info));
+        assert_eq!(filter.max_level(), LogLevel::Debug);
+    }
+
+    #[rstest]
+    fn test_without_default_unmatched_targets_are_off(_setup: ()) {
+        let filter = LogFilter::parse("common=trace").unwrap();
+        assert_eq!(filter.level_for("nat_test"), LogLevel::Off);
+    }
+
+    #[rstest]
+    fn test_display_round_trip(_setup: ()) {
+        let filter = LogFilter::parse("common=TRACE,common=debug,warn").unwrap();
+        assert_eq!(filter.to_string(), "common=debug,warn");
+        assert_eq!(LogFilter::parse(&filter.to_string()).unwrap(), filter);
+    }
+
+    #[rstest]
+    #[case("common=loud")]
+    #[case("=info")]
+    #[case("verbose")]
+    #[case("")]
+    #[case(" , ")]
+    fn test_invalid_spec(_setup: (), #[case] spec: &str) {
+        assert!(LogFilter::parse(spec).is_err());
+    }
+}
+
+mod log_format {
+    use super::*;
+
+    fn record_with_fields() -> LogRecord {
+        let mut record = record(7, LogLevel::Warn);
+        record.fields = vec![LogField {
+            key: "canister_id".to_string(),
+            value: "aaaaa-aa".to_string(),
+        }];
+        record
+    }
+
+    #[rstest]
+    fn test_plain(_setup: ()) {
+        assert_eq!(
+            LogFormat::Plain.format(&record_with_fields()),
+            Some("test, common::ic_logger: WARN - message 7 canister_id=aaaaa-aa".to_string())
+        );
+        assert_eq!(LogFormat::Off.format(&record_with_fields()), None);
+    }
+
+    #[rstest]
+    fn test_json(_setup: ()) {
+        let line = LogFormat::Json.format(&record_with_fields()).unwrap();
+        let value: serde_json::Value = serde_json::from_str(&line).unwrap();
+
+        assert!(!line.contains('\n'));
+        assert_eq!(value["timestamp"], 7);
+        assert_eq!(value["level"], "warn");
+        assert_eq!(value["target"], "common::ic_logger");
+        assert_eq!(value["canister"], "test");
+        assert_eq!(value["message"], "message 7");
+        assert_eq!(value["fields"]["canister_id"], "aaaaa-aa");
+    }
+}
@@ -0,0 +1,138 @@
+use std::collections::HashMap;
+
+use candid::Principal;
+
+use crate::errors::{CommonError, ServiceResult};
+use crate::permissions::{must_be_named_principal, must_be_system_owner, must_not_anonymous};
+
+#[cfg(test)]
+mod tests;
+
+/// 64 KiB, enough for every argument except state and wasm uploads.
+pub const INSPECT_DEFAULT_MAX_ARG_SIZE: usize = 64 * 1024;
+
+/// Who may call a method, checked with the rules of `permissions`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MethodAccess {
+    Public,
+    /// any caller except the anonymous principal
+    Authenticated,
+    SystemOwner,
+    NamedPrincipal(&'static str),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MethodRule {
+    pub access: MethodAccess,
+    /// `None` for the policy default
+    pub max_arg_size: Option<usize>,
+    /// admin methods stay callable so maintenance mode can be switched off again
+    pub allowed_in_maintenance: bool,
+}
+
+impl MethodRule {
+    pub fn new(access: MethodAccess) -> Self {
+        MethodRule {
+            access,
+            max_arg_size: None,
+            allowed_in_maintenance: false,
+        }
+    }
+
+    pub fn max_arg_size(mut self, size: usize) -> Self {
+        self.max_arg_size = Some(size);
+        self
+    }
+
+    pub fn allowed_in_maintenance(mut self) -> Self {
+        self.allowed_in_maintenance = true;
+        self
+    }
+}
+
+fn check_rule_maintenance(rule: &MethodRule, maintenance_mode: bool) -> ServiceResult<()> {
+    if maintenance_mode && !rule.allowed_in_maintenance {
+        return Err(CommonError::UnderMaintenance);
+    }
+    Ok(())
+}
+
+/// Allowlist of the methods a canister accepts ingress calls for, checked in
+/// `canister_inspect_message` so a call which would be refused anyway is rejected before it
+/// consumes cycles. Inter-canister calls are not inspected, endpoints keep their own access
+/// checks and call `check_maintenance` with the same policy.
+#[derive(Clone, Debug)]
+pub struct InspectPolicy {
+    rules: HashMap<&'static str, MethodRule>,
+    default_max_arg_size: usize,
+}
+
+impl Default for InspectPolicy {
+    fn default() -> Self {
+        InspectPolicy {
+            rules: HashMap::new(),
+            default_max_arg_size: INSPECT_DEFAULT_MAX_ARG_SIZE,
+        }
+    }
+}
+
+impl InspectPolicy {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn method(mut self, name: &'static str, rule: MethodRule) -> Self {
+        self.rules.insert(name, rule);
+        self
+    }
+
+    pub fn default_max_arg_size(mut self, size: usize) -> Self {
+        self.default_max_arg_size = size;
+        self
+    }
+
+    pub fn rule(&self, method: &str) -> Option<&MethodRule> {
+        self.rules.get(method)
+    }
+
+    /// The maintenance part of `inspect`, for endpoints which are also called by canisters.
+    /// An unlisted method is not allowed in maintenance.
+    pub fn check_maintenance(&self, method: &str, maintenance_mode: bool) -> ServiceResult<()> {
+        match self.rules.get(method) {
+            Some(rule) => check_rule_maintenance(rule, maintenance_mode),
+            None if maintenance_mode => Err(CommonError::UnderMaintenance),
+            None => Ok(()),
+        }
+    }
+
+    pub fn inspect(
+        &self,
+        method: &str,
+        caller: &Principal,
+        arg_size: usize,
+        maintenance_mode: bool,
+    ) -> ServiceResult<()> {
+        let rule = self
+            .rules
+            .get(method)
+            .ok_or_else(|| CommonError::InvalidArgument {
+                field: "method".to_string(),
+                detail: format!("{} is not accepted as an ingress call", method),
+            })?;
+        check_rule_maintenance(rule, maintenance_mode)?;
+        let max_arg_size = rule.max_arg_size.unwrap_or(self.default_max_arg_size);
+        if arg_size > max_arg_size {
+            return Err(CommonError::ValueShouldBeInRangeError {
+                field: "args".to_string(),
+                min: 0,
+                max: max_arg_size,
+            });
+        }
+        match rule.access {
+            MethodAccess::Public => Ok(()),
+            MethodAccess::Authenticated => must_not_anonymous(caller).map(|_| ()),
+            MethodAccess::SystemOwner => must_be_system_owner(caller),
+            MethodAccess::NamedPrincipal(name) => must_be_named_principal(caller, name),
+        }
+    }
+}
@@ -0,0 +1,108 @@
+use rstest::*;
+
+use super::*;
+use crate::constants::envs::COMMON_PRINCIPAL_NAME_ADMIN;
+use crate::named_principals::PRINCIPAL_NAME_TIMER_TRIGGER;
+use crate::test_common::test::init_test;
+
+#[fixture]
+pub fn setup() {
+    init_test();
+}
+
+fn policy() -> InspectPolicy {
+    InspectPolicy::new()
+        .default_max_arg_size(100)
+        .method("get_stats", MethodRule::new(MethodAccess::Public))
+        .method("transfer", MethodRule::new(MethodAccess::Authenticated))
+        .method(
+            "set_config",
+            MethodRule::new(MethodAccess::SystemOwner).allowed_in_maintenance(),
+        )
+        .method(
+            "load_state",
+            MethodRule::new(MethodAccess::SystemOwner)
+                .max_arg_size(1000)
+                .allowed_in_maintenance(),
+        )
+        .method(
+            "trigger_jobs",
+            MethodRule::new(MethodAccess::NamedPrincipal(PRINCIPAL_NAME_TIMER_TRIGGER)),
+        )
+}
+
+fn admin() -> Principal {
+    COMMON_PRINCIPAL_NAME_ADMIN[0]
+}
+
+fn user() -> Principal {
+    Principal::from_slice(&[7, 7, 7])
+}
+
+#[rstest]
+fn test_unlisted_method_is_rejected(_setup: ()) {
+    assert!(matches!(
+        policy().inspect("drop_all", &admin(), 0, false),
+        Err(CommonError::InvalidArgument { field, .. }) if field == "method"
+    ));
+}
+
+#[rstest]
+#[case("get_stats", Principal::anonymous(), true)]
+#[case("transfer", Principal::anonymous(), false)]
+#[case("transfer", user(), true)]
+#[case("set_config", user(), false)]
+#[case("set_config", admin(), true)]
+#[case("trigger_jobs", admin(), false)]
+fn test_access(
+    _setup: (),
+    #[case] method: &str,
+    #[case] caller: Principal,
+    #[case] accepted: bool,
+) {
+    assert_eq!(
+        policy().inspect(method, &caller, 0, false).is_ok(),
+        accepted
+    );
+}
+
+#[rstest]
+#[case("transfer", 101, false)]
+#[case("transfer", 100, true)]
+#[case("load_state", 1000, true)]
+fn test_arg_size(_setup: (), #[case] method: &str, #[case] size: usize, #[case] accepted: bool) {
+    let caller = admin();
+    assert_eq!(
+        policy().inspect(method, &caller, size, false).is_ok(),
+        accepted
+    );
+}
+
+#[rstest]
+fn test_maintenance_mode(_setup: ()) {
+    let policy = policy();
+    assert_eq!(
+        policy.inspect("transfer", &user(), 0, true),
+        Err(CommonError::UnderMaintenance)
+    );
+    assert!(policy.inspect("set_config", &admin(), 0, true).is_ok());
+    assert_eq!(
+        policy.inspect("get_stats", &Principal::anonymous(), 0, true),
+        Err(CommonError::UnderMaintenance)
+    );
+}
+
+#[rstest]
+fn test_check_maintenance(_setup: ()) {
+    let policy = policy();
+    assert_eq!(
+        policy.check_maintenance("trigger_jobs", true),
+        Err(CommonError::UnderMaintenance)
+    );
+    assert_eq!(policy.check_maintenance("set_config", true), Ok(()));
+    assert_eq!(
+        policy.check_maintenance("unlisted", true),
+        Err(CommonError::UnderMaintenance)
+    );
+    assert_eq!(policy.check_maintenance("trigger_jobs", false), Ok(()));
+}
@@ -7,18 +7,33 @@ use ic_cdk::api;
 use std::fmt::{Display, Formatter};
 use std::ops::{Add, Sub};
 
+pub mod audit_log;
+pub mod backup;
+pub mod canister_factory;
+pub mod config;
 pub mod constants;
+pub mod crash_log;
+pub mod dedup;
 pub mod dto;
+pub mod ecdsa_signer;
 pub mod errors;
 pub mod http;
 pub mod ic_logger;
+pub mod inspect;
 pub mod metrics_encoder;
 pub mod named_canister_ids;
 pub mod named_principals;
+pub mod pagination;
 pub mod permissions;
+pub mod proposals;
+pub mod rate_limit;
+pub mod saga;
+pub mod scheduler;
+pub mod schnorr;
 pub mod state;
 pub mod timeout_lock;
 pub mod types;
+pub mod wasm_info;
 
 pub mod canister_api;
 #[cfg(test)]
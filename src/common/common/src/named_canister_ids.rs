@@ -1,76 +1,185 @@
-use candid::{CandidType, Deserialize};
+use candid::{decode_args, encode_args, CandidType, Deserialize};
 use std::cell::RefCell;
-use std::collections::HashMap;
+use std::collections::BTreeMap;
 use std::fmt::{Display, Formatter};
-use std::ops::Deref;
 
-use crate::constants::*;
+use crate::constants::is_dev_env;
+use crate::errors::{CommonError, ServiceResult};
+use crate::state::StableState;
+use crate::types::ic_ledger_types::{
+    MAINNET_CYCLES_MINTING_CANISTER_ID, MAINNET_LEDGER_CANISTER_ID,
+};
 use crate::types::CanisterId;
 use candid::Principal;
 use ic_cdk::api;
 use log::info;
 
+#[cfg(test)]
+mod tests;
+
 thread_local! {
     pub static NAMED_CANISTER_IDS :RefCell<NamedCanisterIds> = RefCell::new(NamedCanisterIds::default());
-    pub static DEV_NAMED_CANISTER_IDS :RefCell<HashMap<CanisterNames, Principal>> = RefCell::new(HashMap::default());
 }
 
+#[derive(CandidType, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct NamedCanisterId {
+    pub name: CanisterNames,
+    pub canister_id: CanisterId,
+}
+
+/// Ids of the canisters this canister calls or trusts, supplied by init / upgrade args and kept
+/// in the state, so the same wasm can be promoted from staging to production.
+#[derive(Clone, Debug, Default)]
 pub struct NamedCanisterIds {
-    pub dynamic_canisters: HashMap<&'static str, Principal>,
+    pub canister_ids: BTreeMap<CanisterNames, CanisterId>,
+    /// set by `ICLogger::init` on every install, not persisted
     pub current_name: String,
 }
 
 impl Display for NamedCanisterIds {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        for (name, canister) in self.dynamic_canisters.iter() {
-            writeln!(f, "{} = {}", name, canister)?;
+        for (name, canister) in self.canister_ids.iter() {
+            writeln!(f, "{:?} = {}", name, canister)?;
         }
         Ok(())
     }
 }
 
-impl Default for NamedCanisterIds {
-    fn default() -> Self {
-        let map = HashMap::new();
-        let result = NamedCanisterIds {
-            dynamic_canisters: map,
-            current_name: "".to_string(),
-        };
-        info!("named canister ids: {}", &result);
-        result
+impl NamedCanisterIds {
+    pub fn get_canister_id(&self, name: CanisterNames) -> Option<CanisterId> {
+        match name {
+            CanisterNames::DFTCanister(canister_id) => Some(canister_id),
+            name => self.canister_ids.get(&name).copied(),
+        }
+    }
+
+    /// Replaces the given ids and fills the missing ones with the defaults of the env.
+    /// Nothing is changed unless every required name has an id afterwards.
+    pub fn update(&mut self, ids: &[NamedCanisterId]) -> ServiceResult<()> {
+        let mut canister_ids = self.canister_ids.clone();
+        for id in ids {
+            validate_named_canister_id(id)?;
+            canister_ids.insert(id.name, id.canister_id);
+        }
+        for (name, canister_id) in default_canister_ids() {
+            canister_ids.entry(name).or_insert(canister_id);
+        }
+        if let Some(missing) = CanisterNames::required()
+            .iter()
+            .find(|name| !canister_ids.contains_key(name))
+        {
+            return Err(invalid_canister_ids(format!(
+                "canister id of {:?} is missing",
+                missing
+            )));
+        }
+        self.canister_ids = canister_ids;
+        Ok(())
+    }
+
+    pub fn list(&self) -> Vec<NamedCanisterId> {
+        self.canister_ids
+            .iter()
+            .map(|(name, canister_id)| NamedCanisterId {
+                name: *name,
+                canister_id: *canister_id,
+            })
+            .collect()
     }
 }
 
-impl NamedCanisterIds {
-    pub fn get_canister_id(&self, name: CanisterNames) -> CanisterId {
-        match name {
-            CanisterNames::MockSampleCanister => {
-                CanisterId(*CANISTER_IDS_MOCK_SAMPLE_CANISTER.deref())
-            }
-            CanisterNames::ICLedger => CanisterId(*CANISTER_IDS_IC_LEDGER_CANISTER.deref()),
-            CanisterNames::ICManagement => CanisterId(*CANISTER_IDS_IC_MANAGEMENT_CANISTER.deref()),
-            CanisterNames::DFTCanister(canister_id) => canister_id,
+impl StableState for NamedCanisterIds {
+    fn encode(&self) -> Vec<u8> {
+        encode_args((&self.canister_ids,)).unwrap()
+    }
+
+    fn decode(bytes: Vec<u8>) -> Result<Self, String> {
+        let (canister_ids,): (BTreeMap<CanisterNames, CanisterId>,) =
+            decode_args(&bytes).map_err(|e| format!("{:?}", e))?;
+        Ok(NamedCanisterIds {
+            canister_ids,
+            current_name: "".to_string(),
+        })
+    }
+
+    /// An empty set is fine, `restore_named_canister_ids` keeps the current ids then.
+    fn check_invariants(&self) -> Vec<String> {
+        if self.canister_ids.is_empty() {
+            return vec![];
         }
+        CanisterNames::required()
+            .iter()
+            .filter(|name| !self.canister_ids.contains_key(name))
+            .map(|name| format!("canister id of {:?} is missing", name))
+            .collect()
     }
 }
 
-pub fn get_named_canister_id(name: CanisterNames) -> CanisterId {
-    NAMED_CANISTER_IDS.with(|n| {
-        let n = n.borrow();
-        n.get_canister_id(name)
-    })
+fn invalid_canister_ids(detail: String) -> CommonError {
+    CommonError::InvalidArgument {
+        field: "named_canister_ids".to_string(),
+        detail,
+    }
+}
+
+fn validate_named_canister_id(id: &NamedCanisterId) -> ServiceResult<()> {
+    if let CanisterNames::DFTCanister(_) = id.name {
+        return Err(invalid_canister_ids(
+            "DFTCanister carries its own id and can not be registered".to_string(),
+        ));
+    }
+    if id.canister_id.0 == Principal::anonymous() {
+        return Err(invalid_canister_ids(format!(
+            "canister id of {:?} is the anonymous principal",
+            id.name
+        )));
+    }
+    Ok(())
+}
+
+/// Ids which are the same on every network of the env, args only need to supply the others.
+fn default_canister_ids() -> Vec<(CanisterNames, CanisterId)> {
+    let mut ids = vec![(
+        CanisterNames::ICManagement,
+        CanisterId(Principal::management_canister()),
+    )];
+    if !is_dev_env() {
+        ids.push((
+            CanisterNames::ICLedger,
+            CanisterId(MAINNET_LEDGER_CANISTER_ID),
+        ));
+        ids.push((
+            CanisterNames::CyclesMinting,
+            CanisterId(MAINNET_CYCLES_MINTING_CANISTER_ID),
+        ));
+    }
+    ids
+}
+
+/// `None` when no id was supplied, e.g. for the optional `Backup`.
+pub fn find_named_canister_id(name: CanisterNames) -> Option<CanisterId> {
+    NAMED_CANISTER_IDS.with(|n| n.borrow().get_canister_id(name))
+}
+
+/// `update_named_canister_ids` rules out a missing id since init, except for optional names.
+pub fn get_named_canister_id(name: CanisterNames) -> ServiceResult<CanisterId> {
+    NAMED_CANISTER_IDS
+        .with(|n| n.borrow().get_canister_id(name))
+        .ok_or_else(|| CommonError::InvalidState {
+            detail: format!("canister id of {:?} is not registered", name),
+        })
 }
 
 pub fn is_named_canister_id(name: CanisterNames, id: CanisterId) -> bool {
     NAMED_CANISTER_IDS.with(|n| {
         let n = n.borrow();
-        n.get_canister_id(name) == id
+        n.get_canister_id(name) == Some(id)
     })
 }
 
 pub fn ensure_current_canister_id_match(name: CanisterNames) -> Result<(), String> {
     let current = CanisterId(api::id());
-    let expected = get_named_canister_id(name);
+    let expected = get_named_canister_id(name).map_err(|e| e.to_string())?;
     if current != expected {
         Err(format!(
             "Current canister id does not match expected canister id. Expected: {}, Current: {}",
@@ -92,12 +201,27 @@ pub fn update_current_canister_name(name: &str) {
     });
 }
 
-pub fn update_dev_named_canister_ids(ids: &HashMap<CanisterNames, Principal>) {
-    DEV_NAMED_CANISTER_IDS.with(|n| {
-        let mut x = n.borrow_mut();
-        x.clear();
-        x.extend(ids.iter());
-    });
+/// Called with the args of init and post_upgrade, and by admins at runtime.
+pub fn update_named_canister_ids(ids: &[NamedCanisterId]) -> ServiceResult<()> {
+    NAMED_CANISTER_IDS.with(|n| {
+        let mut n = n.borrow_mut();
+        n.update(ids)?;
+        info!("named canister ids:\n{}", n);
+        Ok(())
+    })
+}
+
+pub fn get_named_canister_ids() -> Vec<NamedCanisterId> {
+    NAMED_CANISTER_IDS.with(|n| n.borrow().list())
+}
+
+/// Restores the persisted ids, the current name stays the one of the running wasm.
+/// States saved before the ids were persisted have none, the current ids are kept for them.
+pub fn restore_named_canister_ids(ids: NamedCanisterIds) {
+    if ids.canister_ids.is_empty() {
+        return;
+    }
+    NAMED_CANISTER_IDS.with(|n| n.borrow_mut().canister_ids = ids.canister_ids);
 }
 
 #[derive(Eq, Ord, PartialOrd, PartialEq, Hash, Debug, Copy, Clone, CandidType, Deserialize)]
@@ -107,4 +231,21 @@ pub enum CanisterNames {
     DFTCanister(CanisterId),
     ICLedger,
     ICManagement,
+    CyclesMinting,
+    /// archive of state snapshots, optional, backups are off without it
+    Backup,
+}
+
+impl CanisterNames {
+    /// Names every deployment must have an id for, `DFTCanister` carries its own id and
+    /// `MockSampleCanister` is only called by test deployments which register it.
+    pub fn required() -> [CanisterNames; 3] {
+        [
+            CanisterNames::ICLedger,
+            CanisterNames::ICManagement,
+            CanisterNames::CyclesMinting,
+        ]
+    }
 }
+
+crate::actor_response!(pub GetNamedCanisterIdsResponse, Vec<NamedCanisterId>);
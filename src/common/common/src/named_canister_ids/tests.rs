@@ -0,0 +1,124 @@
+use rstest::*;
+
+use super::*;
+use crate::test_common::test::init_test;
+
+#[fixture]
+pub fn setup() {
+    init_test();
+}
+
+fn canister_id(index: u64) -> CanisterId {
+    let mut bytes = index.to_be_bytes().to_vec();
+    bytes.extend_from_slice(&[1, 1]);
+    CanisterId(Principal::from_slice(&bytes))
+}
+
+fn named(name: CanisterNames, index: u64) -> NamedCanisterId {
+    NamedCanisterId {
+        name,
+        canister_id: canister_id(index),
+    }
+}
+
+fn dev_args() -> Vec<NamedCanisterId> {
+    vec![
+        named(CanisterNames::MockSampleCanister, 1),
+        named(CanisterNames::ICLedger, 2),
+        named(CanisterNames::CyclesMinting, 3),
+    ]
+}
+
+#[rstest]
+fn test_update_fills_defaults(_setup: ()) {
+    let mut ids = NamedCanisterIds::default();
+    ids.update(&dev_args()).unwrap();
+
+    assert_eq!(
+        ids.get_canister_id(CanisterNames::ICManagement),
+        Some(CanisterId(Principal::management_canister()))
+    );
+    assert_eq!(
+        ids.get_canister_id(CanisterNames::ICLedger),
+        Some(canister_id(2))
+    );
+    // the required names and MockSampleCanister
+    assert_eq!(ids.list().len(), CanisterNames::required().len() + 1);
+}
+
+#[rstest]
+fn test_dft_canister_is_its_own_id(_setup: ()) {
+    let ids = NamedCanisterIds::default();
+    assert_eq!(
+        ids.get_canister_id(CanisterNames::DFTCanister(canister_id(9))),
+        Some(canister_id(9))
+    );
+}
+
+#[rstest]
+fn test_missing_id_is_rejected(_setup: ()) {
+    let mut ids = NamedCanisterIds::default();
+    let result = ids.update(&[named(CanisterNames::ICLedger, 2)]);
+
+    assert!(matches!(
+        result,
+        Err(CommonError::InvalidArgument { detail, .. }) if detail.contains("CyclesMinting")
+    ));
+    // nothing is applied from a rejected update
+    assert_eq!(ids.get_canister_id(CanisterNames::ICLedger), None);
+}
+
+#[rstest]
+fn test_mock_sample_canister_is_optional(_setup: ()) {
+    let mut ids = NamedCanisterIds::default();
+    ids.update(&[
+        named(CanisterNames::ICLedger, 2),
+        named(CanisterNames::CyclesMinting, 3),
+    ])
+    .unwrap();
+
+    assert_eq!(ids.get_canister_id(CanisterNames::MockSampleCanister), None);
+}
+
+#[rstest]
+#[case(named(CanisterNames::DFTCanister(canister_id(9)), 9))]
+#[case(NamedCanisterId { name: CanisterNames::ICLedger, canister_id: CanisterId(Principal::anonymous()) })]
+fn test_invalid_id_is_rejected(_setup: (), #[case] invalid: NamedCanisterId) {
+    let mut ids = NamedCanisterIds::default();
+    let mut args = dev_args();
+    args.push(invalid);
+
+    assert!(matches!(
+        ids.update(&args),
+        Err(CommonError::InvalidArgument { .. })
+    ));
+}
+
+#[rstest]
+fn test_update_replaces_only_given_ids(_setup: ()) {
+    let mut ids = NamedCanisterIds::default();
+    ids.update(&dev_args()).unwrap();
+
+    ids.update(&[named(CanisterNames::ICLedger, 5)]).unwrap();
+
+    assert_eq!(
+        ids.get_canister_id(CanisterNames::ICLedger),
+        Some(canister_id(5))
+    );
+    assert_eq!(
+        ids.get_canister_id(CanisterNames::MockSampleCanister),
+        Some(canister_id(1))
+    );
+}
+
+#[rstest]
+fn test_encode_decode(_setup: ()) {
+    let mut ids = NamedCanisterIds::default();
+    ids.update(&dev_args()).unwrap();
+    ids.current_name = "test".to_string();
+
+    let decoded = NamedCanisterIds::decode(ids.encode()).unwrap();
+
+    assert_eq!(decoded.canister_ids, ids.canister_ids);
+    assert_eq!(decoded.current_name, "");
+}
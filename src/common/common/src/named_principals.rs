@@ -1,24 +1,46 @@
-use std::collections::{HashMap, HashSet};
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::fmt::Display;
 use std::str::FromStr;
 
 use crate::constants::*;
-use candid::Principal;
+use crate::errors::{CommonError, ServiceResult};
+use crate::state::StableState;
+use candid::{decode_args, encode_args, CandidType, Deserialize, Principal};
 use log::{debug, info};
 
+#[cfg(test)]
+mod tests;
+
 thread_local! {
-    pub static NAME_DPRINCIPALS :NamedPrincipals = NamedPrincipals::new();
+    pub static NAME_DPRINCIPALS :RefCell<NamedPrincipals> = RefCell::new(NamedPrincipals::new());
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct NamedPrincipalSet {
+    pub name: String,
+    pub principals: Vec<Principal>,
 }
 
+/// Principals of the env files, replaced per name by the ones set with a `SetNamedPrincipals`
+/// proposal. Only the replacements are persisted.
+#[derive(Clone, Debug)]
 pub struct NamedPrincipals {
     pub principals: HashMap<&'static str, HashSet<Principal>>,
+    pub overrides: BTreeMap<String, BTreeSet<Principal>>,
+}
+
+impl Default for NamedPrincipals {
+    fn default() -> Self {
+        NamedPrincipals::new()
+    }
 }
 
 impl Display for NamedPrincipals {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        for (name, principals) in self.principals.iter() {
-            write!(f, "{}:\n", name)?;
-            for principal in principals.iter() {
+        for set in self.list() {
+            write!(f, "{}:\n", set.name)?;
+            for principal in set.principals.iter() {
                 write!(f, "  {}\n", principal)?;
             }
         }
@@ -31,24 +53,129 @@ impl NamedPrincipals {
         let mut map = HashMap::new();
         map.insert(
             PRINCIPAL_NAME_ADMIN,
-            lines_hashset(COMMON_PRINCIPAL_NAME_ADMIN),
+            principal_set(envs::COMMON_PRINCIPAL_NAME_ADMIN),
         );
         map.insert(
             PRINCIPAL_NAME_STATE_EXPORTER,
-            lines_hashset(COMMON_PRINCIPAL_NAME_STATE_EXPORTER),
+            principal_set(envs::COMMON_PRINCIPAL_NAME_STATE_EXPORTER),
         );
         map.insert(
             PRINCIPAL_NAME_TIMER_TRIGGER,
-            lines_hashset(COMMON_PRINCIPAL_NAME_TIMER_TRIGGER),
+            principal_set(envs::COMMON_PRINCIPAL_NAME_TIMER_TRIGGER),
         );
 
-        let result = NamedPrincipals { principals: map };
+        let result = NamedPrincipals {
+            principals: map,
+            overrides: BTreeMap::new(),
+        };
         info!("named principals: {}", &result);
         result
     }
+
+    /// Panics when `name` is not one of the `PRINCIPAL_NAME_*` groups.
+    pub fn get(&self, name: &str) -> HashSet<Principal> {
+        match self.overrides.get(name) {
+            Some(principals) => principals.iter().cloned().collect(),
+            None => self.principals.get(name).unwrap().clone(),
+        }
+    }
+
+    pub fn contains(&self, name: &str, principal: &Principal) -> bool {
+        match self.overrides.get(name) {
+            Some(principals) => principals.contains(principal),
+            None => self.principals.get(name).unwrap().contains(principal),
+        }
+    }
+
+    /// Replaces the principals of `name`. The admins can not be emptied, nobody could approve
+    /// a proposal to add them back.
+    pub fn set(&mut self, name: &str, principals: &[Principal]) -> ServiceResult<()> {
+        if !self.principals.contains_key(name) {
+            return Err(invalid_named_principals(format!(
+                "{} is not a principal name",
+                name
+            )));
+        }
+        if principals.contains(&Principal::anonymous()) {
+            return Err(invalid_named_principals(format!(
+                "{} must not contain the anonymous principal",
+                name
+            )));
+        }
+        if name == PRINCIPAL_NAME_ADMIN && principals.is_empty() {
+            return Err(invalid_named_principals(format!(
+                "{} must not be empty",
+                name
+            )));
+        }
+        self.overrides
+            .insert(name.to_string(), principals.iter().cloned().collect());
+        Ok(())
+    }
+
+    /// Every name with its principals, ordered by name.
+    pub fn list(&self) -> Vec<NamedPrincipalSet> {
+        let mut names: Vec<&str> = self.principals.keys().copied().collect();
+        names.sort_unstable();
+        names
+            .into_iter()
+            .map(|name| {
+                let mut principals: Vec<Principal> = self.get(name).into_iter().collect();
+                principals.sort();
+                NamedPrincipalSet {
+                    name: name.to_string(),
+                    principals,
+                }
+            })
+            .collect()
+    }
 }
 
-pub(crate) fn lines_hashset(s: &str) -> HashSet<Principal> {
+impl StableState for NamedPrincipals {
+    fn encode(&self) -> Vec<u8> {
+        encode_args((&self.overrides,)).unwrap()
+    }
+
+    fn decode(bytes: Vec<u8>) -> Result<Self, String> {
+        let (overrides,): (BTreeMap<String, BTreeSet<Principal>>,) =
+            decode_args(&bytes).map_err(|e| format!("{:?}", e))?;
+        let mut result = NamedPrincipals::new();
+        result.overrides = overrides;
+        Ok(result)
+    }
+
+    fn check_invariants(&self) -> Vec<String> {
+        let mut violations: Vec<String> = self
+            .overrides
+            .keys()
+            .filter(|name| !self.principals.contains_key(name.as_str()))
+            .map(|name| format!("{} is not a principal name", name))
+            .collect();
+        if self
+            .overrides
+            .get(PRINCIPAL_NAME_ADMIN)
+            .map_or(false, |admins| admins.is_empty())
+        {
+            violations.push(format!("{} is empty", PRINCIPAL_NAME_ADMIN));
+        }
+        violations
+    }
+}
+
+fn invalid_named_principals(detail: String) -> CommonError {
+    CommonError::InvalidArgument {
+        field: "named_principals".to_string(),
+        detail,
+    }
+}
+
+fn principal_set(principals: &[Principal]) -> HashSet<Principal> {
+    principals.iter().cloned().collect()
+}
+
+/// Parses a principal list passed on as `cargo:rustc-env` by `build_common::generate_envs`,
+/// its lines joined with `||||`.
+pub fn lines_hashset(s: &str) -> HashSet<Principal> {
     let mut set = HashSet::new();
     for line in s.split("||||") {
         if line.starts_with("#") {
@@ -64,13 +191,12 @@ pub(crate) fn lines_hashset(s: &str) -> HashSet<Principal> {
 }
 
 pub fn is_named_principal(name: &str, principal: &Principal) -> bool {
-    let result =
-        NAME_DPRINCIPALS.with(|store| store.principals.get(name).unwrap().contains(principal));
+    let result = NAME_DPRINCIPALS.with(|store| store.borrow().contains(name, principal));
     if is_dev_env() {
         debug!("is_named_principal({}, {}) = {}", name, principal, result);
         if !result {
             NAME_DPRINCIPALS.with(|store| {
-                store.principals.get(name).unwrap().iter().for_each(|p| {
+                store.borrow().get(name).iter().for_each(|p| {
                     debug!("  {}", p);
                 });
             });
@@ -80,9 +206,28 @@ pub fn is_named_principal(name: &str, principal: &Principal) -> bool {
 }
 
 pub fn get_named_principals(name: &str) -> HashSet<Principal> {
-    NAME_DPRINCIPALS.with(|store| store.principals.get(name).unwrap().clone())
+    NAME_DPRINCIPALS.with(|store| store.borrow().get(name))
+}
+
+pub fn list_named_principals() -> Vec<NamedPrincipalSet> {
+    NAME_DPRINCIPALS.with(|store| store.borrow().list())
+}
+
+pub fn set_named_principals(name: &str, principals: &[Principal]) -> ServiceResult<()> {
+    NAME_DPRINCIPALS.with(|store| {
+        let mut store = store.borrow_mut();
+        store.set(name, principals)?;
+        info!("named principals: {}", &store);
+        Ok(())
+    })
 }
 
+pub fn restore_named_principals(named_principals: NamedPrincipals) {
+    NAME_DPRINCIPALS.with(|store| store.replace(named_principals));
+}
+
+crate::actor_response!(pub GetNamedPrincipalsResponse, Vec<NamedPrincipalSet>);
+
 pub const PRINCIPAL_NAME_ADMIN: &str = "user:administrator";
 pub const PRINCIPAL_NAME_STATE_EXPORTER: &str = "app:state_exporter";
 pub const PRINCIPAL_NAME_TIMER_TRIGGER: &str = "app:timer_trigger";
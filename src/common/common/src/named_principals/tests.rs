@@ -0,0 +1,65 @@
+use rstest::*;
+
+use super::*;
+use crate::test_common::test::init_test;
+
+#[fixture]
+pub fn setup() {
+    init_test();
+}
+
+fn principal(i: u8) -> Principal {
+    Principal::from_slice(&[7, i])
+}
+
+#[rstest]
+fn test_set_replaces_env_principals(_setup: ()) {
+    let mut store = NamedPrincipals::new();
+    let env_admin = envs::COMMON_PRINCIPAL_NAME_ADMIN[0];
+    assert!(store.contains(PRINCIPAL_NAME_ADMIN, &env_admin));
+
+    store
+        .set(PRINCIPAL_NAME_ADMIN, &[principal(1), principal(2)])
+        .unwrap();
+
+    assert!(!store.contains(PRINCIPAL_NAME_ADMIN, &env_admin));
+    assert!(store.contains(PRINCIPAL_NAME_ADMIN, &principal(2)));
+    assert_eq!(store.get(PRINCIPAL_NAME_ADMIN).len(), 2);
+    let admins = store
+        .list()
+        .into_iter()
+        .find(|set| set.name == PRINCIPAL_NAME_ADMIN)
+        .unwrap();
+    assert_eq!(admins.principals, vec![principal(1), principal(2)]);
+}
+
+#[rstest]
+#[case("unknown", vec![principal(1)])]
+#[case(PRINCIPAL_NAME_ADMIN, vec![])]
+#[case(PRINCIPAL_NAME_TIMER_TRIGGER, vec![Principal::anonymous()])]
+fn test_invalid_set_is_rejected(
+    _setup: (),
+    #[case] name: &str,
+    #[case] principals: Vec<Principal>,
+) {
+    let mut store = NamedPrincipals::new();
+    assert!(matches!(
+        store.set(name, &principals),
+        Err(CommonError::InvalidArgument { .. })
+    ));
+    assert!(store.overrides.is_empty());
+}
+
+#[rstest]
+fn test_encode_decode(_setup: ()) {
+    let mut store = NamedPrincipals::new();
+    store
+        .set(PRINCIPAL_NAME_TIMER_TRIGGER, &[principal(3)])
+        .unwrap();
+
+    let decoded = NamedPrincipals::decode(store.encode()).unwrap();
+
+    assert_eq!(decoded.overrides, store.overrides);
+    assert!(decoded.contains(PRINCIPAL_NAME_TIMER_TRIGGER, &principal(3)));
+    assert!(decoded.check_invariants().is_empty());
+}
@@ -0,0 +1,213 @@
+use std::collections::BTreeMap;
+use std::ops::Bound;
+
+use candid::{decode_one, encode_one, CandidType, Deserialize};
+use serde::de::DeserializeOwned;
+
+use crate::dto::{GetPageInput, GetPageOutput, SortDirection};
+use crate::errors::{CommonError, ServiceResult};
+
+#[cfg(test)]
+mod tests;
+
+/// Content of the opaque `cursor` strings, hex encoded candid.
+#[derive(CandidType, Deserialize, Debug, PartialEq, Eq)]
+enum PageCursor {
+    /// position of the next item of an iterator and the order it was returned in
+    Offset {
+        position: u64,
+        direction: SortDirection,
+    },
+    /// candid encoded key of the last returned item and the order it was returned in
+    After {
+        sort_by: Option<String>,
+        direction: SortDirection,
+        key: Vec<u8>,
+    },
+}
+
+fn invalid_cursor(detail: &str) -> CommonError {
+    CommonError::InvalidArgument {
+        field: "cursor".to_string(),
+        detail: detail.to_string(),
+    }
+}
+
+impl PageCursor {
+    fn encode(&self) -> String {
+        hex::encode(encode_one(self).unwrap())
+    }
+
+    fn decode(cursor: &str) -> ServiceResult<Self> {
+        let bytes = hex::decode(cursor).map_err(|_| invalid_cursor("malformed cursor"))?;
+        decode_one(&bytes).map_err(|_| invalid_cursor("malformed cursor"))
+    }
+
+    fn after<K: CandidType>(page: &GetPageInput, key: &K) -> Self {
+        PageCursor::After {
+            sort_by: page.sort_by.clone(),
+            direction: page.direction(),
+            key: encode_one(key).unwrap(),
+        }
+    }
+}
+
+/// Key of the last item of the previous page, `None` when the page is offset based.
+fn cursor_key<K>(page: &GetPageInput) -> ServiceResult<Option<K>>
+where
+    K: CandidType + DeserializeOwned,
+{
+    let cursor = match &page.cursor {
+        Some(cursor) => PageCursor::decode(cursor)?,
+        None => return Ok(None),
+    };
+    match cursor {
+        PageCursor::After {
+            sort_by,
+            direction,
+            key,
+        } => {
+            if sort_by != page.sort_by || direction != page.direction() {
+                return Err(invalid_cursor(
+                    "cursor was created with another sort_by or direction",
+                ));
+            }
+            decode_one(&key)
+                .map(Some)
+                .map_err(|_| invalid_cursor("cursor was created by another query"))
+        }
+        PageCursor::Offset { .. } => Err(invalid_cursor("cursor was created by another query")),
+    }
+}
+
+/// Pages through items which are already in the order to return them, or in reverse order for
+/// `SortDirection::Desc`. `sort_by` must be `None`, the items have only the one order.
+/// Counts the whole iterator for `total`, the cursor is the position of the next item.
+/// `page` must have passed `GetPageInput::validate`, like for the other `paginate_` functions.
+pub fn paginate_iter<T>(
+    items: impl Iterator<Item = T>,
+    page: &GetPageInput,
+) -> ServiceResult<GetPageOutput<T>> {
+    page.validate_sort_by(&[])?;
+    let direction = page.direction();
+    let start = match &page.cursor {
+        Some(cursor) => match PageCursor::decode(cursor)? {
+            PageCursor::Offset {
+                position,
+                direction: cursor_direction,
+            } => {
+                if cursor_direction != direction {
+                    return Err(invalid_cursor("cursor was created with another direction"));
+                }
+                position as usize
+            }
+            PageCursor::After { .. } => {
+                return Err(invalid_cursor("cursor was created by another query"))
+            }
+        },
+        None => page.offset,
+    };
+    match direction {
+        SortDirection::Asc => Ok(page_of_iter(items, page, start)),
+        SortDirection::Desc => {
+            let items: Vec<T> = items.collect();
+            Ok(page_of_iter(items.into_iter().rev(), page, start))
+        }
+    }
+}
+
+fn page_of_iter<T>(
+    items: impl Iterator<Item = T>,
+    page: &GetPageInput,
+    start: usize,
+) -> GetPageOutput<T> {
+    let mut total = 0;
+    let mut page_items = vec![];
+    for (i, item) in items.enumerate() {
+        total += 1;
+        if i >= start && page_items.len() < page.limit {
+            page_items.push(item);
+        }
+    }
+    let next = start + page_items.len();
+    let next_cursor = if next < total {
+        let cursor = PageCursor::Offset {
+            position: next as u64,
+            direction: page.direction(),
+        };
+        Some(cursor.encode())
+    } else {
+        None
+    };
+    GetPageOutput::new(page_items, total as u64, next_cursor)
+}
+
+/// Pages through a map in key order, or reverse key order for `SortDirection::Desc`.
+/// `sort_by` must be `None`, a map is only ordered by its key.
+pub fn paginate_btree_map<K, V, T>(
+    map: &BTreeMap<K, V>,
+    page: &GetPageInput,
+    f: impl Fn(&K, &V) -> T,
+) -> ServiceResult<GetPageOutput<T>>
+where
+    K: Ord + CandidType + DeserializeOwned,
+{
+    page.validate_sort_by(&[])?;
+    let after: Option<K> = cursor_key(page)?;
+    let iter: Box<dyn Iterator<Item = (&K, &V)>> = match (page.direction(), &after) {
+        (SortDirection::Asc, None) => Box::new(map.iter()),
+        (SortDirection::Asc, Some(key)) => {
+            Box::new(map.range((Bound::Excluded(key), Bound::Unbounded)))
+        }
+        (SortDirection::Desc, None) => Box::new(map.iter().rev()),
+        (SortDirection::Desc, Some(key)) => {
+            Box::new(map.range((Bound::Unbounded, Bound::Excluded(key))).rev())
+        }
+    };
+    let skip = if after.is_some() { 0 } else { page.offset };
+    let mut entries: Vec<(&K, &V)> = iter.skip(skip).take(page.limit + 1).collect();
+    let next_cursor = if entries.len() > page.limit {
+        entries.truncate(page.limit);
+        entries
+            .last()
+            .map(|(key, _)| PageCursor::after(page, *key).encode())
+    } else {
+        None
+    };
+    let items = entries.into_iter().map(|(k, v)| f(k, v)).collect();
+    Ok(GetPageOutput::new(items, map.len() as u64, next_cursor))
+}
+
+/// Sorts `items` by `key` in the direction of the page and returns one page of them.
+/// `key` must be unique, e.g. `(created_at, id)`, otherwise a cursor may skip items with an equal key.
+/// Callers pick `key` from `page.sort_by` after checking it with `GetPageInput::validate_sort_by`.
+pub fn paginate_sorted<T, K>(
+    mut items: Vec<T>,
+    page: &GetPageInput,
+    key: impl Fn(&T) -> K,
+) -> ServiceResult<GetPageOutput<T>>
+where
+    K: Ord + CandidType + DeserializeOwned,
+{
+    let direction = page.direction();
+    items.sort_by_cached_key(|item| key(item));
+    if direction == SortDirection::Desc {
+        items.reverse();
+    }
+    let total = items.len();
+    let start = match cursor_key::<K>(page)? {
+        Some(after) => match direction {
+            SortDirection::Asc => items.partition_point(|item| key(item) <= after),
+            SortDirection::Desc => items.partition_point(|item| key(item) >= after),
+        },
+        None => page.offset.min(total),
+    };
+    let end = (start + page.limit).min(total);
+    let next_cursor = if end < total && end > start {
+        Some(PageCursor::after(page, &key(&items[end - 1])).encode())
+    } else {
+        None
+    };
+    let items = items.drain(start..end).collect();
+    Ok(GetPageOutput::new(items, total as u64, next_cursor))
+}
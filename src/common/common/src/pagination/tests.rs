@@ -0,0 +1,272 @@
+use rstest::*;
+
+use super::*;
+use crate::constants::PAGE_INPUT_MAX_OFFSET;
+use crate::test_common::test::init_test;
+
+#[fixture]
+pub fn setup() {
+    init_test();
+}
+
+fn sorted_page(sort_by: &str, direction: SortDirection, limit: usize) -> GetPageInput {
+    GetPageInput {
+        limit,
+        sort_by: Some(sort_by.to_string()),
+        direction: Some(direction),
+        ..Default::default()
+    }
+}
+
+fn next_page(page: &GetPageInput, next_cursor: Option<String>) -> GetPageInput {
+    GetPageInput {
+        cursor: next_cursor,
+        ..page.clone()
+    }
+}
+
+mod iter {
+    use super::*;
+
+    #[rstest]
+    fn test_offset(_setup: ()) {
+        let page = paginate_iter(0..10, &GetPageInput::new(8, 5)).unwrap();
+        assert_eq!(page.items, vec![8, 9]);
+        assert_eq!(page.total, 10);
+        assert_eq!(page.next_cursor, None);
+    }
+
+    #[rstest]
+    fn test_cursor_reaches_beyond_max_offset(_setup: ()) {
+        let total = PAGE_INPUT_MAX_OFFSET + 150;
+        let mut page = GetPageInput::new(0, 100);
+        let mut seen = 0;
+        loop {
+            let output = paginate_iter(0..total, &page).unwrap();
+            assert_eq!(output.total, total as u64);
+            assert_eq!(output.items.first(), Some(&seen));
+            seen += output.items.len();
+            match output.next_cursor {
+                Some(cursor) => page = GetPageInput::after(cursor, 100),
+                None => break,
+            }
+        }
+        assert_eq!(seen, total);
+    }
+
+    #[rstest]
+    fn test_sorted_cursor_is_rejected(_setup: ()) {
+        let map: BTreeMap<u64, ()> = (0..3).map(|i| (i, ())).collect();
+        let page = GetPageInput::new(0, 1);
+        let cursor = paginate_btree_map(&map, &page, |k, _| *k)
+            .unwrap()
+            .next_cursor
+            .unwrap();
+
+        let result = paginate_iter(0..3, &GetPageInput::after(cursor, 1));
+        assert!(matches!(
+            result,
+            Err(CommonError::InvalidArgument { field, .. }) if field == "cursor"
+        ));
+    }
+
+    #[rstest]
+    fn test_desc_reverses_items(_setup: ()) {
+        let mut page = GetPageInput {
+            limit: 2,
+            direction: Some(SortDirection::Desc),
+            ..Default::default()
+        };
+        let mut items = vec![];
+        loop {
+            let output = paginate_iter(0..5, &page).unwrap();
+            items.extend(output.items);
+            match output.next_cursor {
+                Some(_) => page = next_page(&page, output.next_cursor),
+                None => break,
+            }
+        }
+        assert_eq!(items, vec![4, 3, 2, 1, 0]);
+    }
+
+    #[rstest]
+    fn test_direction_mismatch_is_rejected(_setup: ()) {
+        let page = GetPageInput::new(0, 1);
+        let output = paginate_iter(0..3, &page).unwrap();
+
+        let page = GetPageInput {
+            direction: Some(SortDirection::Desc),
+            ..next_page(&page, output.next_cursor)
+        };
+        assert!(matches!(
+            paginate_iter(0..3, &page),
+            Err(CommonError::InvalidArgument { field, .. }) if field == "cursor"
+        ));
+    }
+
+    #[rstest]
+    fn test_sort_by_is_rejected(_setup: ()) {
+        let result = paginate_iter(0..3, &sorted_page("value", SortDirection::Asc, 2));
+        assert!(matches!(
+            result,
+            Err(CommonError::InvalidArgument { field, .. }) if field == "sort_by"
+        ));
+    }
+}
+
+mod btree_map {
+    use super::*;
+
+    fn map() -> BTreeMap<u64, String> {
+        (1..=5).map(|i| (i, format!("v{}", i))).collect()
+    }
+
+    #[rstest]
+    #[case(SortDirection::Asc, vec![vec![1, 2], vec![3, 4], vec![5]])]
+    #[case(SortDirection::Desc, vec![vec![5, 4], vec![3, 2], vec![1]])]
+    fn test_cursor_pages(
+        _setup: (),
+        #[case] direction: SortDirection,
+        #[case] expected: Vec<Vec<u64>>,
+    ) {
+        let map = map();
+        let mut page = GetPageInput {
+            limit: 2,
+            direction: Some(direction),
+            ..Default::default()
+        };
+        let mut pages = vec![];
+        loop {
+            let output = paginate_btree_map(&map, &page, |k, _| *k).unwrap();
+            assert_eq!(output.total, 5);
+            pages.push(output.items);
+            match output.next_cursor {
+                Some(_) => page = next_page(&page, output.next_cursor),
+                None => break,
+            }
+        }
+        assert_eq!(pages, expected);
+    }
+
+    #[rstest]
+    fn test_cursor_survives_removed_key(_setup: ()) {
+        let mut map = map();
+        let page = GetPageInput::new(0, 2);
+        let output = paginate_btree_map(&map, &page, |k, _| *k).unwrap();
+        map.remove(&2);
+        map.remove(&3);
+
+        let output =
+            paginate_btree_map(&map, &next_page(&page, output.next_cursor), |k, _| *k).unwrap();
+        assert_eq!(output.items, vec![4, 5]);
+    }
+
+    #[rstest]
+    fn test_direction_mismatch_is_rejected(_setup: ()) {
+        let map = map();
+        let page = GetPageInput::new(0, 2);
+        let output = paginate_btree_map(&map, &page, |k, _| *k).unwrap();
+
+        let page = GetPageInput {
+            direction: Some(SortDirection::Desc),
+            ..next_page(&page, output.next_cursor)
+        };
+        let result = paginate_btree_map(&map, &page, |k, _| *k);
+        assert!(matches!(result, Err(CommonError::InvalidArgument { .. })));
+    }
+
+    #[rstest]
+    fn test_sort_by_is_rejected(_setup: ()) {
+        let result = paginate_btree_map(
+            &map(),
+            &sorted_page("value", SortDirection::Asc, 2),
+            |k, _| *k,
+        );
+        assert!(matches!(
+            result,
+            Err(CommonError::InvalidArgument { field, .. }) if field == "sort_by"
+        ));
+    }
+}
+
+mod sorted {
+    use super::*;
+
+    #[derive(Clone, Debug, PartialEq)]
+    struct Item {
+        id: u64,
+        name: &'static str,
+    }
+
+    fn items() -> Vec<Item> {
+        vec![
+            Item { id: 1, name: "c" },
+            Item { id: 2, name: "a" },
+            Item { id: 3, name: "b" },
+            Item { id: 4, name: "a" },
+        ]
+    }
+
+    fn get_items(page: &GetPageInput) -> ServiceResult<GetPageOutput<u64>> {
+        page.validate_sort_by(&["id", "name"])?;
+        let output = match page.sort_by.as_deref() {
+            Some("name") => paginate_sorted(items(), page, |i| (i.name.to_string(), i.id))?,
+            _ => paginate_sorted(items(), page, |i| i.id)?,
+        };
+        Ok(output.map(|i| i.id))
+    }
+
+    #[rstest]
+    #[case(SortDirection::Asc, vec![2, 4, 3, 1])]
+    #[case(SortDirection::Desc, vec![1, 3, 4, 2])]
+    fn test_sort_by_name_with_cursor(
+        _setup: (),
+        #[case] direction: SortDirection,
+        #[case] expected: Vec<u64>,
+    ) {
+        let mut page = sorted_page("name", direction, 3);
+        let first = get_items(&page).unwrap();
+        assert_eq!(first.total, 4);
+        page = next_page(&page, first.next_cursor);
+        let second = get_items(&page).unwrap();
+        assert_eq!(second.next_cursor, None);
+
+        let ids: Vec<u64> = first.items.into_iter().chain(second.items).collect();
+        assert_eq!(ids, expected);
+    }
+
+    #[rstest]
+    fn test_unknown_sort_by_is_rejected(_setup: ()) {
+        let result = get_items(&sorted_page("created_at", SortDirection::Asc, 3));
+        assert!(matches!(
+            result,
+            Err(CommonError::InvalidArgument { field, .. }) if field == "sort_by"
+        ));
+    }
+
+    #[rstest]
+    fn test_sort_by_mismatch_is_rejected(_setup: ()) {
+        let page = sorted_page("name", SortDirection::Asc, 1);
+        let output = get_items(&page).unwrap();
+
+        let page = GetPageInput {
+            sort_by: Some("id".to_string()),
+            ..next_page(&page, output.next_cursor)
+        };
+        assert!(matches!(
+            get_items(&page),
+            Err(CommonError::InvalidArgument { .. })
+        ));
+    }
+}
+
+#[rstest]
+#[case("not hex")]
+#[case("00ff")]
+fn test_malformed_cursor(_setup: (), #[case] cursor: &str) {
+    let result = paginate_iter(0..3, &GetPageInput::after(cursor.to_string(), 1));
+    assert!(matches!(
+        result,
+        Err(CommonError::InvalidArgument { field, .. }) if field == "cursor"
+    ));
+}
@@ -0,0 +1,535 @@
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashSet};
+
+use candid::{decode_args, encode_args, CandidType, Deserialize, Principal};
+use log::{info, warn};
+
+use crate::audit_log::args_digest;
+use crate::backup::SnapshotId;
+use crate::config::get_config;
+use crate::dto::{GetPageInput, GetPageOutput};
+use crate::errors::{CommonError, ErrorInfo, ServiceResult};
+use crate::named_canister_ids::NamedCanisterId;
+use crate::named_principals::{get_named_principals, PRINCIPAL_NAME_ADMIN};
+use crate::pagination::paginate_iter;
+use crate::state::StableState;
+use crate::types::{CanisterId, TimeInNs};
+
+#[cfg(test)]
+mod tests;
+
+/// 3 days, long enough for approvers in every time zone.
+pub const PROPOSAL_VOTING_PERIOD: TimeInNs = TimeInNs(3 * 24 * 60 * 60 * 1_000_000_000);
+/// An execution still running after an hour trapped after an await, `fail_execution` may close it.
+pub const PROPOSAL_EXECUTION_TIMEOUT: TimeInNs = TimeInNs(60 * 60 * 1_000_000_000);
+/// A single admin can never act alone, whatever the threshold.
+pub const PROPOSAL_MIN_APPROVALS: u32 = 2;
+
+thread_local! {
+    pub static PROPOSAL_STORE: RefCell<ProposalStore> = RefCell::new(ProposalStore::default());
+}
+
+/// Admin actions which are only executed once enough approvers agreed.
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub enum ProposalAction {
+    /// replaces the state, `state_data` as returned by `export_state`, the history, named
+    /// principals, canister ids and config of this canister are kept
+    LoadState {
+        #[serde(with = "serde_bytes")]
+        state_data: Vec<u8>,
+    },
+    SetNamedCanisterIds(Vec<NamedCanisterId>),
+    /// replaces the principals of `name`, one of the `PRINCIPAL_NAME_*` groups, for good
+    SetNamedPrincipals {
+        name: String,
+        principals: Vec<Principal>,
+    },
+    UpgradeCanister {
+        canister_id: CanisterId,
+        #[serde(with = "serde_bytes")]
+        wasm_module: Vec<u8>,
+        #[serde(with = "serde_bytes")]
+        arg: Vec<u8>,
+    },
+    /// sends cycles of this canister to `canister_id`
+    WithdrawCycles {
+        canister_id: CanisterId,
+        amount: u64,
+    },
+    /// goes back to the state replaced by the latest `LoadState`
+    RollbackState,
+    /// loads a snapshot of this canister from the backup canister
+    RestoreBackup {
+        snapshot_id: SnapshotId,
+    },
+}
+
+impl ProposalAction {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ProposalAction::LoadState { .. } => "load_state",
+            ProposalAction::SetNamedCanisterIds(_) => "set_named_canister_ids",
+            ProposalAction::SetNamedPrincipals { .. } => "set_named_principals",
+            ProposalAction::UpgradeCanister { .. } => "upgrade_canister",
+            ProposalAction::WithdrawCycles { .. } => "withdraw_cycles",
+            ProposalAction::RollbackState => "rollback_state",
+            ProposalAction::RestoreBackup { .. } => "restore_backup",
+        }
+    }
+
+    pub fn digest(&self) -> String {
+        args_digest(&encode_args((self,)).unwrap())
+    }
+
+    pub fn validate(&self) -> ServiceResult<()> {
+        let empty = match self {
+            ProposalAction::LoadState { state_data } => state_data.is_empty(),
+            ProposalAction::SetNamedCanisterIds(ids) => ids.is_empty(),
+            ProposalAction::SetNamedPrincipals { name, .. } => name.is_empty(),
+            ProposalAction::UpgradeCanister { wasm_module, .. } => wasm_module.is_empty(),
+            ProposalAction::WithdrawCycles { amount, .. } => *amount == 0,
+            ProposalAction::RollbackState | ProposalAction::RestoreBackup { .. } => false,
+        };
+        if empty {
+            return Err(CommonError::InvalidArgument {
+                field: "action".to_string(),
+                detail: format!("{} without payload", self.name()),
+            });
+        }
+        Ok(())
+    }
+
+    /// Drops the wasm and state bytes once a proposal is closed, `payload_digest` still identifies them.
+    fn drop_payload(&mut self) {
+        match self {
+            ProposalAction::LoadState { state_data } => state_data.clear(),
+            ProposalAction::UpgradeCanister {
+                wasm_module, arg, ..
+            } => {
+                wasm_module.clear();
+                arg.clear();
+            }
+            ProposalAction::SetNamedCanisterIds(_)
+            | ProposalAction::SetNamedPrincipals { .. }
+            | ProposalAction::WithdrawCycles { .. }
+            | ProposalAction::RollbackState
+            | ProposalAction::RestoreBackup { .. } => {}
+        }
+    }
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub enum ProposalStatus {
+    Open,
+    /// enough approvals, waiting to be executed by the call which approved it
+    Approved,
+    Executing,
+    Executed,
+    Failed(ErrorInfo),
+    /// too many rejections to still reach the required approvals
+    Rejected,
+    Expired,
+}
+
+impl ProposalStatus {
+    pub fn is_closed(&self) -> bool {
+        matches!(
+            self,
+            ProposalStatus::Executed
+                | ProposalStatus::Failed(_)
+                | ProposalStatus::Rejected
+                | ProposalStatus::Expired
+        )
+    }
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Vote {
+    pub voter: Principal,
+    pub approve: bool,
+    pub voted_at: TimeInNs,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Proposal {
+    pub id: u64,
+    pub action: ProposalAction,
+    pub payload_digest: String,
+    pub proposer: Principal,
+    pub created_at: TimeInNs,
+    pub deadline: TimeInNs,
+    pub required_approvals: u32,
+    pub votes: Vec<Vote>,
+    pub status: ProposalStatus,
+    pub closed_at: Option<TimeInNs>,
+    /// `None` until the proposal is approved and its execution starts
+    pub execution_started_at: Option<TimeInNs>,
+}
+
+impl Proposal {
+    fn count(&self, approve: bool) -> u32 {
+        self.votes.iter().filter(|v| v.approve == approve).count() as u32
+    }
+
+    fn close(&mut self, status: ProposalStatus, now: TimeInNs) {
+        self.status = status;
+        self.closed_at = Some(now);
+        self.action.drop_payload();
+    }
+}
+
+/// A proposal without its action payload, small enough to be listed.
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct ProposalInfo {
+    pub id: u64,
+    pub action: String,
+    pub payload_digest: String,
+    pub proposer: Principal,
+    pub created_at: TimeInNs,
+    pub deadline: TimeInNs,
+    pub required_approvals: u32,
+    pub votes: Vec<Vote>,
+    pub status: ProposalStatus,
+    pub closed_at: Option<TimeInNs>,
+}
+
+impl ProposalInfo {
+    /// An open proposal past its deadline is reported as expired before the next update closes it.
+    pub fn new(proposal: &Proposal, now: TimeInNs) -> Self {
+        let status = match proposal.status {
+            ProposalStatus::Open if now > proposal.deadline => ProposalStatus::Expired,
+            ref status => status.clone(),
+        };
+        ProposalInfo {
+            id: proposal.id,
+            action: proposal.action.name().to_string(),
+            payload_digest: proposal.payload_digest.clone(),
+            proposer: proposal.proposer,
+            created_at: proposal.created_at,
+            deadline: proposal.deadline,
+            required_approvals: proposal.required_approvals,
+            votes: proposal.votes.clone(),
+            status,
+            closed_at: proposal.closed_at,
+        }
+    }
+}
+
+/// N of M approval, M being the principals of `approver_name`.
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct ProposalPolicy {
+    pub approver_name: String,
+    /// N, `None` for a majority of the approvers, at least `PROPOSAL_MIN_APPROVALS` either way
+    pub threshold: Option<u32>,
+    pub voting_period: TimeInNs,
+}
+
+impl Default for ProposalPolicy {
+    fn default() -> Self {
+        ProposalPolicy {
+            approver_name: PRINCIPAL_NAME_ADMIN.to_string(),
+            threshold: None,
+            voting_period: PROPOSAL_VOTING_PERIOD,
+        }
+    }
+}
+
+impl ProposalPolicy {
+    pub fn required_approvals(&self, approvers: usize) -> ServiceResult<u32> {
+        let approvers = approvers as u32;
+        let required = self
+            .threshold
+            .unwrap_or((approvers / 2 + 1).max(PROPOSAL_MIN_APPROVALS));
+        if required < PROPOSAL_MIN_APPROVALS || required > approvers {
+            return Err(CommonError::InvalidState {
+                detail: format!(
+                    "{} approvals required, at least {} and at most the {} principals of {}",
+                    required, PROPOSAL_MIN_APPROVALS, approvers, self.approver_name
+                ),
+            });
+        }
+        Ok(required)
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct ProposalStore {
+    pub policy: ProposalPolicy,
+    next_id: u64,
+    proposals: BTreeMap<u64, Proposal>,
+}
+
+impl ProposalStore {
+    pub fn get(&self, id: u64) -> Option<&Proposal> {
+        self.proposals.get(&id)
+    }
+
+    fn get_mut(&mut self, id: u64) -> ServiceResult<&mut Proposal> {
+        self.proposals
+            .get_mut(&id)
+            .ok_or_else(|| CommonError::InvalidArgument {
+                field: "id".to_string(),
+                detail: format!("proposal {} not found", id),
+            })
+    }
+
+    /// The proposer approves its own proposal, it still needs the approval of another approver.
+    pub fn propose(
+        &mut self,
+        action: ProposalAction,
+        proposer: Principal,
+        approvers: &HashSet<Principal>,
+        now: TimeInNs,
+    ) -> ServiceResult<u64> {
+        if !approvers.contains(&proposer) {
+            return Err(CommonError::PermissionDenied);
+        }
+        action.validate()?;
+        let required_approvals = self.policy.required_approvals(approvers.len())?;
+        self.expire(now);
+        let id = self.next_id;
+        self.next_id += 1;
+        let proposal = Proposal {
+            id,
+            payload_digest: action.digest(),
+            action,
+            proposer,
+            created_at: now,
+            deadline: TimeInNs(now.0 + self.policy.voting_period.0),
+            required_approvals,
+            votes: vec![Vote {
+                voter: proposer,
+                approve: true,
+                voted_at: now,
+            }],
+            status: ProposalStatus::Open,
+            closed_at: None,
+            execution_started_at: None,
+        };
+        info!(
+            "proposal {} to {} by {}",
+            id,
+            proposal.action.name(),
+            proposer
+        );
+        self.proposals.insert(id, proposal);
+        Ok(id)
+    }
+
+    pub fn vote(
+        &mut self,
+        id: u64,
+        voter: Principal,
+        approve: bool,
+        approvers: &HashSet<Principal>,
+        now: TimeInNs,
+    ) -> ServiceResult<ProposalStatus> {
+        if !approvers.contains(&voter) {
+            return Err(CommonError::PermissionDenied);
+        }
+        self.expire(now);
+        let proposal = self.get_mut(id)?;
+        if proposal.status != ProposalStatus::Open {
+            return Err(CommonError::InvalidState {
+                detail: format!("proposal {} is {:?}", id, proposal.status),
+            });
+        }
+        if proposal.votes.iter().any(|v| v.voter == voter) {
+            return Err(CommonError::InvalidArgument {
+                field: "voter".to_string(),
+                detail: format!("{} already voted on proposal {}", voter, id),
+            });
+        }
+        proposal.votes.push(Vote {
+            voter,
+            approve,
+            voted_at: now,
+        });
+        // approvers removed since the proposal was made can no longer tip it
+        let approvals = proposal
+            .votes
+            .iter()
+            .filter(|v| v.approve && approvers.contains(&v.voter))
+            .count() as u32;
+        let remaining = approvers
+            .iter()
+            .filter(|a| !proposal.votes.iter().any(|v| v.voter == **a))
+            .count() as u32;
+        if approvals >= proposal.required_approvals {
+            proposal.status = ProposalStatus::Approved;
+        } else if approvals + remaining < proposal.required_approvals {
+            proposal.close(ProposalStatus::Rejected, now);
+        }
+        info!(
+            "proposal {}: {} by {}, {} of {} approvals, {} rejections",
+            id,
+            if approve { "approved" } else { "rejected" },
+            voter,
+            approvals,
+            proposal.required_approvals,
+            proposal.count(false)
+        );
+        Ok(proposal.status.clone())
+    }
+
+    /// Hands out the action of an approved proposal once, the caller executes it and reports
+    /// back with `finish`.
+    pub fn start_execution(&mut self, id: u64, now: TimeInNs) -> ServiceResult<ProposalAction> {
+        let proposal = self.get_mut(id)?;
+        if proposal.status != ProposalStatus::Approved {
+            return Err(CommonError::InvalidState {
+                detail: format!("proposal {} is {:?}", id, proposal.status),
+            });
+        }
+        proposal.status = ProposalStatus::Executing;
+        proposal.execution_started_at = Some(now);
+        Ok(proposal.action.clone())
+    }
+
+    /// Closes a proposal left `Executing` by a message which trapped after an await, nothing
+    /// else would finish it. The action is not run again, it may have been executed partly.
+    pub fn fail_execution(&mut self, id: u64, now: TimeInNs) -> ServiceResult<()> {
+        let proposal = self.get_mut(id)?;
+        if proposal.status != ProposalStatus::Executing {
+            return Err(CommonError::InvalidState {
+                detail: format!("proposal {} is {:?}", id, proposal.status),
+            });
+        }
+        let started_at = proposal.execution_started_at.unwrap_or(proposal.created_at);
+        if now.0 < started_at.0 + PROPOSAL_EXECUTION_TIMEOUT.0 {
+            return Err(CommonError::Busy {
+                resource: format!("proposal {}", id),
+            });
+        }
+        let error = CommonError::InvalidState {
+            detail: "execution abandoned".to_string(),
+        };
+        warn!("proposal {} marked failed, its execution was abandoned", id);
+        proposal.close(ProposalStatus::Failed(ErrorInfo::from(error)), now);
+        Ok(())
+    }
+
+    pub fn finish(&mut self, id: u64, result: &ServiceResult<()>, now: TimeInNs) {
+        if let Ok(proposal) = self.get_mut(id) {
+            let status = match result {
+                Ok(_) => ProposalStatus::Executed,
+                Err(e) => ProposalStatus::Failed(ErrorInfo::from(e.clone())),
+            };
+            info!("proposal {} finished: {:?}", id, status);
+            proposal.close(status, now);
+        }
+    }
+
+    /// Closes the open proposals past their deadline.
+    pub fn expire(&mut self, now: TimeInNs) {
+        for proposal in self.proposals.values_mut() {
+            if proposal.status == ProposalStatus::Open && now > proposal.deadline {
+                proposal.close(ProposalStatus::Expired, now);
+            }
+        }
+    }
+
+    /// Newest proposals first.
+    pub fn get_page(
+        &self,
+        page: &GetPageInput,
+        now: TimeInNs,
+    ) -> ServiceResult<GetPageOutput<ProposalInfo>> {
+        Ok(paginate_iter(self.proposals.values().rev(), page)?
+            .map(|proposal| ProposalInfo::new(proposal, now)))
+    }
+}
+
+impl StableState for ProposalStore {
+    fn encode(&self) -> Vec<u8> {
+        let proposals: Vec<&Proposal> = self.proposals.values().collect();
+        encode_args((&self.policy, self.next_id, proposals)).unwrap()
+    }
+
+    fn decode(bytes: Vec<u8>) -> Result<Self, String> {
+        let (policy, next_id, proposals): (ProposalPolicy, u64, Vec<Proposal>) =
+            decode_args(&bytes).map_err(|e| format!("{:?}", e))?;
+        Ok(ProposalStore {
+            policy,
+            next_id,
+            proposals: proposals.into_iter().map(|p| (p.id, p)).collect(),
+        })
+    }
+
+    fn check_invariants(&self) -> Vec<String> {
+        let mut violations = vec![];
+        for (id, proposal) in self.proposals.iter() {
+            if *id != proposal.id || *id >= self.next_id {
+                violations.push(format!(
+                    "proposal {} is stored as {}, next id is {}",
+                    proposal.id, id, self.next_id
+                ));
+            }
+            if !proposal.status.is_closed() && proposal.action.validate().is_err() {
+                violations.push(format!("open proposal {} has no payload", id));
+            }
+            if proposal.status == ProposalStatus::Executing
+                && proposal.execution_started_at.is_none()
+            {
+                violations.push(format!("proposal {} is executing but never started", id));
+            }
+        }
+        violations
+    }
+}
+
+fn approvers() -> HashSet<Principal> {
+    let name = PROPOSAL_STORE.with(|s| s.borrow().policy.approver_name.clone());
+    get_named_principals(&name)
+}
+
+pub fn propose(action: ProposalAction, proposer: Principal, now: TimeInNs) -> ServiceResult<u64> {
+    let approvers = approvers();
+    PROPOSAL_STORE.with(|s| s.borrow_mut().propose(action, proposer, &approvers, now))
+}
+
+pub fn vote_proposal(
+    id: u64,
+    voter: Principal,
+    approve: bool,
+    now: TimeInNs,
+) -> ServiceResult<ProposalStatus> {
+    let approvers = approvers();
+    PROPOSAL_STORE.with(|s| s.borrow_mut().vote(id, voter, approve, &approvers, now))
+}
+
+pub fn start_proposal_execution(id: u64, now: TimeInNs) -> ServiceResult<ProposalAction> {
+    PROPOSAL_STORE.with(|s| s.borrow_mut().start_execution(id, now))
+}
+
+pub fn fail_proposal_execution(id: u64, now: TimeInNs) -> ServiceResult<()> {
+    PROPOSAL_STORE.with(|s| s.borrow_mut().fail_execution(id, now))
+}
+
+pub fn finish_proposal(id: u64, result: &ServiceResult<()>, now: TimeInNs) {
+    PROPOSAL_STORE.with(|s| s.borrow_mut().finish(id, result, now))
+}
+
+pub fn get_proposal(id: u64, now: TimeInNs) -> ServiceResult<ProposalInfo> {
+    PROPOSAL_STORE.with(|s| {
+        s.borrow()
+            .get(id)
+            .map(|proposal| ProposalInfo::new(proposal, now))
+            .ok_or_else(|| CommonError::InvalidArgument {
+                field: "id".to_string(),
+                detail: format!("proposal {} not found", id),
+            })
+    })
+}
+
+pub fn get_proposals(
+    page: &GetPageInput,
+    now: TimeInNs,
+) -> ServiceResult<GetPageOutput<ProposalInfo>> {
+    page.validate(get_config().max_page_limit)?;
+    PROPOSAL_STORE.with(|s| s.borrow().get_page(page, now))
+}
+
+crate::actor_response!(pub ProposalResponse, ProposalInfo);
+
+crate::actor_response!(pub GetProposalsResponse, GetPageOutput<ProposalInfo>);
@@ -0,0 +1,215 @@
+use rstest::*;
+
+use super::*;
+use crate::test_common::test::init_test;
+
+#[fixture]
+pub fn setup() {
+    init_test();
+}
+
+fn principal(i: u8) -> Principal {
+    Principal::from_slice(&[i; 29])
+}
+
+fn approvers(count: u8) -> HashSet<Principal> {
+    (1..=count).map(principal).collect()
+}
+
+fn withdraw() -> ProposalAction {
+    ProposalAction::WithdrawCycles {
+        canister_id: CanisterId(principal(9)),
+        amount: 1_000,
+    }
+}
+
+#[rstest]
+fn test_required_approvals(_setup: ()) {
+    let mut policy = ProposalPolicy::default();
+    assert!(policy.required_approvals(0).is_err());
+    assert!(policy.required_approvals(1).is_err(), "no single approver");
+    assert_eq!(policy.required_approvals(2), Ok(2));
+    assert_eq!(policy.required_approvals(3), Ok(2));
+    assert_eq!(policy.required_approvals(5), Ok(3));
+
+    policy.threshold = Some(4);
+    assert_eq!(policy.required_approvals(5), Ok(4));
+    assert!(policy.required_approvals(3).is_err());
+
+    policy.threshold = Some(1);
+    assert!(policy.required_approvals(5).is_err());
+}
+
+#[rstest]
+fn test_approved_by_threshold(_setup: ()) {
+    let mut store = ProposalStore::default();
+    let approvers = approvers(3);
+    let id = store
+        .propose(withdraw(), principal(1), &approvers, TimeInNs(0))
+        .unwrap();
+    assert_eq!(store.get(id).unwrap().status, ProposalStatus::Open);
+    assert!(store.start_execution(id, TimeInNs(0)).is_err());
+
+    let status = store
+        .vote(id, principal(2), true, &approvers, TimeInNs(1))
+        .unwrap();
+    assert_eq!(status, ProposalStatus::Approved);
+
+    assert_eq!(store.start_execution(id, TimeInNs(1)), Ok(withdraw()));
+    assert!(
+        store.start_execution(id, TimeInNs(1)).is_err(),
+        "executed only once"
+    );
+    store.finish(id, &Ok(()), TimeInNs(2));
+    let proposal = store.get(id).unwrap();
+    assert_eq!(proposal.status, ProposalStatus::Executed);
+    assert_eq!(proposal.closed_at, Some(TimeInNs(2)));
+}
+
+#[rstest]
+fn test_rejected_when_approvals_can_not_be_reached(_setup: ()) {
+    let mut store = ProposalStore::default();
+    let approvers = approvers(3);
+    let id = store
+        .propose(withdraw(), principal(1), &approvers, TimeInNs(0))
+        .unwrap();
+    store
+        .vote(id, principal(2), false, &approvers, TimeInNs(1))
+        .unwrap();
+    let status = store
+        .vote(id, principal(3), false, &approvers, TimeInNs(1))
+        .unwrap();
+    assert_eq!(status, ProposalStatus::Rejected);
+}
+
+#[rstest]
+fn test_only_approvers_vote_once(_setup: ()) {
+    let mut store = ProposalStore::default();
+    let approvers = approvers(3);
+    assert_eq!(
+        store.propose(withdraw(), principal(7), &approvers, TimeInNs(0)),
+        Err(CommonError::PermissionDenied)
+    );
+    let id = store
+        .propose(withdraw(), principal(1), &approvers, TimeInNs(0))
+        .unwrap();
+    assert_eq!(
+        store.vote(id, principal(7), true, &approvers, TimeInNs(1)),
+        Err(CommonError::PermissionDenied)
+    );
+    assert!(store
+        .vote(id, principal(1), true, &approvers, TimeInNs(1))
+        .is_err());
+}
+
+#[rstest]
+fn test_expired_after_deadline(_setup: ()) {
+    let mut store = ProposalStore::default();
+    let approvers = approvers(3);
+    let id = store
+        .propose(withdraw(), principal(1), &approvers, TimeInNs(0))
+        .unwrap();
+    let after_deadline = TimeInNs(PROPOSAL_VOTING_PERIOD.0 + 1);
+
+    let page = store
+        .get_page(
+            &GetPageInput {
+                offset: 0,
+                limit: 10,
+                ..Default::default()
+            },
+            after_deadline,
+        )
+        .unwrap();
+    assert_eq!(page.items[0].status, ProposalStatus::Expired);
+
+    assert!(store
+        .vote(id, principal(2), true, &approvers, after_deadline)
+        .is_err());
+    assert_eq!(store.get(id).unwrap().status, ProposalStatus::Expired);
+}
+
+#[rstest]
+fn test_payload_dropped_when_closed(_setup: ()) {
+    let mut store = ProposalStore::default();
+    let approvers = approvers(2);
+    let action = ProposalAction::LoadState {
+        state_data: vec![1, 2, 3],
+    };
+    let digest = action.digest();
+    let id = store
+        .propose(action, principal(1), &approvers, TimeInNs(0))
+        .unwrap();
+    store
+        .vote(id, principal(2), true, &approvers, TimeInNs(0))
+        .unwrap();
+    store.start_execution(id, TimeInNs(0)).unwrap();
+    store.finish(
+        id,
+        &Err(CommonError::InvalidState {
+            detail: "bad state".to_string(),
+        }),
+        TimeInNs(1),
+    );
+
+    let proposal = store.get(id).unwrap();
+    assert!(matches!(proposal.status, ProposalStatus::Failed(_)));
+    assert_eq!(
+        proposal.action,
+        ProposalAction::LoadState { state_data: vec![] }
+    );
+    assert_eq!(proposal.payload_digest, digest);
+}
+
+#[rstest]
+fn test_abandoned_execution_is_failed(_setup: ()) {
+    let mut store = ProposalStore::default();
+    let approvers = approvers(2);
+    let id = store
+        .propose(withdraw(), principal(1), &approvers, TimeInNs(0))
+        .unwrap();
+    assert!(
+        store.fail_execution(id, TimeInNs(0)).is_err(),
+        "not executing"
+    );
+    store
+        .vote(id, principal(2), true, &approvers, TimeInNs(1))
+        .unwrap();
+    store.start_execution(id, TimeInNs(1)).unwrap();
+
+    assert!(matches!(
+        store.fail_execution(id, TimeInNs(2)),
+        Err(CommonError::Busy { .. })
+    ));
+    let after_timeout = TimeInNs(1 + PROPOSAL_EXECUTION_TIMEOUT.0);
+    assert_eq!(store.fail_execution(id, after_timeout), Ok(()));
+    let proposal = store.get(id).unwrap();
+    assert!(matches!(proposal.status, ProposalStatus::Failed(_)));
+    assert_eq!(proposal.closed_at, Some(after_timeout));
+    assert!(store.start_execution(id, after_timeout).is_err());
+}
+
+#[rstest]
+fn test_encode_decode(_setup: ()) {
+    let mut store = ProposalStore::default();
+    store
+        .propose(withdraw(), principal(1), &approvers(2), TimeInNs(0))
+        .unwrap();
+
+    let decoded = ProposalStore::decode(store.encode()).unwrap();
+    assert_eq!(decoded.proposals, store.proposals);
+    assert_eq!(decoded.next_id, 1);
+}
+
+#[rstest]
+fn test_check_invariants(_setup: ()) {
+    let mut store = ProposalStore::default();
+    let id = store
+        .propose(withdraw(), principal(1), &approvers(2), TimeInNs(0))
+        .unwrap();
+    assert!(store.check_invariants().is_empty());
+
+    store.next_id = 0;
+    store.proposals.get_mut(&id).unwrap().action = ProposalAction::LoadState { state_data: vec![] };
+    assert_eq!(store.check_invariants().len(), 2);
+}
@@ -0,0 +1,359 @@
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+
+use candid::{CandidType, Deserialize, Principal};
+use log::{debug, warn};
+
+use crate::errors::{CommonError, ServiceResult};
+use crate::named_principals::{
+    is_named_principal, PRINCIPAL_NAME_ADMIN, PRINCIPAL_NAME_TIMER_TRIGGER,
+};
+use crate::types::{TimeInNs, TimeInSec};
+
+#[cfg(test)]
+mod tests;
+
+/// Buckets kept at most, one per (caller, method) pair which called recently.
+pub const RATE_LIMIT_MAX_BUCKETS: usize = 10_000;
+/// Limit of every method without its own, a burst of 60 calls then one per second.
+pub const RATE_LIMIT_DEFAULT: RateLimit = RateLimit {
+    capacity: 60,
+    refill_interval: TimeInNs(1_000_000_000),
+};
+
+thread_local! {
+    pub static RATE_LIMITER: RefCell<RateLimiter> = RefCell::new(RateLimiter::default());
+}
+
+/// Token bucket, a caller may burst `capacity` calls and gets one more every `refill_interval`.
+#[derive(CandidType, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RateLimit {
+    pub capacity: u32,
+    pub refill_interval: TimeInNs,
+}
+
+impl RateLimit {
+    /// `calls` per `period`, all of which may be used at once.
+    pub fn per(calls: u32, period: TimeInNs) -> Self {
+        RateLimit {
+            capacity: calls,
+            refill_interval: TimeInNs(period.0 / calls.max(1) as u64),
+        }
+    }
+
+    fn is_valid(&self) -> bool {
+        self.capacity > 0 && self.refill_interval.0 > 0
+    }
+}
+
+/// The limits an admin sets with the config, see `Config::rate_limits`.
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct RateLimits {
+    /// limit of the methods without an entry in `limits`, `None` leaves them unlimited
+    pub default_limit: Option<RateLimit>,
+    pub limits: BTreeMap<String, RateLimit>,
+}
+
+impl Default for RateLimits {
+    fn default() -> Self {
+        RateLimits {
+            default_limit: Some(RATE_LIMIT_DEFAULT),
+            limits: BTreeMap::new(),
+        }
+    }
+}
+
+impl RateLimits {
+    pub fn validate(&self) -> ServiceResult<()> {
+        let mut limits = self.default_limit.iter().chain(self.limits.values());
+        if limits.any(|limit| !limit.is_valid()) {
+            return Err(CommonError::InvalidArgument {
+                field: "rate_limits".to_string(),
+                detail: "capacity and refill_interval of a rate limit must be greater than 0"
+                    .to_string(),
+            });
+        }
+        if self.limits.keys().any(|method| method.is_empty()) {
+            return Err(CommonError::InvalidArgument {
+                field: "rate_limits".to_string(),
+                detail: "a method name must not be empty".to_string(),
+            });
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Bucket {
+    tokens: u32,
+    /// time the last token was added, the remainder of an interval carries over
+    refilled_at: TimeInNs,
+}
+
+impl Bucket {
+    fn full(limit: &RateLimit, now: TimeInNs) -> Self {
+        Bucket {
+            tokens: limit.capacity,
+            refilled_at: now,
+        }
+    }
+
+    fn refill(&mut self, limit: &RateLimit, now: TimeInNs) {
+        if self.tokens >= limit.capacity || limit.refill_interval.0 == 0 {
+            self.tokens = limit.capacity;
+            self.refilled_at = now;
+            return;
+        }
+        let elapsed = now.0.saturating_sub(self.refilled_at.0);
+        let added = elapsed / limit.refill_interval.0;
+        if added > 0 {
+            self.tokens = (self.tokens as u64 + added).min(limit.capacity as u64) as u32;
+            self.refilled_at = TimeInNs(self.refilled_at.0 + added * limit.refill_interval.0);
+        }
+    }
+
+    fn retry_after(&self, limit: &RateLimit, now: TimeInNs) -> TimeInSec {
+        let next_token_at = self.refilled_at.0 + limit.refill_interval.0;
+        let wait_ns = next_token_at.saturating_sub(now.0);
+        // rounded up, a client retrying after 0 seconds would be rejected again
+        TimeInSec(((wait_ns + 999_999_999) / 1_000_000_000).max(1))
+    }
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct MethodRateLimit {
+    pub method: String,
+    pub limit: RateLimit,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct RateLimiterStats {
+    pub buckets: u64,
+    pub max_buckets: u64,
+    pub allowed: u64,
+    pub rejected: u64,
+    /// buckets dropped to stay below `max_buckets` while they still held back a caller
+    pub evicted: u64,
+    pub default_limit: Option<RateLimit>,
+    pub limits: Vec<MethodRateLimit>,
+    pub exempt_principal_names: Vec<String>,
+}
+
+/// Limits calls per caller and method. Buckets live in memory only, an upgrade refills them.
+#[derive(Debug)]
+pub struct RateLimiter {
+    limits: HashMap<String, RateLimit>,
+    /// limit of the methods without an entry in `limits`, `None` leaves them unlimited
+    default_limit: Option<RateLimit>,
+    /// callers in one of these named principal groups are never limited
+    exempt_principal_names: Vec<String>,
+    buckets: HashMap<(Principal, String), Bucket>,
+    max_buckets: usize,
+    allowed: u64,
+    rejected: u64,
+    evicted: u64,
+}
+
+impl Default for RateLimiter {
+    fn default() -> Self {
+        RateLimiter {
+            limits: HashMap::new(),
+            default_limit: Some(RATE_LIMIT_DEFAULT),
+            exempt_principal_names: vec![
+                PRINCIPAL_NAME_ADMIN.to_string(),
+                PRINCIPAL_NAME_TIMER_TRIGGER.to_string(),
+            ],
+            buckets: HashMap::new(),
+            max_buckets: RATE_LIMIT_MAX_BUCKETS,
+            allowed: 0,
+            rejected: 0,
+            evicted: 0,
+        }
+    }
+}
+
+impl RateLimiter {
+    pub fn set_limit(&mut self, method: &str, limit: Option<RateLimit>) {
+        match limit {
+            Some(limit) => self.limits.insert(method.to_string(), limit),
+            None => self.limits.remove(method),
+        };
+        self.buckets.retain(|(_, m), _| m != method);
+    }
+
+    pub fn set_default_limit(&mut self, limit: Option<RateLimit>) {
+        self.default_limit = limit;
+        self.buckets.clear();
+    }
+
+    /// Replaces all limits, the buckets are only dropped if a limit changed.
+    pub fn configure(&mut self, limits: &RateLimits) {
+        let method_limits: HashMap<String, RateLimit> = limits.limits.clone().into_iter().collect();
+        if method_limits == self.limits && limits.default_limit == self.default_limit {
+            return;
+        }
+        self.limits = method_limits;
+        self.default_limit = limits.default_limit;
+        self.buckets.clear();
+    }
+
+    pub fn set_max_buckets(&mut self, max_buckets: usize) {
+        self.max_buckets = max_buckets;
+    }
+
+    /// `principal_name` must be one of the `PRINCIPAL_NAME_*` groups of `named_principals`.
+    pub fn exempt(&mut self, principal_name: &str) {
+        if !self
+            .exempt_principal_names
+            .iter()
+            .any(|n| n == principal_name)
+        {
+            self.exempt_principal_names.push(principal_name.to_string());
+        }
+    }
+
+    fn limit(&self, method: &str) -> Option<RateLimit> {
+        self.limits.get(method).copied().or(self.default_limit)
+    }
+
+    fn is_exempt(&self, caller: &Principal) -> bool {
+        self.exempt_principal_names
+            .iter()
+            .any(|name| is_named_principal(name, caller))
+    }
+
+    /// Takes a token of the bucket of `caller` and `method`.
+    pub fn check(&mut self, caller: &Principal, method: &str, now: TimeInNs) -> ServiceResult<()> {
+        let limit = match self.limit(method) {
+            Some(limit) if !self.is_exempt(caller) => limit,
+            _ => return Ok(()),
+        };
+        let key = (*caller, method.to_string());
+        if !self.buckets.contains_key(&key) {
+            self.make_room(now);
+        }
+        let bucket = self
+            .buckets
+            .entry(key)
+            .or_insert_with(|| Bucket::full(&limit, now));
+        bucket.refill(&limit, now);
+        if bucket.tokens == 0 {
+            self.rejected += 1;
+            let retry_after = bucket.retry_after(&limit, now);
+            debug!(
+                "rate limited {} calling {}, retry after {}",
+                caller, method, retry_after
+            );
+            return Err(CommonError::RateLimited { retry_after });
+        }
+        bucket.tokens -= 1;
+        self.allowed += 1;
+        Ok(())
+    }
+
+    /// Like `check` without taking a token, for `inspect_message` where state changes are discarded.
+    pub fn peek(&self, caller: &Principal, method: &str, now: TimeInNs) -> ServiceResult<()> {
+        let limit = match self.limit(method) {
+            Some(limit) if !self.is_exempt(caller) => limit,
+            _ => return Ok(()),
+        };
+        let mut bucket = match self.buckets.get(&(*caller, method.to_string())) {
+            Some(bucket) => bucket.clone(),
+            None => return Ok(()),
+        };
+        bucket.refill(&limit, now);
+        if bucket.tokens == 0 {
+            return Err(CommonError::RateLimited {
+                retry_after: bucket.retry_after(&limit, now),
+            });
+        }
+        Ok(())
+    }
+
+    /// Drops the buckets which are full again, they limit nobody. If that is not enough the
+    /// bucket refilled longest ago is dropped, its caller gets a full bucket on the next call.
+    fn make_room(&mut self, now: TimeInNs) {
+        if self.buckets.len() < self.max_buckets {
+            return;
+        }
+        let limits = &self.limits;
+        let default_limit = self.default_limit;
+        self.buckets.retain(|(_, method), bucket| {
+            match limits.get(method).copied().or(default_limit) {
+                Some(limit) => {
+                    let mut bucket = bucket.clone();
+                    bucket.refill(&limit, now);
+                    bucket.tokens < limit.capacity
+                }
+                None => false,
+            }
+        });
+        let evicted_before = self.evicted;
+        while self.buckets.len() >= self.max_buckets {
+            let oldest = self
+                .buckets
+                .iter()
+                .min_by_key(|(_, bucket)| bucket.refilled_at)
+                .map(|(key, _)| key.clone());
+            match oldest {
+                Some(key) => {
+                    self.buckets.remove(&key);
+                    self.evicted += 1;
+                }
+                None => break,
+            }
+        }
+        if self.evicted > evicted_before {
+            warn!(
+                "rate limiter is full, {} buckets evicted",
+                self.evicted - evicted_before
+            );
+        }
+    }
+
+    pub fn stats(&self) -> RateLimiterStats {
+        let mut limits: Vec<MethodRateLimit> = self
+            .limits
+            .iter()
+            .map(|(method, limit)| MethodRateLimit {
+                method: method.clone(),
+                limit: *limit,
+            })
+            .collect();
+        limits.sort_by(|a, b| a.method.cmp(&b.method));
+        RateLimiterStats {
+            buckets: self.buckets.len() as u64,
+            max_buckets: self.max_buckets as u64,
+            allowed: self.allowed,
+            rejected: self.rejected,
+            evicted: self.evicted,
+            default_limit: self.default_limit,
+            limits,
+            exempt_principal_names: self.exempt_principal_names.clone(),
+        }
+    }
+}
+
+pub fn check_rate_limit(caller: &Principal, method: &str, now: TimeInNs) -> ServiceResult<()> {
+    RATE_LIMITER.with(|l| l.borrow_mut().check(caller, method, now))
+}
+
+pub fn peek_rate_limit(caller: &Principal, method: &str, now: TimeInNs) -> ServiceResult<()> {
+    RATE_LIMITER.with(|l| l.borrow().peek(caller, method, now))
+}
+
+pub fn set_rate_limit(method: &str, limit: Option<RateLimit>) {
+    RATE_LIMITER.with(|l| l.borrow_mut().set_limit(method, limit))
+}
+
+pub fn set_default_rate_limit(limit: Option<RateLimit>) {
+    RATE_LIMITER.with(|l| l.borrow_mut().set_default_limit(limit))
+}
+
+pub fn configure_rate_limits(limits: &RateLimits) {
+    RATE_LIMITER.with(|l| l.borrow_mut().configure(limits))
+}
+
+pub fn get_rate_limiter_stats() -> RateLimiterStats {
+    RATE_LIMITER.with(|l| l.borrow().stats())
+}
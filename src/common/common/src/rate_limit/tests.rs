@@ -0,0 +1,155 @@
+use rstest::*;
+
+use super::*;
+use crate::constants::envs::COMMON_PRINCIPAL_NAME_ADMIN;
+use crate::test_common::test::init_test;
+
+#[fixture]
+pub fn setup() {
+    init_test();
+}
+
+const SECOND: u64 = 1_000_000_000;
+
+fn user(i: u8) -> Principal {
+    Principal::from_slice(&[9, i])
+}
+
+fn limiter() -> RateLimiter {
+    let mut limiter = RateLimiter::default();
+    // the tests opt in to a default limit
+    limiter.set_default_limit(None);
+    limiter.set_limit("transfer", Some(RateLimit::per(2, TimeInNs(10 * SECOND))));
+    limiter
+}
+
+#[rstest]
+fn test_bucket_empties_and_refills(_setup: ()) {
+    let mut limiter = limiter();
+    let now = TimeInNs(100 * SECOND);
+    limiter.check(&user(1), "transfer", now).unwrap();
+    limiter.check(&user(1), "transfer", now).unwrap();
+
+    let result = limiter.check(&user(1), "transfer", TimeInNs(now.0 + SECOND));
+    assert_eq!(
+        result,
+        Err(CommonError::RateLimited {
+            retry_after: TimeInSec(4)
+        })
+    );
+    assert!(limiter.peek(&user(1), "transfer", now).is_err());
+
+    // one token every 5 seconds
+    limiter
+        .check(&user(1), "transfer", TimeInNs(now.0 + 5 * SECOND))
+        .unwrap();
+    let stats = limiter.stats();
+    assert_eq!((stats.allowed, stats.rejected), (3, 1));
+}
+
+#[rstest]
+fn test_buckets_are_per_caller_and_method(_setup: ()) {
+    let mut limiter = limiter();
+    let now = TimeInNs(0);
+    for _ in 0..2 {
+        limiter.check(&user(1), "transfer", now).unwrap();
+    }
+    assert!(limiter.check(&user(2), "transfer", now).is_ok());
+    // methods without a limit are not limited without a default
+    for _ in 0..10 {
+        limiter.check(&user(1), "get_balance", now).unwrap();
+    }
+
+    limiter.set_default_limit(Some(RateLimit::per(1, TimeInNs(SECOND))));
+    limiter.check(&user(1), "get_balance", now).unwrap();
+    assert!(limiter.check(&user(1), "get_balance", now).is_err());
+}
+
+#[rstest]
+fn test_admin_is_exempt(_setup: ()) {
+    let mut limiter = limiter();
+    let admin = COMMON_PRINCIPAL_NAME_ADMIN[0];
+    for _ in 0..10 {
+        limiter.check(&admin, "transfer", TimeInNs(0)).unwrap();
+    }
+    assert_eq!(limiter.stats().buckets, 0);
+}
+
+#[rstest]
+fn test_buckets_are_bounded(_setup: ()) {
+    let mut limiter = limiter();
+    limiter.set_max_buckets(3);
+    for i in 0..3 {
+        limiter
+            .check(&user(i), "transfer", TimeInNs(i as u64))
+            .unwrap();
+    }
+
+    // none of the buckets is full again yet, the oldest one goes
+    limiter.check(&user(3), "transfer", TimeInNs(3)).unwrap();
+    let stats = limiter.stats();
+    assert_eq!((stats.buckets, stats.evicted), (3, 1));
+
+    // later all of them are full again and dropped without counting as evicted
+    limiter
+        .check(&user(4), "transfer", TimeInNs(60 * SECOND))
+        .unwrap();
+    let stats = limiter.stats();
+    assert_eq!((stats.buckets, stats.evicted), (1, 1));
+}
+
+#[rstest]
+fn test_retry_after_is_at_least_a_second(_setup: ()) {
+    let limit = RateLimit::per(1, TimeInNs(SECOND / 2));
+    let bucket = Bucket {
+        tokens: 0,
+        refilled_at: TimeInNs(0),
+    };
+    assert_eq!(bucket.retry_after(&limit, TimeInNs(1)), TimeInSec(1));
+}
+
+#[rstest]
+fn test_default_limit_applies_to_every_method(_setup: ()) {
+    let mut limiter = RateLimiter::default();
+    for _ in 0..RATE_LIMIT_DEFAULT.capacity {
+        limiter.check(&user(1), "test", TimeInNs(0)).unwrap();
+    }
+    assert!(limiter.check(&user(1), "test", TimeInNs(0)).is_err());
+}
+
+#[rstest]
+fn test_configure_keeps_buckets_of_unchanged_limits(_setup: ()) {
+    let mut limiter = RateLimiter::default();
+    let mut limits = RateLimits::default();
+    limits
+        .limits
+        .insert("transfer".to_string(), RateLimit::per(1, TimeInNs(SECOND)));
+    limiter.configure(&limits);
+    limiter.check(&user(1), "transfer", TimeInNs(0)).unwrap();
+
+    limiter.configure(&limits);
+    assert!(limiter.check(&user(1), "transfer", TimeInNs(0)).is_err());
+
+    limits.limits.clear();
+    limiter.configure(&limits);
+    assert_eq!(limiter.stats().buckets, 0);
+    assert!(limiter.stats().limits.is_empty());
+}
+
+#[rstest]
+fn test_invalid_limits_are_rejected(_setup: ()) {
+    let mut limits = RateLimits::default();
+    assert!(limits.validate().is_ok());
+
+    limits.limits.insert(
+        "transfer".to_string(),
+        RateLimit {
+            capacity: 1,
+            refill_interval: TimeInNs(0),
+        },
+    );
+    assert!(matches!(
+        limits.validate(),
+        Err(CommonError::InvalidArgument { .. })
+    ));
+}
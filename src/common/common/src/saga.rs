@@ -0,0 +1,529 @@
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Debug;
+use std::marker::PhantomData;
+use std::rc::Rc;
+
+use async_trait::async_trait;
+use candid::{decode_args, decode_one, encode_args, encode_one, CandidType, Deserialize, Nat};
+use log::{error, info, warn};
+
+use crate::canister_api::{IDFTApi, IICLedgerApi};
+use crate::errors::{CommonError, ServiceResult};
+use crate::scheduler::Job;
+use crate::state::StableState;
+use crate::timeout_lock::{try_acquire_lock, LockId, LockKey};
+use crate::types::ic_ledger_types::{Subaccount, TransferArgs, TransferError};
+use crate::types::TimeInNs;
+
+pub type SagaId = u64;
+
+pub const SAGA_MAX_FINISHED: usize = 1_000;
+pub const SAGA_RESUME_JOB_NAME: &str = "resume_sagas";
+
+thread_local! {
+    pub static SAGA_STORE: RefCell<SagaStore> = RefCell::new(SagaStore::default());
+    /// runners are code, they are registered again on every init / post_upgrade and never persisted
+    static SAGA_RUNNERS: RefCell<HashMap<String, Rc<dyn SagaRunner>>> = RefCell::new(HashMap::new());
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub enum SagaStepStatus {
+    Pending,
+    /// the step was started, if a saga is resumed with a step in this state the step
+    /// may or may not have taken effect, so it is compensated as well
+    Running,
+    Done,
+    Failed(String),
+    Compensating,
+    Compensated,
+    CompensationFailed(String),
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub enum SagaStatus {
+    Running,
+    Compensating,
+    Completed,
+    RolledBack,
+    /// a compensation failed, the saga needs manual intervention
+    Failed,
+}
+
+impl SagaStatus {
+    pub fn is_in_flight(&self) -> bool {
+        matches!(self, SagaStatus::Running | SagaStatus::Compensating)
+    }
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct SagaStepRecord {
+    /// human readable description of the step, for inspection only
+    pub description: String,
+    /// candid encoded step
+    pub step: Vec<u8>,
+    pub status: SagaStepStatus,
+    pub updated_at: TimeInNs,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct SagaRecord {
+    pub id: SagaId,
+    pub name: String,
+    pub status: SagaStatus,
+    pub steps: Vec<SagaStepRecord>,
+    pub error: Option<String>,
+    pub created_at: TimeInNs,
+    pub updated_at: TimeInNs,
+}
+
+enum SagaAction {
+    Execute(usize, Vec<u8>),
+    Compensate(usize, Vec<u8>),
+    Finished(SagaStatus),
+}
+
+impl SagaRecord {
+    fn next_action(&mut self, now: TimeInNs) -> SagaAction {
+        if self.status == SagaStatus::Running {
+            if self
+                .steps
+                .iter()
+                .any(|s| s.status == SagaStepStatus::Running)
+            {
+                warn!("saga {} was interrupted, rolling back", self.id);
+                self.status = SagaStatus::Compensating;
+                self.error = Some("interrupted".to_string());
+                self.updated_at = now;
+            } else if let Some((index, step)) = self
+                .steps
+                .iter()
+                .enumerate()
+                .find(|(_, s)| s.status == SagaStepStatus::Pending)
+            {
+                return SagaAction::Execute(index, step.step.clone());
+            } else {
+                self.status = SagaStatus::Completed;
+                self.updated_at = now;
+            }
+        }
+        if self.status == SagaStatus::Compensating {
+            if let Some((index, step)) = self.steps.iter().enumerate().rev().find(|(_, s)| {
+                matches!(
+                    s.status,
+                    SagaStepStatus::Done | SagaStepStatus::Running | SagaStepStatus::Compensating
+                )
+            }) {
+                return SagaAction::Compensate(index, step.step.clone());
+            }
+            self.status = SagaStatus::RolledBack;
+            self.updated_at = now;
+        }
+        SagaAction::Finished(self.status.clone())
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct SagaStore {
+    next_id: SagaId,
+    sagas: BTreeMap<SagaId, SagaRecord>,
+}
+
+impl SagaStore {
+    pub fn get(&self, id: SagaId) -> Option<&SagaRecord> {
+        self.sagas.get(&id)
+    }
+
+    pub fn get_mut(&mut self, id: SagaId) -> Option<&mut SagaRecord> {
+        self.sagas.get_mut(&id)
+    }
+
+    pub fn in_flight(&self) -> Vec<SagaRecord> {
+        self.sagas
+            .values()
+            .filter(|s| s.status.is_in_flight())
+            .cloned()
+            .collect()
+    }
+
+    pub fn all(&self) -> Vec<SagaRecord> {
+        self.sagas.values().cloned().collect()
+    }
+
+    fn insert(&mut self, name: &str, steps: Vec<SagaStepRecord>, now: TimeInNs) -> SagaId {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.sagas.insert(
+            id,
+            SagaRecord {
+                id,
+                name: name.to_string(),
+                status: SagaStatus::Running,
+                steps,
+                error: None,
+                created_at: now,
+                updated_at: now,
+            },
+        );
+        self.prune_finished();
+        id
+    }
+
+    /// Keeps the latest `SAGA_MAX_FINISHED` finished sagas, in flight sagas are never removed.
+    fn prune_finished(&mut self) {
+        let finished: Vec<SagaId> = self
+            .sagas
+            .values()
+            .filter(|s| !s.status.is_in_flight())
+            .map(|s| s.id)
+            .collect();
+        if finished.len() > SAGA_MAX_FINISHED {
+            for id in &finished[..finished.len() - SAGA_MAX_FINISHED] {
+                self.sagas.remove(id);
+            }
+        }
+    }
+}
+
+impl StableState for SagaStore {
+    fn encode(&self) -> Vec<u8> {
+        encode_args((self.next_id, &self.sagas)).unwrap()
+    }
+
+    fn decode(bytes: Vec<u8>) -> Result<Self, String> {
+        let (next_id, sagas): (SagaId, BTreeMap<SagaId, SagaRecord>) =
+            decode_args(&bytes).map_err(|e| format!("{:?}", e))?;
+        Ok(SagaStore { next_id, sagas })
+    }
+}
+
+/// Executes and compensates the steps of one kind of saga.
+/// Compensations must be idempotent and must tolerate steps that did not take effect,
+/// because an interrupted step is compensated without knowing whether it completed.
+#[async_trait(?Send)]
+pub trait SagaStepHandler<S> {
+    async fn execute(&self, step: &S) -> ServiceResult<()>;
+    async fn compensate(&self, step: &S) -> ServiceResult<()>;
+}
+
+/// Runs multi-step flows whose progress is recorded in `SAGA_STORE` before every await,
+/// so they can be resumed or rolled back after a trap or an upgrade.
+pub struct SagaExecutor<S, H: SagaStepHandler<S>> {
+    handler: H,
+    _step: PhantomData<S>,
+}
+
+fn saga_not_found(id: SagaId) -> CommonError {
+    CommonError::InvalidArgument {
+        field: "id".to_string(),
+        detail: format!("saga {} not found", id),
+    }
+}
+
+fn saga_error(detail: impl ToString) -> CommonError {
+    CommonError::InvalidState {
+        detail: detail.to_string(),
+    }
+}
+
+impl<S, H> SagaExecutor<S, H>
+where
+    S: CandidType + for<'de> Deserialize<'de> + Debug,
+    H: SagaStepHandler<S>,
+{
+    pub fn new(handler: H) -> Self {
+        Self {
+            handler,
+            _step: PhantomData,
+        }
+    }
+
+    pub fn handler(&self) -> &H {
+        &self.handler
+    }
+
+    /// Records a new saga, nothing is executed until `run` is called.
+    pub fn start(&self, name: &str, steps: Vec<S>, now: TimeInNs) -> SagaId {
+        let steps = steps
+            .iter()
+            .map(|step| SagaStepRecord {
+                description: format!("{:?}", step),
+                step: encode_one(step).unwrap(),
+                status: SagaStepStatus::Pending,
+                updated_at: now,
+            })
+            .collect();
+        let id = SAGA_STORE.with(|s| s.borrow_mut().insert(name, steps, now));
+        info!("saga {} {} started", id, name);
+        id
+    }
+
+    pub async fn start_and_run(
+        &self,
+        name: &str,
+        steps: Vec<S>,
+        now: TimeInNs,
+    ) -> ServiceResult<SagaStatus> {
+        let id = self.start(name, steps, now);
+        self.run(id, now).await
+    }
+
+    /// Drives a saga until it is completed, rolled back or failed.
+    pub async fn run(&self, id: SagaId, now: TimeInNs) -> ServiceResult<SagaStatus> {
+        let _guard = try_acquire_lock(LockKey::entity(LockId::Saga, id), now).ok_or_else(|| {
+            CommonError::Busy {
+                resource: format!("saga {}", id),
+            }
+        })?;
+        loop {
+            let action = SAGA_STORE.with(|s| {
+                s.borrow_mut()
+                    .get_mut(id)
+                    .map(|saga| saga.next_action(now))
+                    .ok_or_else(|| saga_not_found(id))
+            })?;
+            match action {
+                SagaAction::Execute(index, bytes) => {
+                    let step: S = decode_one(&bytes).map_err(saga_error)?;
+                    self.update_step(id, index, SagaStepStatus::Running, now);
+                    match self.handler.execute(&step).await {
+                        Ok(_) => self.update_step(id, index, SagaStepStatus::Done, now),
+                        Err(e) => {
+                            error!("saga {} step {} failed: {}", id, index, e);
+                            self.update_step(id, index, SagaStepStatus::Failed(e.to_string()), now);
+                            self.update_saga(
+                                id,
+                                SagaStatus::Compensating,
+                                Some(e.to_string()),
+                                now,
+                            );
+                        }
+                    }
+                }
+                SagaAction::Compensate(index, bytes) => {
+                    let step: S = decode_one(&bytes).map_err(saga_error)?;
+                    self.update_step(id, index, SagaStepStatus::Compensating, now);
+                    match self.handler.compensate(&step).await {
+                        Ok(_) => self.update_step(id, index, SagaStepStatus::Compensated, now),
+                        Err(e) => {
+                            error!("saga {} compensation of step {} failed: {}", id, index, e);
+                            self.update_step(
+                                id,
+                                index,
+                                SagaStepStatus::CompensationFailed(e.to_string()),
+                                now,
+                            );
+                            self.update_saga(id, SagaStatus::Failed, Some(e.to_string()), now);
+                        }
+                    }
+                }
+                SagaAction::Finished(status) => {
+                    info!("saga {} finished: {:?}", id, status);
+                    return Ok(status);
+                }
+            }
+        }
+    }
+
+    /// Resumes every in flight saga, e.g. from `post_upgrade` or a timer.
+    pub async fn resume_all(&self, now: TimeInNs) -> Vec<(SagaId, ServiceResult<SagaStatus>)> {
+        let ids: Vec<SagaId> =
+            SAGA_STORE.with(|s| s.borrow().in_flight().iter().map(|s| s.id).collect());
+        let mut results = vec![];
+        for id in ids {
+            results.push((id, self.run(id, now).await));
+        }
+        results
+    }
+
+    /// Stops executing new steps of a running saga and rolls back the completed ones on the next `run`.
+    pub fn abort(&self, id: SagaId, now: TimeInNs) -> ServiceResult<()> {
+        SAGA_STORE.with(|s| {
+            let mut store = s.borrow_mut();
+            let saga = store.get_mut(id).ok_or_else(|| saga_not_found(id))?;
+            if saga.status != SagaStatus::Running {
+                return Err(saga_error(format!("saga {} is not running", id)));
+            }
+            saga.status = SagaStatus::Compensating;
+            saga.error = Some("aborted".to_string());
+            saga.updated_at = now;
+            Ok(())
+        })
+    }
+
+    fn update_step(&self, id: SagaId, index: usize, status: SagaStepStatus, now: TimeInNs) {
+        SAGA_STORE.with(|s| {
+            if let Some(saga) = s.borrow_mut().get_mut(id) {
+                saga.steps[index].status = status;
+                saga.steps[index].updated_at = now;
+                saga.updated_at = now;
+            }
+        });
+    }
+
+    fn update_saga(&self, id: SagaId, status: SagaStatus, error: Option<String>, now: TimeInNs) {
+        SAGA_STORE.with(|s| {
+            if let Some(saga) = s.borrow_mut().get_mut(id) {
+                saga.status = status;
+                saga.error = error;
+                saga.updated_at = now;
+            }
+        });
+    }
+}
+
+/// A `SagaExecutor` without its step type, so the executors of every saga name can be registered
+/// together and resumed by `resume_saga`.
+#[async_trait(?Send)]
+pub trait SagaRunner {
+    async fn run_saga(&self, id: SagaId, now: TimeInNs) -> ServiceResult<SagaStatus>;
+}
+
+#[async_trait(?Send)]
+impl<S, H> SagaRunner for SagaExecutor<S, H>
+where
+    S: CandidType + for<'de> Deserialize<'de> + Debug,
+    H: SagaStepHandler<S>,
+{
+    async fn run_saga(&self, id: SagaId, now: TimeInNs) -> ServiceResult<SagaStatus> {
+        self.run(id, now).await
+    }
+}
+
+/// Registers the executor of the sagas started under `name`, must be called from init and
+/// post_upgrade since runners are not persisted.
+pub fn register_saga_runner(name: &str, runner: Rc<dyn SagaRunner>) {
+    SAGA_RUNNERS.with(|r| r.borrow_mut().insert(name.to_string(), runner));
+    info!("saga runner {} registered", name);
+}
+
+/// Runs saga `id` with the runner registered for its name, until it is completed, rolled back
+/// or failed.
+pub async fn resume_saga(id: SagaId, now: TimeInNs) -> ServiceResult<SagaStatus> {
+    let name = get_saga(id)
+        .map(|saga| saga.name)
+        .ok_or_else(|| saga_not_found(id))?;
+    let runner = SAGA_RUNNERS
+        .with(|r| r.borrow().get(&name).cloned())
+        .ok_or_else(|| saga_error(format!("no runner is registered for saga {}", name)))?;
+    runner.run_saga(id, now).await
+}
+
+/// Resumes every in flight saga, those interrupted by a trap or an upgrade included.
+pub async fn resume_in_flight_sagas(now: TimeInNs) -> Vec<(SagaId, ServiceResult<SagaStatus>)> {
+    let ids: Vec<SagaId> = get_in_flight_sagas().iter().map(|s| s.id).collect();
+    let mut results = vec![];
+    for id in ids {
+        results.push((id, resume_saga(id, now).await));
+    }
+    results
+}
+
+/// Periodically resumes the in flight sagas, nothing else runs a saga whose message trapped.
+pub struct SagaResumeJob;
+
+#[async_trait(?Send)]
+impl Job for SagaResumeJob {
+    async fn run(&self, now: TimeInNs) -> ServiceResult<()> {
+        let failed: Vec<String> = resume_in_flight_sagas(now)
+            .await
+            .into_iter()
+            .filter_map(|(id, result)| result.err().map(|e| format!("saga {}: {}", id, e)))
+            .collect();
+        if failed.is_empty() {
+            Ok(())
+        } else {
+            Err(saga_error(failed.join(", ")))
+        }
+    }
+}
+
+/// A transfer of a DFT token or of ICP. `created_at` / `created_at_time` are fixed when the step
+/// is recorded, the token deduplicates a transfer sent again with them, so a resumed or
+/// compensated step is not paid twice.
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub enum TokenTransfer {
+    Dft {
+        from_sub_account: Option<Subaccount>,
+        to: String,
+        value: Nat,
+        created_at: u64,
+    },
+    IcLedger(TransferArgs),
+}
+
+/// A step of a refund flow, e.g. taking a payment, compensated by `refund`.
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct TransferStep {
+    pub transfer: TokenTransfer,
+    /// `None` when the transfer is not undone, e.g. the final payout of the flow
+    pub refund: Option<TokenTransfer>,
+}
+
+/// Executes `TransferStep`s with the DFT token `dft_api` and the ICP ledger `ledger_api`.
+pub struct TransferStepHandler<D: IDFTApi, L: IICLedgerApi> {
+    dft_api: D,
+    ledger_api: L,
+}
+
+impl<D: IDFTApi, L: IICLedgerApi> TransferStepHandler<D, L> {
+    pub fn new(dft_api: D, ledger_api: L) -> Self {
+        TransferStepHandler {
+            dft_api,
+            ledger_api,
+        }
+    }
+
+    async fn send(&self, transfer: &TokenTransfer) -> ServiceResult<()> {
+        match transfer {
+            TokenTransfer::Dft {
+                from_sub_account,
+                to,
+                value,
+                created_at,
+            } => {
+                self.dft_api
+                    .transfer(
+                        *from_sub_account,
+                        to.clone(),
+                        value.clone(),
+                        Some(*created_at),
+                    )
+                    .await?;
+                Ok(())
+            }
+            TokenTransfer::IcLedger(args) => match self.ledger_api.transfer(args.clone()).await? {
+                Ok(_) => Ok(()),
+                // sent before the saga was interrupted, the ledger did not apply it twice
+                Err(TransferError::TxDuplicate { .. }) => Ok(()),
+                Err(e) => Err(saga_error(format!("ledger transfer failed, {}", e))),
+            },
+        }
+    }
+}
+
+#[async_trait(?Send)]
+impl<D: IDFTApi, L: IICLedgerApi> SagaStepHandler<TransferStep> for TransferStepHandler<D, L> {
+    async fn execute(&self, step: &TransferStep) -> ServiceResult<()> {
+        self.send(&step.transfer).await
+    }
+
+    async fn compensate(&self, step: &TransferStep) -> ServiceResult<()> {
+        match &step.refund {
+            Some(refund) => self.send(refund).await,
+            None => Ok(()),
+        }
+    }
+}
+
+pub fn get_saga(id: SagaId) -> Option<SagaRecord> {
+    SAGA_STORE.with(|s| s.borrow().get(id).cloned())
+}
+
+pub fn get_in_flight_sagas() -> Vec<SagaRecord> {
+    SAGA_STORE.with(|s| s.borrow().in_flight())
+}
+
+crate::actor_response!(pub GetSagasResponse, Vec<SagaRecord>);
+
+crate::actor_response!(pub SagaStatusResponse, SagaStatus);
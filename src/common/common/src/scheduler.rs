@@ -0,0 +1,250 @@
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::rc::Rc;
+
+use async_trait::async_trait;
+use candid::{decode_args, encode_args, CandidType, Deserialize};
+use log::{debug, error, info, warn};
+
+use crate::errors::ServiceResult;
+use crate::state::StableState;
+use crate::timeout_lock::{try_acquire_lock, LockId, LockKey};
+use crate::types::TimeInNs;
+
+#[cfg(test)]
+mod tests;
+
+pub const JOB_HISTORY_LEN: usize = 20;
+
+thread_local! {
+    pub static JOB_STORE: RefCell<JobStore> = RefCell::new(JobStore::default());
+    /// handlers are code, they are registered again on every init / post_upgrade and never persisted
+    static JOB_HANDLERS: RefCell<HashMap<String, Rc<dyn Job>>> = RefCell::new(HashMap::new());
+}
+
+#[async_trait(?Send)]
+pub trait Job {
+    async fn run(&self, now: TimeInNs) -> ServiceResult<()>;
+}
+
+#[derive(CandidType, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum JobSchedule {
+    /// runs every `interval`, the first time one interval after registration
+    Periodic { interval: TimeInNs },
+    /// runs once at or after `at`
+    Once { at: TimeInNs },
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub enum JobRunOutcome {
+    Succeeded,
+    Failed(String),
+    /// the previous run still holds the job lock
+    Skipped,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct JobRun {
+    pub started_at: TimeInNs,
+    pub outcome: JobRunOutcome,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct JobRecord {
+    pub name: String,
+    pub schedule: JobSchedule,
+    /// `None` once a one-shot job has run
+    pub next_run_at: Option<TimeInNs>,
+    pub run_count: u64,
+    pub failure_count: u64,
+    pub last_run_at: Option<TimeInNs>,
+    pub last_error: Option<String>,
+    /// latest `JOB_HISTORY_LEN` runs, oldest first
+    pub history: Vec<JobRun>,
+}
+
+impl JobRecord {
+    fn new(name: &str, schedule: JobSchedule, now: TimeInNs) -> Self {
+        JobRecord {
+            name: name.to_string(),
+            schedule,
+            next_run_at: Some(first_run_at(schedule, now)),
+            run_count: 0,
+            failure_count: 0,
+            last_run_at: None,
+            last_error: None,
+            history: vec![],
+        }
+    }
+
+    pub fn is_due(&self, now: TimeInNs) -> bool {
+        matches!(self.next_run_at, Some(at) if at <= now)
+    }
+
+    fn record(&mut self, run: JobRun, now: TimeInNs) {
+        match &run.outcome {
+            JobRunOutcome::Succeeded => {
+                self.run_count += 1;
+                self.last_run_at = Some(run.started_at);
+                self.last_error = None;
+            }
+            JobRunOutcome::Failed(e) => {
+                self.run_count += 1;
+                self.failure_count += 1;
+                self.last_run_at = Some(run.started_at);
+                self.last_error = Some(e.clone());
+            }
+            JobRunOutcome::Skipped => {}
+        }
+        if run.outcome != JobRunOutcome::Skipped {
+            self.next_run_at = match self.schedule {
+                JobSchedule::Periodic { interval } => Some(now + interval),
+                JobSchedule::Once { .. } => None,
+            };
+        }
+        self.history.push(run);
+        if self.history.len() > JOB_HISTORY_LEN {
+            let overflow = self.history.len() - JOB_HISTORY_LEN;
+            self.history.drain(..overflow);
+        }
+    }
+}
+
+fn first_run_at(schedule: JobSchedule, now: TimeInNs) -> TimeInNs {
+    match schedule {
+        JobSchedule::Periodic { interval } => now + interval,
+        JobSchedule::Once { at } => at,
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct JobStore {
+    jobs: BTreeMap<String, JobRecord>,
+}
+
+impl JobStore {
+    pub fn get(&self, name: &str) -> Option<&JobRecord> {
+        self.jobs.get(name)
+    }
+
+    pub fn all(&self) -> Vec<JobRecord> {
+        self.jobs.values().cloned().collect()
+    }
+
+    fn due(&self, now: TimeInNs) -> Vec<String> {
+        self.jobs
+            .values()
+            .filter(|job| job.is_due(now))
+            .map(|job| job.name.clone())
+            .collect()
+    }
+
+    /// Keeps the history of a job registered before, its next run is only reset when the schedule changed.
+    fn register(&mut self, name: &str, schedule: JobSchedule, now: TimeInNs) {
+        match self.jobs.get_mut(name) {
+            Some(job) if job.schedule == schedule => {}
+            Some(job) => {
+                job.schedule = schedule;
+                job.next_run_at = Some(first_run_at(schedule, now));
+            }
+            None => {
+                self.jobs
+                    .insert(name.to_string(), JobRecord::new(name, schedule, now));
+            }
+        }
+    }
+
+    fn remove(&mut self, name: &str) -> Option<JobRecord> {
+        self.jobs.remove(name)
+    }
+
+    fn record(&mut self, name: &str, run: JobRun, now: TimeInNs) {
+        if let Some(job) = self.jobs.get_mut(name) {
+            job.record(run, now);
+        }
+    }
+}
+
+impl StableState for JobStore {
+    fn encode(&self) -> Vec<u8> {
+        encode_args((&self.jobs,)).unwrap()
+    }
+
+    fn decode(bytes: Vec<u8>) -> Result<Self, String> {
+        let (jobs,): (BTreeMap<String, JobRecord>,) =
+            decode_args(&bytes).map_err(|e| format!("{:?}", e))?;
+        Ok(JobStore { jobs })
+    }
+}
+
+/// Registers `job` under `name`, must be called from init and post_upgrade since handlers are not persisted.
+pub fn register_job(name: &str, schedule: JobSchedule, job: Rc<dyn Job>, now: TimeInNs) {
+    JOB_HANDLERS.with(|h| h.borrow_mut().insert(name.to_string(), job));
+    JOB_STORE.with(|s| s.borrow_mut().register(name, schedule, now));
+    info!("job {} registered: {:?}", name, schedule);
+}
+
+pub fn unregister_job(name: &str) -> Option<JobRecord> {
+    JOB_HANDLERS.with(|h| h.borrow_mut().remove(name));
+    JOB_STORE.with(|s| s.borrow_mut().remove(name))
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct JobRunReport {
+    pub name: String,
+    pub outcome: JobRunOutcome,
+}
+
+/// Runs every job which is due at `now`, one after another.
+/// A job is locked while it runs, so a trigger arriving during a long run skips it instead of
+/// running it twice.
+pub async fn run_due_jobs(now: TimeInNs) -> Vec<JobRunReport> {
+    let due = JOB_STORE.with(|s| s.borrow().due(now));
+    let mut reports = vec![];
+    for name in due {
+        let handler = JOB_HANDLERS.with(|h| h.borrow().get(&name).cloned());
+        let handler = match handler {
+            Some(handler) => handler,
+            None => {
+                warn!("job {} is due but has no handler registered", name);
+                continue;
+            }
+        };
+        let outcome = match try_acquire_lock(LockKey::named(LockId::SchedulerJob, &name), now) {
+            Some(_guard) => {
+                debug!("job {} started", name);
+                match handler.run(now).await {
+                    Ok(_) => JobRunOutcome::Succeeded,
+                    Err(e) => {
+                        error!("job {} failed: {}", name, e);
+                        JobRunOutcome::Failed(e.to_string())
+                    }
+                }
+            }
+            None => {
+                warn!("job {} is still running, skipped", name);
+                JobRunOutcome::Skipped
+            }
+        };
+        JOB_STORE.with(|s| {
+            s.borrow_mut().record(
+                &name,
+                JobRun {
+                    started_at: now,
+                    outcome: outcome.clone(),
+                },
+                now,
+            )
+        });
+        reports.push(JobRunReport { name, outcome });
+    }
+    reports
+}
+
+pub fn get_jobs() -> Vec<JobRecord> {
+    JOB_STORE.with(|s| s.borrow().all())
+}
+
+crate::actor_response!(pub TriggerJobsResponse, Vec<JobRunReport>);
+
+crate::actor_response!(pub GetJobsResponse, Vec<JobRecord>);
@@ -0,0 +1,188 @@
+use std::cell::Cell;
+
+use rstest::*;
+
+use super::*;
+use crate::errors::CommonError;
+use crate::test_common::test::init_test;
+use crate::timeout_lock::{release_lock_key, try_lock_key};
+
+#[fixture]
+pub fn setup() {
+    init_test();
+}
+
+const SECOND: u64 = 1_000_000_000;
+
+/// counts its runs, fails while `fail` is set
+#[derive(Default)]
+struct CountingJob {
+    runs: Cell<u32>,
+    fail: Cell<bool>,
+}
+
+#[async_trait(?Send)]
+impl Job for CountingJob {
+    async fn run(&self, _now: TimeInNs) -> ServiceResult<()> {
+        self.runs.set(self.runs.get() + 1);
+        if self.fail.get() {
+            return Err(CommonError::Unknown {
+                detail: "boom".to_string(),
+            });
+        }
+        Ok(())
+    }
+}
+
+fn job(name: &str) -> JobRecord {
+    JOB_STORE.with(|s| s.borrow().get(name).cloned().unwrap())
+}
+
+#[rstest]
+#[async_std::test]
+async fn test_periodic_job_runs_every_interval(_setup: ()) {
+    let counter = Rc::new(CountingJob::default());
+    let interval = TimeInNs(10 * SECOND);
+    register_job(
+        "periodic",
+        JobSchedule::Periodic { interval },
+        counter.clone(),
+        TimeInNs(0),
+    );
+
+    assert!(run_due_jobs(TimeInNs(5 * SECOND)).await.is_empty());
+    let reports = run_due_jobs(TimeInNs(10 * SECOND)).await;
+    assert_eq!(
+        reports,
+        vec![JobRunReport {
+            name: "periodic".to_string(),
+            outcome: JobRunOutcome::Succeeded
+        }]
+    );
+    assert!(run_due_jobs(TimeInNs(15 * SECOND)).await.is_empty());
+    run_due_jobs(TimeInNs(20 * SECOND)).await;
+
+    assert_eq!(counter.runs.get(), 2);
+    let record = job("periodic");
+    assert_eq!(record.run_count, 2);
+    assert_eq!(record.last_run_at, Some(TimeInNs(20 * SECOND)));
+    assert_eq!(record.next_run_at, Some(TimeInNs(30 * SECOND)));
+}
+
+#[rstest]
+#[async_std::test]
+async fn test_one_shot_job_runs_once(_setup: ()) {
+    let counter = Rc::new(CountingJob::default());
+    register_job(
+        "once",
+        JobSchedule::Once {
+            at: TimeInNs(3 * SECOND),
+        },
+        counter.clone(),
+        TimeInNs(0),
+    );
+
+    run_due_jobs(TimeInNs(3 * SECOND)).await;
+    run_due_jobs(TimeInNs(100 * SECOND)).await;
+
+    assert_eq!(counter.runs.get(), 1);
+    assert_eq!(job("once").next_run_at, None);
+}
+
+#[rstest]
+#[async_std::test]
+async fn test_failure_is_recorded(_setup: ()) {
+    let counter = Rc::new(CountingJob::default());
+    counter.fail.set(true);
+    let interval = TimeInNs(SECOND);
+    register_job(
+        "failing",
+        JobSchedule::Periodic { interval },
+        counter.clone(),
+        TimeInNs(0),
+    );
+
+    run_due_jobs(TimeInNs(SECOND)).await;
+    let record = job("failing");
+    assert_eq!(record.failure_count, 1);
+    assert!(record.last_error.unwrap().contains("boom"));
+
+    counter.fail.set(false);
+    run_due_jobs(TimeInNs(2 * SECOND)).await;
+    let record = job("failing");
+    assert_eq!(record.run_count, 2);
+    assert_eq!(record.last_error, None);
+    assert_eq!(record.history.len(), 2);
+}
+
+#[rstest]
+#[async_std::test]
+async fn test_locked_job_is_skipped(_setup: ()) {
+    let counter = Rc::new(CountingJob::default());
+    let interval = TimeInNs(SECOND);
+    register_job(
+        "locked",
+        JobSchedule::Periodic { interval },
+        counter.clone(),
+        TimeInNs(0),
+    );
+    let key = LockKey::named(LockId::SchedulerJob, "locked");
+    assert!(try_lock_key(key.clone(), TimeInNs(SECOND)));
+
+    let reports = run_due_jobs(TimeInNs(SECOND)).await;
+    assert_eq!(reports[0].outcome, JobRunOutcome::Skipped);
+    assert_eq!(counter.runs.get(), 0);
+    // still due, it runs on the next trigger once the lock is free
+    assert!(job("locked").is_due(TimeInNs(SECOND)));
+
+    release_lock_key(&key);
+    run_due_jobs(TimeInNs(SECOND)).await;
+    assert_eq!(counter.runs.get(), 1);
+}
+
+#[rstest]
+#[async_std::test]
+async fn test_history_is_bounded(_setup: ()) {
+    let interval = TimeInNs(SECOND);
+    register_job(
+        "bounded",
+        JobSchedule::Periodic { interval },
+        Rc::new(CountingJob::default()),
+        TimeInNs(0),
+    );
+
+    for i in 1..=(JOB_HISTORY_LEN as u64 + 5) {
+        run_due_jobs(TimeInNs(i * SECOND)).await;
+    }
+
+    let record = job("bounded");
+    assert_eq!(record.history.len(), JOB_HISTORY_LEN);
+    assert_eq!(record.history[0].started_at, TimeInNs(6 * SECOND));
+}
+
+#[rstest]
+fn test_register_again_keeps_history(_setup: ()) {
+    let mut store = JobStore::default();
+    let schedule = JobSchedule::Periodic {
+        interval: TimeInNs(SECOND),
+    };
+    store.register("job", schedule, TimeInNs(0));
+    store.record(
+        "job",
+        JobRun {
+            started_at: TimeInNs(SECOND),
+            outcome: JobRunOutcome::Succeeded,
+        },
+        TimeInNs(SECOND),
+    );
+
+    store.register("job", schedule, TimeInNs(5 * SECOND));
+    assert_eq!(store.get("job").unwrap().run_count, 1);
+    assert_eq!(
+        store.get("job").unwrap().next_run_at,
+        Some(TimeInNs(2 * SECOND))
+    );
+
+    let decoded = JobStore::decode(store.encode()).unwrap();
+    assert_eq!(decoded.get("job").unwrap().history.len(), 1);
+}
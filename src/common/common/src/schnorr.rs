@@ -0,0 +1,66 @@
+use k256::schnorr;
+
+use crate::errors::{CommonError, ServiceResult};
+use crate::types::ic_management_types::SchnorrAlgorithm;
+
+#[cfg(test)]
+mod tests;
+
+fn invalid_argument(field: &str, detail: impl ToString) -> CommonError {
+    CommonError::InvalidArgument {
+        field: field.to_string(),
+        detail: detail.to_string(),
+    }
+}
+
+/// Verifies a BIP340 signature of a 32 bytes message.
+/// `public_key` may be the 33 bytes SEC1 compressed key returned by `schnorr_public_key`
+/// or the 32 bytes x-only key.
+pub fn verify_bip340_signature(
+    public_key: &[u8],
+    message: &[u8],
+    signature: &[u8],
+) -> ServiceResult<()> {
+    let x_only = match public_key.len() {
+        33 => &public_key[1..],
+        _ => public_key,
+    };
+    let key =
+        schnorr::VerifyingKey::from_bytes(x_only).map_err(|e| invalid_argument("public_key", e))?;
+    let signature =
+        schnorr::Signature::try_from(signature).map_err(|e| invalid_argument("signature", e))?;
+    let message: &[u8; 32] = message
+        .try_into()
+        .map_err(|_| invalid_argument("message", "BIP340 message must be 32 bytes"))?;
+    key.verify_prehashed(message, &signature)
+        .map_err(|e| invalid_argument("signature", e))
+}
+
+/// Verifies an Ed25519 signature, rejecting non canonical encodings.
+pub fn verify_ed25519_signature(
+    public_key: &[u8],
+    message: &[u8],
+    signature: &[u8],
+) -> ServiceResult<()> {
+    let key = ed25519_dalek::PublicKey::from_bytes(public_key)
+        .map_err(|e| invalid_argument("public_key", e))?;
+    let signature = ed25519_dalek::Signature::try_from(signature)
+        .map_err(|e| invalid_argument("signature", e))?;
+    key.verify_strict(message, &signature)
+        .map_err(|e| invalid_argument("signature", e))
+}
+
+/// Verifies a `sign_with_schnorr` signature against the key returned by `schnorr_public_key`.
+pub fn verify_schnorr_signature(
+    algorithm: SchnorrAlgorithm,
+    public_key: &[u8],
+    message: &[u8],
+    signature: &[u8],
+) -> ServiceResult<()> {
+    match algorithm {
+        SchnorrAlgorithm::Bip340Secp256k1 => {
+            verify_bip340_signature(public_key, message, signature)
+        }
+        SchnorrAlgorithm::Ed25519 => verify_ed25519_signature(public_key, message, signature),
+    }
+}
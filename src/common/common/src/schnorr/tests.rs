@@ -0,0 +1,79 @@
+use rstest::*;
+
+use super::*;
+
+// BIP340 test vector 0
+const BIP340_PUBLIC_KEY: &str = "F9308A019258C31049344F85F89D5229B531C845836F99B08601F113BCE036F9";
+const BIP340_MESSAGE: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+const BIP340_SIGNATURE: &str = "E907831F80848D1069A5371B402410364BDF1C5F8307B0084C55F1CE2DCA821525F66A4A85EA8B71E482A74F382D2CE5EBEEE8FDB2172F477DF4900D310536C0";
+
+// RFC 8032 test 1
+const ED25519_PUBLIC_KEY: &str = "d75a980182b10ab7d54bfed3c964073a0ee172f3daa62325af021a68f707511a";
+const ED25519_SIGNATURE: &str = "e5564300c360ac729086e2cc806e828a84877f1eb8e5d974d873e065224901555fb8821590a33bacc61e39701cf9b46bd25bf5f0595bbe24655141438e7a100b";
+
+mod bip340 {
+    use super::*;
+
+    #[rstest]
+    fn test_verify_x_only_key() {
+        let result = verify_schnorr_signature(
+            SchnorrAlgorithm::Bip340Secp256k1,
+            &hex::decode(BIP340_PUBLIC_KEY).unwrap(),
+            &hex::decode(BIP340_MESSAGE).unwrap(),
+            &hex::decode(BIP340_SIGNATURE).unwrap(),
+        );
+        assert_eq!(result, Ok(()));
+    }
+
+    #[rstest]
+    fn test_verify_sec1_key() {
+        let mut public_key = vec![0x02];
+        public_key.extend(hex::decode(BIP340_PUBLIC_KEY).unwrap());
+        let result = verify_bip340_signature(
+            &public_key,
+            &hex::decode(BIP340_MESSAGE).unwrap(),
+            &hex::decode(BIP340_SIGNATURE).unwrap(),
+        );
+        assert_eq!(result, Ok(()));
+    }
+
+    #[rstest]
+    fn test_verify_tampered_message() {
+        let mut message = hex::decode(BIP340_MESSAGE).unwrap();
+        message[0] = 1;
+        let result = verify_bip340_signature(
+            &hex::decode(BIP340_PUBLIC_KEY).unwrap(),
+            &message,
+            &hex::decode(BIP340_SIGNATURE).unwrap(),
+        );
+        assert!(matches!(
+            result,
+            Err(CommonError::InvalidArgument { field, .. }) if field == "signature"
+        ));
+    }
+}
+
+mod ed25519 {
+    use super::*;
+
+    #[rstest]
+    fn test_verify() {
+        let result = verify_schnorr_signature(
+            SchnorrAlgorithm::Ed25519,
+            &hex::decode(ED25519_PUBLIC_KEY).unwrap(),
+            b"",
+            &hex::decode(ED25519_SIGNATURE).unwrap(),
+        );
+        assert_eq!(result, Ok(()));
+    }
+
+    #[rstest]
+    fn test_verify_tampered_message() {
+        let result = verify_ed25519_signature(
+            &hex::decode(ED25519_PUBLIC_KEY).unwrap(),
+            b"x",
+            &hex::decode(ED25519_SIGNATURE).unwrap(),
+        );
+        assert!(result.is_err());
+    }
+}
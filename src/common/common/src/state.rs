@@ -1,6 +1,12 @@
 pub trait StableState: Sized {
     fn encode(&self) -> Vec<u8>;
     fn decode(bytes: Vec<u8>) -> Result<Self, String>;
+
+    /// Violations of the invariants the store relies on, checked before a snapshot is loaded.
+    /// Decoding only proves the types match, not that the content makes sense.
+    fn check_invariants(&self) -> Vec<String> {
+        vec![]
+    }
 }
 
 #[cfg(test)]
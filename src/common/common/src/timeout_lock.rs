@@ -1,16 +1,89 @@
 use core::cell::RefCell;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 
+use candid::{CandidType, Deserialize, Principal};
+use log::warn;
+
+use crate::errors::ServiceResult;
 use crate::types::*;
 
+#[cfg(test)]
+mod tests;
+
 #[repr(u8)]
-#[derive(Copy, Clone, Debug, Hash, PartialEq, Eq)]
+#[derive(Copy, Clone, Debug, Hash, PartialEq, Eq, PartialOrd, Ord, CandidType, Deserialize)]
 pub enum LockId {
     TokenServiceRefund,
+    CanisterFactoryUpgrade,
+    CanisterFactoryTopUp,
+    Saga,
+    SchedulerJob,
+}
+
+/// What a lock protects inside a `LockId`, so e.g. refunds of different users do not block each other.
+#[derive(Clone, Debug, Hash, PartialEq, Eq, PartialOrd, Ord, CandidType, Deserialize)]
+pub enum LockScope {
+    Global,
+    Principal(Principal),
+    Account(String),
+    Entity(u64),
+    Name(String),
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, PartialOrd, Ord, CandidType, Deserialize)]
+pub struct LockKey {
+    pub id: LockId,
+    pub scope: LockScope,
+}
+
+impl LockKey {
+    pub fn global(id: LockId) -> Self {
+        Self {
+            id,
+            scope: LockScope::Global,
+        }
+    }
+
+    pub fn principal(id: LockId, principal: Principal) -> Self {
+        Self {
+            id,
+            scope: LockScope::Principal(principal),
+        }
+    }
+
+    pub fn account(id: LockId, account: impl Into<String>) -> Self {
+        Self {
+            id,
+            scope: LockScope::Account(account.into()),
+        }
+    }
+
+    pub fn entity(id: LockId, entity_id: u64) -> Self {
+        Self {
+            id,
+            scope: LockScope::Entity(entity_id),
+        }
+    }
+
+    pub fn named(id: LockId, name: impl Into<String>) -> Self {
+        Self {
+            id,
+            scope: LockScope::Name(name.into()),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, CandidType, Deserialize)]
+pub struct HeldLock {
+    pub key: LockKey,
+    pub acquired_at: TimeInNs,
+    pub timeout: TimeInNs,
+    /// an expired lock is still listed until it is released or acquired again
+    pub expired: bool,
 }
 
 // 60 seconds
-const LOCKER_TIMEOUT_NS: TimeInNs = TimeInNs(60_000_000_000);
+pub const LOCKER_TIMEOUT_NS: TimeInNs = TimeInNs(60_000_000_000);
 
 thread_local! {
     static TIMEOUT_LOCKS: RefCell<TimeoutLocker> = RefCell::new(TimeoutLocker::default());
@@ -18,26 +91,137 @@ thread_local! {
 
 #[derive(Default, Debug)]
 pub struct TimeoutLocker {
-    lockers: HashMap<LockId, TimeInNs>,
+    lockers: HashMap<LockKey, TimeInNs>,
+    timeouts: HashMap<LockId, TimeInNs>,
+    /// `LOCKER_TIMEOUT_NS` unless configured
+    default_timeout: Option<TimeInNs>,
 }
 
 impl TimeoutLocker {
-    pub fn try_lock(&mut self, lock_id: LockId, now: TimeInNs) -> bool {
-        if let Some(lock_time) = self.lockers.get(&lock_id) {
-            if now - *lock_time > LOCKER_TIMEOUT_NS {
-                self.lockers.insert(lock_id, now);
+    pub fn timeout(&self, lock_id: LockId) -> TimeInNs {
+        self.timeouts
+            .get(&lock_id)
+            .cloned()
+            .or(self.default_timeout)
+            .unwrap_or(LOCKER_TIMEOUT_NS)
+    }
+
+    pub fn set_timeout(&mut self, lock_id: LockId, timeout: TimeInNs) {
+        self.timeouts.insert(lock_id, timeout);
+    }
+
+    /// Replaces every timeout, a lock id without an entry in `timeouts` uses `default_timeout`.
+    pub fn configure(&mut self, default_timeout: TimeInNs, timeouts: &BTreeMap<LockId, TimeInNs>) {
+        self.default_timeout = Some(default_timeout);
+        self.timeouts = timeouts.iter().map(|(id, t)| (*id, *t)).collect();
+    }
+
+    pub fn try_lock_key(&mut self, key: LockKey, now: TimeInNs) -> bool {
+        let timeout = self.timeout(key.id);
+        if let Some(lock_time) = self.lockers.get(&key) {
+            if now - *lock_time > timeout {
+                warn!("lock {:?} timed out, acquired at {}", key, lock_time);
+                self.lockers.insert(key, now);
                 return true;
             }
         } else {
-            self.lockers.insert(lock_id, now);
+            self.lockers.insert(key, now);
             return true;
         }
         false
     }
 
+    pub fn release_key(&mut self, key: &LockKey) {
+        self.lockers.remove(key);
+    }
+
+    /// Releases `key` only if it is still held since `acquired_at`. Once a lock timed out and was
+    /// acquired again, the former holder must not release it.
+    pub fn release_key_acquired_at(&mut self, key: &LockKey, acquired_at: TimeInNs) {
+        if self.lockers.get(key) == Some(&acquired_at) {
+            self.lockers.remove(key);
+        }
+    }
+
+    pub fn try_lock(&mut self, lock_id: LockId, now: TimeInNs) -> bool {
+        self.try_lock_key(LockKey::global(lock_id), now)
+    }
+
     pub fn release(&mut self, lock_id: LockId) {
-        self.lockers.remove(&lock_id);
+        self.release_key(&LockKey::global(lock_id));
     }
+
+    pub fn held_locks(&self, now: TimeInNs) -> Vec<HeldLock> {
+        let mut locks: Vec<HeldLock> = self
+            .lockers
+            .iter()
+            .map(|(key, acquired_at)| {
+                let timeout = self.timeout(key.id);
+                HeldLock {
+                    key: key.clone(),
+                    acquired_at: *acquired_at,
+                    timeout,
+                    expired: now - *acquired_at > timeout,
+                }
+            })
+            .collect();
+        locks.sort_by(|a, b| a.key.cmp(&b.key));
+        locks
+    }
+}
+
+/// Releases its lock when dropped, so early returns and `?` can not leak it.
+/// A trap does not run destructors, in that case the lock is only freed by its timeout.
+/// A guard whose lock timed out and was acquired by another call leaves the new holder's lock alone.
+#[derive(Debug)]
+pub struct TimeoutLockGuard {
+    key: LockKey,
+    acquired_at: TimeInNs,
+}
+
+impl TimeoutLockGuard {
+    pub fn key(&self) -> &LockKey {
+        &self.key
+    }
+
+    pub fn acquired_at(&self) -> TimeInNs {
+        self.acquired_at
+    }
+}
+
+impl Drop for TimeoutLockGuard {
+    fn drop(&mut self) {
+        TIMEOUT_LOCKS.with(|locker| {
+            locker
+                .borrow_mut()
+                .release_key_acquired_at(&self.key, self.acquired_at)
+        })
+    }
+}
+
+pub fn try_acquire_lock(key: LockKey, now: TimeInNs) -> Option<TimeoutLockGuard> {
+    if try_lock_key(key.clone(), now) {
+        Some(TimeoutLockGuard {
+            key,
+            acquired_at: now,
+        })
+    } else {
+        None
+    }
+}
+
+pub fn try_lock_key(key: LockKey, now: TimeInNs) -> bool {
+    TIMEOUT_LOCKS.with(|locker| {
+        let mut locker = locker.borrow_mut();
+        locker.try_lock_key(key, now)
+    })
+}
+
+pub fn release_lock_key(key: &LockKey) {
+    TIMEOUT_LOCKS.with(|locker| {
+        let mut locker = locker.borrow_mut();
+        locker.release_key(key)
+    })
 }
 
 pub fn try_lock_with_timeout(lock_id: LockId, now: TimeInNs) -> bool {
@@ -53,3 +237,23 @@ pub fn release_timeout_locker(lock_id: LockId) {
         locker.release(lock_id)
     })
 }
+
+pub fn set_lock_timeout(lock_id: LockId, timeout: TimeInNs) {
+    TIMEOUT_LOCKS.with(|locker| {
+        let mut locker = locker.borrow_mut();
+        locker.set_timeout(lock_id, timeout)
+    })
+}
+
+pub fn configure_lock_timeouts(default_timeout: TimeInNs, timeouts: &BTreeMap<LockId, TimeInNs>) {
+    TIMEOUT_LOCKS.with(|locker| {
+        let mut locker = locker.borrow_mut();
+        locker.configure(default_timeout, timeouts)
+    })
+}
+
+pub fn get_held_locks(now: TimeInNs) -> Vec<HeldLock> {
+    TIMEOUT_LOCKS.with(|locker| locker.borrow().held_locks(now))
+}
+
+crate::actor_response!(pub HeldLocksResponse, Vec<HeldLock>);
@@ -0,0 +1,117 @@
+use candid::Principal;
+use rstest::*;
+
+use super::*;
+
+fn user(index: u8) -> Principal {
+    Principal::from_slice(&[index; 29])
+}
+
+mod timeout_locker {
+    use super::*;
+
+    #[rstest]
+    fn test_lock_is_exclusive_until_released() {
+        let mut locker = TimeoutLocker::default();
+        assert!(locker.try_lock(LockId::TokenServiceRefund, TimeInNs(1)));
+        assert!(!locker.try_lock(LockId::TokenServiceRefund, TimeInNs(2)));
+        locker.release(LockId::TokenServiceRefund);
+        assert!(locker.try_lock(LockId::TokenServiceRefund, TimeInNs(3)));
+    }
+
+    #[rstest]
+    fn test_keys_do_not_block_each_other() {
+        let mut locker = TimeoutLocker::default();
+        let now = TimeInNs(1);
+        assert!(locker.try_lock_key(LockKey::principal(LockId::TokenServiceRefund, user(1)), now));
+        assert!(locker.try_lock_key(LockKey::principal(LockId::TokenServiceRefund, user(2)), now));
+        assert!(locker.try_lock_key(LockKey::account(LockId::TokenServiceRefund, "abc"), now));
+        assert!(locker.try_lock(LockId::TokenServiceRefund, now));
+        assert!(!locker.try_lock_key(LockKey::principal(LockId::TokenServiceRefund, user(1)), now));
+    }
+
+    #[rstest]
+    fn test_lock_expires_after_configured_timeout() {
+        let mut locker = TimeoutLocker::default();
+        locker.set_timeout(LockId::CanisterFactoryUpgrade, TimeInNs(100));
+        assert!(locker.try_lock(LockId::CanisterFactoryUpgrade, TimeInNs(1)));
+        assert!(!locker.try_lock(LockId::CanisterFactoryUpgrade, TimeInNs(101)));
+        assert!(locker.try_lock(LockId::CanisterFactoryUpgrade, TimeInNs(102)));
+
+        // other locks keep the default timeout
+        assert!(locker.try_lock(LockId::TokenServiceRefund, TimeInNs(1)));
+        assert!(!locker.try_lock(LockId::TokenServiceRefund, TimeInNs(102)));
+        assert!(locker.try_lock(LockId::TokenServiceRefund, LOCKER_TIMEOUT_NS + TimeInNs(2)));
+    }
+
+    #[rstest]
+    fn test_held_locks() {
+        let mut locker = TimeoutLocker::default();
+        locker.set_timeout(LockId::CanisterFactoryTopUp, TimeInNs(10));
+        locker.try_lock(LockId::TokenServiceRefund, TimeInNs(5));
+        locker.try_lock(LockId::CanisterFactoryTopUp, TimeInNs(1));
+
+        assert_eq!(
+            locker.held_locks(TimeInNs(20)),
+            vec![
+                HeldLock {
+                    key: LockKey::global(LockId::TokenServiceRefund),
+                    acquired_at: TimeInNs(5),
+                    timeout: LOCKER_TIMEOUT_NS,
+                    expired: false,
+                },
+                HeldLock {
+                    key: LockKey::global(LockId::CanisterFactoryTopUp),
+                    acquired_at: TimeInNs(1),
+                    timeout: TimeInNs(10),
+                    expired: true,
+                },
+            ]
+        );
+    }
+}
+
+mod guard {
+    use super::*;
+
+    fn do_locked_work(key: LockKey, fail: bool) -> Result<(), String> {
+        let _guard = try_acquire_lock(key, TimeInNs(1)).ok_or("locked")?;
+        if fail {
+            return Err("failed".to_string());
+        }
+        Ok(())
+    }
+
+    #[rstest]
+    fn test_guard_releases_on_drop() {
+        let key = LockKey::principal(LockId::TokenServiceRefund, user(3));
+        {
+            let guard = try_acquire_lock(key.clone(), TimeInNs(1));
+            assert!(guard.is_some());
+            assert!(try_acquire_lock(key.clone(), TimeInNs(2)).is_none());
+            assert_eq!(get_held_locks(TimeInNs(2)).len(), 1);
+        }
+        assert!(get_held_locks(TimeInNs(3)).is_empty());
+    }
+
+    #[rstest]
+    fn test_stale_guard_keeps_lock_of_new_holder() {
+        let key = LockKey::principal(LockId::CanisterFactoryUpgrade, user(5));
+        set_lock_timeout(LockId::CanisterFactoryUpgrade, TimeInNs(10));
+        let stale = try_acquire_lock(key.clone(), TimeInNs(1)).unwrap();
+        let current = try_acquire_lock(key.clone(), TimeInNs(20)).unwrap();
+
+        drop(stale);
+        assert!(try_acquire_lock(key.clone(), TimeInNs(21)).is_none());
+
+        drop(current);
+        assert!(try_acquire_lock(key, TimeInNs(22)).is_some());
+    }
+
+    #[rstest]
+    fn test_guard_releases_on_early_return() {
+        let key = LockKey::principal(LockId::TokenServiceRefund, user(4));
+        assert_eq!(do_locked_work(key.clone(), true), Err("failed".to_string()));
+        assert_eq!(do_locked_work(key, false), Ok(()));
+    }
+}
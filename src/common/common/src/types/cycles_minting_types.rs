@@ -1,6 +1,8 @@
-use candid::{CandidType, Deserialize};
+use candid::{CandidType, Deserialize, Nat, Principal};
 use serde::Serialize;
 
+use crate::types::ic_ledger_types::{BlockIndex, Memo, Subaccount, Tokens};
+
 #[derive(Serialize, Deserialize, CandidType, Clone, PartialEq, Eq, Debug)]
 pub struct IcpXdrConversionRateCertifiedResponse {
     pub data: IcpXdrConversionRate,
@@ -19,3 +21,139 @@ pub struct IcpXdrConversionRate {
     /// rate to four decimal places.
     pub xdr_permyriad_per_icp: u64,
 }
+
+/// Number of cycles that corresponds to 1 XDR.
+pub const CYCLES_PER_XDR: u128 = 1_000_000_000_000;
+
+/// Memo the cycles minting canister expects on a ledger transfer that creates a canister ("CREA").
+pub const MEMO_CREATE_CANISTER: Memo = Memo(0x41455243);
+
+/// Memo the cycles minting canister expects on a ledger transfer that tops up a canister ("TPUP").
+pub const MEMO_TOP_UP_CANISTER: Memo = Memo(0x50555054);
+
+pub type Cycles = Nat;
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct NotifyTopUpArg {
+    pub block_index: BlockIndex,
+    pub canister_id: Principal,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct NotifyCreateCanisterArg {
+    pub block_index: BlockIndex,
+    pub controller: Principal,
+    pub subnet_type: Option<String>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub enum NotifyError {
+    Refunded {
+        reason: String,
+        block_index: Option<BlockIndex>,
+    },
+    Processing,
+    TransactionTooOld(BlockIndex),
+    InvalidTransaction(String),
+    Other {
+        error_code: u64,
+        error_message: String,
+    },
+}
+
+pub type NotifyTopUpResult = Result<Cycles, NotifyError>;
+
+pub type NotifyCreateCanisterResult = Result<Principal, NotifyError>;
+
+impl IcpXdrConversionRate {
+    /// Converts an amount of ICP into cycles at this rate, rounding down.
+    /// Returns `None` if the result does not fit into `u128`.
+    pub fn tokens_to_cycles(&self, tokens: Tokens) -> Option<u128> {
+        let cycles = (tokens.e8s() as u128)
+            .checked_mul(self.xdr_permyriad_per_icp as u128)?
+            .checked_mul(CYCLES_PER_XDR)?
+            / (10_000 * Tokens::SUBDIVIDABLE_BY as u128);
+        Some(cycles)
+    }
+
+    /// Converts an amount of cycles into the ICP needed to mint them at this rate, rounding up.
+    /// Returns `None` if the rate is zero or the result does not fit into `Tokens`.
+    pub fn cycles_to_tokens(&self, cycles: u128) -> Option<Tokens> {
+        if self.xdr_permyriad_per_icp == 0 {
+            return None;
+        }
+        let divisor = self.xdr_permyriad_per_icp as u128 * CYCLES_PER_XDR;
+        let e8s = cycles
+            .checked_mul(10_000 * Tokens::SUBDIVIDABLE_BY as u128)?
+            .checked_add(divisor - 1)?
+            / divisor;
+        u64::try_from(e8s).ok().map(Tokens::from_e8s)
+    }
+}
+
+/// The subaccount of the cycles minting canister that a top up for `canister_id` must be sent to.
+pub fn top_up_subaccount(canister_id: &Principal) -> Subaccount {
+    let mut subaccount = [0u8; 32];
+    let bytes = canister_id.as_slice();
+    subaccount[0] = bytes.len() as u8;
+    subaccount[1..1 + bytes.len()].copy_from_slice(bytes);
+    Subaccount(subaccount)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rate(xdr_permyriad_per_icp: u64) -> IcpXdrConversionRate {
+        IcpXdrConversionRate {
+            timestamp_seconds: 1651571294,
+            xdr_permyriad_per_icp,
+        }
+    }
+
+    #[test]
+    fn test_tokens_to_cycles() {
+        // 1 ICP = 5.0 XDR
+        let rate = rate(50_000);
+        assert_eq!(
+            rate.tokens_to_cycles(Tokens::from_e8s(100_000_000)),
+            Some(5_000_000_000_000)
+        );
+        assert_eq!(rate.tokens_to_cycles(Tokens::ZERO), Some(0));
+    }
+
+    #[test]
+    fn test_tokens_to_cycles_overflow() {
+        assert_eq!(
+            rate(u64::MAX).tokens_to_cycles(Tokens::from_e8s(u64::MAX)),
+            None
+        );
+    }
+
+    #[test]
+    fn test_cycles_to_tokens() {
+        let rate = rate(50_000);
+        assert_eq!(
+            rate.cycles_to_tokens(5_000_000_000_000),
+            Some(Tokens::from_e8s(100_000_000))
+        );
+        // rounds up so the minted cycles are never less than requested
+        assert_eq!(rate.cycles_to_tokens(1), Some(Tokens::from_e8s(1)));
+        let tokens = rate.cycles_to_tokens(1_234_567_890_123).unwrap();
+        assert!(rate.tokens_to_cycles(tokens).unwrap() >= 1_234_567_890_123);
+    }
+
+    #[test]
+    fn test_cycles_to_tokens_zero_rate() {
+        assert_eq!(rate(0).cycles_to_tokens(1_000), None);
+    }
+
+    #[test]
+    fn test_top_up_subaccount() {
+        let canister_id = Principal::from_text("rkp4c-7iaaa-aaaaa-aaaca-cai").unwrap();
+        let subaccount = top_up_subaccount(&canister_id);
+        assert_eq!(subaccount.0[0], 10);
+        assert_eq!(&subaccount.0[1..11], canister_id.as_slice());
+        assert_eq!(&subaccount.0[11..], &[0u8; 21]);
+    }
+}
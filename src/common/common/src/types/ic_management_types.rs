@@ -1,6 +1,8 @@
 use candid::{CandidType, Deserialize, Nat, Principal};
 use serde::Serialize;
 
+use crate::constants::{COMMON_CANISTER_ENV, ENV_PRODUCTION, ENV_STAGING};
+
 #[derive(CandidType, Clone, Deserialize, Debug, PartialEq, PartialOrd, Ord, Eq)]
 pub struct CanisterIdRecord {
     pub canister_id: Principal,
@@ -35,8 +37,14 @@ pub struct CanisterStatusResponse {
     pub cycles: Nat,
 }
 
+#[derive(CandidType, Debug, Clone, Deserialize)]
+pub struct UpdateSettingsArgument {
+    pub canister_id: Principal,
+    pub settings: CanisterSettings,
+}
+
 // Install Wasm
-#[derive(CandidType, Deserialize)]
+#[derive(CandidType, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
 pub enum InstallMode {
     #[serde(rename = "install")]
     Install,
@@ -46,7 +54,7 @@ pub enum InstallMode {
     Upgrade,
 }
 
-#[derive(CandidType, Deserialize)]
+#[derive(CandidType, Deserialize, Clone)]
 pub struct CanisterInstall {
     pub mode: InstallMode,
     pub canister_id: Principal,
@@ -58,6 +66,7 @@ pub struct CanisterInstall {
 
 #[derive(CandidType, Clone, Deserialize)]
 pub struct CreateCanisterArgs {
+    /// cycles attached to the create_canister call, they become the initial balance of the new canister
     pub cycles: u64,
     pub settings: CanisterSettings,
 }
@@ -97,14 +106,109 @@ pub struct SignWithECDSAReply {
     pub signature: Vec<u8>,
 }
 
-#[derive(CandidType, Serialize, Debug, Clone)]
+#[derive(CandidType, Serialize, Debug, Clone, PartialEq, Eq)]
 pub struct EcdsaKeyId {
     pub curve: EcdsaCurve,
     pub name: String,
 }
 
-#[derive(CandidType, Serialize, Debug, Clone)]
+#[derive(CandidType, Serialize, Debug, Clone, PartialEq, Eq)]
 pub enum EcdsaCurve {
     #[serde(rename = "secp256k1")]
     Secp256k1,
 }
+
+#[derive(CandidType, Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SchnorrAlgorithm {
+    #[serde(rename = "bip340secp256k1")]
+    Bip340Secp256k1,
+    #[serde(rename = "ed25519")]
+    Ed25519,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct SchnorrKeyId {
+    pub algorithm: SchnorrAlgorithm,
+    pub name: String,
+}
+
+#[derive(CandidType, Serialize, Debug, Clone)]
+pub struct SchnorrPublicKeyArgs {
+    pub canister_id: Option<Principal>,
+    pub derivation_path: Vec<Vec<u8>>,
+    pub key_id: SchnorrKeyId,
+}
+
+#[derive(CandidType, Deserialize, Debug, Clone)]
+pub struct SchnorrPublicKeyResult {
+    pub public_key: Vec<u8>,
+    pub chain_code: Vec<u8>,
+}
+
+#[derive(CandidType, Serialize, Debug, Clone)]
+pub struct SignWithSchnorrArgs {
+    pub message: Vec<u8>,
+    pub derivation_path: Vec<Vec<u8>>,
+    pub key_id: SchnorrKeyId,
+}
+
+#[derive(CandidType, Deserialize, Debug, Clone)]
+pub struct SignWithSchnorrResult {
+    pub signature: Vec<u8>,
+}
+
+/// 10B cycles corresponds to 1 SDR cent. Assuming we can create 1 signature per
+/// second, that would come to  26k SDR per month if we spent the whole time
+/// creating signatures. At 13 nodes and 2k SDR per node per month this would
+/// cover the cost of the subnet.
+pub const ECDSA_SIGNATURE_FEE: u64 = 10_000_000_000;
+
+/// Fee attached to `sign_with_schnorr`, the same for both algorithms.
+pub const SCHNORR_SIGNATURE_FEE: u64 = 10_000_000_000;
+
+/// Threshold keys provided by the management canister, the same names are used for ECDSA and Schnorr.
+#[derive(CandidType, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ThresholdKeyName {
+    /// the key of the local replica
+    DfxTestKey,
+    /// test key on the IC, signatures are cheap but not backed up
+    TestKey1,
+    /// production key on the IC
+    Key1,
+}
+
+impl ThresholdKeyName {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ThresholdKeyName::DfxTestKey => "dfx_test_key",
+            ThresholdKeyName::TestKey1 => "test_key_1",
+            ThresholdKeyName::Key1 => "key_1",
+        }
+    }
+
+    pub fn ecdsa_key_id(&self) -> EcdsaKeyId {
+        EcdsaKeyId {
+            curve: EcdsaCurve::Secp256k1,
+            name: self.name().to_string(),
+        }
+    }
+
+    pub fn schnorr_key_id(&self, algorithm: SchnorrAlgorithm) -> SchnorrKeyId {
+        SchnorrKeyId {
+            algorithm,
+            name: self.name().to_string(),
+        }
+    }
+
+    pub fn for_env(env: &str) -> ThresholdKeyName {
+        match env {
+            ENV_PRODUCTION => ThresholdKeyName::Key1,
+            ENV_STAGING => ThresholdKeyName::TestKey1,
+            _ => ThresholdKeyName::DfxTestKey,
+        }
+    }
+
+    pub fn current() -> ThresholdKeyName {
+        ThresholdKeyName::for_env(COMMON_CANISTER_ENV)
+    }
+}
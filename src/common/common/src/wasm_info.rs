@@ -0,0 +1,234 @@
+use std::cell::RefCell;
+
+use candid::{decode_args, encode_args, CandidType, Deserialize};
+use serde_json::json;
+
+use crate::http::{HeaderField, HttpResponse};
+use crate::state::StableState;
+use crate::types::TimeInNs;
+
+#[cfg(test)]
+mod tests;
+
+thread_local! {
+    pub static INSTALL_HISTORY: RefCell<InstallHistory> = RefCell::new(InstallHistory::default());
+}
+
+/// Values written by `build_common::build_info::generate_build_info` in the build script of the
+/// actor, see `build_info!`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BuildInfo {
+    pub git_sha: &'static str,
+    pub semver: &'static str,
+    /// nanoseconds since the epoch
+    pub build_timestamp: &'static str,
+    pub env: &'static str,
+    pub rustc_version: &'static str,
+    /// separated by `,`
+    pub features: &'static str,
+    /// `name=version` separated by `,`
+    pub dependencies: &'static str,
+}
+
+/// The `BuildInfo` of the calling crate, its build script must call
+/// `build_common::build_info::generate_build_info`.
+#[macro_export]
+macro_rules! build_info {
+    () => {
+        $crate::wasm_info::BuildInfo {
+            git_sha: env!("COMMON_BUILD_GIT_SHA"),
+            semver: env!("CARGO_PKG_VERSION"),
+            build_timestamp: env!("COMMON_BUILD_TIMESTAMP"),
+            env: $crate::constants::COMMON_CANISTER_ENV,
+            rustc_version: env!("COMMON_BUILD_RUSTC_VERSION"),
+            features: env!("COMMON_BUILD_FEATURES"),
+            dependencies: env!("COMMON_BUILD_DEPENDENCIES"),
+        }
+    };
+}
+
+/// Embeds the build info of the calling crate as the `icp:public wasm_info` custom section of the
+/// wasm, readable with `dfx canister metadata <canister> wasm_info` without calling the canister.
+#[macro_export]
+macro_rules! wasm_info_metadata {
+    () => {
+        #[cfg_attr(target_arch = "wasm32", link_section = "icp:public wasm_info")]
+        #[used]
+        static WASM_INFO_METADATA: [u8; include_bytes!(concat!(
+            env!("OUT_DIR"),
+            "/build_info.json"
+        ))
+        .len()] = *include_bytes!(concat!(env!("OUT_DIR"), "/build_info.json"));
+    };
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct DependencyVersion {
+    pub name: String,
+    pub version: String,
+}
+
+#[derive(CandidType, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InstallKind {
+    Install,
+    Upgrade,
+}
+
+impl InstallKind {
+    fn name(&self) -> &'static str {
+        match self {
+            InstallKind::Install => "install",
+            InstallKind::Upgrade => "upgrade",
+        }
+    }
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct InstallRecord {
+    pub kind: InstallKind,
+    pub timestamp: TimeInNs,
+    /// of the installed wasm
+    pub git_sha: String,
+    pub semver: String,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct WasmInfo {
+    pub git_sha: String,
+    pub semver: String,
+    pub build_timestamp: TimeInNs,
+    /// `COMMON_CANISTER_ENV` the wasm was built for
+    pub env: String,
+    pub rustc_version: String,
+    pub features: Vec<String>,
+    pub dependencies: Vec<DependencyVersion>,
+    /// oldest first
+    pub install_history: Vec<InstallRecord>,
+}
+
+/// Installs and upgrades of this canister, appended by init and post_upgrade. Like the audit log,
+/// `load_state` keeps the history of the canister instead of the one in the loaded snapshot.
+#[derive(Clone, Debug, Default)]
+pub struct InstallHistory {
+    records: Vec<InstallRecord>,
+}
+
+impl InstallHistory {
+    pub fn record(&mut self, kind: InstallKind, build: &BuildInfo, now: TimeInNs) {
+        self.records.push(InstallRecord {
+            kind,
+            timestamp: now,
+            git_sha: build.git_sha.to_string(),
+            semver: build.semver.to_string(),
+        });
+    }
+
+    pub fn records(&self) -> &[InstallRecord] {
+        &self.records
+    }
+}
+
+impl StableState for InstallHistory {
+    fn encode(&self) -> Vec<u8> {
+        encode_args((&self.records,)).unwrap()
+    }
+
+    fn decode(bytes: Vec<u8>) -> Result<Self, String> {
+        let (records,): (Vec<InstallRecord>,) =
+            decode_args(&bytes).map_err(|e| format!("{:?}", e))?;
+        Ok(InstallHistory { records })
+    }
+
+    fn check_invariants(&self) -> Vec<String> {
+        self.records
+            .windows(2)
+            .filter(|pair| pair[1].timestamp < pair[0].timestamp)
+            .map(|pair| {
+                format!(
+                    "{} at {} is recorded after {} at {}",
+                    pair[1].kind.name(),
+                    pair[1].timestamp.0,
+                    pair[0].kind.name(),
+                    pair[0].timestamp.0
+                )
+            })
+            .collect()
+    }
+}
+
+fn split_list(list: &str) -> impl Iterator<Item = &str> {
+    list.split(',').filter(|item| !item.is_empty())
+}
+
+impl BuildInfo {
+    /// The info of this build with `install_history`, a timestamp which does not parse is 0.
+    pub fn to_wasm_info(&self, install_history: Vec<InstallRecord>) -> WasmInfo {
+        WasmInfo {
+            git_sha: self.git_sha.to_string(),
+            semver: self.semver.to_string(),
+            build_timestamp: TimeInNs(self.build_timestamp.parse().unwrap_or_default()),
+            env: self.env.to_string(),
+            rustc_version: self.rustc_version.to_string(),
+            features: split_list(self.features).map(|f| f.to_string()).collect(),
+            dependencies: split_list(self.dependencies)
+                .filter_map(|dependency| dependency.split_once('='))
+                .map(|(name, version)| DependencyVersion {
+                    name: name.to_string(),
+                    version: version.to_string(),
+                })
+                .collect(),
+            install_history,
+        }
+    }
+}
+
+impl WasmInfo {
+    pub fn to_json(&self) -> serde_json::Value {
+        let dependencies: serde_json::Map<String, serde_json::Value> = self
+            .dependencies
+            .iter()
+            .map(|d| (d.name.clone(), json!(d.version)))
+            .collect();
+        let install_history: Vec<serde_json::Value> = self
+            .install_history
+            .iter()
+            .map(|r| {
+                json!({
+                    "kind": r.kind.name(),
+                    "timestamp": r.timestamp.0,
+                    "git_sha": r.git_sha,
+                    "semver": r.semver,
+                })
+            })
+            .collect();
+        json!({
+            "git_sha": self.git_sha,
+            "semver": self.semver,
+            "build_timestamp": self.build_timestamp.0,
+            "env": self.env,
+            "rustc_version": self.rustc_version,
+            "features": self.features,
+            "dependencies": dependencies,
+            "install_history": install_history,
+        })
+    }
+}
+
+pub fn record_install(kind: InstallKind, build: &BuildInfo, now: TimeInNs) {
+    INSTALL_HISTORY.with(|h| h.borrow_mut().record(kind, build, now));
+}
+
+pub fn get_wasm_info(build: &BuildInfo) -> WasmInfo {
+    build.to_wasm_info(INSTALL_HISTORY.with(|h| h.borrow().records().to_vec()))
+}
+
+/// `GET /wasm_info` of `http_request`, the wasm info as JSON.
+pub fn wasm_info_http_response(build: &BuildInfo) -> HttpResponse {
+    let body = get_wasm_info(build).to_json().to_string();
+    let mut response = HttpResponse::new(200, body.into_bytes());
+    response.headers.push(HeaderField(
+        "Content-Type".to_string(),
+        "application/json".to_string(),
+    ));
+    response
+}
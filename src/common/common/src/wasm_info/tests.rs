@@ -0,0 +1,96 @@
+use rstest::*;
+
+use super::*;
+use crate::test_common::test::init_test;
+
+#[fixture]
+pub fn setup() {
+    init_test();
+}
+
+fn build() -> BuildInfo {
+    BuildInfo {
+        git_sha: "0123abc",
+        semver: "0.1.0",
+        build_timestamp: "1660000000000000000",
+        env: "dev",
+        rustc_version: "rustc 1.62.0",
+        features: "default,dev_env",
+        dependencies: "candid=0.7.14,ic-cdk=0.5.1",
+    }
+}
+
+#[rstest]
+fn test_to_wasm_info(_setup: ()) {
+    let info = build().to_wasm_info(vec![]);
+
+    assert_eq!(info.build_timestamp, TimeInNs(1660000000000000000));
+    assert_eq!(info.features, vec!["default", "dev_env"]);
+    assert_eq!(
+        info.dependencies,
+        vec![
+            DependencyVersion {
+                name: "candid".to_string(),
+                version: "0.7.14".to_string(),
+            },
+            DependencyVersion {
+                name: "ic-cdk".to_string(),
+                version: "0.5.1".to_string(),
+            },
+        ]
+    );
+}
+
+#[rstest]
+fn test_empty_lists(_setup: ()) {
+    let build = BuildInfo {
+        features: "",
+        dependencies: "",
+        build_timestamp: "unknown",
+        ..build()
+    };
+
+    let info = build.to_wasm_info(vec![]);
+
+    assert!(info.features.is_empty());
+    assert!(info.dependencies.is_empty());
+    assert_eq!(info.build_timestamp, TimeInNs(0));
+}
+
+#[rstest]
+fn test_install_history_round_trip(_setup: ()) {
+    let mut history = InstallHistory::default();
+    history.record(InstallKind::Install, &build(), TimeInNs(1));
+    history.record(InstallKind::Upgrade, &build(), TimeInNs(2));
+
+    let decoded = InstallHistory::decode(history.encode()).unwrap();
+
+    assert_eq!(decoded.records(), history.records());
+    assert_eq!(decoded.records()[1].kind, InstallKind::Upgrade);
+    assert!(decoded.check_invariants().is_empty());
+}
+
+#[rstest]
+fn test_install_history_out_of_order(_setup: ()) {
+    let mut history = InstallHistory::default();
+    history.record(InstallKind::Install, &build(), TimeInNs(5));
+    history.record(InstallKind::Upgrade, &build(), TimeInNs(2));
+
+    assert_eq!(
+        history.check_invariants(),
+        vec!["upgrade at 2 is recorded after install at 5"]
+    );
+}
+
+#[rstest]
+fn test_to_json(_setup: ()) {
+    let mut history = InstallHistory::default();
+    history.record(InstallKind::Install, &build(), TimeInNs(7));
+
+    let value = build().to_wasm_info(history.records().to_vec()).to_json();
+
+    assert_eq!(value["git_sha"], "0123abc");
+    assert_eq!(value["dependencies"]["ic-cdk"], "0.5.1");
+    assert_eq!(value["install_history"][0]["kind"], "install");
+    assert_eq!(value["install_history"][0]["timestamp"], 7);
+}
@@ -0,0 +1,49 @@
+use candid::candid_method;
+use rstest::*;
+
+use common::errors::{BooleanActorResponse, CommonError};
+
+common::actor_response!(pub GetNameResponse, String);
+
+common::actor_response!(pub GetLabelResponse, String);
+
+#[candid_method(query, rename = "get_name")]
+fn get_name() -> GetNameResponse {
+    GetNameResponse::new(Ok("name".to_string()))
+}
+
+#[candid_method(query, rename = "get_label")]
+fn get_label() -> GetLabelResponse {
+    GetLabelResponse::new(Err(CommonError::PermissionDenied))
+}
+
+#[candid_method(update, rename = "set_name")]
+fn set_name(_name: String) -> BooleanActorResponse {
+    BooleanActorResponse::new(Ok(true))
+}
+
+candid::export_service!();
+
+/// Responses of the same shape are exported under their own names instead of being merged.
+#[rstest]
+fn test_responses_are_named_per_endpoint_in_did() {
+    let did = __export_service();
+
+    assert!(did.contains("type GetNameResponse = variant"), "{}", did);
+    assert!(did.contains("type GetLabelResponse = variant"), "{}", did);
+    assert!(
+        did.contains("get_name : () -> (GetNameResponse) query;"),
+        "{}",
+        did
+    );
+    assert!(
+        did.contains("get_label : () -> (GetLabelResponse) query;"),
+        "{}",
+        did
+    );
+    assert!(
+        did.contains("set_name : (text) -> (BooleanActorResponse);"),
+        "{}",
+        did
+    );
+}
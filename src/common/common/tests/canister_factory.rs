@@ -0,0 +1,228 @@
+use std::cell::Cell;
+use std::rc::Rc;
+
+use candid::{Nat, Principal};
+use rstest::*;
+
+use common::canister_factory::*;
+use common::errors::{CommonError, ErrorInfo};
+use common::types::ic_management_types::*;
+use common::types::TimeInNs;
+use test_common::canister_api::*;
+use test_common::ic_api::init_test;
+use test_common::principal::*;
+
+fn settings() -> CanisterSettings {
+    CanisterSettings {
+        controllers: None,
+        compute_allocation: None,
+        memory_allocation: None,
+        freezing_threshold: None,
+    }
+}
+
+fn status(cycles: u64) -> CanisterStatusResponse {
+    CanisterStatusResponse {
+        status: CanisterStatus::Running,
+        settings: settings(),
+        module_hash: None,
+        controller: Principal::anonymous(),
+        memory_size: Nat::from(0),
+        cycles: Nat::from(cycles),
+    }
+}
+
+/// Every read advances the clock by one, so timestamps taken after an await differ from the ones before.
+fn ticking_factory(api: MockICManagementAPI) -> CanisterFactory<MockICManagementAPI> {
+    let time = Rc::new(Cell::new(0));
+    CanisterFactory::with_clock(
+        api,
+        Rc::new(move || {
+            time.set(time.get() + 1);
+            TimeInNs(time.get())
+        }),
+    )
+}
+
+#[rstest]
+#[async_std::test]
+async fn test_create_canister_installs_stored_wasm(
+    _init_test: (),
+    mut mock_ic_management_api: MockICManagementAPI,
+) {
+    let canister_id = mock_canister1().0;
+    mock_ic_management_api
+        .expect_create_canister()
+        .withf(|args| args.cycles == 1_000)
+        .returning(move |_| Ok(CanisterIdRecord { canister_id }));
+    mock_ic_management_api
+        .expect_install_code()
+        .withf(move |install| {
+            install.mode == InstallMode::Install
+                && install.canister_id == canister_id
+                && install.wasm_module == vec![1, 2, 3]
+        })
+        .returning(|_| Ok(()));
+
+    let factory = ticking_factory(mock_ic_management_api);
+    let hash = factory.set_wasm(vec![1, 2, 3]);
+    let created = factory.create_canister(settings(), 1_000, vec![]).await;
+
+    assert_eq!(created, Ok(canister_id));
+    let canisters = factory.get_canisters();
+    assert_eq!(canisters.len(), 1);
+    assert_eq!(canisters[0].status, ManagedCanisterStatus::Installed);
+    assert_eq!(canisters[0].wasm_hash, Some(hash));
+    assert!(canisters[0].updated_at > canisters[0].created_at);
+}
+
+#[rstest]
+#[async_std::test]
+async fn test_create_canister_without_wasm(
+    _init_test: (),
+    mock_ic_management_api: MockICManagementAPI,
+) {
+    let factory = ticking_factory(mock_ic_management_api);
+    let created = factory.create_canister(settings(), 1_000, vec![]).await;
+    assert!(matches!(created, Err(CommonError::InvalidState { .. })));
+    assert!(factory.get_canisters().is_empty());
+}
+
+#[rstest]
+#[async_std::test]
+async fn test_upgrade_in_batches_records_failures(
+    _init_test: (),
+    mut mock_ic_management_api: MockICManagementAPI,
+) {
+    let ids = vec![mock_canister1().0, mock_canister2().0, mock_canister3().0];
+    let mut created = ids.clone().into_iter();
+    mock_ic_management_api
+        .expect_create_canister()
+        .returning(move |_| {
+            Ok(CanisterIdRecord {
+                canister_id: created.next().unwrap(),
+            })
+        });
+    let failing = ids[1];
+    mock_ic_management_api
+        .expect_install_code()
+        .returning(move |install| {
+            if install.mode == InstallMode::Upgrade && install.canister_id == failing {
+                Err(ErrorInfo::new(6, "trapped"))
+            } else {
+                Ok(())
+            }
+        });
+
+    let factory = ticking_factory(mock_ic_management_api);
+    factory.set_wasm(vec![1]);
+    for _ in 0..3 {
+        factory
+            .create_canister(settings(), 0, vec![])
+            .await
+            .unwrap();
+    }
+    let new_hash = factory.set_wasm(vec![2]);
+
+    let progress = factory.upgrade_next_batch(2, vec![]).await.unwrap();
+    assert_eq!(progress.target_wasm_hash, Some(new_hash));
+    assert_eq!(progress.upgraded, 1);
+    assert_eq!(progress.failed, vec![failing]);
+    assert_eq!(progress.pending, 1);
+
+    let progress = factory.upgrade_next_batch(2, vec![]).await.unwrap();
+    assert_eq!(progress.upgraded, 2);
+    assert_eq!(progress.failed, vec![failing]);
+    assert_eq!(progress.pending, 0);
+    assert_eq!(factory.get_failed_canisters().len(), 1);
+}
+
+#[rstest]
+#[async_std::test]
+async fn test_top_up_canisters_below_threshold(
+    _init_test: (),
+    mut mock_ic_management_api: MockICManagementAPI,
+) {
+    let ids = vec![mock_canister1().0, mock_canister2().0];
+    let mut created = ids.clone().into_iter();
+    mock_ic_management_api
+        .expect_create_canister()
+        .returning(move |_| {
+            Ok(CanisterIdRecord {
+                canister_id: created.next().unwrap(),
+            })
+        });
+    mock_ic_management_api
+        .expect_install_code()
+        .returning(|_| Ok(()));
+    let low = ids[0];
+    mock_ic_management_api
+        .expect_canister_status()
+        .returning(move |record| {
+            if record.canister_id == low {
+                Ok(status(100))
+            } else {
+                Ok(status(10_000))
+            }
+        });
+    mock_ic_management_api
+        .expect_deposit_cycles()
+        .withf(move |record, cycles| record.canister_id == low && *cycles == 5_000)
+        .times(1)
+        .returning(|_, _| Ok(()));
+
+    let factory = ticking_factory(mock_ic_management_api);
+    factory.set_wasm(vec![1]);
+    for _ in 0..2 {
+        factory
+            .create_canister(settings(), 0, vec![])
+            .await
+            .unwrap();
+    }
+
+    let records = factory.top_up_canisters(1_000, 5_000).await.unwrap();
+    assert_eq!(
+        records,
+        vec![TopUpRecord {
+            canister_id: low,
+            cycles_before: Nat::from(100),
+            deposited: 5_000,
+        }]
+    );
+    assert!(factory
+        .get_canisters()
+        .iter()
+        .all(|c| c.last_checked_at > Some(c.updated_at)));
+}
+
+#[rstest]
+#[async_std::test]
+async fn test_commit_wasm_checks_hash_of_chunks(
+    _init_test: (),
+    mut mock_ic_management_api: MockICManagementAPI,
+) {
+    mock_ic_management_api
+        .expect_create_canister()
+        .returning(|_| {
+            Ok(CanisterIdRecord {
+                canister_id: mock_canister1().0,
+            })
+        });
+    mock_ic_management_api
+        .expect_install_code()
+        .withf(|install| install.wasm_module == vec![1, 2, 3, 4])
+        .returning(|_| Ok(()));
+
+    let factory = ticking_factory(mock_ic_management_api);
+    factory.upload_wasm_chunk(vec![1, 2]);
+    factory.upload_wasm_chunk(vec![3, 4]);
+    assert!(factory.commit_wasm(&wasm_hash(&[1, 2])).is_err());
+    assert_eq!(factory.get_upgrade_progress().target_wasm_hash, None);
+
+    factory.upload_wasm_chunk(vec![1, 2]);
+    assert_eq!(factory.upload_wasm_chunk(vec![3, 4]), 4);
+    let hash = wasm_hash(&[1, 2, 3, 4]);
+    assert_eq!(factory.commit_wasm(&hash), Ok(hash.clone()));
+    assert_eq!(factory.get_upgrade_progress().target_wasm_hash, Some(hash));
+    assert!(factory.create_canister(settings(), 0, vec![]).await.is_ok());
+}
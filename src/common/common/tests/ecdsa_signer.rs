@@ -0,0 +1,121 @@
+use k256::ecdsa::signature::hazmat::PrehashSigner;
+use k256::ecdsa::{recoverable, Signature, SigningKey, VerifyingKey};
+use k256::FieldBytes;
+use rstest::*;
+
+use common::ecdsa_signer::*;
+use common::types::ic_management_types::*;
+use test_common::canister_api::*;
+use test_common::ic_api::init_test;
+
+#[fixture]
+fn signing_key() -> SigningKey {
+    let mut secret = [0u8; 32];
+    secret[31] = 1;
+    SigningKey::from_bytes(&secret).unwrap()
+}
+
+/// management api mock which signs with a local key instead of a threshold key
+fn local_ecdsa_api(signing_key: SigningKey, key_name: ThresholdKeyName) -> MockICManagementAPI {
+    let mut api = MockICManagementAPI::new();
+    let public_key = signing_key.verifying_key().to_bytes().to_vec();
+    api.expect_ecdsa_public_key()
+        .withf(move |req| req.key_id == key_name.ecdsa_key_id())
+        .times(1)
+        .returning(move |_| {
+            Ok(ECDSAPublicKeyReply {
+                public_key: public_key.clone(),
+                chain_code: vec![],
+            })
+        });
+    api.expect_sign_with_ecdsa()
+        .withf(move |req| req.key_id == key_name.ecdsa_key_id())
+        .returning(move |req| {
+            let signature: Signature = signing_key.sign_prehash(&req.message_hash).unwrap();
+            Ok(SignWithECDSAReply {
+                signature: signature.as_ref().to_vec(),
+            })
+        });
+    api
+}
+
+#[rstest]
+fn test_key_name_for_env() {
+    assert_eq!(ThresholdKeyName::for_env("dev").name(), "dfx_test_key");
+    assert_eq!(ThresholdKeyName::for_env("staging").name(), "test_key_1");
+    assert_eq!(ThresholdKeyName::for_env("production").name(), "key_1");
+}
+
+#[rstest]
+#[async_std::test]
+async fn test_addresses(_init_test: (), signing_key: SigningKey) {
+    let signer = EcdsaSigner::new(
+        local_ecdsa_api(signing_key, ThresholdKeyName::TestKey1),
+        ThresholdKeyName::TestKey1,
+    );
+    let path = vec![b"user".to_vec()];
+
+    assert_eq!(
+        signer.ethereum_address(&path).await.unwrap(),
+        "0x7E5F4552091A69125d5DfCb7b8C2659029395Bdf"
+    );
+    assert_eq!(
+        signer
+            .bitcoin_p2pkh_address(&path, BitcoinNetwork::Mainnet)
+            .await
+            .unwrap(),
+        "1BgGZ9tcN4rm9KBzDn7KprQz87SZ26SAMH"
+    );
+    assert_eq!(
+        signer
+            .bitcoin_p2wpkh_address(&path, BitcoinNetwork::Mainnet)
+            .await
+            .unwrap(),
+        "bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4"
+    );
+}
+
+#[rstest]
+#[async_std::test]
+async fn test_sign_ethereum_is_recoverable(_init_test: (), signing_key: SigningKey) {
+    let expected = signing_key.verifying_key();
+    let signer = EcdsaSigner::new(
+        local_ecdsa_api(signing_key, ThresholdKeyName::Key1),
+        ThresholdKeyName::Key1,
+    );
+    let path = vec![];
+
+    for message in ["hello", "world", "threshold ecdsa"] {
+        let hash = ethereum_message_hash(message.as_bytes());
+        let signature = signer.sign_ethereum(&path, &hash).await.unwrap();
+        let bytes = signature.to_bytes();
+        assert_eq!(bytes.len(), 65);
+        assert!(bytes[64] == 27 || bytes[64] == 28);
+
+        let recoverable = recoverable::Signature::new(
+            &Signature::try_from(&bytes[..64]).unwrap(),
+            recoverable::Id::new(signature.v).unwrap(),
+        )
+        .unwrap();
+        let recovered: VerifyingKey = recoverable
+            .recover_verifying_key_from_digest_bytes(FieldBytes::from_slice(&hash))
+            .unwrap();
+        assert_eq!(recovered, expected);
+    }
+}
+
+#[rstest]
+fn test_to_ethereum_signature_rejects_other_key(signing_key: SigningKey) {
+    let hash = keccak256(b"message");
+    let signature: Signature = signing_key.sign_prehash(&hash).unwrap();
+    let mut other = [0u8; 32];
+    other[31] = 2;
+    let other_key = SigningKey::from_bytes(&other).unwrap();
+
+    let result = to_ethereum_signature(
+        signature.as_ref(),
+        &hash,
+        &other_key.verifying_key().to_bytes(),
+    );
+    assert!(result.is_err());
+}
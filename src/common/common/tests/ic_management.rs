@@ -0,0 +1,191 @@
+use candid::Principal;
+use mockall::Sequence;
+use rstest::*;
+
+use common::canister_api::IICManagementAPI;
+use common::errors::{ActorResult, ErrorInfo};
+use common::types::ic_management_types::*;
+use test_common::canister_api::*;
+use test_common::ic_api::init_test;
+
+fn canister_id() -> Principal {
+    Principal::from_text("rkp4c-7iaaa-aaaaa-aaaca-cai").unwrap()
+}
+
+fn id_record() -> CanisterIdRecord {
+    CanisterIdRecord {
+        canister_id: canister_id(),
+    }
+}
+
+fn not_stopped() -> ErrorInfo {
+    ErrorInfo::new(5, "canister is not stopped")
+}
+
+/// Replaces the code of a canister the way an operator would, stopped while it is reinstalled.
+async fn reinstall<T: IICManagementAPI>(api: &T, wasm_module: Vec<u8>) -> ActorResult<()> {
+    api.stop_canister(id_record()).await?;
+    api.uninstall_code(id_record()).await?;
+    api.install_code(CanisterInstall {
+        mode: InstallMode::Reinstall,
+        canister_id: canister_id(),
+        wasm_module,
+        arg: vec![],
+    })
+    .await?;
+    api.start_canister(id_record()).await
+}
+
+#[rstest]
+#[async_std::test]
+async fn test_install_code_in_upgrade_mode(
+    _init_test: (),
+    mut mock_ic_management_api: MockICManagementAPI,
+) {
+    mock_ic_management_api
+        .expect_install_code()
+        .withf(|install| {
+            install.mode == InstallMode::Upgrade
+                && install.canister_id == canister_id()
+                && install.wasm_module == vec![1, 2]
+                && install.arg == vec![3]
+        })
+        .times(1)
+        .returning(|_| Ok(()));
+
+    let result = mock_ic_management_api
+        .install_code(CanisterInstall {
+            mode: InstallMode::Upgrade,
+            canister_id: canister_id(),
+            wasm_module: vec![1, 2],
+            arg: vec![3],
+        })
+        .await;
+
+    assert_eq!(result, Ok(()));
+}
+
+#[rstest]
+#[async_std::test]
+async fn test_reinstall_stops_the_canister_first(
+    _init_test: (),
+    mut mock_ic_management_api: MockICManagementAPI,
+) {
+    let mut seq = Sequence::new();
+    mock_ic_management_api
+        .expect_stop_canister()
+        .withf(|record| *record == id_record())
+        .times(1)
+        .in_sequence(&mut seq)
+        .returning(|_| Ok(()));
+    mock_ic_management_api
+        .expect_uninstall_code()
+        .withf(|record| *record == id_record())
+        .times(1)
+        .in_sequence(&mut seq)
+        .returning(|_| Ok(()));
+    mock_ic_management_api
+        .expect_install_code()
+        .withf(|install| install.mode == InstallMode::Reinstall)
+        .times(1)
+        .in_sequence(&mut seq)
+        .returning(|_| Ok(()));
+    mock_ic_management_api
+        .expect_start_canister()
+        .withf(|record| *record == id_record())
+        .times(1)
+        .in_sequence(&mut seq)
+        .returning(|_| Ok(()));
+
+    assert_eq!(reinstall(&mock_ic_management_api, vec![1]).await, Ok(()));
+}
+
+#[rstest]
+#[async_std::test]
+async fn test_failed_stop_keeps_the_code(
+    _init_test: (),
+    mut mock_ic_management_api: MockICManagementAPI,
+) {
+    mock_ic_management_api
+        .expect_stop_canister()
+        .returning(|_| Err(not_stopped()));
+    mock_ic_management_api.expect_uninstall_code().never();
+    mock_ic_management_api.expect_install_code().never();
+
+    assert_eq!(
+        reinstall(&mock_ic_management_api, vec![1]).await,
+        Err(not_stopped())
+    );
+}
+
+#[rstest]
+#[async_std::test]
+async fn test_update_settings(_init_test: (), mut mock_ic_management_api: MockICManagementAPI) {
+    let controller = Principal::anonymous();
+    mock_ic_management_api
+        .expect_update_settings()
+        .withf(move |args| {
+            args.canister_id == canister_id() && args.settings.controllers == Some(vec![controller])
+        })
+        .times(1)
+        .returning(|_| Ok(()));
+
+    let result = mock_ic_management_api
+        .update_settings(UpdateSettingsArgument {
+            canister_id: canister_id(),
+            settings: CanisterSettings {
+                controllers: Some(vec![controller]),
+                compute_allocation: None,
+                memory_allocation: None,
+                freezing_threshold: None,
+            },
+        })
+        .await;
+
+    assert_eq!(result, Ok(()));
+}
+
+#[rstest]
+#[async_std::test]
+async fn test_delete_canister_error(
+    _init_test: (),
+    mut mock_ic_management_api: MockICManagementAPI,
+) {
+    mock_ic_management_api
+        .expect_delete_canister()
+        .times(1)
+        .returning(|_| Err(not_stopped()));
+
+    let result = mock_ic_management_api.delete_canister(id_record()).await;
+
+    assert_eq!(result, Err(not_stopped()));
+}
+
+#[rstest]
+#[async_std::test]
+async fn test_deposit_cycles(_init_test: (), mut mock_ic_management_api: MockICManagementAPI) {
+    mock_ic_management_api
+        .expect_deposit_cycles()
+        .withf(|record, cycles| *record == id_record() && *cycles == 1_000_000)
+        .times(1)
+        .returning(|_, _| Ok(()));
+
+    let result = mock_ic_management_api
+        .deposit_cycles(id_record(), 1_000_000)
+        .await;
+
+    assert_eq!(result, Ok(()));
+}
+
+#[rstest]
+#[async_std::test]
+async fn test_raw_rand(_init_test: (), mut mock_ic_management_api: MockICManagementAPI) {
+    mock_ic_management_api
+        .expect_raw_rand()
+        .times(1)
+        .returning(|| Ok(vec![7; 32]));
+
+    let bytes = mock_ic_management_api.raw_rand().await.unwrap();
+
+    assert_eq!(bytes.len(), 32);
+}
@@ -0,0 +1,290 @@
+use std::rc::Rc;
+
+use candid::Nat;
+use rstest::*;
+
+use common::canister_api::DFTTransactionResponse;
+use common::errors::ErrorInfo;
+use common::saga::*;
+use common::scheduler::Job;
+use common::state::StableState;
+use common::types::ic_ledger_types::{Memo, Tokens, TransferArgs, TransferError};
+use common::types::TimeInNs;
+use test_common::canister_api::*;
+use test_common::ic_api::init_test;
+
+const PAYMENT: Memo = Memo(1);
+const REFUND: Memo = Memo(2);
+
+type TestExecutor = SagaExecutor<TransferStep, TransferStepHandler<MockDFTApi, MockICLedgerApi>>;
+
+fn ledger_transfer(memo: Memo) -> TokenTransfer {
+    TokenTransfer::IcLedger(TransferArgs {
+        memo,
+        amount: Tokens::from_e8s(10),
+        fee: Tokens::from_e8s(1),
+        from_subaccount: None,
+        to: [0; 32],
+        created_at_time: None,
+    })
+}
+
+/// takes an ICP payment, refunded on failure, then pays out the DFT token
+fn steps() -> Vec<TransferStep> {
+    vec![
+        TransferStep {
+            transfer: ledger_transfer(PAYMENT),
+            refund: Some(ledger_transfer(REFUND)),
+        },
+        TransferStep {
+            transfer: TokenTransfer::Dft {
+                from_sub_account: None,
+                to: "bob".to_string(),
+                value: Nat::from(10),
+                created_at: 1,
+            },
+            refund: None,
+        },
+    ]
+}
+
+fn dft_transferred() -> DFTTransactionResponse {
+    DFTTransactionResponse {
+        tx_id: "1".to_string(),
+        block_height: Nat::from(1),
+    }
+}
+
+fn executor(dft_api: MockDFTApi, ledger_api: MockICLedgerApi) -> TestExecutor {
+    SagaExecutor::new(TransferStepHandler::new(dft_api, ledger_api))
+}
+
+fn step_statuses(id: SagaId) -> Vec<SagaStepStatus> {
+    get_saga(id)
+        .unwrap()
+        .steps
+        .into_iter()
+        .map(|s| s.status)
+        .collect()
+}
+
+/// simulates a trap while the first step was awaiting
+fn interrupt_first_step(id: SagaId) {
+    SAGA_STORE.with(|s| {
+        s.borrow_mut().get_mut(id).unwrap().steps[0].status = SagaStepStatus::Running;
+    });
+}
+
+#[rstest]
+#[async_std::test]
+async fn test_saga_completes(
+    _init_test: (),
+    mut mock_dft_api: MockDFTApi,
+    mut mock_ic_ledger_api: MockICLedgerApi,
+) {
+    mock_ic_ledger_api
+        .expect_transfer()
+        .withf(|args| args.memo == PAYMENT)
+        .times(1)
+        .returning(|_| Ok(Ok(1)));
+    mock_dft_api
+        .expect_transfer()
+        .withf(|_, to, value, created_at| {
+            to == "bob" && *value == Nat::from(10) && *created_at == Some(1)
+        })
+        .times(1)
+        .returning(|_, _, _, _| Ok(dft_transferred()));
+    let executor = executor(mock_dft_api, mock_ic_ledger_api);
+    let id = executor.start("refund", steps(), TimeInNs(1));
+
+    let status = executor.run(id, TimeInNs(2)).await.unwrap();
+
+    assert_eq!(status, SagaStatus::Completed);
+    assert_eq!(
+        step_statuses(id),
+        vec![SagaStepStatus::Done, SagaStepStatus::Done]
+    );
+    assert!(get_in_flight_sagas().is_empty());
+}
+
+#[rstest]
+#[async_std::test]
+async fn test_failed_step_compensates_completed_steps(
+    _init_test: (),
+    mut mock_dft_api: MockDFTApi,
+    mut mock_ic_ledger_api: MockICLedgerApi,
+) {
+    mock_ic_ledger_api
+        .expect_transfer()
+        .withf(|args| args.memo == PAYMENT)
+        .times(1)
+        .returning(|_| Ok(Ok(1)));
+    mock_dft_api
+        .expect_transfer()
+        .times(1)
+        .returning(|_, _, _, _| Err(ErrorInfo::new(1, "insufficient balance")));
+    mock_ic_ledger_api
+        .expect_transfer()
+        .withf(|args| args.memo == REFUND)
+        .times(1)
+        .returning(|_| Ok(Ok(2)));
+    let executor = executor(mock_dft_api, mock_ic_ledger_api);
+    let id = executor.start("refund", steps(), TimeInNs(1));
+
+    let status = executor.run(id, TimeInNs(2)).await.unwrap();
+
+    assert_eq!(status, SagaStatus::RolledBack);
+    let statuses = step_statuses(id);
+    assert_eq!(statuses[0], SagaStepStatus::Compensated);
+    assert!(matches!(statuses[1], SagaStepStatus::Failed(_)));
+}
+
+#[rstest]
+#[async_std::test]
+async fn test_failed_compensation_marks_saga_failed(
+    _init_test: (),
+    mut mock_dft_api: MockDFTApi,
+    mut mock_ic_ledger_api: MockICLedgerApi,
+) {
+    mock_ic_ledger_api
+        .expect_transfer()
+        .withf(|args| args.memo == PAYMENT)
+        .returning(|_| Ok(Ok(1)));
+    mock_dft_api
+        .expect_transfer()
+        .returning(|_, _, _, _| Err(ErrorInfo::new(1, "insufficient balance")));
+    mock_ic_ledger_api
+        .expect_transfer()
+        .withf(|args| args.memo == REFUND)
+        .returning(|_| {
+            Ok(Err(TransferError::InsufficientFunds {
+                balance: Tokens::from_e8s(0),
+            }))
+        });
+    let executor = executor(mock_dft_api, mock_ic_ledger_api);
+    let id = executor.start("refund", steps(), TimeInNs(1));
+
+    let status = executor.run(id, TimeInNs(2)).await.unwrap();
+
+    assert_eq!(status, SagaStatus::Failed);
+    let saga = get_saga(id).unwrap();
+    assert!(saga.error.unwrap().contains("ledger transfer failed"));
+    assert!(matches!(
+        saga.steps[0].status,
+        SagaStepStatus::CompensationFailed(_)
+    ));
+}
+
+#[rstest]
+#[async_std::test]
+async fn test_duplicate_ledger_transfer_is_done(
+    _init_test: (),
+    mut mock_dft_api: MockDFTApi,
+    mut mock_ic_ledger_api: MockICLedgerApi,
+) {
+    // the payment was sent by the call which trapped
+    mock_ic_ledger_api
+        .expect_transfer()
+        .returning(|_| Ok(Err(TransferError::TxDuplicate { duplicate_of: 3 })));
+    mock_dft_api
+        .expect_transfer()
+        .returning(|_, _, _, _| Ok(dft_transferred()));
+    let executor = executor(mock_dft_api, mock_ic_ledger_api);
+
+    let status = executor
+        .start_and_run("refund", steps(), TimeInNs(1))
+        .await
+        .unwrap();
+
+    assert_eq!(status, SagaStatus::Completed);
+}
+
+#[rstest]
+#[async_std::test]
+async fn test_resume_rolls_back_interrupted_saga(
+    _init_test: (),
+    mock_dft_api: MockDFTApi,
+    mut mock_ic_ledger_api: MockICLedgerApi,
+) {
+    mock_ic_ledger_api
+        .expect_transfer()
+        .withf(|args| args.memo == REFUND)
+        .times(1)
+        .returning(|_| Ok(Ok(2)));
+    let executor = executor(mock_dft_api, mock_ic_ledger_api);
+    let id = executor.start("refund", steps(), TimeInNs(1));
+    interrupt_first_step(id);
+
+    let results = executor.resume_all(TimeInNs(2)).await;
+
+    assert_eq!(results.len(), 1);
+    assert_eq!(results[0].1, Ok(SagaStatus::RolledBack));
+    assert_eq!(
+        step_statuses(id),
+        vec![SagaStepStatus::Compensated, SagaStepStatus::Pending]
+    );
+}
+
+#[rstest]
+#[async_std::test]
+async fn test_resume_job_runs_registered_runner(
+    _init_test: (),
+    mock_dft_api: MockDFTApi,
+    mut mock_ic_ledger_api: MockICLedgerApi,
+) {
+    mock_ic_ledger_api
+        .expect_transfer()
+        .withf(|args| args.memo == REFUND)
+        .times(1)
+        .returning(|_| Ok(Ok(2)));
+    let executor = Rc::new(executor(mock_dft_api, mock_ic_ledger_api));
+    register_saga_runner("refund", executor.clone());
+    let id = executor.start("refund", steps(), TimeInNs(1));
+    interrupt_first_step(id);
+
+    SagaResumeJob.run(TimeInNs(2)).await.unwrap();
+
+    assert_eq!(get_saga(id).unwrap().status, SagaStatus::RolledBack);
+    assert!(get_in_flight_sagas().is_empty());
+}
+
+#[rstest]
+#[async_std::test]
+async fn test_resume_saga_without_runner(
+    _init_test: (),
+    mock_dft_api: MockDFTApi,
+    mock_ic_ledger_api: MockICLedgerApi,
+) {
+    let executor = executor(mock_dft_api, mock_ic_ledger_api);
+    let id = executor.start("unregistered", steps(), TimeInNs(1));
+
+    assert!(resume_saga(id, TimeInNs(2)).await.is_err());
+    assert!(SagaResumeJob.run(TimeInNs(2)).await.is_err());
+    assert_eq!(get_in_flight_sagas().len(), 1);
+}
+
+#[rstest]
+#[async_std::test]
+async fn test_abort(_init_test: (), mock_dft_api: MockDFTApi, mock_ic_ledger_api: MockICLedgerApi) {
+    // no transfer is expected, the mocks fail on any call
+    let executor = executor(mock_dft_api, mock_ic_ledger_api);
+    let id = executor.start("refund", steps(), TimeInNs(1));
+    executor.abort(id, TimeInNs(2)).unwrap();
+
+    let status = executor.run(id, TimeInNs(3)).await.unwrap();
+
+    assert_eq!(status, SagaStatus::RolledBack);
+    assert!(executor.abort(id, TimeInNs(4)).is_err());
+}
+
+#[rstest]
+fn test_store_encode_decode(mock_dft_api: MockDFTApi, mock_ic_ledger_api: MockICLedgerApi) {
+    let executor = executor(mock_dft_api, mock_ic_ledger_api);
+    let id = executor.start("refund", steps(), TimeInNs(1));
+    let store = SAGA_STORE.with(|s| s.borrow().clone());
+
+    let decoded = SagaStore::decode(store.encode()).unwrap();
+
+    assert_eq!(decoded.get(id).unwrap().steps.len(), 2);
+    assert_eq!(decoded.in_flight().len(), 1);
+}
@@ -1,8 +1,7 @@
 use anyhow::{Ok, Result};
-use vergen::{vergen, Config};
+use build_common::build_info::generate_build_info;
 
 fn main() -> Result<()> {
-    // Generate the default 'cargo:' instruction output
-    vergen(Config::default())?;
+    generate_build_info()?;
     Ok(())
 }
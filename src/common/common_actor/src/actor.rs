@@ -1,23 +1,99 @@
-use std::collections::HashMap;
+use std::rc::Rc;
 
-use candid::candid_method;
-use ic_cdk::api;
+use candid::{candid_method, Principal};
+use ic_cdk::api::call::arg_data_raw;
+use ic_cdk::{api, storage};
 use ic_cdk_macros::*;
 use log::{debug, error, info};
 
-use common::constants::is_dev_env;
+use common::audit_log::{args_digest, audit, get_audit_records, GetAuditRecordsResponse};
+use common::backup::{
+    fetch_snapshot, get_backup_list, refresh_backup_list, BackupJob, SnapshotListResponse,
+    BACKUP_JOB_NAME,
+};
+use common::canister_api::ic_impl::{BackupApi, ICManagementAPI};
+use common::canister_api::IICManagementAPI;
+use common::canister_factory::{
+    get_failed_canisters, get_upgrade_progress, CanisterFactory, CommitWasmResponse,
+    CreateCanisterResponse, GetManagedCanistersResponse, GetUpgradeProgressResponse,
+    TopUpCanistersResponse, TopUpRecord, WasmStore, WASM_STORE,
+};
+use common::config::{
+    apply_config, get_config_history, get_config_record, is_feature_enabled, set_config, Config,
+    GetConfigHistoryResponse, GetConfigResponse, SetConfigResponse, FEATURE_LOAD_STATE,
+};
+use common::crash_log::{
+    enter_method, enter_update, get_crash_records, record_abandoned_calls, GetCrashRecordsResponse,
+};
+use common::dedup::{dedup_call, fail_dedup_request};
 use common::dto::{
-    from_state_export_data, to_state_export_data, GetStatsResponse, LoadStateRequest,
-    StateExportResponse,
+    decode_zlib, to_state_export_data, CanisterArgs, GetPageInput, GetStatsResponse,
+    LoadStateRequest, StateExportResponse, StateValidation, ValidateStateResponse,
+};
+use common::errors::{
+    get_error_catalog, ActorResult, BooleanActorResponse, CommonError, ErrorCatalogEntry,
+    ErrorInfo, ServiceResult,
+};
+use common::http::{HttpRequest, HttpResponse};
+use common::ic_logger::{
+    get_logs, set_log_filter, set_log_format, set_log_level, GetLogsResponse, LogFormat, LogLevel,
+};
+use common::named_canister_ids::{
+    find_named_canister_id, get_named_canister_ids, update_named_canister_ids, CanisterNames,
+    GetNamedCanisterIdsResponse,
+};
+use common::named_principals::{
+    list_named_principals, set_named_principals, GetNamedPrincipalsResponse,
+    PRINCIPAL_NAME_STATE_EXPORTER, PRINCIPAL_NAME_TIMER_TRIGGER,
 };
-use common::errors::{BooleanActorResponse, CommonError, ErrorInfo};
-use common::named_principals::PRINCIPAL_NAME_STATE_EXPORTER;
 use common::permissions::{must_be_named_principal, must_be_system_owner};
+use common::proposals::{
+    fail_proposal_execution, finish_proposal, get_proposal, get_proposals, propose,
+    start_proposal_execution, vote_proposal, GetProposalsResponse, ProposalAction, ProposalInfo,
+    ProposalResponse,
+};
+use common::saga::{
+    get_in_flight_sagas, resume_saga, GetSagasResponse, SagaId, SagaResumeJob, SagaStatusResponse,
+    SAGA_RESUME_JOB_NAME,
+};
+use common::scheduler::{
+    get_jobs, register_job, run_due_jobs, unregister_job, GetJobsResponse, JobSchedule,
+    TriggerJobsResponse,
+};
 use common::state::StableState;
+use common::timeout_lock::{get_held_locks, HeldLocksResponse};
+use common::types::ic_management_types::{
+    CanisterIdRecord, CanisterInstall, CanisterSettings, InstallMode,
+};
+use common::types::TimeInNs;
+use common::wasm_info::{
+    get_wasm_info, record_install, wasm_info_http_response, BuildInfo, InstallKind, WasmInfo,
+};
 
-use crate::state::{State, STATE};
+use crate::inspect::check_update;
+use crate::state::{
+    get_previous_state, replace_state, restore_previous_state, rollback_state, State,
+};
 use crate::stats_service::{Stats, StatsService};
 
+common::wasm_info_metadata!();
+
+const BUILD_INFO: BuildInfo = common::build_info!();
+
+/// Records the outcome of a privileged call in the audit log, the caller must have passed the
+/// permission checks already. Reads the arguments of the message, async endpoints take their
+/// digest before the first await and call `audit` themselves.
+fn audited<T>(caller: &Principal, method: &str, result: ServiceResult<T>) -> ServiceResult<T> {
+    audit(
+        caller,
+        method,
+        args_digest(&arg_data_raw()),
+        &result,
+        TimeInNs(api::time()),
+    );
+    result
+}
+
 #[query(name = "get_stats")]
 #[candid_method(query, rename = "get_stats")]
 pub fn get_stats() -> GetStatsResponse<Stats> {
@@ -27,62 +103,778 @@ pub fn get_stats() -> GetStatsResponse<Stats> {
     GetStatsResponse::new(Ok(stats))
 }
 
+#[query(name = "get_held_locks")]
+#[candid_method(query, rename = "get_held_locks")]
+pub fn get_timeout_locks() -> HeldLocksResponse {
+    let caller = &api::caller();
+    if let Err(e) = must_be_system_owner(caller) {
+        return HeldLocksResponse::new(Err(e));
+    }
+    HeldLocksResponse::new(Ok(get_held_locks(TimeInNs(api::time()))))
+}
+
+/// Runs the due jobs, called periodically by the timer trigger since ic-cdk 0.5 has no timers.
+#[update(name = "trigger_jobs")]
+#[candid_method(update, rename = "trigger_jobs")]
+pub async fn trigger_jobs() -> TriggerJobsResponse {
+    let _method = enter_update("trigger_jobs", TimeInNs(api::time()));
+    if let Err(e) = check_update("trigger_jobs") {
+        return TriggerJobsResponse::new(Err(e));
+    }
+    let caller = &api::caller();
+    if let Err(e) = must_be_named_principal(caller, PRINCIPAL_NAME_TIMER_TRIGGER) {
+        return TriggerJobsResponse::new(Err(e));
+    }
+    TriggerJobsResponse::new(Ok(run_due_jobs(TimeInNs(api::time())).await))
+}
+
+#[query(name = "get_jobs")]
+#[candid_method(query, rename = "get_jobs")]
+pub fn get_jobs_query() -> GetJobsResponse {
+    let caller = &api::caller();
+    if let Err(e) = must_be_system_owner(caller) {
+        return GetJobsResponse::new(Err(e));
+    }
+    GetJobsResponse::new(Ok(get_jobs()))
+}
+
+#[query(name = "get_logs")]
+#[candid_method(query, rename = "get_logs")]
+pub fn get_logs_query(page: GetPageInput, level: Option<LogLevel>) -> GetLogsResponse {
+    let caller = &api::caller();
+    if let Err(e) = must_be_system_owner(caller) {
+        return GetLogsResponse::new(Err(e));
+    }
+    GetLogsResponse::new(get_logs(&page, level))
+}
+
+#[update(name = "set_log_level")]
+#[candid_method(update, rename = "set_log_level")]
+pub fn set_log_level_update(level: LogLevel) -> BooleanActorResponse {
+    let _method = enter_update("set_log_level", TimeInNs(api::time()));
+    if let Err(e) = check_update("set_log_level") {
+        return BooleanActorResponse::new(Err(e));
+    }
+    let caller = &api::caller();
+    if let Err(e) = must_be_system_owner(caller) {
+        return BooleanActorResponse::new(Err(e));
+    }
+    set_log_level(level);
+    info!("log level set to {:?}", level);
+    BooleanActorResponse::new(audited(caller, "set_log_level", Ok(true)))
+}
+
+/// Accepts env_logger style directives, e.g. `common::canister_api=debug,info`.
+#[update(name = "set_log_filter")]
+#[candid_method(update, rename = "set_log_filter")]
+pub fn set_log_filter_update(filter: String) -> BooleanActorResponse {
+    let _method = enter_update("set_log_filter", TimeInNs(api::time()));
+    if let Err(e) = check_update("set_log_filter") {
+        return BooleanActorResponse::new(Err(e));
+    }
+    let caller = &api::caller();
+    if let Err(e) = must_be_system_owner(caller) {
+        return BooleanActorResponse::new(Err(e));
+    }
+    let result = set_log_filter(&filter).map(|_| true);
+    if result.is_ok() {
+        info!("log filter set to {}", filter);
+    }
+    BooleanActorResponse::new(audited(caller, "set_log_filter", result))
+}
+
+#[update(name = "set_log_format")]
+#[candid_method(update, rename = "set_log_format")]
+pub fn set_log_format_update(format: LogFormat) -> BooleanActorResponse {
+    let _method = enter_update("set_log_format", TimeInNs(api::time()));
+    if let Err(e) = check_update("set_log_format") {
+        return BooleanActorResponse::new(Err(e));
+    }
+    let caller = &api::caller();
+    if let Err(e) = must_be_system_owner(caller) {
+        return BooleanActorResponse::new(Err(e));
+    }
+    set_log_format(format);
+    BooleanActorResponse::new(audited(caller, "set_log_format", Ok(true)))
+}
+
+#[query(name = "get_crash_records")]
+#[candid_method(query, rename = "get_crash_records")]
+pub fn get_crash_records_query(page: GetPageInput) -> GetCrashRecordsResponse {
+    let caller = &api::caller();
+    if let Err(e) = must_be_system_owner(caller) {
+        return GetCrashRecordsResponse::new(Err(e));
+    }
+    GetCrashRecordsResponse::new(get_crash_records(&page))
+}
+
 #[update(name = "export_state")]
 #[candid_method(update, rename = "export_state")]
 pub async fn export_state() -> StateExportResponse {
+    let _method = enter_update("export_state", TimeInNs(api::time()));
+    if let Err(e) = check_update("export_state") {
+        return StateExportResponse::new(Err(e));
+    }
     let caller = &api::caller();
     let permission_result = must_be_named_principal(caller, PRINCIPAL_NAME_STATE_EXPORTER);
     if permission_result.is_err() {
         return StateExportResponse::new(Err(permission_result.err().unwrap()));
     }
+    let result = Ok(to_state_export_data(State::capture().encode()));
+    StateExportResponse::new(audited(caller, "export_state", result))
+}
 
-    let source_data = STATE.with(|state| to_state_export_data(state.encode()));
-    StateExportResponse::new(Ok(source_data))
+/// Decompresses and decodes a snapshot as returned by `export_state`.
+fn decode_state_data(state_data: &[u8]) -> ServiceResult<(Vec<u8>, State)> {
+    let bytes = decode_zlib(state_data)?;
+    let state = State::decode(bytes.clone()).map_err(|detail| CommonError::InvalidState {
+        detail: format!("Failed to decode state: {}", detail),
+    })?;
+    Ok((bytes, state))
 }
 
-#[update(name = "load_state")]
-#[candid_method(update, rename = "load_state")]
-pub fn load_state(request: LoadStateRequest) -> BooleanActorResponse {
-    if !is_dev_env() {
-        return BooleanActorResponse::new(Err(CommonError::Unknown {
-            detail: "!is_dev_env()".to_string(),
-        }));
+fn load_state_data(state_data: Vec<u8>) -> ServiceResult<()> {
+    if !is_feature_enabled(FEATURE_LOAD_STATE) {
+        return Err(CommonError::InvalidState {
+            detail: format!("feature {} is disabled", FEATURE_LOAD_STATE),
+        });
     }
-    debug!("load_state: {}", request);
+    apply_state_data(state_data)
+}
+
+/// Replaces the state with a snapshot, without the `load_state` feature check. Restoring a
+/// backup only needs the approvals of its proposal, the snapshot was taken from this canister.
+fn apply_state_data(state_data: Vec<u8>) -> ServiceResult<()> {
+    let (_, new_state) = decode_state_data(&state_data)?;
+    let violations = new_state.check_invariants();
+    if !violations.is_empty() {
+        error!("load_state: invalid state, {}", violations.join("; "));
+        return Err(CommonError::InvalidState {
+            detail: violations.join("; "),
+        });
+    }
+    replace_state(new_state);
+    info!("load_state: success");
+    Ok(())
+}
+
+/// Checks a snapshot the way a `LoadState` proposal would, without applying it.
+#[query(name = "validate_state")]
+#[candid_method(query, rename = "validate_state")]
+pub fn validate_state(request: LoadStateRequest) -> ValidateStateResponse {
     let caller = &api::caller();
-    if must_be_system_owner(caller).is_err() {
-        error!("load_state: caller is not system owner");
-        return BooleanActorResponse::new(Err(CommonError::PermissionDenied));
+    if let Err(e) = must_be_system_owner(caller) {
+        return ValidateStateResponse::new(Err(e));
     }
-    STATE.with(|s| {
-        let bytes = from_state_export_data(request);
-        let result = State::decode(bytes);
-        if result.is_err() {
-            let err_msg = format!("Failed to decode state: {:?}", result.err());
-            error!("{}", err_msg.to_string());
-            return BooleanActorResponse::Err(ErrorInfo::from(CommonError::Unknown {
-                detail: err_msg,
-            }));
+    let result = decode_state_data(&request.state_data).map(|(bytes, state)| StateValidation {
+        compressed_size: request.state_data.len() as u64,
+        size: bytes.len() as u64,
+        hash: args_digest(&bytes),
+        schema_version: state.schema_version,
+        violations: state.check_invariants(),
+    });
+    ValidateStateResponse::new(result)
+}
+
+async fn execute_action(action: ProposalAction) -> ServiceResult<()> {
+    let api = ICManagementAPI::default();
+    match action {
+        ProposalAction::LoadState { state_data } => load_state_data(state_data),
+        ProposalAction::SetNamedCanisterIds(ids) => update_named_canister_ids(&ids),
+        ProposalAction::SetNamedPrincipals { name, principals } => {
+            set_named_principals(&name, &principals)
         }
-        let new_state = result.unwrap();
-        s.replace(new_state);
-        info!("load_state: success");
-        return BooleanActorResponse::Ok(true);
-    })
+        ProposalAction::RollbackState => {
+            rollback_state().map_err(|detail| CommonError::InvalidState { detail })
+        }
+        ProposalAction::RestoreBackup { snapshot_id } => {
+            let state_data = fetch_snapshot(&BackupApi, snapshot_id).await?;
+            apply_state_data(state_data)
+        }
+        ProposalAction::UpgradeCanister {
+            canister_id,
+            wasm_module,
+            arg,
+        } => api
+            .install_code(CanisterInstall {
+                mode: InstallMode::Upgrade,
+                canister_id: canister_id.0,
+                wasm_module,
+                arg,
+            })
+            .await
+            .map_err(CommonError::from),
+        ProposalAction::WithdrawCycles {
+            canister_id,
+            amount,
+        } => api
+            .deposit_cycles(
+                CanisterIdRecord {
+                    canister_id: canister_id.0,
+                },
+                amount,
+            )
+            .await
+            .map_err(CommonError::from),
+    }
 }
 
-#[query(name = "get_wasm_info")]
-#[candid_method(query)]
-fn get_wasm_info() -> HashMap<&'static str, &'static str> {
-    let mut map = HashMap::new();
-    map.insert("VERGEN_BUILD_TIMESTAMP", env!("VERGEN_BUILD_TIMESTAMP"));
-    map.insert("VERGEN_BUILD_SEMVER", env!("VERGEN_BUILD_SEMVER"));
-    map.insert("VERGEN_GIT_BRANCH", env!("VERGEN_GIT_BRANCH"));
-    map.insert(
-        "VERGEN_GIT_COMMIT_TIMESTAMP",
-        env!("VERGEN_GIT_COMMIT_TIMESTAMP"),
+/// Executes proposal `id` if it is approved, by the call which approved it. A trap after an
+/// await leaves the proposal `Executing`, it is never run twice and `fail_proposal_execution`
+/// closes it.
+async fn execute_approved_proposal(caller: &Principal, id: u64) {
+    let action = match start_proposal_execution(id, TimeInNs(api::time())) {
+        Ok(action) => action,
+        Err(_) => return,
+    };
+    // the args of the call are not readable after an await, the action is recorded instead
+    let method = format!("execute_proposal:{}", action.name());
+    let digest = action.digest();
+    let result = execute_action(action).await;
+    let now = TimeInNs(api::time());
+    finish_proposal(id, &result, now);
+    audit(caller, &method, digest, &result, now);
+}
+
+async fn propose_and_execute(
+    caller: &Principal,
+    action: ProposalAction,
+    digest: String,
+) -> ActorResult<ProposalInfo> {
+    let result = propose(action, *caller, TimeInNs(api::time()));
+    audit(caller, "propose", digest, &result, TimeInNs(api::time()));
+    let id = result?;
+    execute_approved_proposal(caller, id).await;
+    Ok(get_proposal(id, TimeInNs(api::time()))?)
+}
+
+/// Proposes a dangerous admin action, it is executed once enough approvers voted for it.
+/// A retry with the same `request_id` returns the proposal of the first call instead of
+/// proposing the action again.
+#[update(name = "propose")]
+#[candid_method(update, rename = "propose")]
+pub async fn propose_update(
+    action: ProposalAction,
+    request_id: Option<String>,
+) -> ProposalResponse {
+    let _method = enter_update("propose", TimeInNs(api::time()));
+    if let Err(e) = check_update("propose") {
+        return ProposalResponse::new(Err(e));
+    }
+    let caller = &api::caller();
+    if let Err(e) = must_be_system_owner(caller) {
+        return ProposalResponse::new(Err(e));
+    }
+    let digest = args_digest(&arg_data_raw());
+    let result = match request_id {
+        Some(request_id) => {
+            dedup_call(
+                *caller,
+                request_id,
+                "propose",
+                TimeInNs(api::time()),
+                || propose_and_execute(caller, action, digest),
+            )
+            .await
+        }
+        None => propose_and_execute(caller, action, digest).await,
+    };
+    result.into()
+}
+
+#[update(name = "vote_proposal")]
+#[candid_method(update, rename = "vote_proposal")]
+pub async fn vote_proposal_update(id: u64, approve: bool) -> ProposalResponse {
+    let _method = enter_update("vote_proposal", TimeInNs(api::time()));
+    if let Err(e) = check_update("vote_proposal") {
+        return ProposalResponse::new(Err(e));
+    }
+    let caller = &api::caller();
+    if let Err(e) = must_be_system_owner(caller) {
+        return ProposalResponse::new(Err(e));
+    }
+    let result = vote_proposal(id, *caller, approve, TimeInNs(api::time()));
+    if let Err(e) = audited(caller, "vote_proposal", result) {
+        return ProposalResponse::new(Err(e));
+    }
+    execute_approved_proposal(caller, id).await;
+    ProposalResponse::new(get_proposal(id, TimeInNs(api::time())))
+}
+
+/// Marks proposal `id` failed when its execution trapped after an await and left it `Executing`.
+#[update(name = "fail_proposal_execution")]
+#[candid_method(update, rename = "fail_proposal_execution")]
+pub fn fail_proposal_execution_update(id: u64) -> ProposalResponse {
+    let _method = enter_update("fail_proposal_execution", TimeInNs(api::time()));
+    if let Err(e) = check_update("fail_proposal_execution") {
+        return ProposalResponse::new(Err(e));
+    }
+    let caller = &api::caller();
+    if let Err(e) = must_be_system_owner(caller) {
+        return ProposalResponse::new(Err(e));
+    }
+    let result = fail_proposal_execution(id, TimeInNs(api::time()));
+    if let Err(e) = audited(caller, "fail_proposal_execution", result) {
+        return ProposalResponse::new(Err(e));
+    }
+    ProposalResponse::new(get_proposal(id, TimeInNs(api::time())))
+}
+
+#[query(name = "get_proposal")]
+#[candid_method(query, rename = "get_proposal")]
+pub fn get_proposal_query(id: u64) -> ProposalResponse {
+    let caller = &api::caller();
+    if let Err(e) = must_be_system_owner(caller) {
+        return ProposalResponse::new(Err(e));
+    }
+    ProposalResponse::new(get_proposal(id, TimeInNs(api::time())))
+}
+
+#[query(name = "get_proposals")]
+#[candid_method(query, rename = "get_proposals")]
+pub fn get_proposals_query(page: GetPageInput) -> GetProposalsResponse {
+    let caller = &api::caller();
+    if let Err(e) = must_be_system_owner(caller) {
+        return GetProposalsResponse::new(Err(e));
+    }
+    GetProposalsResponse::new(get_proposals(&page, TimeInNs(api::time())))
+}
+
+/// Nothing else runs a saga whose message trapped, the job is registered again by init and
+/// post_upgrade since handlers are not persisted. Actors running sagas register their executors
+/// with `register_saga_runner` next to it.
+fn configure_saga_resume_job(now: TimeInNs) {
+    register_job(
+        SAGA_RESUME_JOB_NAME,
+        JobSchedule::Periodic {
+            interval: TimeInNs(10 * 60 * 1_000_000_000),
+        },
+        Rc::new(SagaResumeJob),
+        now,
     );
-    map.insert("VERGEN_GIT_SEMVER", env!("VERGEN_GIT_SEMVER"));
-    map.insert("VERGEN_GIT_SHA", env!("VERGEN_GIT_SHA"));
-    map
+}
+
+#[query(name = "get_config")]
+#[candid_method(query, rename = "get_config")]
+pub fn get_config_query() -> GetConfigResponse {
+    let caller = &api::caller();
+    if let Err(e) = must_be_system_owner(caller) {
+        return GetConfigResponse::new(Err(e));
+    }
+    GetConfigResponse::new(Ok(get_config_record()))
+}
+
+#[query(name = "get_config_history")]
+#[candid_method(query, rename = "get_config_history")]
+pub fn get_config_history_query() -> GetConfigHistoryResponse {
+    let caller = &api::caller();
+    if let Err(e) = must_be_system_owner(caller) {
+        return GetConfigHistoryResponse::new(Err(e));
+    }
+    GetConfigHistoryResponse::new(Ok(get_config_history()))
+}
+
+/// Replaces the whole config, `expected_version` is the version returned by `get_config`.
+#[update(name = "set_config")]
+#[candid_method(update, rename = "set_config")]
+pub fn set_config_update(config: Config, expected_version: u64) -> SetConfigResponse {
+    let _method = enter_update("set_config", TimeInNs(api::time()));
+    if let Err(e) = check_update("set_config") {
+        return SetConfigResponse::new(Err(e));
+    }
+    let caller = &api::caller();
+    if let Err(e) = must_be_system_owner(caller) {
+        return SetConfigResponse::new(Err(e));
+    }
+    let result = set_config(config, expected_version, *caller, TimeInNs(api::time()));
+    SetConfigResponse::new(audited(caller, "set_config", result))
+}
+
+#[query(name = "get_named_canister_ids")]
+#[candid_method(query, rename = "get_named_canister_ids")]
+pub fn get_named_canister_ids_query() -> GetNamedCanisterIdsResponse {
+    GetNamedCanisterIdsResponse::new(Ok(get_named_canister_ids()))
+}
+
+#[query(name = "get_named_principals")]
+#[candid_method(query, rename = "get_named_principals")]
+pub fn get_named_principals_query() -> GetNamedPrincipalsResponse {
+    let caller = &api::caller();
+    if let Err(e) = must_be_system_owner(caller) {
+        return GetNamedPrincipalsResponse::new(Err(e));
+    }
+    GetNamedPrincipalsResponse::new(Ok(list_named_principals()))
+}
+
+#[query(name = "get_audit_records")]
+#[candid_method(query, rename = "get_audit_records")]
+pub fn get_audit_records_query(page: GetPageInput) -> GetAuditRecordsResponse {
+    let caller = &api::caller();
+    if let Err(e) = must_be_system_owner(caller) {
+        return GetAuditRecordsResponse::new(Err(e));
+    }
+    GetAuditRecordsResponse::new(get_audit_records(&page))
+}
+
+/// Snapshots kept by the backup canister, newest first, as of the latest backup or
+/// `refresh_backups`. The list is kept in memory only, an upgrade empties it.
+#[query(name = "list_backups")]
+#[candid_method(query, rename = "list_backups")]
+pub fn list_backups() -> SnapshotListResponse {
+    let caller = &api::caller();
+    if let Err(e) = must_be_system_owner(caller) {
+        return SnapshotListResponse::new(Err(e));
+    }
+    SnapshotListResponse::new(Ok(get_backup_list()))
+}
+
+/// Reads the snapshots kept by the backup canister again, see `list_backups`.
+#[update(name = "refresh_backups")]
+#[candid_method(update, rename = "refresh_backups")]
+pub async fn refresh_backups() -> SnapshotListResponse {
+    let _method = enter_update("refresh_backups", TimeInNs(api::time()));
+    if let Err(e) = check_update("refresh_backups") {
+        return SnapshotListResponse::new(Err(e));
+    }
+    let caller = &api::caller();
+    if let Err(e) = must_be_system_owner(caller) {
+        return SnapshotListResponse::new(Err(e));
+    }
+    SnapshotListResponse::new(refresh_backup_list(&BackupApi).await)
+}
+
+/// Backups run when a backup canister id is supplied, the job is registered again by init and
+/// post_upgrade since handlers are not persisted.
+fn configure_backup_job(now: TimeInNs) {
+    if find_named_canister_id(CanisterNames::Backup).is_none() {
+        if unregister_job(BACKUP_JOB_NAME).is_some() {
+            info!("backup canister id removed, backups are off");
+        }
+        return;
+    }
+    // the compressed state, the same bytes `export_state` returns and `LoadState` accepts
+    let snapshot = Rc::new(|| to_state_export_data(State::capture().encode()).state_data);
+    register_job(
+        BACKUP_JOB_NAME,
+        JobSchedule::Periodic {
+            interval: TimeInNs(24 * 60 * 60 * 1_000_000_000),
+        },
+        Rc::new(BackupJob::new(BackupApi, snapshot)),
+        now,
+    );
+}
+
+#[init]
+#[candid_method(init)]
+fn init(args: CanisterArgs) {
+    let _method = enter_method("init");
+    apply_config();
+    if let Err(e) = update_named_canister_ids(&args.named_canister_ids) {
+        api::trap(&format!("init: invalid args, {}", e));
+    }
+    configure_saga_resume_job(TimeInNs(api::time()));
+    record_install(InstallKind::Install, &BUILD_INFO, TimeInNs(api::time()));
+    configure_backup_job(TimeInNs(api::time()));
+}
+
+#[pre_upgrade]
+fn pre_upgrade() {
+    let _method = enter_method("pre_upgrade");
+    let bytes = State::capture().encode();
+    // the wasm module is saved next to the state, it is not part of state exports
+    let wasm_bytes = WASM_STORE.with(|s| s.borrow().encode());
+    // kept so a `RollbackState` proposal still works after an upgrade
+    let previous_state = get_previous_state();
+    storage::stable_save((bytes, wasm_bytes, previous_state))
+        .expect("failed to save state to stable memory");
+}
+
+/// Upgrades without args keep the persisted canister ids.
+#[post_upgrade]
+fn post_upgrade(args: Option<CanisterArgs>) {
+    let _method = enter_method("post_upgrade");
+    apply_config();
+    // versions before the state was persisted left the stable memory empty
+    let restored: Result<(Vec<u8>, Vec<u8>, Option<Vec<u8>>), String> = storage::stable_restore();
+    match restored {
+        Ok((bytes, wasm_bytes, previous_state)) => {
+            let state = State::decode(bytes).expect("failed to decode state from stable memory");
+            state.restore();
+            let wasm_store =
+                WasmStore::decode(wasm_bytes).expect("failed to decode wasm from stable memory");
+            WASM_STORE.with(|s| s.replace(wasm_store));
+            restore_previous_state(previous_state);
+            info!("post_upgrade: state restored");
+            // the canister was stopped for the upgrade, a call still open trapped after an await
+            record_abandoned_calls(TimeInNs(api::time()), TimeInNs(api::time()));
+        }
+        Err(e) => error!("post_upgrade: no state in stable memory, {}", e),
+    }
+    let ids = args.unwrap_or_default().named_canister_ids;
+    if let Err(e) = update_named_canister_ids(&ids) {
+        api::trap(&format!("post_upgrade: invalid args, {}", e));
+    }
+    configure_saga_resume_job(TimeInNs(api::time()));
+    record_install(InstallKind::Upgrade, &BUILD_INFO, TimeInNs(api::time()));
+    configure_backup_job(TimeInNs(api::time()));
+}
+
+/// Appends a chunk to the wasm uploaded to the canister factory, see `commit_canister_factory_wasm`.
+#[update(name = "upload_canister_factory_wasm_chunk")]
+#[candid_method(update, rename = "upload_canister_factory_wasm_chunk")]
+pub fn upload_canister_factory_wasm_chunk(chunk: Vec<u8>) -> BooleanActorResponse {
+    let _method = enter_update("upload_canister_factory_wasm_chunk", TimeInNs(api::time()));
+    if let Err(e) = check_update("upload_canister_factory_wasm_chunk") {
+        return BooleanActorResponse::new(Err(e));
+    }
+    let caller = &api::caller();
+    if let Err(e) = must_be_system_owner(caller) {
+        return BooleanActorResponse::new(Err(e));
+    }
+    let factory = CanisterFactory::new(ICManagementAPI::default());
+    let size = factory.upload_wasm_chunk(chunk);
+    debug!(
+        "upload_canister_factory_wasm_chunk: {} bytes uploaded",
+        size
+    );
+    BooleanActorResponse::new(Ok(true))
+}
+
+/// Stores the uploaded chunks as the wasm of the canister factory if they hash to `wasm_hash`.
+#[update(name = "commit_canister_factory_wasm")]
+#[candid_method(update, rename = "commit_canister_factory_wasm")]
+pub fn commit_canister_factory_wasm(wasm_hash: String) -> CommitWasmResponse {
+    let _method = enter_update("commit_canister_factory_wasm", TimeInNs(api::time()));
+    if let Err(e) = check_update("commit_canister_factory_wasm") {
+        return CommitWasmResponse::new(Err(e));
+    }
+    let caller = &api::caller();
+    if let Err(e) = must_be_system_owner(caller) {
+        return CommitWasmResponse::new(Err(e));
+    }
+    let factory = CanisterFactory::new(ICManagementAPI::default());
+    let result = factory.commit_wasm(&wasm_hash);
+    CommitWasmResponse::new(audited(caller, "commit_canister_factory_wasm", result))
+}
+
+/// A retry with the same `request_id` returns the canister of the first call instead of
+/// creating another one.
+#[update(name = "create_factory_canister")]
+#[candid_method(update, rename = "create_factory_canister")]
+pub async fn create_factory_canister(
+    settings: CanisterSettings,
+    cycles: u64,
+    arg: Vec<u8>,
+    request_id: Option<String>,
+) -> CreateCanisterResponse {
+    let _method = enter_update("create_factory_canister", TimeInNs(api::time()));
+    if let Err(e) = check_update("create_factory_canister") {
+        return CreateCanisterResponse::new(Err(e));
+    }
+    let caller = &api::caller();
+    if let Err(e) = must_be_system_owner(caller) {
+        return CreateCanisterResponse::new(Err(e));
+    }
+    let digest = args_digest(&arg_data_raw());
+    let create = || async move {
+        let factory = CanisterFactory::new(ICManagementAPI::default());
+        factory
+            .create_canister(settings, cycles, arg)
+            .await
+            .map_err(ErrorInfo::from)
+    };
+    let result: ActorResult<Principal> = match request_id {
+        Some(request_id) => {
+            dedup_call(
+                *caller,
+                request_id,
+                "create_factory_canister",
+                TimeInNs(api::time()),
+                create,
+            )
+            .await
+        }
+        None => create().await,
+    };
+    audit(
+        caller,
+        "create_factory_canister",
+        digest,
+        &result,
+        TimeInNs(api::time()),
+    );
+    result.into()
+}
+
+/// Upgrades the next `batch_size` canisters to the stored wasm.
+#[update(name = "upgrade_factory_canisters")]
+#[candid_method(update, rename = "upgrade_factory_canisters")]
+pub async fn upgrade_factory_canisters(
+    batch_size: u32,
+    arg: Vec<u8>,
+) -> GetUpgradeProgressResponse {
+    let _method = enter_update("upgrade_factory_canisters", TimeInNs(api::time()));
+    if let Err(e) = check_update("upgrade_factory_canisters") {
+        return GetUpgradeProgressResponse::new(Err(e));
+    }
+    let caller = &api::caller();
+    if let Err(e) = must_be_system_owner(caller) {
+        return GetUpgradeProgressResponse::new(Err(e));
+    }
+    let digest = args_digest(&arg_data_raw());
+    let factory = CanisterFactory::new(ICManagementAPI::default());
+    let result = factory.upgrade_next_batch(batch_size as usize, arg).await;
+    audit(
+        caller,
+        "upgrade_factory_canisters",
+        digest,
+        &result,
+        TimeInNs(api::time()),
+    );
+    GetUpgradeProgressResponse::new(result)
+}
+
+/// Deposits `amount` cycles into every factory canister whose balance is below `threshold`.
+/// A retry with the same `request_id` returns the top ups of the first call instead of
+/// depositing again.
+#[update(name = "top_up_factory_canisters")]
+#[candid_method(update, rename = "top_up_factory_canisters")]
+pub async fn top_up_factory_canisters(
+    threshold: u64,
+    amount: u64,
+    request_id: Option<String>,
+) -> TopUpCanistersResponse {
+    let _method = enter_update("top_up_factory_canisters", TimeInNs(api::time()));
+    if let Err(e) = check_update("top_up_factory_canisters") {
+        return TopUpCanistersResponse::new(Err(e));
+    }
+    let caller = &api::caller();
+    if let Err(e) = must_be_system_owner(caller) {
+        return TopUpCanistersResponse::new(Err(e));
+    }
+    let digest = args_digest(&arg_data_raw());
+    let top_up = || async move {
+        let factory = CanisterFactory::new(ICManagementAPI::default());
+        factory
+            .top_up_canisters(threshold, amount)
+            .await
+            .map_err(ErrorInfo::from)
+    };
+    let result: ActorResult<Vec<TopUpRecord>> = match request_id {
+        Some(request_id) => {
+            dedup_call(
+                *caller,
+                request_id,
+                "top_up_factory_canisters",
+                TimeInNs(api::time()),
+                top_up,
+            )
+            .await
+        }
+        None => top_up().await,
+    };
+    audit(
+        caller,
+        "top_up_factory_canisters",
+        digest,
+        &result,
+        TimeInNs(api::time()),
+    );
+    result.into()
+}
+
+/// Forgets a request which is stuck in progress because its call trapped, so it can be retried.
+/// Only do this once it is known that the call did not change anything.
+#[update(name = "fail_dedup_request")]
+#[candid_method(update, rename = "fail_dedup_request")]
+pub fn fail_dedup_request_update(caller: Principal, request_id: String) -> BooleanActorResponse {
+    let _method = enter_update("fail_dedup_request", TimeInNs(api::time()));
+    if let Err(e) = check_update("fail_dedup_request") {
+        return BooleanActorResponse::new(Err(e));
+    }
+    let admin = &api::caller();
+    if let Err(e) = must_be_system_owner(admin) {
+        return BooleanActorResponse::new(Err(e));
+    }
+    let result = fail_dedup_request(caller, request_id).map(|_| true);
+    BooleanActorResponse::new(audited(admin, "fail_dedup_request", result))
+}
+
+#[query(name = "get_canister_factory_progress")]
+#[candid_method(query, rename = "get_canister_factory_progress")]
+pub fn get_canister_factory_progress() -> GetUpgradeProgressResponse {
+    let caller = &api::caller();
+    if let Err(e) = must_be_system_owner(caller) {
+        return GetUpgradeProgressResponse::new(Err(e));
+    }
+    GetUpgradeProgressResponse::new(Ok(get_upgrade_progress()))
+}
+
+/// Canisters whose last install, upgrade, status check or top up failed.
+#[query(name = "get_canister_factory_failures")]
+#[candid_method(query, rename = "get_canister_factory_failures")]
+pub fn get_canister_factory_failures() -> GetManagedCanistersResponse {
+    let caller = &api::caller();
+    if let Err(e) = must_be_system_owner(caller) {
+        return GetManagedCanistersResponse::new(Err(e));
+    }
+    GetManagedCanistersResponse::new(Ok(get_failed_canisters()))
+}
+
+/// Sagas which are not completed, rolled back or failed yet, a saga stuck here was interrupted
+/// and is resumed by the `resume_sagas` job or `resume_saga`.
+#[query(name = "get_in_flight_sagas")]
+#[candid_method(query, rename = "get_in_flight_sagas")]
+pub fn get_in_flight_sagas_query() -> GetSagasResponse {
+    let caller = &api::caller();
+    if let Err(e) = must_be_system_owner(caller) {
+        return GetSagasResponse::new(Err(e));
+    }
+    GetSagasResponse::new(Ok(get_in_flight_sagas()))
+}
+
+/// Runs saga `id` now instead of waiting for the `resume_sagas` job.
+#[update(name = "resume_saga")]
+#[candid_method(update, rename = "resume_saga")]
+pub async fn resume_saga_update(id: SagaId) -> SagaStatusResponse {
+    let _method = enter_update("resume_saga", TimeInNs(api::time()));
+    if let Err(e) = check_update("resume_saga") {
+        return SagaStatusResponse::new(Err(e));
+    }
+    let caller = &api::caller();
+    if let Err(e) = must_be_system_owner(caller) {
+        return SagaStatusResponse::new(Err(e));
+    }
+    let digest = args_digest(&arg_data_raw());
+    let result = resume_saga(id, TimeInNs(api::time())).await;
+    audit(
+        caller,
+        "resume_saga",
+        digest,
+        &result,
+        TimeInNs(api::time()),
+    );
+    SagaStatusResponse::new(result)
+}
+
+#[query(name = "get_error_catalog")]
+#[candid_method(query, rename = "get_error_catalog")]
+pub fn get_error_catalog_query() -> Vec<ErrorCatalogEntry> {
+    get_error_catalog()
+}
+
+#[query(name = "get_wasm_info")]
+#[candid_method(query, rename = "get_wasm_info")]
+fn get_wasm_info_query() -> WasmInfo {
+    get_wasm_info(&BUILD_INFO)
+}
+
+/// Serves `GET /wasm_info` as JSON, for tools which read the canister over HTTP.
+#[query(name = "http_request")]
+#[candid_method(query, rename = "http_request")]
+fn http_request(request: HttpRequest) -> HttpResponse {
+    match (request.method.as_str(), request.get_url().path()) {
+        ("GET", "/wasm_info") => wasm_info_http_response(&BUILD_INFO),
+        _ => HttpResponse::string(404, "not found"),
+    }
 }
@@ -0,0 +1,94 @@
+use ic_cdk::api;
+use ic_cdk::api::call::{accept_message, arg_data_raw, method_name};
+use ic_cdk_macros::inspect_message;
+use log::debug;
+
+use common::config::get_config;
+use common::errors::ServiceResult;
+use common::inspect::{InspectPolicy, MethodAccess, MethodRule};
+use common::named_principals::{PRINCIPAL_NAME_STATE_EXPORTER, PRINCIPAL_NAME_TIMER_TRIGGER};
+use common::rate_limit::{check_rate_limit, peek_rate_limit};
+use common::types::TimeInNs;
+
+/// 4 MiB, above the ingress message limit, a state or wasm chunk is only bounded by the IC.
+const PAYLOAD_MAX_ARG_SIZE: usize = 4 * 1024 * 1024;
+
+/// Every method of this actor, queries included since they can be called as update calls too.
+fn inspect_policy() -> InspectPolicy {
+    use MethodAccess::*;
+    let public = MethodRule::new(Public);
+    let owner = MethodRule::new(SystemOwner);
+    let admin = owner.allowed_in_maintenance();
+    InspectPolicy::new()
+        .method("get_stats", public)
+        .method("get_error_catalog", public)
+        .method("get_wasm_info", public)
+        .method("http_request", public)
+        .method("get_named_canister_ids", public)
+        .method("get_held_locks", admin)
+        .method("get_canister_factory_progress", admin)
+        .method("get_canister_factory_failures", admin)
+        .method("get_jobs", admin)
+        .method("get_in_flight_sagas", admin)
+        .method("resume_saga", admin)
+        .method("get_logs", admin)
+        .method("get_crash_records", admin)
+        .method("get_config", admin)
+        .method("get_config_history", admin)
+        .method("get_audit_records", admin)
+        .method("list_backups", admin)
+        .method("refresh_backups", admin)
+        .method("set_log_level", admin)
+        .method("set_log_filter", admin)
+        .method("set_log_format", admin)
+        .method("set_config", admin)
+        .method("get_named_principals", admin)
+        .method("get_proposal", admin)
+        .method("get_proposals", admin)
+        .method("propose", admin.max_arg_size(PAYLOAD_MAX_ARG_SIZE))
+        .method("vote_proposal", admin)
+        .method("fail_proposal_execution", admin)
+        .method("validate_state", admin.max_arg_size(PAYLOAD_MAX_ARG_SIZE))
+        .method("fail_dedup_request", admin)
+        .method(
+            "upload_canister_factory_wasm_chunk",
+            owner.max_arg_size(PAYLOAD_MAX_ARG_SIZE),
+        )
+        .method("commit_canister_factory_wasm", owner)
+        .method("create_factory_canister", owner)
+        .method("upgrade_factory_canisters", owner)
+        .method("top_up_factory_canisters", owner)
+        .method(
+            "export_state",
+            MethodRule::new(NamedPrincipal(PRINCIPAL_NAME_STATE_EXPORTER)).allowed_in_maintenance(),
+        )
+        .method(
+            "trigger_jobs",
+            MethodRule::new(NamedPrincipal(PRINCIPAL_NAME_TIMER_TRIGGER)),
+        )
+}
+
+/// Maintenance mode and rate limit of an update endpoint, inter-canister calls do not go through
+/// `inspect_message`. Takes a token of the caller's bucket.
+pub(crate) fn check_update(method: &str) -> ServiceResult<()> {
+    inspect_policy().check_maintenance(method, get_config().maintenance_mode)?;
+    check_rate_limit(&api::caller(), method, TimeInNs(api::time()))
+}
+
+/// Rejects ingress calls the endpoint would refuse, before they consume cycles.
+#[inspect_message]
+fn inspect_message() {
+    let method = method_name();
+    let caller = api::caller();
+    let arg_size = arg_data_raw().len();
+    let result = inspect_policy()
+        .inspect(&method, &caller, arg_size, get_config().maintenance_mode)
+        .and_then(|_| peek_rate_limit(&caller, &method, TimeInNs(api::time())));
+    match result {
+        Ok(_) => accept_message(),
+        Err(e) => debug!(
+            "inspect_message: {} from {} rejected, {}",
+            method, caller, e
+        ),
+    }
+}
@@ -1,3 +1,4 @@
 mod actor;
+mod inspect;
 mod state;
 mod stats_service;
@@ -1,27 +1,255 @@
+use std::cell::RefCell;
+
+use candid::{decode_args, encode_args};
+
+use common::audit_log::{AuditLog, AUDIT_LOG};
+use common::canister_factory::{CanisterFactoryState, CANISTER_FACTORY_STATE};
+use common::config::{restore_config, ConfigStore, CONFIG_STORE};
+use common::crash_log::{CrashLog, CRASH_LOG};
+use common::dedup::{DedupStore, DEDUP_STORE};
+use common::ic_logger::{restore_log_buffer, LogBuffer, LOG_BUFFER};
+use common::named_canister_ids::{
+    restore_named_canister_ids, NamedCanisterIds, NAMED_CANISTER_IDS,
+};
+use common::named_principals::{restore_named_principals, NamedPrincipals, NAME_DPRINCIPALS};
+use common::proposals::{ProposalStore, PROPOSAL_STORE};
+use common::saga::{SagaStore, SAGA_STORE};
+use common::scheduler::{JobStore, JOB_STORE};
 use common::state::StableState;
+use common::wasm_info::{InstallHistory, INSTALL_HISTORY};
+
+/// Bumped when a field is added to `State`. Snapshots encoded before the version was written
+/// are version 1.
+pub const STATE_SCHEMA_VERSION: u32 = 2;
 
 thread_local! {
-    pub static STATE : State = State::default();
+    /// The encoded state replaced by the latest `LoadState` proposal, saved by `pre_upgrade`
+    /// next to the state so a rollback still works after an upgrade.
+    static PREVIOUS_STATE: RefCell<Option<Vec<u8>>> = RefCell::new(None);
 }
 
-#[derive(Default)]
+/// Snapshot of everything this canister persists across upgrades.
+/// The live data is kept by the stores in `common`, `capture` and `restore` move it in and out of them.
 pub struct State {
     // NOTE: When adding new persistent fields here, ensure that these fields
-    // are being persisted in the `replace` method below.
+    // are being captured and restored below, and are decoded as `Option` so
+    // snapshots taken before the field existed can still be loaded.
+    pub schema_version: u32,
+    pub canister_factory: CanisterFactoryState,
+    pub dedup_store: DedupStore,
+    pub saga_store: SagaStore,
+    pub job_store: JobStore,
+    pub log_buffer: LogBuffer,
+    pub crash_log: CrashLog,
+    pub named_canister_ids: NamedCanisterIds,
+    pub config_store: ConfigStore,
+    pub audit_log: AuditLog,
+    pub proposal_store: ProposalStore,
+    pub named_principals: NamedPrincipals,
+    pub install_history: InstallHistory,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        State {
+            schema_version: STATE_SCHEMA_VERSION,
+            canister_factory: Default::default(),
+            dedup_store: Default::default(),
+            saga_store: Default::default(),
+            job_store: Default::default(),
+            log_buffer: Default::default(),
+            crash_log: Default::default(),
+            named_canister_ids: Default::default(),
+            config_store: Default::default(),
+            audit_log: Default::default(),
+            proposal_store: Default::default(),
+            named_principals: Default::default(),
+            install_history: Default::default(),
+        }
+    }
 }
 
 impl State {
-    pub fn replace(&self, _new_state: State) {
-        unreachable!("State should not be replaced");
+    pub fn capture() -> State {
+        State {
+            schema_version: STATE_SCHEMA_VERSION,
+            canister_factory: CANISTER_FACTORY_STATE.with(|s| s.borrow().clone()),
+            dedup_store: DEDUP_STORE.with(|s| s.borrow().clone()),
+            saga_store: SAGA_STORE.with(|s| s.borrow().clone()),
+            job_store: JOB_STORE.with(|s| s.borrow().clone()),
+            log_buffer: LOG_BUFFER.with(|b| b.borrow().clone()),
+            crash_log: CRASH_LOG.with(|c| c.borrow().clone()),
+            named_canister_ids: NAMED_CANISTER_IDS.with(|n| n.borrow().clone()),
+            config_store: CONFIG_STORE.with(|s| s.borrow().clone()),
+            audit_log: AUDIT_LOG.with(|l| l.borrow().clone()),
+            proposal_store: PROPOSAL_STORE.with(|s| s.borrow().clone()),
+            named_principals: NAME_DPRINCIPALS.with(|n| n.borrow().clone()),
+            install_history: INSTALL_HISTORY.with(|h| h.borrow().clone()),
+        }
+    }
+
+    pub fn restore(self) {
+        CANISTER_FACTORY_STATE.with(|s| s.replace(self.canister_factory));
+        DEDUP_STORE.with(|s| s.replace(self.dedup_store));
+        SAGA_STORE.with(|s| s.replace(self.saga_store));
+        JOB_STORE.with(|s| s.replace(self.job_store));
+        restore_log_buffer(self.log_buffer);
+        CRASH_LOG.with(|c| c.replace(self.crash_log));
+        restore_named_canister_ids(self.named_canister_ids);
+        restore_config(self.config_store);
+        AUDIT_LOG.with(|l| l.replace(self.audit_log));
+        PROPOSAL_STORE.with(|s| s.replace(self.proposal_store));
+        restore_named_principals(self.named_principals);
+        INSTALL_HISTORY.with(|h| h.replace(self.install_history));
+    }
+
+    /// Invariant violations of every store, prefixed with the field name.
+    pub fn check_invariants(&self) -> Vec<String> {
+        let stores: [(&str, Vec<String>); 12] = [
+            ("canister_factory", self.canister_factory.check_invariants()),
+            ("dedup_store", self.dedup_store.check_invariants()),
+            ("saga_store", self.saga_store.check_invariants()),
+            ("job_store", self.job_store.check_invariants()),
+            ("log_buffer", self.log_buffer.check_invariants()),
+            ("crash_log", self.crash_log.check_invariants()),
+            (
+                "named_canister_ids",
+                self.named_canister_ids.check_invariants(),
+            ),
+            ("config_store", self.config_store.check_invariants()),
+            ("audit_log", self.audit_log.check_invariants()),
+            ("proposal_store", self.proposal_store.check_invariants()),
+            ("named_principals", self.named_principals.check_invariants()),
+            ("install_history", self.install_history.check_invariants()),
+        ];
+        stores
+            .into_iter()
+            .flat_map(|(name, violations)| {
+                violations
+                    .into_iter()
+                    .map(move |violation| format!("{}: {}", name, violation))
+            })
+            .collect()
     }
 }
 
 impl StableState for State {
     fn encode(&self) -> Vec<u8> {
-        unreachable!("State should not be encoded");
+        encode_args((
+            self.canister_factory.encode(),
+            Some(self.dedup_store.encode()),
+            Some(self.saga_store.encode()),
+            Some(self.job_store.encode()),
+            Some(self.log_buffer.encode()),
+            Some(self.crash_log.encode()),
+            Some(self.named_canister_ids.encode()),
+            Some(self.config_store.encode()),
+            Some(self.audit_log.encode()),
+            Some(self.proposal_store.encode()),
+            Some(self.named_principals.encode()),
+            Some(self.schema_version),
+            Some(self.install_history.encode()),
+        ))
+        .unwrap()
     }
 
-    fn decode(_bytes: Vec<u8>) -> Result<Self, String> {
-        unreachable!("State::decode should never be called");
+    fn decode(bytes: Vec<u8>) -> Result<Self, String> {
+        let (
+            canister_factory_bytes,
+            dedup_store_bytes,
+            saga_store_bytes,
+            job_store_bytes,
+            log_buffer_bytes,
+            crash_log_bytes,
+            named_canister_ids_bytes,
+            config_store_bytes,
+            audit_log_bytes,
+            proposal_store_bytes,
+            named_principals_bytes,
+            schema_version,
+            install_history_bytes,
+        ): (
+            Vec<u8>,
+            Option<Vec<u8>>,
+            Option<Vec<u8>>,
+            Option<Vec<u8>>,
+            Option<Vec<u8>>,
+            Option<Vec<u8>>,
+            Option<Vec<u8>>,
+            Option<Vec<u8>>,
+            Option<Vec<u8>>,
+            Option<Vec<u8>>,
+            Option<Vec<u8>>,
+            Option<u32>,
+            Option<Vec<u8>>,
+        ) = decode_args(&bytes).map_err(|e| format!("Failed to decode state: {:?}", e))?;
+        let schema_version = schema_version.unwrap_or(1);
+        if schema_version > STATE_SCHEMA_VERSION {
+            return Err(format!(
+                "state schema version {} is newer than {}",
+                schema_version, STATE_SCHEMA_VERSION
+            ));
+        }
+        Ok(State {
+            schema_version,
+            canister_factory: CanisterFactoryState::decode(canister_factory_bytes)?,
+            dedup_store: decode_optional(dedup_store_bytes)?,
+            saga_store: decode_optional(saga_store_bytes)?,
+            job_store: decode_optional(job_store_bytes)?,
+            log_buffer: decode_optional(log_buffer_bytes)?,
+            crash_log: decode_optional(crash_log_bytes)?,
+            named_canister_ids: decode_optional(named_canister_ids_bytes)?,
+            config_store: decode_optional(config_store_bytes)?,
+            audit_log: decode_optional(audit_log_bytes)?,
+            proposal_store: decode_optional(proposal_store_bytes)?,
+            named_principals: decode_optional(named_principals_bytes)?,
+            install_history: decode_optional(install_history_bytes)?,
+        })
     }
 }
+
+fn decode_optional<T: StableState + Default>(bytes: Option<Vec<u8>>) -> Result<T, String> {
+    match bytes {
+        Some(bytes) => T::decode(bytes),
+        None => Ok(T::default()),
+    }
+}
+
+/// Replaces the live state with `state`, keeping the current one for `rollback_state`.
+pub fn replace_state(mut state: State) {
+    let current = State::capture();
+    keep_canister_fields(&mut state, &current);
+    PREVIOUS_STATE.with(|p| p.replace(Some(current.encode())));
+    state.restore();
+}
+
+/// Restores the state replaced by the latest `replace_state`, once.
+pub fn rollback_state() -> Result<(), String> {
+    let bytes = PREVIOUS_STATE
+        .with(|p| p.borrow_mut().take())
+        .ok_or_else(|| "no previous state to roll back to".to_string())?;
+    let mut previous = State::decode(bytes)?;
+    keep_canister_fields(&mut previous, &State::capture());
+    previous.restore();
+    Ok(())
+}
+
+pub fn get_previous_state() -> Option<Vec<u8>> {
+    PREVIOUS_STATE.with(|p| p.borrow().clone())
+}
+
+pub fn restore_previous_state(previous_state: Option<Vec<u8>>) {
+    PREVIOUS_STATE.with(|p| p.replace(previous_state));
+}
+
+/// A loaded snapshot may come from another canister or env. It must not rewrite the history of
+/// this canister (audit log, proposals, installs), change who may approve, or point it at the
+/// canister ids and config of another env, so those fields of `current` are kept.
+fn keep_canister_fields(state: &mut State, current: &State) {
+    state.audit_log = current.audit_log.clone();
+    state.proposal_store = current.proposal_store.clone();
+    state.install_history = current.install_history.clone();
+    state.named_principals = current.named_principals.clone();
+    state.named_canister_ids = current.named_canister_ids.clone();
+    state.config_store = current.config_store.clone();
+}
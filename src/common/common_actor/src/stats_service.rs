@@ -1,13 +1,19 @@
 use candid::{CandidType, Deserialize};
 
+use common::rate_limit::{get_rate_limiter_stats, RateLimiterStats};
+
 #[derive(Default)]
 pub struct StatsService {}
 
 impl StatsService {
     pub fn get_stats(&self, _now: u64) -> Stats {
-        unreachable!("StatsService::get_stats()")
+        Stats {
+            rate_limiter: get_rate_limiter_stats(),
+        }
     }
 }
 
 #[derive(CandidType, Deserialize)]
-pub struct Stats {}
+pub struct Stats {
+    pub rate_limiter: RateLimiterStats,
+}
@@ -3,10 +3,13 @@ use candid::{Nat, Principal};
 use mockall::{mock, predicate::*};
 use rstest::*;
 
+use serde_bytes::ByteBuf;
+
 use common::{
+    backup::{BeginSnapshotArgs, SnapshotId, SnapshotMeta},
     canister_api::*,
     errors::ActorResult,
-    types::{ic_ledger_types::*, ic_management_types::*},
+    types::{cycles_minting_types::*, ic_ledger_types::*, ic_management_types::*},
 };
 
 mock! {
@@ -44,8 +47,18 @@ mock! {
         async fn create_canister(&self, args: CreateCanisterArgs) -> ActorResult<CanisterIdRecord>;
         async fn canister_status(&self, id_record: CanisterIdRecord) -> ActorResult<CanisterStatusResponse>;
         async fn canister_install(&self, canister_id: &Principal,wasm_module: Vec<u8>,args: Vec<u8>) -> ActorResult<()>;
+        async fn install_code(&self, install: CanisterInstall) -> ActorResult<()>;
+        async fn update_settings(&self, args: UpdateSettingsArgument) -> ActorResult<()>;
+        async fn uninstall_code(&self, id_record: CanisterIdRecord) -> ActorResult<()>;
+        async fn start_canister(&self, id_record: CanisterIdRecord) -> ActorResult<()>;
+        async fn stop_canister(&self, id_record: CanisterIdRecord) -> ActorResult<()>;
+        async fn delete_canister(&self, id_record: CanisterIdRecord) -> ActorResult<()>;
+        async fn deposit_cycles(&self, id_record: CanisterIdRecord, cycles: u64) -> ActorResult<()>;
+        async fn raw_rand(&self) -> ActorResult<Vec<u8>>;
         async fn ecdsa_public_key(&self, get_public_key_req: ECDSAPublicKey) -> ActorResult<ECDSAPublicKeyReply>;
         async fn sign_with_ecdsa(&self, sign_request: SignWithECDSA) -> ActorResult<SignWithECDSAReply>;
+        async fn schnorr_public_key(&self, args: SchnorrPublicKeyArgs) -> ActorResult<SchnorrPublicKeyResult>;
+        async fn sign_with_schnorr(&self, args: SignWithSchnorrArgs) -> ActorResult<SignWithSchnorrResult>;
     }
 }
 
@@ -53,3 +66,36 @@ mock! {
 pub fn mock_ic_management_api() -> MockICManagementAPI {
     MockICManagementAPI::new()
 }
+
+mock! {
+    pub CyclesMintingApi { }
+    #[async_trait]
+    impl ICyclesMintingApi for CyclesMintingApi {
+        async fn get_icp_xdr_conversion_rate(&self) -> ActorResult<IcpXdrConversionRateCertifiedResponse>;
+        async fn notify_top_up(&self, args: NotifyTopUpArg) -> ActorResult<NotifyTopUpResult>;
+        async fn notify_create_canister(&self, args: NotifyCreateCanisterArg) -> ActorResult<NotifyCreateCanisterResult>;
+    }
+}
+
+#[fixture]
+pub fn mock_cycles_minting_api() -> MockCyclesMintingApi {
+    MockCyclesMintingApi::new()
+}
+
+mock! {
+    pub BackupApi { }
+    #[async_trait]
+    impl IBackupApi for BackupApi {
+        async fn begin_snapshot(&self, args: BeginSnapshotArgs) -> ActorResult<SnapshotId>;
+        async fn put_chunk(&self, id: SnapshotId, index: u32, chunk: ByteBuf) -> ActorResult<()>;
+        async fn commit_snapshot(&self, id: SnapshotId) -> ActorResult<SnapshotMeta>;
+        async fn list_snapshots(&self) -> ActorResult<Vec<SnapshotMeta>>;
+        async fn get_snapshot(&self, id: SnapshotId) -> ActorResult<SnapshotMeta>;
+        async fn get_chunk(&self, id: SnapshotId, index: u32) -> ActorResult<ByteBuf>;
+    }
+}
+
+#[fixture]
+pub fn mock_backup_api() -> MockBackupApi {
+    MockBackupApi::new()
+}